@@ -188,9 +188,7 @@ impl<F: Field + From<u64> + Hash> halo2_proofs::plonk::Circuit<F> for FiboCircui
     ) -> Result<(), halo2_proofs::plonk::Error> {
         // ChiquitoHalo2 objects have their own configure and synthesize functions defined in the
         // Chiquito Halo2 backend
-        config.compiled.synthesize(&mut layouter, ());
-
-        Ok(())
+        config.compiled.synthesize(&mut layouter, ())
     }
 }
 
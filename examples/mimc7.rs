@@ -225,8 +225,7 @@ impl<F: PrimeField + Hash> halo2_proofs::plonk::Circuit<F> for Mimc7Circuit<F> {
     ) -> Result<(), halo2_proofs::plonk::Error> {
         config
             .compiled
-            .synthesize(&mut layouter, (self.x_in_value, self.k_value));
-        Ok(())
+            .synthesize(&mut layouter, (self.x_in_value, self.k_value))
     }
 }
 
@@ -1,8 +1,12 @@
+pub mod canonical;
+pub mod constant;
 pub mod expr;
+pub mod render;
 
-use std::{collections::HashMap, fmt::Debug, rc::Rc};
+use std::{collections::HashMap, fmt::Debug, rc::Rc, sync::Arc};
 
 use crate::{
+    ast::query::Queriable,
     compiler::{FixedGenContext, TraceContext, WitnessGenContext},
     dsl::StepTypeHandler,
     util::uuid,
@@ -10,21 +14,100 @@ use crate::{
 
 pub use expr::*;
 
-use halo2_proofs::plonk::{Advice, Column as Halo2Column, ColumnType, Fixed};
+use halo2_proofs::{
+    arithmetic::Field,
+    plonk::{Advice, Column as Halo2Column, ColumnType, Fixed},
+};
+
+/// Other names of signals and step types, kept so that the names that leave the process, in
+/// serialized witnesses, public input schemas and the annotations of the compiled columns, do not
+/// change when a signal or step type is renamed. The first alias of a signal or step type is its
+/// public name, used instead of its name in those places, and all its aliases resolve to it when
+/// names are read back.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Aliases {
+    names: HashMap<u32, Vec<String>>,
+}
+
+impl Aliases {
+    pub fn add<N: Into<String>>(&mut self, uuid: u32, alias: N) {
+        let alias = alias.into();
+        let names = self.names.entry(uuid).or_default();
+        if !names.contains(&alias) {
+            names.push(alias);
+        }
+    }
+
+    pub fn extend(&mut self, other: &Aliases) {
+        for (uuid, names) in other.names.iter() {
+            for name in names.iter() {
+                self.add(*uuid, name.clone());
+            }
+        }
+    }
+
+    pub fn get(&self, uuid: u32) -> &[String] {
+        self.names
+            .get(&uuid)
+            .map(|names| names.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// The first alias of `uuid`, or `name` when it has none.
+    pub fn public_name<'a>(&'a self, uuid: u32, name: &'a str) -> &'a str {
+        self.get(uuid)
+            .first()
+            .map(|alias| alias.as_str())
+            .unwrap_or(name)
+    }
+
+    /// Whether `candidate` is `name`, the name of `uuid`, or one of its aliases.
+    pub fn resolves(&self, uuid: u32, name: &str, candidate: &str) -> bool {
+        name == candidate || self.get(uuid).iter().any(|alias| alias == candidate)
+    }
+}
 
 /// SuperCircuit
 pub struct Circuit<F, TraceArgs, StepArgs> {
     pub forward_signals: Vec<ForwardSignal>,
+    /// Forward signals whose values are supplied with the witness instead of assigned by the
+    /// witness generation of the step types, see `ExternalColumns`.
+    pub external_signals: Vec<ForwardSignal>,
     pub halo2_advice: Vec<ImportedHalo2Advice>,
     pub halo2_fixed: Vec<ImportedHalo2Fixed>,
     pub step_types: HashMap<u32, Rc<StepType<F, StepArgs>>>,
     pub trace: Option<Rc<Trace<TraceArgs, StepArgs>>>,
+    /// The trace when it is set with `set_try_trace`, then `trace` panics on its errors.
+    pub try_trace: Option<Rc<TryTrace<TraceArgs, StepArgs>>>,
     pub fixed_gen: Option<Rc<FixedGen<F>>>,
 
     pub annotations: HashMap<u32, String>,
+    /// Aliases of the forward signals and step types, see `CircuitContext::alias`.
+    pub aliases: Aliases,
 
     pub first_step: Option<StepTypeHandler>,
     pub last_step: Option<StepTypeHandler>,
+    pub num_steps: usize,
+    pub step_schedule: StepSchedule,
+
+    pub exposed: Vec<(Queriable<F>, ExposeOffset)>,
+    pub exposed_results: Vec<ExposedResult<F>>,
+    /// Exposed signals computed from the other forward signals, see
+    /// `CircuitContext::expose_derived`.
+    pub derived_values: Vec<DerivedValue<F>>,
+
+    pub padding_steps: Vec<StepTypeHandler>,
+    pub padding_allowed: Vec<ForwardSignal>,
+    /// Defaults of the forward signals, see `CircuitContext::signal_default`.
+    pub signal_defaults: Vec<(ForwardSignal, SignalDefault)>,
+
+    pub signal_widths: Vec<SignalWidth<F>>,
+    pub step_fixed: Vec<(StepFixedSignal, Rc<StepFixedGen<F>>)>,
+    pub fixed_tables: Vec<FixedTable<F>>,
+    /// Challenges of the verifier that the constraints can query, see `CircuitContext::challenge`.
+    pub challenges: Vec<Challenge>,
+    pub invariants: Vec<Invariant<F>>,
+    pub trace_constraints: Vec<TraceConstraint<F>>,
 }
 
 impl<F: Debug, TraceArgs: Debug, StepArgs: Debug> Debug for Circuit<F, TraceArgs, StepArgs> {
@@ -34,6 +117,7 @@ impl<F: Debug, TraceArgs: Debug, StepArgs: Debug> Debug for Circuit<F, TraceArgs
             .field("halo2_advice", &self.halo2_advice)
             .field("step_types", &self.step_types)
             .field("annotations", &self.annotations)
+            .field("exposed", &self.exposed)
             .finish()
     }
 }
@@ -42,14 +126,31 @@ impl<F, TraceArgs, StepArgs> Default for Circuit<F, TraceArgs, StepArgs> {
     fn default() -> Self {
         Self {
             forward_signals: Default::default(),
+            external_signals: Default::default(),
             halo2_advice: Default::default(),
             halo2_fixed: Default::default(),
             step_types: Default::default(),
             trace: None,
+            try_trace: None,
             fixed_gen: None,
             annotations: Default::default(),
+            aliases: Default::default(),
             first_step: None,
             last_step: None,
+            num_steps: 0,
+            step_schedule: StepSchedule::default(),
+            exposed: Default::default(),
+            exposed_results: Default::default(),
+            derived_values: Default::default(),
+            padding_steps: Default::default(),
+            padding_allowed: Default::default(),
+            signal_defaults: Default::default(),
+            signal_widths: Default::default(),
+            step_fixed: Default::default(),
+            fixed_tables: Default::default(),
+            challenges: Default::default(),
+            invariants: Default::default(),
+            trace_constraints: Default::default(),
         }
     }
 }
@@ -65,6 +166,24 @@ impl<F, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
         signal
     }
 
+    /// Adds a challenge drawn by the verifier after the advice columns of `phase` are committed.
+    pub fn add_challenge<N: Into<String>>(&mut self, name: N, phase: usize) -> Challenge {
+        let name = name.into();
+        let challenge = Challenge::new(phase, name.clone());
+
+        self.challenges.push(challenge);
+        self.annotations.insert(challenge.uuid(), name);
+
+        challenge
+    }
+
+    pub fn add_external<N: Into<String>>(&mut self, name: N) -> ForwardSignal {
+        let signal = self.add_forward(name, 0);
+        self.external_signals.push(signal);
+
+        signal
+    }
+
     pub fn add_halo2_advice(
         &mut self,
         name: &str,
@@ -91,6 +210,45 @@ impl<F, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
         advice
     }
 
+    pub fn add_step_fixed<N: Into<String>, G>(&mut self, name: N, gen: G) -> StepFixedSignal
+    where
+        G: Fn(usize) -> F + 'static,
+    {
+        let name = name.into();
+        let signal = StepFixedSignal::new(name.clone());
+
+        self.step_fixed.push((signal, Rc::new(gen)));
+        self.annotations.insert(signal.uuid(), name);
+
+        signal
+    }
+
+    /// Adds a table of fixed columns named `{name}[i]`, one for each value of the rows.
+    pub fn add_fixed_table<N: Into<String>>(
+        &mut self,
+        name: N,
+        rows: Vec<Vec<F>>,
+    ) -> Vec<StepFixedSignal> {
+        let name = name.into();
+        let width = rows.first().map(|row| row.len()).unwrap_or_default();
+        let columns: Vec<StepFixedSignal> = (0..width)
+            .map(|i| {
+                let column = format!("{}[{}]", name, i);
+                let signal = StepFixedSignal::new(column.clone());
+                self.annotations.insert(signal.uuid(), column);
+
+                signal
+            })
+            .collect();
+
+        self.fixed_tables.push(FixedTable {
+            columns: columns.clone(),
+            rows: Rc::new(rows),
+        });
+
+        columns
+    }
+
     pub fn add_step_type<N: Into<String>>(&mut self, handler: StepTypeHandler, name: N) {
         self.annotations.insert(handler.uuid(), name.into());
     }
@@ -115,6 +273,28 @@ impl<F, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
         }
     }
 
+    /// Like `set_trace`, for a trace that can fail. Its errors are returned by the witness
+    /// generation functions that support them, like `ChiquitoHalo2::try_witness`, and the others
+    /// panic with them.
+    pub fn set_try_trace<D, E>(&mut self, def: D)
+    where
+        D: Fn(&mut dyn TraceContext<StepArgs>, TraceArgs) -> Result<(), E> + 'static,
+        E: Into<TraceError>,
+        TraceArgs: 'static,
+        StepArgs: 'static,
+    {
+        let def: Rc<TryTrace<TraceArgs, StepArgs>> =
+            Rc::new(move |ctx, args| def(ctx, args).map_err(Into::into));
+
+        let fallible = Rc::clone(&def);
+        self.set_trace(move |ctx, args| {
+            if let Err(err) = fallible(ctx, args) {
+                panic!("trace failed: {}", err);
+            }
+        });
+        self.try_trace = Some(def);
+    }
+
     pub fn set_fixed_gen<D>(&mut self, def: D)
     where
         D: Fn(&mut dyn FixedGenContext<F>) + 'static,
@@ -130,14 +310,375 @@ impl<F, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
 
         Rc::clone(step_rc)
     }
+
+    pub fn expose(&mut self, signal: Queriable<F>, offset: ExposeOffset) {
+        match signal {
+            Queriable::Forward(_, false) => {
+                if self.exposed.iter().any(|(s, _)| s.uuid() == signal.uuid()) {
+                    panic!("signal {} is already exposed", signal.annotation());
+                }
+
+                self.exposed.push((signal, offset));
+            }
+            _ => panic!("can only expose a forward signal without rotation"),
+        }
+    }
+
+    /// Adds the forward signals and step types of the library to the circuit. The step types are
+    /// shared with the library and every other circuit linking it, and are not defined again.
+    pub fn link(&mut self, library: &StepTypeLibrary<F, StepArgs>) {
+        for signal in library.forward_signals.iter() {
+            if !self.forward_signals.contains(signal) {
+                self.forward_signals.push(*signal);
+            }
+        }
+
+        for (uuid, step) in library.step_types.iter() {
+            self.step_types.insert(*uuid, Rc::clone(step));
+        }
+
+        self.annotations.extend(
+            library
+                .annotations
+                .iter()
+                .map(|(uuid, name)| (*uuid, name.clone())),
+        );
+    }
+}
+
+/// A set of step types and the forward signals they use, defined once and linked into any
+/// number of circuits with the same `StepArgs`. The library keeps its own signal and step type
+/// UUIDs, so constraints and witness generation written against it are valid in every circuit
+/// linking it.
+pub struct StepTypeLibrary<F, StepArgs> {
+    pub name: String,
+    pub forward_signals: Vec<ForwardSignal>,
+    pub step_types: HashMap<u32, Rc<StepType<F, StepArgs>>>,
+    pub handlers: Vec<StepTypeHandler>,
+    pub annotations: HashMap<u32, String>,
+}
+
+impl<F, StepArgs> StepTypeLibrary<F, StepArgs> {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Self {
+            name: name.into(),
+            forward_signals: Default::default(),
+            step_types: Default::default(),
+            handlers: Default::default(),
+            annotations: Default::default(),
+        }
+    }
+
+    /// Returns the handler of the step type with the given name.
+    pub fn step_type(&self, name: &str) -> StepTypeHandler {
+        *self
+            .handlers
+            .iter()
+            .find(|handler| handler.annotation == name)
+            .unwrap_or_else(|| panic!("step type {} not found in library {}", name, self.name))
+    }
+
+    /// Returns the forward signal with the given name.
+    pub fn forward(&self, name: &str) -> Queriable<F> {
+        let signal = self
+            .forward_signals
+            .iter()
+            .find(|signal| self.annotations.get(&signal.uuid()).map(String::as_str) == Some(name))
+            .unwrap_or_else(|| {
+                panic!("forward signal {} not found in library {}", name, self.name)
+            });
+
+        Queriable::Forward(*signal, false)
+    }
+}
+
+impl<F: Clone, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
+    /// Step types declared as padding, including the padding step types of exposed results.
+    pub fn padding_step_types(&self) -> Vec<StepTypeHandler> {
+        let mut padding = self.padding_steps.clone();
+        for result in self.exposed_results.iter() {
+            if !padding.contains(&result.padding) {
+                padding.push(result.padding);
+            }
+        }
+
+        padding
+    }
+
+    /// Forward signals that padding steps must keep unchanged: the exposed signals and the
+    /// signals carried from step to step by the transitions of non-padding step types, except
+    /// those allowed to change with `allow_padding_change`.
+    pub fn padding_protected_signals(&self) -> Vec<ForwardSignal> {
+        let padding = self.padding_step_types();
+
+        let mut protected: Vec<ForwardSignal> = self
+            .exposed
+            .iter()
+            .filter_map(|(signal, _)| match signal {
+                Queriable::Forward(forward, _) => Some(*forward),
+                _ => None,
+            })
+            .collect();
+
+        for step in self.step_types.values() {
+            if padding.iter().any(|handler| handler.uuid() == step.uuid()) {
+                continue;
+            }
+
+            for constraint in step.transition_constraints.iter() {
+                for queriable in constraint.expr.queriables() {
+                    if let Queriable::Forward(forward, true) = queriable {
+                        if !protected.contains(&forward) {
+                            protected.push(forward);
+                        }
+                    }
+                }
+            }
+        }
+
+        protected.retain(|signal| !self.padding_allowed.contains(signal));
+        protected.sort_by_key(|signal| signal.uuid());
+
+        protected
+    }
+
+    /// Adds the range checks of the signals declared with a checked width to every step type,
+    /// including the step types linked from a library, which the circuit gets its own copy of.
+    /// Must be called once all step types are defined.
+    pub fn lower_signal_widths(&mut self)
+    where
+        F: Clone,
+    {
+        for width in self.signal_widths.iter().filter(|width| width.checked) {
+            for step in self.step_types.values_mut() {
+                Rc::make_mut(step).add_range_check(width);
+            }
+        }
+    }
+
+    /// Applies the signal defaults to every step type querying the signals, see
+    /// `StepType::add_signal_default`. Must be called once all step types are defined.
+    pub fn lower_signal_defaults(&mut self)
+    where
+        F: Clone,
+    {
+        for (signal, default) in self.signal_defaults.iter() {
+            for step in self.step_types.values_mut() {
+                Rc::make_mut(step).add_signal_default(*signal, *default);
+            }
+        }
+    }
+
+    /// Adds the constraints of the exposed results to every step type. Must be called once all
+    /// step types are defined.
+    pub fn lower_exposed_results(&mut self) {
+        for result in self.exposed_results.iter() {
+            for step in self.step_types.values_mut() {
+                let step = Rc::get_mut(step).expect(
+                    "step type is already in use, results cannot be exposed from a library",
+                );
+
+                step.add_transition(
+                    format!(
+                        "if(next step is {})then(next({}) == {})",
+                        result.padding.annotation,
+                        result.carrier.annotation(),
+                        result.carrier.annotation()
+                    ),
+                    result.padding.next() * (result.carrier.next() - result.carrier),
+                );
+
+                if step.uuid() != result.padding.uuid() {
+                    step.add_constr(
+                        format!(
+                            "{} == {}",
+                            result.carrier.annotation(),
+                            result.signal.annotation()
+                        ),
+                        result.carrier - result.signal,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Adds the constraints of the derived values to every step type, and the values to assign in
+    /// its instances. Must be called once all step types are defined.
+    pub fn lower_derived_values(&mut self) {
+        for value in self.derived_values.iter() {
+            for step in self.step_types.values_mut() {
+                let step = Rc::get_mut(step)
+                    .expect("step type is already in use, values cannot be derived from a library");
+
+                step.add_constr(
+                    format!("derived({})", value.signal.annotation()),
+                    value.signal - value.expr.clone(),
+                );
+                step.derived_values.push(value.clone());
+            }
+        }
+    }
 }
 
 pub type Trace<TraceArgs, StepArgs> = dyn Fn(&mut dyn TraceContext<StepArgs>, TraceArgs) + 'static;
+/// Error of a trace set with `Circuit::set_try_trace`.
+pub type TraceError = Box<dyn std::error::Error>;
+pub type TryTrace<TraceArgs, StepArgs> =
+    dyn Fn(&mut dyn TraceContext<StepArgs>, TraceArgs) -> Result<(), TraceError> + 'static;
 pub type FixedGen<F> = dyn Fn(&mut dyn FixedGenContext<F>) + 'static;
+/// Value of a step-indexed fixed signal at a step index.
+pub type StepFixedGen<F> = dyn Fn(usize) -> F + 'static;
 pub type StepWitnessGen<F, Args> = dyn Fn(&mut dyn WitnessGenContext<F>, Args) + 'static;
+pub type SyncStepWitnessGen<F, Args> =
+    dyn Fn(&mut dyn WitnessGenContext<F>, Args) + Send + Sync + 'static;
 
 pub type StepTypeUUID = u32;
 
+/// Order of the step instances of the witness, set with `pragma_step_schedule`. The backend
+/// assigns the instances of a step type with the same layout, so runs of instances of one type
+/// are assigned without looking up their placement again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StepSchedule {
+    /// The instances are in the order the trace adds them.
+    #[default]
+    InOrder,
+    /// The trace adds all the instances of a step type one after the other. The witness
+    /// generation panics when a step type is added again after another one.
+    Contiguous,
+    /// The witness generation groups the instances by step type, in the order the step types are
+    /// first added. The compiler only allows it when no step queries the rows of another step.
+    Grouped,
+}
+
+/// Step of the trace at which an exposed signal is read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExposeOffset {
+    First,
+    Last,
+    Step(usize),
+}
+
+/// A result exposed at the last step of the trace. The `carrier` forward signal takes the value
+/// of `signal` in every step that is not of the `padding` step type, and keeps its previous value
+/// in padding steps, so the last step carries the value of the last non-padding step.
+#[derive(Clone, Debug)]
+pub struct ExposedResult<F> {
+    pub signal: Queriable<F>,
+    pub carrier: Queriable<F>,
+    pub padding: StepTypeHandler,
+}
+
+/// A forward signal that takes the value of `expr`, over the other forward signals of the same
+/// step, in every step of the trace. The trace context assigns it after the witness generation of
+/// each step, so the instance values exposed from it are never computed by hand.
+#[derive(Clone, Debug)]
+pub struct DerivedValue<F> {
+    pub signal: Queriable<F>,
+    pub expr: Expr<F>,
+}
+
+/// What the trace context assigns to a forward signal in a step that queries it but whose witness
+/// generation does not assign it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalDefault {
+    Zero,
+    /// The value of the signal in the previous step, the usual default of the signals that
+    /// padding steps keep unchanged. The first step must assign it.
+    Carry,
+    /// Witness generation panics, the signal must always be assigned.
+    Error,
+}
+
+/// A lookup table of fixed columns, queried like step-indexed fixed signals, see
+/// `CircuitContext::fixed_table_from_iter`.
+#[derive(Clone, Debug)]
+pub struct FixedTable<F> {
+    pub columns: Vec<StepFixedSignal>,
+    /// The values of the columns in each row, from the first row of the circuit.
+    pub rows: Rc<Vec<Vec<F>>>,
+}
+
+/// Decomposes a value in little-endian bits, or returns `None` when it does not fit.
+pub type Decompose<F> = dyn Fn(&F) -> Option<Vec<F>>;
+
+/// Builds the constraints that a signal is the sum of the given bit signals, each of them binary.
+pub type RangeConstraints<F> = dyn Fn(&[InternalSignal]) -> Vec<(String, Expr<F>)>;
+
+/// A forward signal declared with a bit-width.
+#[derive(Clone)]
+pub struct SignalWidth<F> {
+    pub signal: ForwardSignal,
+    pub bits: u32,
+    /// The step types defined after the declaration range check the signal, see
+    /// `StepType::add_range_check`.
+    pub checked: bool,
+    pub decompose: Rc<Decompose<F>>,
+    pub constraints: Rc<RangeConstraints<F>>,
+}
+
+/// Bit signals of a step type that decompose a forward signal declared with a bit-width. They are
+/// assigned during trace generation, after the witness generation function of the step.
+#[derive(Clone)]
+pub struct RangeCheck<F> {
+    pub signal: ForwardSignal,
+    pub bits: Vec<InternalSignal>,
+    pub decompose: Rc<Decompose<F>>,
+}
+
+/// Constraints of a step type enabled by the same condition, see `StepTypeSetupContext::group`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintGroup {
+    pub name: String,
+    /// Annotation of the condition enabling the group.
+    pub enable: String,
+    pub constraints: usize,
+    pub transitions: usize,
+}
+
+/// A constraint on forward signals that the compiler adds to every step type that does not
+/// override it, see `CircuitContext::invariant`.
+#[derive(Clone, Debug)]
+pub struct Invariant<F> {
+    pub name: String,
+    pub constraint: Constraint<F>,
+    /// Whether the constraint queries the next step, and is added as a transition constraint.
+    pub transition: bool,
+}
+
+/// Steps of the trace where a `TraceConstraint` is enforced, by their index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceRows {
+    Step(usize),
+    /// The last of the `num_steps` steps.
+    Last,
+    /// The steps `offset`, `offset + period`, `offset + 2 * period` and so on.
+    Periodic {
+        period: usize,
+        offset: usize,
+    },
+}
+
+impl TraceRows {
+    pub fn contains(&self, step: usize, num_steps: usize) -> bool {
+        match self {
+            TraceRows::Step(index) => step == *index,
+            TraceRows::Last => step + 1 == num_steps,
+            TraceRows::Periodic { period, offset } => {
+                step >= *offset && (step - offset) % period == 0
+            }
+        }
+    }
+}
+
+/// A constraint on forward signals enforced at some steps of the trace whatever their step type,
+/// see `CircuitContext::trace_constraint`. The compiler gates it with a fixed column that is one
+/// in the rows of those steps.
+#[derive(Clone, Debug)]
+pub struct TraceConstraint<F> {
+    pub constraint: Constraint<F>,
+    pub rows: TraceRows,
+}
+
 /// Step
 pub struct StepType<F, Args> {
     id: StepTypeUUID,
@@ -146,10 +687,34 @@ pub struct StepType<F, Args> {
     pub signals: Vec<InternalSignal>,
     pub constraints: Vec<Constraint<F>>,
     pub transition_constraints: Vec<TransitionConstraint<F>>,
+    /// Compiled only when enabled in the compiler, see `Compiler::with_debug_constraints`.
+    pub debug_constraints: Vec<Constraint<F>>,
+    pub debug_transition_constraints: Vec<TransitionConstraint<F>>,
     pub lookups: Vec<Lookup<F>>,
     pub annotations: HashMap<u32, String>,
+    /// Aliases of the internal signals, see `StepTypeContext::alias`.
+    pub aliases: Aliases,
+    /// Signals constrained to be 0 or 1 by `cb::is_binary`.
+    pub boolean_signals: Vec<Queriable<F>>,
+    /// Signals used as conditions but not constrained to be boolean, with the annotation of the
+    /// first constraint using them, see `Lint::UnassertedCondition`.
+    pub unasserted_conditions: Vec<(Queriable<F>, String)>,
+    pub range_checks: Vec<RangeCheck<F>>,
+    /// Defaults of the forward signals queried by the step type, see `add_signal_default`.
+    pub signal_defaults: Vec<(ForwardSignal, SignalDefault)>,
+    /// Forward signals assigned from the other signals of the step, see `DerivedValue`.
+    pub derived_values: Vec<DerivedValue<F>>,
+    pub constraint_groups: Vec<ConstraintGroup>,
+    /// Names of the circuit invariants that the compiler does not add to this step type.
+    pub overridden_invariants: Vec<String>,
+
+    /// Expected maximum number of instances of this step type in the trace.
+    pub budget: Option<usize>,
 
-    pub wg: Box<StepWitnessGen<F, Args>>,
+    pub wg: Rc<StepWitnessGen<F, Args>>,
+    /// The witness generation function when it can run in other threads, see
+    /// `wit_gen::parallel::generate_parallel`.
+    pub sync_wg: Option<Arc<SyncStepWitnessGen<F, Args>>>,
 }
 
 impl<F: Debug, Args> Debug for StepType<F, Args> {
@@ -163,6 +728,35 @@ impl<F: Debug, Args> Debug for StepType<F, Args> {
     }
 }
 
+// The witness generation functions are shared by the clones, the compiler clones a step type to
+// add the signals and constraints of the degree reduction.
+impl<F: Clone, Args> Clone for StepType<F, Args> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            name: self.name.clone(),
+            signals: self.signals.clone(),
+            constraints: self.constraints.clone(),
+            transition_constraints: self.transition_constraints.clone(),
+            debug_constraints: self.debug_constraints.clone(),
+            debug_transition_constraints: self.debug_transition_constraints.clone(),
+            lookups: self.lookups.clone(),
+            annotations: self.annotations.clone(),
+            aliases: self.aliases.clone(),
+            boolean_signals: self.boolean_signals.clone(),
+            unasserted_conditions: self.unasserted_conditions.clone(),
+            range_checks: self.range_checks.clone(),
+            signal_defaults: self.signal_defaults.clone(),
+            derived_values: self.derived_values.clone(),
+            constraint_groups: self.constraint_groups.clone(),
+            overridden_invariants: self.overridden_invariants.clone(),
+            budget: self.budget,
+            wg: Rc::clone(&self.wg),
+            sync_wg: self.sync_wg.clone(),
+        }
+    }
+}
+
 impl<F, Args> StepType<F, Args> {
     pub fn new(uuid: u32, name: String) -> Self {
         Self {
@@ -171,9 +765,21 @@ impl<F, Args> StepType<F, Args> {
             signals: Default::default(),
             constraints: Default::default(),
             transition_constraints: Default::default(),
+            debug_constraints: Default::default(),
+            debug_transition_constraints: Default::default(),
             lookups: Default::default(),
             annotations: Default::default(),
-            wg: Box::new(|_, _| {}),
+            aliases: Default::default(),
+            boolean_signals: Default::default(),
+            unasserted_conditions: Default::default(),
+            range_checks: Default::default(),
+            signal_defaults: Default::default(),
+            derived_values: Default::default(),
+            constraint_groups: Default::default(),
+            overridden_invariants: Default::default(),
+            budget: None,
+            wg: Rc::new(|_, _| {}),
+            sync_wg: None,
         }
     }
     pub fn uuid(&self) -> StepTypeUUID {
@@ -202,12 +808,100 @@ impl<F, Args> StepType<F, Args> {
         self.transition_constraints.push(condition)
     }
 
+    /// Constrains the signal declared with `width` to fit in its bits, by adding an internal
+    /// signal for each bit.
+    pub fn add_range_check(&mut self, width: &SignalWidth<F>) {
+        let name = Queriable::<F>::Forward(width.signal, false).annotation();
+        let bits: Vec<InternalSignal> = (0..width.bits)
+            .map(|i| self.add_signal(format!("{}_bit{}", name, i)))
+            .collect();
+
+        for (annotation, expr) in (width.constraints)(&bits) {
+            self.add_constr(annotation, expr);
+        }
+
+        self.range_checks.push(RangeCheck {
+            signal: width.signal,
+            bits,
+            decompose: Rc::clone(&width.decompose),
+        });
+
+        // a signal of one bit is boolean, as the signals of `forward_bool`
+        if width.bits == 1 {
+            let signal = Queriable::Forward(width.signal, false);
+            self.boolean_signals.push(signal);
+            self.unasserted_conditions
+                .retain(|(condition, _)| *condition != signal);
+        }
+    }
+
     pub fn set_wg<D>(&mut self, def: D)
     where
         D: Fn(&mut dyn WitnessGenContext<F>, Args) + 'static,
     {
         // TODO, only can be called once
-        self.wg = Box::new(def);
+        self.wg = Rc::new(def);
+        self.sync_wg = None;
+    }
+
+    pub fn set_sync_wg<D>(&mut self, def: D)
+    where
+        D: Fn(&mut dyn WitnessGenContext<F>, Args) + Send + Sync + 'static,
+        F: 'static,
+        Args: 'static,
+    {
+        let def: Arc<SyncStepWitnessGen<F, Args>> = Arc::new(def);
+        let wg = Arc::clone(&def);
+
+        self.wg = Rc::new(move |ctx, args| wg(ctx, args));
+        self.sync_wg = Some(def);
+    }
+
+    /// Applies `default` to `signal` in the instances of the step type that do not assign it, if
+    /// its constraints or transitions query the signal.
+    pub fn add_signal_default(&mut self, signal: ForwardSignal, default: SignalDefault) {
+        let queried = self
+            .constraints
+            .iter()
+            .map(|constraint| &constraint.expr)
+            .chain(
+                self.transition_constraints
+                    .iter()
+                    .map(|transition| &transition.expr),
+            )
+            .any(|expr| expr.queries_forward(signal));
+
+        if queried {
+            self.signal_defaults.push((signal, default));
+        }
+    }
+}
+
+impl<F: Field, Args> StepType<F, Args> {
+    /// Internal signals that no constraint of the step type is sensitive to, so they can take any
+    /// value. Constraints with halo2 expressions are skipped.
+    pub fn unconstrained_signals(&self) -> Vec<InternalSignal> {
+        let exprs = self
+            .constraints
+            .iter()
+            .map(|constraint| &constraint.expr)
+            .chain(
+                self.transition_constraints
+                    .iter()
+                    .map(|constraint| &constraint.expr),
+            )
+            .filter(|expr| !expr.contains_halo2_expr());
+
+        let mut sensitive: Vec<Queriable<F>> = Vec::new();
+        for expr in exprs {
+            sensitive.extend(expr.sensitive_signals());
+        }
+
+        self.signals
+            .iter()
+            .filter(|signal| !sensitive.contains(&Queriable::Internal(**signal)))
+            .cloned()
+            .collect()
     }
 }
 
@@ -244,6 +938,10 @@ pub struct Lookup<F> {
     pub annotation: String,
     pub exprs: Vec<(Constraint<F>, Expr<F>)>,
     pub enable: Option<Constraint<F>>,
+    /// Step type whose rows are the table. The table side of `exprs` is then placed in that step
+    /// type and enabled by its step selector, instead of being placed in the step type of the
+    /// lookup.
+    pub table_step: Option<StepTypeUUID>,
 }
 
 impl<F> Default for Lookup<F> {
@@ -252,6 +950,7 @@ impl<F> Default for Lookup<F> {
             annotation: String::new(),
             exprs: Vec::<(Constraint<F>, Expr<F>)>::new(),
             enable: None,
+            table_step: None,
         }
     }
 }
@@ -344,6 +1043,60 @@ impl ForwardSignal {
     }
 }
 
+/// A random value of the verifier, known once the advice columns of its phase and the phases
+/// before are committed. The constraints query it like a signal, and the signals of the later
+/// phases are assigned with its value, see `CircuitContext::challenge`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Challenge {
+    id: u32,
+    phase: usize,
+    annotation: &'static str,
+}
+
+impl Challenge {
+    pub fn new(phase: usize, annotation: String) -> Challenge {
+        Challenge {
+            id: uuid(),
+            phase,
+            annotation: Box::leak(annotation.into_boxed_str()),
+        }
+    }
+
+    pub fn uuid(&self) -> u32 {
+        self.id
+    }
+
+    /// The phase after which the challenge is usable.
+    pub fn phase(&self) -> usize {
+        self.phase
+    }
+
+    pub fn annotation(&self) -> &'static str {
+        self.annotation
+    }
+}
+
+/// A fixed signal whose value in each step is a function of the step index, materialized by the
+/// compiler in a fixed column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StepFixedSignal {
+    id: u32,
+    annotation: &'static str,
+}
+
+impl StepFixedSignal {
+    pub fn new(annotation: String) -> StepFixedSignal {
+        StepFixedSignal {
+            id: uuid(),
+            annotation: Box::leak(annotation.into_boxed_str()),
+        }
+    }
+
+    pub fn uuid(&self) -> u32 {
+        self.id
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct InternalSignal {
     id: u32,
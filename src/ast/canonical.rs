@@ -0,0 +1,244 @@
+use std::fmt;
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::wit_gen::public_inputs::keccak256;
+
+use super::{query::Queriable, Expr};
+
+/// A product of queriables with their exponents, sorted by queriable.
+pub type Monomial<F> = Vec<(Queriable<F>, u32)>;
+
+/// An expression expanded as a sum of monomials, sorted by monomial and with nonzero
+/// coefficients. Two expressions are the same polynomial exactly when their canonical forms are
+/// equal. Queriables are ordered by annotation first, so the order of the terms and the
+/// `stable_hash` do not depend on the UUIDs of a compilation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanonicalExpr<F> {
+    pub terms: Vec<(Monomial<F>, F)>,
+}
+
+impl<F: Field> Expr<F> {
+    /// Panics on halo2 expressions, which cannot be expanded.
+    pub fn canonicalize(&self) -> CanonicalExpr<F> {
+        let terms = match self {
+            Expr::Const(c) => vec![(vec![], *c)],
+            Expr::Query(q) => vec![(vec![(*q, 1)], F::ONE)],
+            Expr::Sum(es) => es.iter().flat_map(|e| e.canonicalize().terms).collect(),
+            Expr::Mul(es) => es.iter().fold(vec![(vec![], F::ONE)], |acc, e| {
+                multiply(&acc, &e.canonicalize().terms)
+            }),
+            Expr::Neg(e) => e
+                .canonicalize()
+                .terms
+                .into_iter()
+                .map(|(monomial, coeff)| (monomial, -coeff))
+                .collect(),
+            Expr::Pow(e, exp) => {
+                let base = e.canonicalize().terms;
+                (0..*exp).fold(vec![(vec![], F::ONE)], |acc, _| multiply(&acc, &base))
+            }
+            Expr::Halo2Expr(_) => panic!("cannot canonicalize a halo2 expression"),
+        };
+
+        normalize(terms)
+    }
+
+    /// See `CanonicalExpr::sensitive_signals`.
+    pub fn sensitive_signals(&self) -> Vec<Queriable<F>> {
+        self.canonicalize().sensitive_signals()
+    }
+
+    /// Whether both expressions are the same polynomial.
+    pub fn equivalent(&self, other: &Expr<F>) -> bool {
+        self.canonicalize() == other.canonicalize()
+    }
+}
+
+impl<F: Field> CanonicalExpr<F> {
+    /// The partial derivative with respect to `q`.
+    pub fn derivative(&self, q: &Queriable<F>) -> CanonicalExpr<F> {
+        let terms = self
+            .terms
+            .iter()
+            .filter_map(|(monomial, coeff)| {
+                let position = monomial.iter().position(|(factor, _)| factor == q)?;
+                let exp = monomial[position].1;
+
+                let mut monomial = monomial.clone();
+                if exp == 1 {
+                    monomial.remove(position);
+                } else {
+                    monomial[position].1 -= 1;
+                }
+
+                let times = (0..exp).fold(F::ZERO, |acc, _| acc + F::ONE);
+
+                Some((monomial, *coeff * times))
+            })
+            .collect();
+
+        normalize(terms)
+    }
+
+    /// The queriables with a nonzero partial derivative, that is the queriables whose value
+    /// changes the value of the expression, sorted by annotation.
+    pub fn sensitive_signals(&self) -> Vec<Queriable<F>> {
+        let mut signals: Vec<Queriable<F>> = Vec::new();
+        for (monomial, _) in self.terms.iter() {
+            for (q, _) in monomial.iter() {
+                if !signals.contains(q) && !self.derivative(q).terms.is_empty() {
+                    signals.push(*q);
+                }
+            }
+        }
+
+        signals.sort_by_key(key);
+
+        signals
+    }
+
+    /// Keccak256 of the canonical form with queriables by annotation, equal for the same
+    /// constraint in different compilations.
+    pub fn stable_hash(&self) -> [u8; 32] {
+        keccak256(self.to_string().as_bytes())
+    }
+}
+
+impl<F: Field> fmt::Display for CanonicalExpr<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        for (i, (monomial, coeff)) in self.terms.iter().enumerate() {
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{:?}", coeff)?;
+            for (q, exp) in monomial.iter() {
+                write!(f, "*{}", q.annotation())?;
+                if *exp > 1 {
+                    write!(f, "^{}", exp)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sort key of a queriable, its annotation and then its UUID.
+type Key = (String, u32);
+
+type MonomialKey = Vec<(Key, u32)>;
+
+fn key<F>(q: &Queriable<F>) -> Key {
+    (q.annotation(), q.uuid())
+}
+
+fn multiply<F: Field>(lhs: &[(Monomial<F>, F)], rhs: &[(Monomial<F>, F)]) -> Vec<(Monomial<F>, F)> {
+    let terms = lhs
+        .iter()
+        .flat_map(|(lhs_monomial, lhs_coeff)| {
+            rhs.iter().map(move |(rhs_monomial, rhs_coeff)| {
+                let mut monomial = lhs_monomial.clone();
+                monomial.extend(rhs_monomial.iter().cloned());
+
+                (monomial, *lhs_coeff * rhs_coeff)
+            })
+        })
+        .collect();
+
+    normalize(terms).terms
+}
+
+fn normalize<F: Field>(terms: Vec<(Monomial<F>, F)>) -> CanonicalExpr<F> {
+    let mut normalized: Vec<(MonomialKey, Monomial<F>, F)> = Vec::new();
+
+    for (monomial, coeff) in terms {
+        let mut factors: Monomial<F> = Vec::new();
+        let mut sorted = monomial;
+        sorted.sort_by_key(|(q, _)| key(q));
+        for (q, exp) in sorted {
+            match factors.last_mut() {
+                Some((last, last_exp)) if *last == q => *last_exp += exp,
+                _ => factors.push((q, exp)),
+            }
+        }
+
+        let keys: MonomialKey = factors.iter().map(|(q, exp)| (key(q), *exp)).collect();
+        match normalized.iter_mut().find(|(other, _, _)| *other == keys) {
+            Some((_, _, other_coeff)) => *other_coeff += coeff,
+            None => normalized.push((keys, factors, coeff)),
+        }
+    }
+
+    normalized.retain(|(_, _, coeff)| !bool::from(coeff.is_zero()));
+    normalized.sort_by(|a, b| a.0.cmp(&b.0));
+
+    CanonicalExpr {
+        terms: normalized
+            .into_iter()
+            .map(|(_, monomial, coeff)| (monomial, coeff))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::ast::{InternalSignal, ToExpr};
+
+    #[test]
+    fn test_canonicalize() {
+        let a: Queriable<Fr> = Queriable::Internal(InternalSignal::new("a".to_string()));
+        let b: Queriable<Fr> = Queriable::Internal(InternalSignal::new("b".to_string()));
+
+        // (a + b) * (a - b) == a^2 - b^2
+        let lhs = (a + b) * (a - b);
+        let rhs = Expr::Pow(Box::new(a.expr()), 2) - b * b;
+
+        assert!(lhs.equivalent(&rhs));
+        assert!(!lhs.equivalent(&(a * a)));
+        assert_eq!(lhs.canonicalize().terms.len(), 2);
+        assert_eq!(
+            lhs.canonicalize().stable_hash(),
+            rhs.canonicalize().stable_hash()
+        );
+
+        // d/da (a^2 - b^2) == 2a
+        assert_eq!(lhs.canonicalize().derivative(&a), (a * 2).canonicalize());
+        assert_eq!(lhs.sensitive_signals(), vec![a, b]);
+        assert_eq!((a * b - b * a + b).sensitive_signals(), vec![b]);
+
+        assert_eq!((a - a).canonicalize().terms, vec![]);
+        assert_eq!((a - a).canonicalize().to_string(), "0");
+    }
+
+    #[test]
+    fn test_unconstrained_signals() {
+        let sc = crate::dsl::circuit::<Fr, (), (), _>("unconstrained", |ctx| {
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, |ctx| {
+                let a = ctx.internal("a");
+                let b = ctx.internal("b");
+                let _c = ctx.internal("c");
+
+                // b cancels out
+                ctx.setup(move |ctx| ctx.constr(crate::dsl::cb::eq(a + b, b + 1)));
+            });
+        });
+
+        let step = sc.step_types.values().next().unwrap();
+        let names: Vec<&str> = step
+            .unconstrained_signals()
+            .iter()
+            .map(|signal| signal.annotation)
+            .collect();
+
+        assert_eq!(names, vec!["b", "c"]);
+    }
+}
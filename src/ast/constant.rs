@@ -0,0 +1,135 @@
+use std::fmt;
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::wit_gen::import::parse_integer;
+
+use super::{Expr, ToExpr, ToField};
+
+/// An integer constant that is not tied to a field. It is converted to the field of the circuit
+/// when used in an expression, so that circuits generic over the field can share constants of any
+/// size, defined once:
+///
+/// ```ignore
+/// const IV: Constant = Constant::text("0x6a09e667f3bcc908");
+///
+/// fn round<F: PrimeField + Hash>(ctx: &mut StepTypeContext<F>, a: Queriable<F>, b: Queriable<F>) {
+///     ctx.constr(eq(a + IV, b));
+/// }
+/// ```
+///
+/// The conversion panics when the constant is not lower than the modulus of the field, see
+/// `Constant::to_field` to check it beforehand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constant {
+    Int(i128),
+    /// A decimal or `0x` hexadecimal integer, negative after a leading `-`.
+    Text(&'static str),
+}
+
+impl Constant {
+    pub const fn int(value: i128) -> Self {
+        Constant::Int(value)
+    }
+
+    pub const fn text(value: &'static str) -> Self {
+        Constant::Text(value)
+    }
+
+    /// The constant in the field `F`, negative values as their opposite.
+    pub fn to_field<F: PrimeField>(&self) -> Result<F, ConstantError> {
+        let text = self.to_string();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.as_str()),
+        };
+
+        match parse_integer::<F>(digits) {
+            Some(value) if negative => Ok(-value),
+            Some(value) => Ok(value),
+            None => Err(ConstantError { constant: text }),
+        }
+    }
+
+    fn expect_field<F: PrimeField>(&self) -> F {
+        self.to_field()
+            .unwrap_or_else(|err| panic!("invalid constant: {}", err))
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstantError {
+    pub constant: String,
+}
+
+impl fmt::Display for ConstantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not an integer lower than the modulus of the field",
+            self.constant
+        )
+    }
+}
+
+impl std::error::Error for ConstantError {}
+
+impl<F: PrimeField> From<Constant> for Expr<F> {
+    fn from(constant: Constant) -> Self {
+        Expr::Const(constant.expect_field())
+    }
+}
+
+impl<F: PrimeField> ToExpr<F> for Constant {
+    fn expr(&self) -> Expr<F> {
+        Expr::Const(self.expect_field())
+    }
+}
+
+impl<F: PrimeField> ToField<F> for Constant {
+    fn field(&self) -> F {
+        self.expect_field()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{arithmetic::Field, halo2curves::bn256::Fr};
+
+    use super::*;
+
+    #[test]
+    fn test_constant() {
+        assert_eq!(Constant::int(-3).to_field::<Fr>(), Ok(-Fr::from(3)));
+        assert_eq!(
+            Constant::int(1 << 40).to_field::<Fr>(),
+            Ok(Fr::from(1 << 20).square())
+        );
+        let sixteen: Fr = Constant::text("0x10").field();
+        assert_eq!(sixteen, Fr::from(16));
+        assert_eq!(Constant::text("-255").to_field::<Fr>(), Ok(-Fr::from(255)));
+        assert!(matches!(
+            Expr::<Fr>::from(Constant::text("0x2a")),
+            Expr::Const(value) if value == Fr::from(42)
+        ));
+
+        let too_large =
+            Constant::text("0x10000000000000000000000000000000000000000000000000000000000000000");
+        assert_eq!(
+            too_large.to_field::<Fr>(),
+            Err(ConstantError {
+                constant: too_large.to_string()
+            })
+        );
+        assert!(Constant::text("12a").to_field::<Fr>().is_err());
+    }
+}
@@ -9,6 +9,8 @@ use crate::dsl::cb::Constraint;
 
 use self::query::Queriable;
 
+use super::ForwardSignal;
+
 pub trait ToExpr<F> {
     fn expr(&self) -> Expr<F>;
 }
@@ -67,6 +69,94 @@ impl<F: Debug> Debug for Expr<F> {
     }
 }
 
+impl<F> Expr<F> {
+    /// Whether the expression queries `signal`, with or without rotation.
+    pub fn queries_forward(&self, signal: ForwardSignal) -> bool {
+        match self {
+            Expr::Query(Queriable::Forward(forward, _)) => *forward == signal,
+            Expr::Sum(es) | Expr::Mul(es) => es.iter().any(|e| e.queries_forward(signal)),
+            Expr::Neg(e) | Expr::Pow(e, _) => e.queries_forward(signal),
+            _ => false,
+        }
+    }
+
+    /// Number of nodes and nesting depth of the expression, a query or constant alone has depth
+    /// 1. Walks the expression without recursion, so that it can measure expressions too deep
+    /// for the recursive passes of the compiler.
+    pub fn size(&self) -> (usize, usize) {
+        let mut nodes = 0;
+        let mut depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((expr, level)) = stack.pop() {
+            nodes += 1;
+            depth = depth.max(level);
+
+            match expr {
+                Expr::Sum(es) | Expr::Mul(es) => stack.extend(es.iter().map(|e| (e, level + 1))),
+                Expr::Neg(e) | Expr::Pow(e, _) => stack.push((e, level + 1)),
+                Expr::Const(_) | Expr::Query(_) | Expr::Halo2Expr(_) => {}
+            }
+        }
+
+        (nodes, depth)
+    }
+}
+
+impl<F: Clone> Expr<F> {
+    /// Returns the queriables of the expression, in the order they appear, with duplicates.
+    pub fn queriables(&self) -> Vec<Queriable<F>> {
+        match self {
+            Expr::Query(q) => vec![*q],
+            Expr::Sum(es) | Expr::Mul(es) => es.iter().flat_map(|e| e.queriables()).collect(),
+            Expr::Neg(e) | Expr::Pow(e, _) => e.queriables(),
+            Expr::Const(_) | Expr::Halo2Expr(_) => vec![],
+        }
+    }
+
+    pub fn contains_halo2_expr(&self) -> bool {
+        match self {
+            Expr::Halo2Expr(_) => true,
+            Expr::Sum(es) | Expr::Mul(es) => es.iter().any(|e| e.contains_halo2_expr()),
+            Expr::Neg(e) | Expr::Pow(e, _) => e.contains_halo2_expr(),
+            Expr::Const(_) | Expr::Query(_) => false,
+        }
+    }
+}
+
+impl<F: Field> Expr<F> {
+    /// Degree of the expression as a polynomial in its queries.
+    pub fn degree(&self) -> usize {
+        match self {
+            Expr::Const(_) => 0,
+            Expr::Sum(es) => es.iter().map(|e| e.degree()).max().unwrap_or(0),
+            Expr::Mul(es) => es.iter().map(|e| e.degree()).sum(),
+            Expr::Neg(e) => e.degree(),
+            Expr::Pow(e, exp) => e.degree() * *exp as usize,
+            Expr::Query(_) => 1,
+            Expr::Halo2Expr(e) => e.degree(),
+        }
+    }
+
+    /// Evaluates the expression, with the values of queriables given by `resolve`. Returns the
+    /// first queriable that `resolve` cannot give a value for as error. Panics on halo2
+    /// expressions, which cannot be evaluated outside of a halo2 circuit.
+    pub fn eval<R: Fn(&Queriable<F>) -> Option<F>>(&self, resolve: &R) -> Result<F, Queriable<F>> {
+        match self {
+            Expr::Const(c) => Ok(*c),
+            Expr::Sum(es) => es
+                .iter()
+                .try_fold(F::ZERO, |acc, e| Ok(acc + e.eval(resolve)?)),
+            Expr::Mul(es) => es
+                .iter()
+                .try_fold(F::ONE, |acc, e| Ok(acc * e.eval(resolve)?)),
+            Expr::Neg(e) => Ok(-e.eval(resolve)?),
+            Expr::Pow(e, exp) => Ok(e.eval(resolve)?.pow_vartime([*exp as u64])),
+            Expr::Query(q) => resolve(q).ok_or(*q),
+            Expr::Halo2Expr(_) => panic!("cannot evaluate a halo2 expression"),
+        }
+    }
+}
+
 impl<F: Clone> ToExpr<F> for Expr<F> {
     fn expr(&self) -> Expr<F> {
         self.clone()
@@ -204,29 +294,94 @@ impl<F> From<Expression<F>> for Expr<F> {
 pub mod query {
     use std::{
         fmt::Debug,
+        hash::{Hash, Hasher},
         marker::PhantomData,
         ops::{Add, Mul, Neg, Sub},
     };
 
     use crate::{
-        ast::{ForwardSignal, ImportedHalo2Advice, ImportedHalo2Fixed, InternalSignal},
+        ast::{
+            Challenge, ForwardSignal, ImportedHalo2Advice, ImportedHalo2Fixed, InternalSignal,
+            StepFixedSignal,
+        },
         dsl::StepTypeHandler,
     };
 
     use super::{Expr, ToExpr};
 
     // Queriable
-    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
     pub enum Queriable<F> {
         Internal(InternalSignal),
         Forward(ForwardSignal, bool),
         StepTypeNext(StepTypeHandler),
         Halo2AdviceQuery(ImportedHalo2Advice, i32),
         Halo2FixedQuery(ImportedHalo2Fixed, i32),
+        StepFixed(StepFixedSignal, bool),
+        Challenge(Challenge),
         #[allow(non_camel_case_types)]
         _unaccessible(PhantomData<F>),
     }
 
+    // Clone, Copy, PartialEq, Eq and Hash are implemented by hand, deriving them would require F
+    // to implement them while it is only used as a marker.
+    impl<F> Clone for Queriable<F> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<F> Copy for Queriable<F> {}
+
+    impl<F> PartialEq for Queriable<F> {
+        fn eq(&self, other: &Self) -> bool {
+            use Queriable::*;
+            match (self, other) {
+                (Internal(a), Internal(b)) => a == b,
+                (Forward(a, a_next), Forward(b, b_next)) => a == b && a_next == b_next,
+                (StepTypeNext(a), StepTypeNext(b)) => a == b,
+                (Halo2AdviceQuery(a, a_rot), Halo2AdviceQuery(b, b_rot)) => {
+                    a == b && a_rot == b_rot
+                }
+                (Halo2FixedQuery(a, a_rot), Halo2FixedQuery(b, b_rot)) => a == b && a_rot == b_rot,
+                (StepFixed(a, a_next), StepFixed(b, b_next)) => a == b && a_next == b_next,
+                (Challenge(a), Challenge(b)) => a == b,
+                (_unaccessible(_), _unaccessible(_)) => true,
+                _ => false,
+            }
+        }
+    }
+
+    impl<F> Eq for Queriable<F> {}
+
+    impl<F> Hash for Queriable<F> {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            use Queriable::*;
+            std::mem::discriminant(self).hash(state);
+            match self {
+                Internal(s) => s.hash(state),
+                Forward(s, next) => {
+                    s.hash(state);
+                    next.hash(state);
+                }
+                StepTypeNext(s) => s.hash(state),
+                Halo2AdviceQuery(s, rot) => {
+                    s.hash(state);
+                    rot.hash(state);
+                }
+                Halo2FixedQuery(s, rot) => {
+                    s.hash(state);
+                    rot.hash(state);
+                }
+                StepFixed(s, next) => {
+                    s.hash(state);
+                    next.hash(state);
+                }
+                Challenge(c) => c.hash(state),
+                _unaccessible(_) => {}
+            }
+        }
+    }
+
     impl<F> Debug for Queriable<F> {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             write!(f, "{}", self.annotation())
@@ -249,7 +404,14 @@ pub mod query {
                 }
                 Halo2AdviceQuery(s, rot) => Halo2AdviceQuery(*s, rot + 1),
                 Halo2FixedQuery(s, r) => Halo2FixedQuery(*s, r + 1),
-                _ => panic!("can only next a forward or halo2 column"),
+                StepFixed(s, next) => {
+                    if !*next {
+                        StepFixed(*s, true)
+                    } else {
+                        panic!("jarrl: cannot rotate next(step fixed)")
+                    }
+                }
+                _ => panic!("can only next a forward, step fixed or halo2 column"),
             }
         }
 
@@ -260,6 +422,8 @@ pub mod query {
                 Queriable::StepTypeNext(s) => s.uuid(),
                 Queriable::Halo2AdviceQuery(s, _) => s.uuid(),
                 Queriable::Halo2FixedQuery(s, _) => s.uuid(),
+                Queriable::StepFixed(s, _) => s.uuid(),
+                Queriable::Challenge(c) => c.uuid(),
                 Queriable::_unaccessible(_) => panic!("jarrl wrong queriable type"),
             }
         }
@@ -289,6 +453,14 @@ pub mod query {
                         s.annotation.to_string()
                     }
                 }
+                Queriable::StepFixed(s, next) => {
+                    if !next {
+                        s.annotation.to_string()
+                    } else {
+                        format!("next({})", s.annotation)
+                    }
+                }
+                Queriable::Challenge(c) => c.annotation().to_string(),
                 Queriable::_unaccessible(_) => todo!(),
             }
         }
@@ -296,7 +468,7 @@ pub mod query {
 
     impl<F: Clone> ToExpr<F> for Queriable<F> {
         fn expr(&self) -> Expr<F> {
-            Expr::Query((*self).clone())
+            Expr::Query(*self)
         }
     }
 
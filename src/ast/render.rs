@@ -0,0 +1,231 @@
+use std::fmt::Write;
+
+use halo2_proofs::arithmetic::Field;
+
+use super::{query::Queriable, Expr, StepType};
+
+impl<F: Field> Expr<F> {
+    /// Renders the expression in LaTeX math, with the signals indexed by the row of the step, for
+    /// example `a_{i+1} + b_i`.
+    pub fn to_latex(&self) -> String {
+        self.latex(false)
+    }
+
+    /// Renders the expression as a constraint that equals zero. The negated terms of a sum are
+    /// moved to the right hand side, so that `eq(a + b, a.next())` renders as
+    /// `a_i + b_i = a_{i+1}`.
+    pub fn to_latex_constraint(&self) -> String {
+        if let Expr::Sum(terms) = self {
+            let (negated, positive): (Vec<&Expr<F>>, Vec<&Expr<F>>) =
+                terms.iter().partition(|term| matches!(term, Expr::Neg(_)));
+
+            if !negated.is_empty() && !positive.is_empty() {
+                let side = |terms: Vec<Expr<F>>| {
+                    if terms.len() == 1 {
+                        terms[0].latex(false)
+                    } else {
+                        Expr::Sum(terms).latex(false)
+                    }
+                };
+                let rhs = negated
+                    .into_iter()
+                    .map(|term| match term {
+                        Expr::Neg(term) => (**term).clone(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+
+                return format!(
+                    "{} = {}",
+                    side(positive.into_iter().cloned().collect()),
+                    side(rhs)
+                );
+            }
+        }
+
+        format!("{} = 0", self.latex(false))
+    }
+
+    /// Renders the expression as an inline markdown math span.
+    pub fn to_markdown(&self) -> String {
+        format!("${}$", self.to_latex())
+    }
+
+    fn latex(&self, grouped: bool) -> String {
+        match self {
+            Expr::Const(value) => constant(value),
+            Expr::Sum(terms) => {
+                let mut out = String::new();
+                for (i, term) in terms.iter().enumerate() {
+                    match (i, term) {
+                        (0, term) => out.push_str(&term.latex(false)),
+                        (_, Expr::Neg(term)) => write!(out, " - {}", term.latex(true)).unwrap(),
+                        (_, term) => write!(out, " + {}", term.latex(false)).unwrap(),
+                    }
+                }
+
+                if grouped && terms.len() > 1 {
+                    format!("\\left({}\\right)", out)
+                } else {
+                    out
+                }
+            }
+            Expr::Mul(factors) => factors
+                .iter()
+                .map(|factor| factor.latex(true))
+                .collect::<Vec<String>>()
+                .join(" \\cdot "),
+            Expr::Neg(term) => format!("-{}", term.latex(true)),
+            Expr::Pow(base, exp) => {
+                let base = match **base {
+                    Expr::Query(_) | Expr::Const(_) => base.latex(true),
+                    _ => format!("\\left({}\\right)", base.latex(false)),
+                };
+                format!("{{{}}}^{{{}}}", base, exp)
+            }
+            Expr::Query(queriable) => query(queriable),
+            Expr::Halo2Expr(expr) => format!("\\text{{halo2}}({})", escape(&format!("{:?}", expr))),
+        }
+    }
+}
+
+impl<F: Field, Args> StepType<F, Args> {
+    /// The constraints of the step type as a markdown section, for documentation and audit
+    /// reports.
+    pub fn constraints_markdown(&self) -> String {
+        let mut out = format!("### {}\n\n", self.name);
+
+        for constraint in self.constraints.iter() {
+            writeln!(
+                out,
+                "- {}: ${}$",
+                constraint.annotation,
+                constraint.expr.to_latex_constraint()
+            )
+            .unwrap();
+        }
+        for constraint in self.transition_constraints.iter() {
+            writeln!(
+                out,
+                "- transition {}: ${}$",
+                constraint.annotation,
+                constraint.expr.to_latex_constraint()
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+fn query<F>(queriable: &Queriable<F>) -> String {
+    let (name, rotation) = match queriable {
+        Queriable::Internal(signal) => (signal.annotation, 0),
+        Queriable::Forward(signal, next) => (signal.annotation, *next as i32),
+        Queriable::StepFixed(signal, next) => (signal.annotation, *next as i32),
+        Queriable::Halo2AdviceQuery(signal, rotation) => (signal.annotation, *rotation),
+        Queriable::Halo2FixedQuery(signal, rotation) => (signal.annotation, *rotation),
+        Queriable::Challenge(challenge) => {
+            return format!("\\text{{{}}}", escape(challenge.annotation()))
+        }
+        Queriable::StepTypeNext(step) => {
+            return format!("[\\text{{next}} = \\text{{{}}}]", escape(step.annotation))
+        }
+        Queriable::_unaccessible(_) => panic!("jarrl wrong queriable type"),
+    };
+
+    let row = match rotation {
+        0 => "i".to_string(),
+        rotation if rotation > 0 => format!("{{i+{}}}", rotation),
+        rotation => format!("{{i{}}}", rotation),
+    };
+
+    if name.chars().count() == 1 {
+        format!("{}_{}", name, row)
+    } else {
+        format!("\\mathit{{{}}}_{}", escape(name), row)
+    }
+}
+
+/// Small constants in decimal, and the others in hexadecimal.
+fn constant<F: Field>(value: &F) -> String {
+    let small = |value: &F| {
+        let formatted = format!("{:?}", value);
+        u64::from_str_radix(formatted.trim_start_matches("0x"), 16)
+            .ok()
+            .filter(|_| formatted.starts_with("0x"))
+    };
+
+    if let Some(value) = small(value) {
+        value.to_string()
+    } else if let Some(value) = small(&-*value) {
+        format!("-{}", value)
+    } else {
+        let formatted = format!("{:?}", value);
+        format!(
+            "\\texttt{{0x{}}}",
+            formatted.trim_start_matches("0x").trim_start_matches('0')
+        )
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '_' | '#' | '%' | '&' | '$' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' ' => escaped.push_str("\\ "),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use crate::dsl::{cb::eq, circuit};
+
+    #[test]
+    fn test_latex() {
+        let sc = circuit::<Fr, (), (), _>("fibonacci", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("fibo step");
+
+            ctx.step_type_def(step, |ctx| {
+                let c = ctx.internal("c_sum");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + b, c));
+                    ctx.constr(eq(a * (a - 1), 0));
+                    ctx.transition(eq(b, a.next()));
+                });
+            });
+        });
+
+        let step = sc.step_types.values().next().unwrap();
+        let constraints: Vec<String> = step
+            .constraints
+            .iter()
+            .map(|constraint| constraint.expr.to_latex_constraint())
+            .collect();
+
+        assert_eq!(constraints[0], "a_i + b_i = \\mathit{c\\_sum}_i");
+        assert_eq!(constraints[1], "a_i \\cdot \\left(a_i - 1\\right) = 0");
+        assert_eq!(
+            step.transition_constraints[0].expr.to_markdown(),
+            "$b_i - a_{i+1}$"
+        );
+
+        let markdown = step.constraints_markdown();
+        assert!(markdown.starts_with("### fibo step\n\n- "));
+        assert!(markdown.contains("- transition "));
+        assert!(markdown.contains("$b_i = a_{i+1}$"));
+    }
+}
@@ -1,26 +1,66 @@
-use std::{collections::HashMap, hash::Hash, rc::Rc};
+use std::{collections::HashMap, hash::Hash, rc::Rc, thread};
 
 use halo2_proofs::{
     arithmetic::Field,
-    circuit::{Layouter, Region, Value},
+    circuit::{Cell, Layouter, Region, Value},
+    halo2curves::group::ff::PrimeField,
     plonk::{
-        Advice, Column, ConstraintSystem, Expression, FirstPhase, Fixed, SecondPhase, ThirdPhase,
-        VirtualCells,
+        Advice, Challenge, Column, ConstraintSystem, Error, Expression, FirstPhase, Fixed,
+        Instance, SecondPhase, ThirdPhase, VirtualCells,
     },
     poly::Rotation,
 };
 
 use crate::{
-    ast::{query::Queriable, ForwardSignal, InternalSignal, StepType, ToField},
-    compiler::{cell_manager::Placement, step_selector::StepSelector, FixedGenContext},
+    ast::{
+        query::Queriable, ExposeOffset, ForwardSignal, InternalSignal, StepSchedule, StepType,
+        ToField, TraceError,
+    },
+    compiler::{
+        cell_manager::Placement, check_fixed_column, check_fixed_rows, step_selector::StepSelector,
+        AssignmentError, FixedGenContext, StepRows, TraceContext,
+    },
     ir::{
         Circuit, Column as cColumn,
         ColumnType::{Advice as cAdvice, Fixed as cFixed, Halo2Advice, Halo2Fixed},
-        PolyExpr,
+        PolyExpr, UnassignedPolicy,
+    },
+    metrics,
+    wit_gen::{
+        assign_log::AssignLog,
+        cache::WitnessCache,
+        challenge::ChallengeValues,
+        determinism::{first_difference, NondeterminismError},
+        external::{ExternalColumnError, ExternalColumns},
+        parallel::generate_parallel,
+        pipeline::{PipelineError, WitnessPipeline},
+        progress::TraceHooks,
+        replay::ReplayError,
+        split::{SplitWitness, SplitWitnessError},
+        GenericTraceContext, StepInstance, TraceOptions, TraceWitness,
     },
-    wit_gen::{GenericTraceContext, TraceWitness},
 };
 
+use super::BackendCapabilities;
+
+use self::copy_report::{InstanceLayoutError, InstanceRow};
+
+pub mod codegen;
+pub mod copy_report;
+pub mod row_check;
+pub mod table_file;
+
+/// Capabilities of the halo2 backend. The degree of the constraints is not bounded, higher degrees
+/// only make the proof more expensive. Shuffles are not available in this version of halo2.
+pub fn capabilities() -> BackendCapabilities {
+    BackendCapabilities {
+        max_degree: None,
+        challenges: true,
+        dynamic_lookups: true,
+        shuffles: false,
+    }
+}
+
 #[allow(non_snake_case)]
 pub fn chiquito2Halo2<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>(
     circuit: Circuit<F, TraceArgs, StepArgs>,
@@ -28,14 +68,53 @@ pub fn chiquito2Halo2<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>(
     ChiquitoHalo2::new(circuit)
 }
 
+/// How the rows of the circuit are assigned to halo2 regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionLayout {
+    /// A single region with every fixed and advice row.
+    Single,
+    /// A region with the fixed rows, and a region with the advice rows of every group of this
+    /// number of consecutive steps, for circuits that lay out their own regions around the steps.
+    /// Transition constraints query the rows of the next step, so the floor planner must place the
+    /// step regions one after the other. To ensure it with `SimpleFloorPlanner`, the last row of
+    /// every region assigns all the advice columns, with zero where the witness has no value.
+    StepGroups(usize),
+}
+
+impl Default for RegionLayout {
+    fn default() -> Self {
+        RegionLayout::Single
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ChiquitoHalo2<F: Field + From<u64>, TraceArgs, StepArgs: Clone> {
     pub debug: bool,
+    pub region_layout: RegionLayout,
+    /// Threads that translate the step instances of the witness into advice assignments, each
+    /// thread a chunk of consecutive steps, zero counting as one. With one thread, the default, or
+    /// an unassigned policy other than `Implicit`, the translation is sequential. Circuits with a
+    /// `StepSchedule` other than `InOrder` are translated with the layout of each step type
+    /// computed once, which fills the runs of instances of a type without looking up their
+    /// placement.
+    pub assignment_threads: usize,
+    /// Usable rows of the circuit, when known the fixed assignments past them fail with
+    /// `AssignmentError::OutOfBounds` instead of an error of the halo2 layouter.
+    pub max_rows: Option<usize>,
+    /// Evaluates the constraints of each step as the trace adds it, see
+    /// `GenericTraceContext::with_constraint_checks`.
+    pub check_constraints: bool,
+    /// Fails the witness generation of a step that assigns a signal twice, see
+    /// `GenericTraceContext::with_strict_assignments`.
+    pub strict_assignments: bool,
 
     circuit: Circuit<F, TraceArgs, StepArgs>,
 
     advice_columns: HashMap<u32, Column<Advice>>,
     fixed_columns: HashMap<u32, Column<Fixed>>,
+    instance_column: Option<Column<Instance>>,
+    challenges: HashMap<u32, Challenge>,
+    blinding_factors: Option<usize>,
 }
 
 impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
@@ -44,9 +123,17 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
     pub fn new(circuit: Circuit<F, TraceArgs, StepArgs>) -> ChiquitoHalo2<F, TraceArgs, StepArgs> {
         ChiquitoHalo2 {
             debug: true,
+            region_layout: RegionLayout::default(),
+            assignment_threads: 1,
+            max_rows: None,
+            check_constraints: false,
+            strict_assignments: false,
             circuit,
             advice_columns: Default::default(),
             fixed_columns: Default::default(),
+            instance_column: None,
+            challenges: Default::default(),
+            blinding_factors: None,
         }
     }
 
@@ -89,8 +176,34 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
             }
         }
 
+        if !self.circuit.exposed.is_empty() {
+            let mut equality_columns: Vec<Column<Advice>> = Vec::new();
+            for (column, _, _) in self.circuit.exposed.iter() {
+                let halo2_column = advice_columns
+                    .get(&column.uuid())
+                    .expect("exposed column not found");
+                if !equality_columns.contains(halo2_column) {
+                    meta.enable_equality(*halo2_column);
+                    equality_columns.push(*halo2_column);
+                }
+            }
+
+            self.instance_column = Some(meta.instance_column());
+        }
+
         self.advice_columns = advice_columns;
         self.fixed_columns = fixed_columns;
+        self.challenges = self
+            .circuit
+            .challenges
+            .iter()
+            .map(|challenge| {
+                (
+                    challenge.uuid(),
+                    to_halo2_challenge(meta, challenge.phase()),
+                )
+            })
+            .collect();
 
         if !self.circuit.polys.is_empty() {
             meta.create_gate("main", |meta| {
@@ -119,40 +232,523 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
                 exprs
             });
         }
+
+        self.blinding_factors = Some(meta.blinding_factors());
+    }
+
+    /// The instance column of the exposed signals, if any signal is exposed.
+    pub fn instance_column(&self) -> Option<Column<Instance>> {
+        self.instance_column
+    }
+
+    /// The instance columns of `witness`, to pass to the prover or the `MockProver`: the
+    /// `public_values` recorded by the trace in the instance column, or none when no signal is
+    /// exposed. The witness must be generated by `witness`, or with a trace context
+    /// `with_exposed`.
+    pub fn instance(&self, witness: &TraceWitness<F>) -> Vec<Vec<F>> {
+        if witness.public_values.len() != self.circuit.exposed.len() {
+            panic!(
+                "witness has {} public values, but the circuit exposes {} signals",
+                witness.public_values.len(),
+                self.circuit.exposed.len()
+            );
+        }
+
+        if self.circuit.exposed.is_empty() {
+            Vec::new()
+        } else {
+            vec![witness.public_values.clone()]
+        }
+    }
+
+    /// The rows of the instance column in the order that `synthesize` constrains them, with the
+    /// advice cells of `witness` copied to them.
+    pub fn instance_layout(&self, witness: TraceWitness<F>) -> Vec<InstanceRow<F>> {
+        let (advice_assignments, _, step_offsets) = self.synthesize_advice(Some(witness));
+        let positions = self.exposed_positions(&step_offsets);
+
+        let mut values: HashMap<(Column<Advice>, usize), F> = HashMap::new();
+        for (column, offset, value) in advice_assignments.iter() {
+            if positions.contains(&(*column, *offset)) {
+                value.map(|value| values.insert((*column, *offset), value));
+            }
+        }
+
+        self.circuit
+            .exposed
+            .iter()
+            .zip(positions)
+            .enumerate()
+            .map(|(instance_row, ((column, _, _), position))| InstanceRow {
+                instance_row,
+                column: column.annotation.clone(),
+                row: position.1,
+                value: values.get(&position).cloned(),
+            })
+            .collect()
+    }
+
+    /// Checks that the instance layout of `witness` matches `public_inputs`, given in the order
+    /// the signals were exposed, as returned by `TraceWitness::public_inputs`. A mismatch means
+    /// that the prover would need public inputs in another order than the verifier passes them.
+    pub fn verify_instance_layout(
+        &self,
+        witness: TraceWitness<F>,
+        public_inputs: &[F],
+    ) -> Result<(), InstanceLayoutError<F>> {
+        let layout = self.instance_layout(witness);
+        if layout.len() != public_inputs.len() {
+            return Err(InstanceLayoutError::Length {
+                expected: layout.len(),
+                found: public_inputs.len(),
+            });
+        }
+
+        for (row, expected) in layout.into_iter().zip(public_inputs) {
+            if row.value != Some(*expected) {
+                return Err(InstanceLayoutError::Misaligned {
+                    instance_row: row.instance_row,
+                    column: row.column,
+                    row: row.row,
+                    expected: *expected,
+                    found: row.value,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn synthesize(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        args: TraceArgs,
+    ) -> Result<(), Error> {
+        let witness = self.witness(args);
+
+        self.synthesize_with(layouter, witness)
+    }
+
+    /// Synthesizes the circuit with a witness generated beforehand, for example with `witness` or
+    /// loaded from a `ReplayBundle`, instead of running the trace.
+    pub fn synthesize_witness(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        witness: TraceWitness<F>,
+    ) -> Result<(), Error> {
+        self.synthesize_with(layouter, Some(witness))
+    }
+
+    /// Runs the trace of the circuit and returns the generated witness.
+    pub fn witness(&self, args: TraceArgs) -> Option<TraceWitness<F>> {
+        self.circuit.trace.as_ref().map(|trace| {
+            let _timer = metrics::Timer::start(metrics::WITNESS_SECONDS);
+            let mut ctx = self.trace_context();
+            trace(&mut ctx, args);
+
+            let witness = ctx.get_witness();
+            metrics::increment_counter(
+                metrics::STEPS_GENERATED,
+                witness.step_instances.len() as u64,
+            );
+
+            witness
+        })
+    }
+
+    /// Like `witness`, generating the witness a second time with the same `args` to catch a
+    /// witness generation whose result changes between runs before it makes verification flaky.
+    /// The second run is in `threads` threads with `generate_parallel` when more than one, which
+    /// also checks that the steps of the `wg_sync` step types do not depend on the order the
+    /// threads run them. Fails with the first difference between the two witnesses.
+    pub fn witness_deterministic(
+        &self,
+        args: TraceArgs,
+        threads: usize,
+    ) -> Result<Option<TraceWitness<F>>, NondeterminismError>
+    where
+        TraceArgs: Clone,
+        StepArgs: Send,
+    {
+        let first = self.witness(args.clone());
+        let second = if threads > 1 {
+            generate_parallel(&self.circuit, args, threads)
+        } else {
+            self.witness(args)
+        };
+
+        match (first, second) {
+            (Some(first), Some(second)) => {
+                match first_difference(&first, &second, &self.circuit.step_types) {
+                    Some(difference) => Err(NondeterminismError {
+                        threads: threads.max(1),
+                        difference,
+                    }),
+                    None => Ok(Some(first)),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Like `witness`, with the log of every assignment made while the trace ran, see
+    /// `AssignLog`.
+    pub fn witness_with_assign_log(
+        &self,
+        args: TraceArgs,
+    ) -> Option<(TraceWitness<F>, AssignLog<F>)> {
+        self.circuit.trace.as_ref().map(|trace| {
+            let _timer = metrics::Timer::start(metrics::WITNESS_SECONDS);
+            let mut ctx = self.trace_context().with_assign_log();
+            trace(&mut ctx, args);
+
+            ctx.get_witness_with_log()
+        })
+    }
+
+    /// Like `witness`, running the callbacks of `hooks` as the trace adds its steps.
+    pub fn witness_with_hooks(
+        &self,
+        args: TraceArgs,
+        hooks: TraceHooks<F>,
+    ) -> Option<TraceWitness<F>> {
+        self.circuit.trace.as_ref().map(|trace| {
+            let _timer = metrics::Timer::start(metrics::WITNESS_SECONDS);
+            let mut ctx = self.trace_context().with_hooks(hooks);
+            trace(&mut ctx, args);
+
+            let witness = ctx.get_witness();
+            metrics::increment_counter(
+                metrics::STEPS_GENERATED,
+                witness.step_instances.len() as u64,
+            );
+
+            witness
+        })
+    }
+
+    /// Like `witness`, returning the error of a trace set with `try_trace` instead of panicking.
+    /// The other traces are run by `witness`.
+    pub fn try_witness(&self, args: TraceArgs) -> Result<Option<TraceWitness<F>>, TraceError> {
+        let trace = match self.circuit.try_trace.as_ref() {
+            Some(trace) => trace,
+            None => return Ok(self.witness(args)),
+        };
+        let _timer = metrics::Timer::start(metrics::WITNESS_SECONDS);
+        let mut ctx = self.trace_context();
+        trace(&mut ctx, args)?;
+
+        let witness = ctx.get_witness();
+        metrics::increment_counter(
+            metrics::STEPS_GENERATED,
+            witness.step_instances.len() as u64,
+        );
+
+        Ok(Some(witness))
+    }
+
+    /// Like `witness`, for a circuit with external signals, whose values are taken from
+    /// `external`. Fails when a column is missing, is supplied for a signal that is not external,
+    /// or does not have a value for each step of the trace.
+    pub fn witness_with_external(
+        &self,
+        args: TraceArgs,
+        external: &ExternalColumns<F>,
+    ) -> Result<Option<TraceWitness<F>>, ExternalColumnError> {
+        let known_steps = Some(self.circuit.num_steps).filter(|num_steps| *num_steps > 0);
+        external.validate(&self.circuit.external_signals, known_steps)?;
+
+        let trace = match self.circuit.trace.as_ref() {
+            Some(trace) => trace,
+            None => return Ok(None),
+        };
+        let mut ctx = self.trace_context().with_external_columns(external);
+        trace(&mut ctx, args);
+        external.validate(&self.circuit.external_signals, Some(ctx.steps()))?;
+
+        Ok(Some(ctx.get_witness()))
+    }
+
+    fn trace_context(&self) -> GenericTraceContext<F, StepArgs> {
+        if self.debug {
+            println!("starting advise generation");
+        }
+
+        GenericTraceContext::for_circuit(
+            &self.circuit,
+            TraceOptions {
+                check_constraints: self.check_constraints,
+                strict_assignments: self.strict_assignments,
+            },
+        )
+    }
+
+    /// Runs the trace of the circuit and applies `pipeline` to the generated witness.
+    pub fn witness_with_pipeline(
+        &self,
+        args: TraceArgs,
+        pipeline: &WitnessPipeline<F>,
+    ) -> Result<Option<TraceWitness<F>>, PipelineError> {
+        self.witness(args)
+            .map(|witness| pipeline.run(witness))
+            .transpose()
+    }
+
+    /// Like `synthesize`, translating every step instance to its advice assignments as the trace
+    /// adds it, see `GenericTraceContext::with_step_sink`, so that the step instances of the
+    /// witness, with their assignment maps, are never kept in memory. The advice assignments of
+    /// the whole trace are still collected before they are assigned to the regions, so the
+    /// memory used grows with the trace, only with a smaller footprint per cell. The steps cannot
+    /// be reordered in this mode, so the circuit must not have a `Grouped` schedule.
+    pub fn synthesize_streaming(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        args: TraceArgs,
+    ) -> Result<(), Error> {
+        let challenges = self.challenge_values(layouter);
+        let (advice_assignments, height, step_offsets) = self.stream_advice(args, challenges);
+
+        self.assign_regions(layouter, advice_assignments, height, step_offsets)
+    }
+
+    fn stream_advice(
+        &self,
+        args: TraceArgs,
+        challenges: Value<ChallengeValues<F>>,
+    ) -> (Vec<Assignment<F, Advice>>, usize, Vec<usize>) {
+        let trace = match self.circuit.trace.as_ref() {
+            Some(trace) => trace,
+            None => return (vec![], 0, vec![]),
+        };
+
+        let mut processor = self.witness_processor();
+        processor.challenges = challenges;
+        let height = {
+            let mut ctx = self
+                .trace_context()
+                .with_step_sink(|instance| processor.process_step(instance));
+
+            trace(&mut ctx, args);
+
+            ctx.get_witness().height
+        };
+
+        let height = if height > 0 {
+            height
+        } else {
+            processor.max_offset + 1
+        };
+
+        (processor.assigments, height, processor.step_offsets)
     }
 
-    pub fn synthesize(&self, layouter: &mut impl Layouter<F>, args: TraceArgs) {
-        let (advice_assignments, height) = self.synthesize_advice(args);
+    fn synthesize_with(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        witness: Option<TraceWitness<F>>,
+    ) -> Result<(), Error> {
+        let challenges = self.challenge_values(layouter);
+        let (advice_assignments, height, step_offsets) =
+            self.synthesize_advice_with_challenges(witness, challenges);
+
+        self.assign_regions(layouter, advice_assignments, height, step_offsets)
+    }
 
-        let _ = layouter.assign_region(
-            || "circuit",
-            |mut region| {
-                self.annotate_circuit(&mut region);
+    /// The values of the challenges of the circuit, known in the phases after theirs. halo2
+    /// synthesizes the circuit once per phase, the signals assigned with challenges are unknown
+    /// until their challenges are.
+    fn challenge_values(&self, layouter: &impl Layouter<F>) -> Value<ChallengeValues<F>> {
+        self.circuit.challenges.iter().fold(
+            Value::known(ChallengeValues::new()),
+            |values, challenge| {
+                let value = layouter.get_challenge(self.challenges[&challenge.uuid()]);
+
+                values.zip(value).map(|(mut values, value)| {
+                    values.insert(*challenge, value);
+                    values
+                })
+            },
+        )
+    }
 
-                let fixed_assignments = self.synthesize_fixed();
-                for (column, offset, value) in fixed_assignments.iter() {
-                    region.assign_fixed(|| "", *column, *offset, || *value)?;
+    fn assign_regions(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        advice_assignments: Vec<Assignment<F, Advice>>,
+        height: usize,
+        step_offsets: Vec<usize>,
+    ) -> Result<(), Error> {
+        let exposed: HashMap<(Column<Advice>, usize), usize> = self
+            .exposed_positions(&step_offsets)
+            .into_iter()
+            .enumerate()
+            .map(|(instance_row, position)| (position, instance_row))
+            .collect();
+        let mut exposed_cells: Vec<Option<Cell>> = vec![None; exposed.len()];
+
+        match self.region_layout {
+            RegionLayout::Single => {
+                exposed_cells = layouter.assign_region(
+                    || "circuit",
+                    |mut region| {
+                        self.annotate_circuit(&mut region);
+                        self.assign_fixed_rows(&mut region, height)?;
+
+                        self.assign_advice_rows(&mut region, &advice_assignments, 0, &exposed)
+                    },
+                )?;
+            }
+            RegionLayout::StepGroups(steps) => {
+                layouter.assign_region(
+                    || "fixed",
+                    |mut region| {
+                        self.annotate_circuit(&mut region);
+                        self.assign_fixed_rows(&mut region, height)
+                    },
+                )?;
+
+                for (start, end, rows) in
+                    self.step_group_regions(&advice_assignments, height, &step_offsets, steps)
+                {
+                    let cells = layouter.assign_region(
+                        || format!("steps at rows {}..{}", start, end),
+                        |mut region| {
+                            self.annotate_circuit(&mut region);
+                            self.assign_advice_rows(&mut region, &rows, start, &exposed)
+                        },
+                    )?;
+
+                    for (exposed_cell, cell) in exposed_cells.iter_mut().zip(cells) {
+                        *exposed_cell = exposed_cell.or(cell);
+                    }
                 }
+            }
+        }
+
+        if let Some(instance_column) = self.instance_column {
+            for (row, cell) in exposed_cells.into_iter().enumerate() {
+                let cell = cell.expect("exposed signal not assigned");
+
+                layouter.constrain_instance(cell, instance_column, row)?;
+            }
+        }
 
-                for (column, offset, value) in advice_assignments.iter() {
-                    region.assign_advice(|| "", *column, *offset, || *value)?;
+        Ok(())
+    }
+
+    /// The regions of `RegionLayout::StepGroups(steps)`. Every advice column, of the circuit or
+    /// imported from halo2, is assigned in the last row of each region, with zero where the
+    /// witness has no value.
+    fn step_group_regions(
+        &self,
+        advice_assignments: &[Assignment<F, Advice>],
+        height: usize,
+        step_offsets: &[usize],
+        steps: usize,
+    ) -> Vec<StepGroupRegion<F>> {
+        let starts: Vec<usize> = step_offsets.iter().step_by(steps.max(1)).cloned().collect();
+        let last = advice_assignments
+            .iter()
+            .map(|(_, offset, _)| *offset + 1)
+            .chain([height])
+            .max()
+            .unwrap_or_default();
+
+        starts
+            .iter()
+            .enumerate()
+            .map(|(i, start)| {
+                let end = starts.get(i + 1).cloned().unwrap_or(last);
+                let mut rows: Vec<Assignment<F, Advice>> = advice_assignments
+                    .iter()
+                    .filter(|(_, offset, _)| *start <= *offset && *offset < end)
+                    .cloned()
+                    .collect();
+
+                for column in self.circuit.columns.iter() {
+                    if !matches!(column.ctype, cAdvice | Halo2Advice) {
+                        continue;
+                    }
+
+                    let column = self.advice_columns[&column.uuid()];
+                    if !rows
+                        .iter()
+                        .any(|(c, offset, _)| *c == column && *offset == end - 1)
+                    {
+                        rows.push((column, end - 1, Value::known(F::ZERO)));
+                    }
                 }
 
-                if height > 0 {
-                    self.default_fixed(&mut region, height);
+                (*start, end, rows)
+            })
+            .collect()
+    }
+
+    fn assign_fixed_rows(&self, region: &mut Region<F>, height: usize) -> Result<(), Error> {
+        let fixed_assignments = self.synthesize_fixed();
+        for (column, offset, value) in fixed_assignments.iter() {
+            region.assign_fixed(|| "", *column, *offset, || *value)?;
+        }
+
+        if height > 0 {
+            self.default_fixed(region, height);
+        }
+
+        Ok(())
+    }
+
+    /// Assigns the advice cells in a region starting at row `start`, and returns the cells of the
+    /// exposed positions found in it. The exposed cells are copied to the instance column as they
+    /// are, with one equality constraint each, so that no cell is assigned twice.
+    fn assign_advice_rows(
+        &self,
+        region: &mut Region<F>,
+        assignments: &[Assignment<F, Advice>],
+        start: usize,
+        exposed: &HashMap<(Column<Advice>, usize), usize>,
+    ) -> Result<Vec<Option<Cell>>, Error> {
+        let mut exposed_cells: Vec<Option<Cell>> = vec![None; exposed.len()];
+        for (column, offset, value) in assignments.iter() {
+            let cell = region.assign_advice(|| "", *column, *offset - start, || *value)?;
+
+            if let Some(instance_row) = exposed.get(&(*column, *offset)) {
+                exposed_cells[*instance_row] = Some(cell.cell());
+            }
+        }
+
+        Ok(exposed_cells)
+    }
+
+    fn exposed_positions(&self, step_offsets: &[usize]) -> Vec<(Column<Advice>, usize)> {
+        self.circuit
+            .exposed
+            .iter()
+            .map(|(column, rotation, offset)| {
+                let halo2_column = self
+                    .advice_columns
+                    .get(&column.uuid())
+                    .expect("exposed column not found");
+
+                let step_offset = match offset {
+                    ExposeOffset::First => step_offsets.first(),
+                    ExposeOffset::Last => step_offsets.last(),
+                    ExposeOffset::Step(step) => step_offsets.get(*step),
                 }
+                .unwrap_or_else(|| panic!("exposed step {:?} not found in witness", offset));
 
-                Ok(())
-            },
-        );
+                (*halo2_column, (*step_offset as i32 + rotation) as usize)
+            })
+            .collect()
     }
 
     fn synthesize_fixed(&self) -> Vec<Assignment<F, Fixed>> {
-        if let Some(fg) = &self.circuit.fixed_gen {
+        let mut assignments = if let Some(fg) = &self.circuit.fixed_gen {
             let mut ctx = FixedGenContextHalo2::<F> {
                 assigments: Default::default(),
 
                 max_offset: 0,
+                rows: self.max_rows,
+                step_rows: self.step_rows(),
             };
 
             fg(&mut ctx);
@@ -160,34 +756,143 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
             ctx.assigments
         } else {
             vec![]
+        };
+
+        assignments.extend(self.synthesize_step_fixed());
+        assignments.extend(self.synthesize_fixed_tables());
+
+        assignments
+    }
+
+    /// The rows of the steps, when the number of steps is set and all the step types have the
+    /// same height.
+    fn step_rows(&self) -> Option<StepRows> {
+        let mut heights = self
+            .circuit
+            .step_types
+            .values()
+            .map(|step| self.circuit.placement.step_height(step) as usize);
+        let step_height = heights.next()?;
+
+        if self.circuit.num_steps == 0 || heights.any(|height| height != step_height) {
+            return None;
         }
+
+        Some(StepRows {
+            num_steps: self.circuit.num_steps,
+            step_height,
+        })
     }
 
-    fn synthesize_advice(&self, args: TraceArgs) -> (Vec<Assignment<F, Advice>>, usize) {
-        if self.debug {
-            println!("starting advise generation");
+    fn synthesize_fixed_tables(&self) -> Vec<Assignment<F, Fixed>> {
+        let mut assignments = Vec::new();
+        for table in self.circuit.fixed_tables.iter() {
+            for (i, column) in table.columns.iter().enumerate() {
+                let halo2_column = self
+                    .fixed_columns
+                    .get(&column.uuid())
+                    .expect("fixed table column not found");
+
+                for (offset, row) in table.rows.iter().enumerate() {
+                    assignments.push((*halo2_column, offset, Value::known(row[i])));
+                }
+            }
         }
 
-        if let Some(trace) = &self.circuit.trace {
-            let mut ctx = GenericTraceContext::new(&self.circuit.step_types);
+        assignments
+    }
 
-            trace(&mut ctx, args);
+    fn synthesize_step_fixed(&self) -> Vec<Assignment<F, Fixed>> {
+        let step_height = match self.circuit.step_types.values().next() {
+            Some(step) => self.circuit.placement.step_height(step) as usize,
+            None => return vec![],
+        };
 
-            let witness = ctx.get_witness();
+        let mut assignments = Vec::new();
+        for (column, gen) in self.circuit.step_fixed.iter() {
+            let halo2_column = self
+                .fixed_columns
+                .get(&column.uuid())
+                .expect("step fixed column not found");
+
+            for step_idx in 0..self.circuit.num_steps {
+                assignments.push((
+                    *halo2_column,
+                    step_idx * step_height,
+                    Value::known(gen(step_idx)),
+                ));
+            }
+        }
+
+        assignments
+    }
+
+    fn witness_processor(&self) -> WitnessProcessor<F, StepArgs> {
+        WitnessProcessor {
+            assigments: Default::default(),
+            advice_columns: self.advice_columns.clone(),
+            placement: self.circuit.placement.clone(),
+            selector: self.circuit.selector.clone(),
+            step_types: self.circuit.step_types.clone(),
+            offset: 0,
+            cur_step: None,
+            max_offset: 0,
+            step_offsets: Vec::new(),
+            unassigned: self.circuit.unassigned.clone(),
+            challenges: Value::unknown(),
+        }
+    }
+
+    fn synthesize_advice(
+        &self,
+        witness: Option<TraceWitness<F>>,
+    ) -> (Vec<Assignment<F, Advice>>, usize, Vec<usize>) {
+        self.synthesize_advice_with_challenges(witness, Value::unknown())
+    }
+
+    fn synthesize_advice_with_challenges(
+        &self,
+        witness: Option<TraceWitness<F>>,
+        challenges: Value<ChallengeValues<F>>,
+    ) -> (Vec<Assignment<F, Advice>>, usize, Vec<usize>) {
+        if let Some(witness) = witness {
             let height = witness.height;
 
-            let mut processor = WitnessProcessor::<F, StepArgs> {
-                assigments: Default::default(),
-                advice_columns: self.advice_columns.clone(),
-                placement: self.circuit.placement.clone(),
-                selector: self.circuit.selector.clone(),
-                step_types: self.circuit.step_types.clone(),
-                offset: 0,
-                cur_step: None,
-                max_offset: 0,
-            };
+            if self.debug {
+                let report = witness.budget_report(&self.circuit.step_types);
+                for budget in report.iter().filter(|budget| budget.is_exceeded()) {
+                    println!(
+                        "step type {} exceeds its budget: {} instances, budget {}",
+                        budget.step_type,
+                        budget.instances,
+                        budget.budget.unwrap_or_default()
+                    );
+                }
 
-            processor.process(witness);
+                let num_steps = witness.step_instances.len();
+                if self.circuit.num_steps > 0 && num_steps > self.circuit.num_steps {
+                    println!(
+                        "trace has {} steps, more than num_steps {}",
+                        num_steps, self.circuit.num_steps
+                    );
+                }
+            }
+
+            let mut processor = self.witness_processor();
+            processor.challenges = challenges;
+
+            let scheduled = self.circuit.step_schedule != StepSchedule::InOrder;
+            if (self.assignment_threads > 1 || scheduled)
+                && matches!(self.circuit.unassigned, UnassignedPolicy::Implicit)
+            {
+                processor.process_parallel(witness, self.assignment_threads);
+            } else {
+                processor.process(witness);
+            }
+
+            if !processor.step_offsets.is_empty() {
+                metrics::record_histogram(metrics::ROWS_USED, (processor.max_offset + 1) as f64);
+            }
 
             let height = if height > 0 {
                 height
@@ -195,9 +900,9 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
                 processor.max_offset + 1
             };
 
-            (processor.assigments, height)
+            (processor.assigments, height, processor.step_offsets)
         } else {
-            (vec![], 0)
+            (vec![], 0, vec![])
         }
     }
 
@@ -283,6 +988,14 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
             }
             PolyExpr::Halo2Expr(e) => e.clone(),
             PolyExpr::Query(column, rotation, _) => self.convert_query(meta, column, *rotation),
+            PolyExpr::Challenge(challenge) => {
+                let challenge = self
+                    .challenges
+                    .get(&challenge.uuid())
+                    .unwrap_or_else(|| panic!("challenge not found {}", challenge.annotation()));
+
+                meta.query_challenge(*challenge)
+            }
         }
     }
 
@@ -313,8 +1026,66 @@ impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
     }
 }
 
+impl<F: PrimeField + Hash, TraceArgs, StepArgs: Clone> ChiquitoHalo2<F, TraceArgs, StepArgs> {
+    /// Runs the trace and splits the witness in the assignments of the `selected` signals, with
+    /// their digest, and the others, see `wit_gen::split`. The whole witness is generated, the
+    /// parts can then be kept apart until `synthesize_split`.
+    pub fn split_witness(
+        &self,
+        args: TraceArgs,
+        selected: &[Queriable<F>],
+    ) -> Option<(SplitWitness<F>, TraceWitness<F>)> {
+        self.witness(args).map(|witness| {
+            let (selected, rest) = witness.split(selected);
+
+            (SplitWitness::new(&self.circuit, selected), rest)
+        })
+    }
+
+    /// Like `witness`, returning the witness cached for `args` in `cache` when there is one, see
+    /// `WitnessCache`.
+    pub fn witness_cached(
+        &self,
+        args: TraceArgs,
+        cache: &mut WitnessCache<F, TraceArgs>,
+    ) -> Result<Option<TraceWitness<F>>, ReplayError> {
+        cache.get_or_generate(&self.circuit, args, |args| self.witness(args))
+    }
+
+    /// Synthesizes the circuit with the two parts of a split witness, the selected one checked
+    /// against its digest. Fails with the `SplitWitnessError` of parts that do not match, before
+    /// anything is assigned, and otherwise returns the result of the synthesis.
+    pub fn synthesize_split(
+        &self,
+        layouter: &mut impl Layouter<F>,
+        split: SplitWitness<F>,
+        rest: TraceWitness<F>,
+    ) -> Result<Result<(), Error>, SplitWitnessError> {
+        let witness = split.join(&self.circuit, rest)?;
+
+        Ok(self.synthesize_with(layouter, Some(witness)))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<F: Field + From<u64> + Hash, TraceArgs: serde::de::DeserializeOwned, StepArgs: Clone>
+    ChiquitoHalo2<F, TraceArgs, StepArgs>
+{
+    /// Runs the trace with arguments decoded from JSON, so that witness generation can be driven
+    /// by external systems without parsing the arguments of each circuit by hand.
+    pub fn generate_from_json(
+        &self,
+        json: &str,
+    ) -> Result<Option<TraceWitness<F>>, serde_json::Error> {
+        Ok(self.witness(serde_json::from_str(json)?))
+    }
+}
+
 type Assignment<F, CT> = (Column<CT>, usize, Value<F>);
 
+/// The first row, the end and the advice assignments of a region of `RegionLayout::StepGroups`.
+type StepGroupRegion<F> = (usize, usize, Vec<Assignment<F, Advice>>);
+
 struct WitnessProcessor<F: Field, StepArgs> {
     advice_columns: HashMap<u32, Column<Advice>>,
     placement: Placement<F, StepArgs>,
@@ -327,48 +1098,219 @@ struct WitnessProcessor<F: Field, StepArgs> {
     assigments: Vec<Assignment<F, Advice>>,
 
     max_offset: usize,
+    step_offsets: Vec<usize>,
+
+    unassigned: UnassignedPolicy<F>,
+    /// Values of the challenges for the signals of the later phases, unknown in the phases before.
+    challenges: Value<ChallengeValues<F>>,
 }
 
 impl<F: Field, StepArgs: Clone> WitnessProcessor<F, StepArgs> {
     fn process(&mut self, witness: TraceWitness<F>) {
         for step_instance in witness.step_instances {
-            let cur_step = Rc::clone(
-                self.step_types
-                    .get(&step_instance.step_type_uuid)
-                    .expect("step type not found"),
-            );
+            self.process_step(step_instance);
+        }
+    }
+
+    /// Translates the next step instance of the trace to its advice assignments.
+    fn process_step(&mut self, step_instance: StepInstance<F>) {
+        let cur_step = Rc::clone(
+            self.step_types
+                .get(&step_instance.step_type_uuid)
+                .expect("step type not found"),
+        );
+
+        self.cur_step = Some(Rc::clone(&cur_step));
+        self.step_offsets.push(self.offset);
+
+        self.assign_unassigned(&cur_step, &step_instance);
 
-            self.cur_step = Some(Rc::clone(&cur_step));
+        for assigment in step_instance.assignments {
+            self.assign(assigment.0, assigment.1);
+        }
+        for assignment in step_instance.challenge_assignments {
+            let value = self
+                .challenges
+                .as_ref()
+                .map(|challenges| assignment.value(challenges));
+            self.assign_value(assignment.signal, value);
+        }
 
-            for assigment in step_instance.assignments {
-                self.assign(assigment.0, assigment.1);
+        let selector_assignment = self
+            .selector
+            .selector_assignment
+            .get(&cur_step)
+            .expect("selector assignment for step not found");
+
+        for (expr, value) in selector_assignment.iter() {
+            match expr {
+                PolyExpr::Query(column, rot, _) => {
+                    let column = self
+                        .advice_columns
+                        .get(&column.uuid())
+                        .expect("selector expression column not found");
+
+                    self.assigments.push((
+                        *column,
+                        self.offset + *rot as usize,
+                        Value::known(*value),
+                    ))
+                }
+                _ => panic!("wrong type of expresion is selector assignment"),
             }
+        }
 
-            let selector_assignment = self
-                .selector
-                .selector_assignment
-                .get(&cur_step)
-                .expect("selector assignment for step not found");
+        self.offset += self.placement.step_height(&cur_step) as usize;
+    }
 
-            for (expr, value) in selector_assignment.iter() {
-                match expr {
-                    PolyExpr::Query(column, rot, _) => {
-                        let column = self
-                            .advice_columns
-                            .get(&column.uuid())
-                            .expect("selector expression column not found");
+    /// Like `process`, translating chunks of consecutive steps in `threads` threads. Every step
+    /// maps to its own rows, so the chunks are independent once the offsets of the steps are
+    /// known, and the assignments are in the same order as with `process`.
+    fn process_parallel(&mut self, witness: TraceWitness<F>, threads: usize) {
+        let layouts: HashMap<u32, StepLayout<F>> = self
+            .step_types
+            .values()
+            .map(|step| (step.uuid(), self.step_layout(step)))
+            .collect();
+
+        for step_instance in witness.step_instances.iter() {
+            let layout = layouts
+                .get(&step_instance.step_type_uuid)
+                .expect("step type not found");
+
+            self.step_offsets.push(self.offset);
+            self.offset += layout.height;
+        }
 
-                        self.assigments.push((
-                            *column,
-                            self.offset + *rot as usize,
-                            Value::known(*value),
-                        ))
-                    }
-                    _ => panic!("wrong type of expresion is selector assignment"),
+        let steps = witness.step_instances.len();
+        let threads = threads.max(1);
+        let chunk_size = ((steps + threads - 1) / threads).max(1);
+        let layouts = &layouts;
+        let step_offsets = &self.step_offsets;
+        let challenges = &self.challenges;
+
+        let chunks: Vec<Vec<Assignment<F, Advice>>> = thread::scope(|scope| {
+            // every thread has to be spawned before joining the first one
+            #[allow(clippy::needless_collect)]
+            let handles: Vec<_> = witness
+                .step_instances
+                .chunks(chunk_size)
+                .enumerate()
+                .map(|(chunk, instances)| {
+                    let offsets = &step_offsets[chunk * chunk_size..];
+
+                    scope.spawn(move || {
+                        let mut assignments = Vec::new();
+                        for (instance, offset) in instances.iter().zip(offsets) {
+                            let layout = &layouts[&instance.step_type_uuid];
+                            layout.assign(instance, *offset, challenges, &mut assignments);
+                        }
+
+                        assignments
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("assignment thread panicked"))
+                .collect()
+        });
+
+        for assignments in chunks {
+            for (_, offset, _) in assignments.iter() {
+                self.max_offset = self.max_offset.max(*offset);
+            }
+            self.assigments.extend(assignments);
+        }
+    }
+
+    fn step_layout(&self, step: &StepType<F, StepArgs>) -> StepLayout<F> {
+        let mut signals = HashMap::new();
+
+        for signal in step.signals.iter() {
+            signals.insert(
+                Queriable::Internal(*signal),
+                self.find_halo2_placement_internal(step, *signal),
+            );
+        }
+        for forward in self.placement.forward.keys() {
+            for next in [false, true] {
+                signals.insert(
+                    Queriable::Forward(*forward, next),
+                    self.find_halo2_placement_forward(step, *forward, next),
+                );
+            }
+        }
+
+        let selector = self
+            .selector
+            .selector_assignment
+            .get(step)
+            .expect("selector assignment for step not found")
+            .iter()
+            .map(|(expr, value)| match expr {
+                PolyExpr::Query(column, rot, _) => {
+                    let column = self
+                        .advice_columns
+                        .get(&column.uuid())
+                        .expect("selector expression column not found");
+
+                    (*column, *rot, *value)
                 }
+                _ => panic!("wrong type of expresion is selector assignment"),
+            })
+            .collect();
+
+        StepLayout {
+            height: self.placement.step_height(step) as usize,
+            signals,
+            selector,
+        }
+    }
+
+    fn assign_unassigned(&mut self, step: &StepType<F, StepArgs>, instance: &StepInstance<F>) {
+        if let UnassignedPolicy::Implicit = self.unassigned {
+            return;
+        }
+
+        let mut forward: Vec<ForwardSignal> = self.placement.forward.keys().cloned().collect();
+        forward.sort_by_key(|signal| signal.uuid());
+
+        let signals = step
+            .signals
+            .iter()
+            .map(|signal| Queriable::Internal(*signal))
+            .chain(
+                forward
+                    .into_iter()
+                    .map(|signal| Queriable::Forward(signal, false)),
+            );
+
+        for signal in signals {
+            let assigned_later = instance
+                .challenge_assignments
+                .iter()
+                .any(|assignment| assignment.signal == signal);
+            if instance.assignments.contains_key(&signal) || assigned_later {
+                continue;
             }
 
-            self.offset += self.placement.step_height(&cur_step) as usize;
+            let value = match &self.unassigned {
+                UnassignedPolicy::Zero => Some(F::ZERO),
+                UnassignedPolicy::Defaults(defaults) => defaults.get(&signal).cloned(),
+                _ => None,
+            };
+
+            match value {
+                Some(value) => self.assign(signal, value),
+                None => panic!(
+                    "signal {} not assigned in step {} of type {}",
+                    signal.annotation(),
+                    self.step_offsets.len() - 1,
+                    step.name
+                ),
+            }
         }
     }
 
@@ -429,11 +1371,15 @@ impl<F: Field, StepArgs: Clone> WitnessProcessor<F, StepArgs> {
     }
 
     fn assign(&mut self, lhs: Queriable<F>, rhs: F) {
+        self.assign_value(lhs, Value::known(rhs));
+    }
+
+    fn assign_value(&mut self, lhs: Queriable<F>, rhs: Value<F>) {
         if let Some(cur_step) = &self.cur_step {
             let (column, rotation) = self.find_halo2_placement(cur_step, lhs);
 
             let offset = (self.offset as i32 + rotation) as usize;
-            self.assigments.push((column, offset, Value::known(rhs)));
+            self.assigments.push((column, offset, rhs));
 
             self.max_offset = self.max_offset.max(offset);
         } else {
@@ -442,32 +1388,130 @@ impl<F: Field, StepArgs: Clone> WitnessProcessor<F, StepArgs> {
     }
 }
 
+/// The halo2 placements of the signals of a step type and its selector assignment, which the
+/// threads of `WitnessProcessor::process_parallel` share.
+struct StepLayout<F> {
+    height: usize,
+    signals: HashMap<Queriable<F>, (Column<Advice>, i32)>,
+    selector: Vec<(Column<Advice>, i32, F)>,
+}
+
+impl<F: Field> StepLayout<F> {
+    fn assign(
+        &self,
+        instance: &StepInstance<F>,
+        offset: usize,
+        challenges: &Value<ChallengeValues<F>>,
+        assignments: &mut Vec<Assignment<F, Advice>>,
+    ) {
+        let values = instance
+            .assignments
+            .iter()
+            .map(|(lhs, rhs)| (*lhs, Value::known(*rhs)))
+            .chain(instance.challenge_assignments.iter().map(|assignment| {
+                let value = challenges
+                    .as_ref()
+                    .map(|challenges| assignment.value(challenges));
+
+                (assignment.signal, value)
+            }));
+
+        for (lhs, rhs) in values {
+            let (column, rotation) = match lhs {
+                Queriable::Halo2AdviceQuery(signal, rotation) => (signal.column, rotation),
+                _ => *self
+                    .signals
+                    .get(&lhs)
+                    .unwrap_or_else(|| panic!("invalid advice assignment on queriable {:?}", lhs)),
+            };
+
+            assignments.push((column, (offset as i32 + rotation) as usize, rhs));
+        }
+
+        for (column, rotation, value) in self.selector.iter() {
+            assignments.push((*column, offset + *rotation as usize, Value::known(*value)));
+        }
+    }
+}
+
 struct FixedGenContextHalo2<F: Field> {
     assigments: Vec<Assignment<F, Fixed>>,
 
     max_offset: usize,
+    /// Usable rows of the circuit, see `ChiquitoHalo2::max_rows`.
+    rows: Option<usize>,
+    step_rows: Option<StepRows>,
 }
 
-impl<F: Field> FixedGenContextHalo2<F> {
-    fn find_halo2_placement(query: Queriable<F>) -> (Column<Fixed>, i32) {
-        match query {
-            Queriable::Halo2FixedQuery(signal, rotation) => (signal.column, rotation),
-            _ => panic!("invalid fixed assignment on queriable {:?}", query),
+impl<F: Field> FixedGenContext<F> for FixedGenContextHalo2<F> {
+    fn assign(&mut self, offset: usize, lhs: Queriable<F>, rhs: F) {
+        if let Err(err) = self.try_assign(offset, lhs, rhs) {
+            panic!("invalid fixed assignment: {}", err);
         }
     }
-}
 
-impl<F: Field> FixedGenContext<F> for FixedGenContextHalo2<F> {
-    fn assign(&mut self, offset: usize, lhs: Queriable<F>, rhs: F) {
-        let (column, rotation) = Self::find_halo2_placement(lhs);
+    fn try_assign(
+        &mut self,
+        offset: usize,
+        lhs: Queriable<F>,
+        rhs: F,
+    ) -> Result<(), AssignmentError> {
+        let column = match lhs {
+            Queriable::Halo2FixedQuery(signal, 0) => signal.column,
+            Queriable::Halo2FixedQuery(_, _) => {
+                return Err(AssignmentError::Rotation {
+                    signal: lhs.annotation(),
+                })
+            }
+            _ => {
+                return Err(AssignmentError::WrongKind {
+                    signal: lhs.annotation(),
+                    expected: "fixed column",
+                })
+            }
+        };
 
-        if rotation != 0 {
-            panic!("cannot assign fixed value with rotation");
+        if let Some(rows) = self.rows.filter(|rows| offset >= *rows) {
+            return Err(AssignmentError::OutOfBounds {
+                signal: lhs.annotation(),
+                offset,
+                rows,
+            });
         }
 
         self.assigments.push((column, offset, Value::known(rhs)));
 
         self.max_offset = self.max_offset.max(offset);
+
+        Ok(())
+    }
+
+    fn rows(&self) -> Option<usize> {
+        self.rows
+    }
+
+    fn assign_slice(&mut self, lhs: Queriable<F>, values: &[F]) -> Result<(), AssignmentError> {
+        check_fixed_column(&lhs)?;
+        check_fixed_rows(&lhs, values.len(), self.rows)?;
+        let column = match lhs {
+            Queriable::Halo2FixedQuery(signal, _) => signal.column,
+            _ => unreachable!("checked fixed column"),
+        };
+
+        self.assigments.reserve(values.len());
+        self.assigments.extend(
+            values
+                .iter()
+                .enumerate()
+                .map(|(offset, value)| (column, offset, Value::known(*value))),
+        );
+        self.max_offset = self.max_offset.max(values.len().saturating_sub(1));
+
+        Ok(())
+    }
+
+    fn step_rows(&self) -> Option<StepRows> {
+        self.step_rows
     }
 }
 
@@ -482,3 +1526,732 @@ pub fn to_halo2_advice<F: Field>(
         _ => panic!("jarll wrong phase"),
     }
 }
+
+fn to_halo2_challenge<F: Field>(meta: &mut ConstraintSystem<F>, phase: usize) -> Challenge {
+    match phase {
+        0 => meta.challenge_usable_after(FirstPhase),
+        1 => meta.challenge_usable_after(SecondPhase),
+        2 => meta.challenge_usable_after(ThirdPhase),
+        _ => panic!("jarll wrong phase"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ci::catch,
+        compiler::{
+            cell_manager::{MaxWidthCellManager, SingleRowCellManager},
+            step_selector::SimpleStepSelectorBuilder,
+            Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_instance_layout() {
+        let sc = circuit::<Fr, (), (), _>("exposed", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.wg(move |ctx, _| {
+                    ctx.assign(a, Fr::from(1));
+                    ctx.assign(b, Fr::from(2));
+                })
+            });
+            ctx.expose(b, ExposeOffset::Last);
+            ctx.expose(a, ExposeOffset::First);
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, ());
+                ctx.add(&step, ());
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let public_inputs = circuit.witness(()).unwrap().public_inputs(&sc);
+        assert_eq!(public_inputs, vec![Fr::from(2), Fr::from(1)]);
+        assert_eq!(
+            circuit.instance(&circuit.witness(()).unwrap()),
+            vec![public_inputs.clone()]
+        );
+
+        let layout = circuit.instance_layout(circuit.witness(()).unwrap());
+        assert_eq!(layout[0].column, "srcm forward b");
+        assert_eq!(layout[0].row, 1);
+        assert_eq!(layout[1].row, 0);
+        assert_eq!(
+            circuit.verify_instance_layout(circuit.witness(()).unwrap(), &public_inputs),
+            Ok(())
+        );
+
+        let swapped = vec![Fr::from(1), Fr::from(2)];
+        let err = circuit
+            .verify_instance_layout(circuit.witness(()).unwrap(), &swapped)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            InstanceLayoutError::Misaligned {
+                instance_row: 0,
+                ..
+            }
+        ));
+        assert!(matches!(
+            circuit.verify_instance_layout(circuit.witness(()).unwrap(), &swapped[..1]),
+            Err(InstanceLayoutError::Length {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_step_fixed() {
+        let sc = circuit::<Fr, (), u64, _>("powers", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let power = ctx.step_fixed("power", |step| Fr::from(1 << step));
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(power * 2, power.next())));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, v.field());
+                });
+            });
+            ctx.pragma_num_steps(4);
+            ctx.trace(move |ctx, _| {
+                for i in 0..4 {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        // a and b in one column, so the steps have two rows
+        let compiled =
+            Compiler::new(MaxWidthCellManager::new(1), SimpleStepSelectorBuilder {}).compile(&sc);
+        assert_eq!(compiled.step_fixed.len(), 1);
+
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let (_, _, step_offsets) = circuit.synthesize_advice(circuit.witness(()));
+        assert_eq!(step_offsets, vec![0, 2, 4, 6]);
+
+        let mut fixed: HashMap<usize, Fr> = HashMap::new();
+        for (_, offset, value) in circuit.synthesize_fixed() {
+            value.map(|value| fixed.insert(offset, value));
+        }
+        assert_eq!(fixed.len(), 4);
+        for (step, offset) in step_offsets.iter().enumerate() {
+            assert_eq!(fixed.get(offset), Some(&Fr::from(1 << step)));
+        }
+
+        // the transition holds between the rows of consecutive steps
+        let step_type = circuit.circuit.step_types.values().next().unwrap();
+        let transition = &step_type.transition_constraints[0].expr;
+        for step in 0..3 {
+            let resolve = |signal: &Queriable<Fr>| match signal {
+                Queriable::StepFixed(_, false) => fixed.get(&step_offsets[step]).copied(),
+                Queriable::StepFixed(_, true) => fixed.get(&step_offsets[step + 1]).copied(),
+                _ => None,
+            };
+            assert_eq!(transition.eval(&resolve), Ok(Fr::ZERO));
+        }
+        // the next row of the last step is past the values of the steps
+        assert_eq!(fixed.get(&(step_offsets[3] + 2)), None);
+    }
+
+    #[test]
+    fn test_fixed_table_from_iter() {
+        let sc = circuit::<Fr, (), (), _>("tables", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let double = ctx.fixed_table_from_iter(
+                "double",
+                [3u64, 1, 2, 3, 1]
+                    .iter()
+                    .map(|v| [v.field(), (v * 2).field()]),
+            );
+            assert_eq!(double.len(), 4);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.add_lookup(|lookup| {
+                        lookup.table(&double, [a, b]);
+                    })
+                });
+                ctx.wg(move |ctx, _| {
+                    ctx.assign(a, Fr::from(2));
+                    ctx.assign(b, Fr::from(4));
+                });
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        assert_eq!(compiled.fixed_tables.len(), 1);
+        assert_eq!(compiled.lookups.len(), 1);
+        let rows: Vec<Vec<Fr>> = [0u64, 1, 2, 3]
+            .iter()
+            .map(|v| vec![Fr::from(*v), Fr::from(v * 2)])
+            .collect();
+        assert_eq!(*compiled.fixed_tables[0].rows, rows);
+
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let doubles: Vec<(usize, Value<Fr>)> = circuit
+            .synthesize_fixed_tables()
+            .into_iter()
+            .skip(4)
+            .map(|(_, offset, value)| (offset, value))
+            .collect();
+        assert_eq!(doubles.len(), 4);
+        assert_eq!(
+            format!("{:?}", doubles[3]),
+            format!("{:?}", (3, Value::known(Fr::from(6))))
+        );
+    }
+
+    #[test]
+    fn test_fixed_try_assign() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let column = meta.fixed_column();
+
+        let sc = circuit::<Fr, (), (), _>("fixed", |ctx| {
+            let a = ctx.forward("a");
+            let table = ctx.import_halo2_fixed("table", column);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |_| {});
+            ctx.fixed_gen(move |ctx| {
+                assert_eq!(ctx.try_assign(0, table, Fr::from(1)), Ok(()));
+                assert_eq!(
+                    ctx.try_assign(8, table, Fr::from(1)),
+                    Err(AssignmentError::OutOfBounds {
+                        signal: "table".to_string(),
+                        offset: 8,
+                        rows: 8
+                    })
+                );
+                assert!(matches!(
+                    ctx.try_assign(0, a, Fr::from(1)),
+                    Err(AssignmentError::WrongKind { .. })
+                ));
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.max_rows = Some(8);
+        circuit.configure(&mut meta);
+
+        assert_eq!(circuit.synthesize_fixed().len(), 1);
+    }
+
+    #[test]
+    fn test_fixed_assign_slice() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let range = meta.fixed_column();
+        let squares = meta.fixed_column();
+
+        let sc = circuit::<Fr, (), (), _>("bulk", |ctx| {
+            let a = ctx.forward("a");
+            let range = ctx.import_halo2_fixed("range", range);
+            let squares = ctx.import_halo2_fixed("squares", squares);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |_| {});
+            ctx.fixed_gen(move |ctx| {
+                let values: Vec<Fr> = (0..9).map(Fr::from).collect();
+                assert_eq!(
+                    ctx.assign_slice(range, &values),
+                    Err(AssignmentError::OutOfBounds {
+                        signal: "range".to_string(),
+                        offset: 8,
+                        rows: 8
+                    })
+                );
+                assert!(matches!(
+                    ctx.assign_slice(a, &values[..1]),
+                    Err(AssignmentError::WrongKind { .. })
+                ));
+                assert_eq!(ctx.assign_slice(range, &values[..8]), Ok(()));
+                assert_eq!(
+                    ctx.assign_from_fn(squares, 4, |offset| Fr::from((offset * offset) as u64)),
+                    Ok(())
+                );
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.max_rows = Some(8);
+        circuit.configure(&mut meta);
+
+        let assignments = circuit.synthesize_fixed();
+        assert_eq!(assignments.len(), 12);
+        assert_eq!(assignments[7].1, 7);
+        assert_eq!(assignments[11].0, squares);
+        assert_eq!(assignments[11].1, 3);
+    }
+
+    #[test]
+    fn test_fixed_assign_step() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let column = meta.fixed_column();
+
+        let sc = circuit::<Fr, (), (), _>("tall", |ctx| {
+            let table = ctx.import_halo2_fixed("table", column);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let _a = ctx.internal("a");
+                let _b = ctx.internal("b");
+            });
+            ctx.pragma_num_steps(3);
+            ctx.fixed_gen(move |ctx| {
+                let rows = StepRows {
+                    num_steps: 3,
+                    step_height: 2,
+                };
+                assert_eq!(ctx.step_rows(), Some(rows));
+                ctx.assign_step(1, 1, table, Fr::from(1));
+                assert_eq!(
+                    ctx.try_assign_step(3, 0, table, Fr::from(1)),
+                    Err(AssignmentError::StepOutOfBounds {
+                        signal: "table".to_string(),
+                        step: 3,
+                        row: 0,
+                        rows,
+                    })
+                );
+                assert!(ctx.try_assign_step(0, 2, table, Fr::from(1)).is_err());
+            });
+        });
+
+        let compiled =
+            Compiler::new(MaxWidthCellManager::new(1), SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut meta);
+
+        let offsets: Vec<usize> = circuit
+            .synthesize_fixed()
+            .into_iter()
+            .map(|(_, offset, _)| offset)
+            .collect();
+        assert_eq!(offsets, vec![3]);
+    }
+
+    fn synthesize_partial(policy: UnassignedPolicy<Fr>) -> Vec<Assignment<Fr, Advice>> {
+        let sc = circuit::<Fr, (), (), _>("partial", |ctx| {
+            let a = ctx.forward("a");
+            let _b = ctx.forward("b");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, _| ctx.assign(a, Fr::from(1))));
+            ctx.trace(move |ctx, _| ctx.add(&step, ()));
+        });
+
+        let compiled = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {})
+            .with_unassigned_policy(policy)
+            .compile(&sc);
+
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let witness = circuit.witness(());
+        circuit.synthesize_advice(witness).0
+    }
+
+    #[test]
+    fn test_unassigned_policy() {
+        let implicit = synthesize_partial(UnassignedPolicy::Implicit);
+        let zero = synthesize_partial(UnassignedPolicy::Zero);
+
+        assert_eq!(zero.len(), implicit.len() + 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "signal b not assigned in step 0 of type step")]
+    fn test_unassigned_policy_error() {
+        synthesize_partial(UnassignedPolicy::Error);
+    }
+
+    #[test]
+    fn test_parallel_assignment() {
+        let sc = circuit::<Fr, u64, u64, _>("counter", |ctx| {
+            let a = ctx.forward("a");
+            let even = ctx.step_type("even");
+            let odd = ctx.step_type("odd");
+
+            ctx.step_type_def(even, |ctx| {
+                let b = ctx.internal("b");
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                });
+            });
+            ctx.step_type_def(odd, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, steps| {
+                for i in 0..steps {
+                    ctx.add(if i % 2 == 0 { &even } else { &odd }, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        // the witnesses are generated twice, with different orders of the assignments in a step
+        let sorted = |assignments: Vec<Assignment<Fr, Advice>>| {
+            let mut assignments: Vec<String> = assignments
+                .iter()
+                .map(|assignment| format!("{:?}", assignment))
+                .collect();
+            assignments.sort();
+            assignments
+        };
+
+        let sequential = circuit.synthesize_advice(circuit.witness(101));
+        circuit.assignment_threads = 4;
+        let parallel = circuit.synthesize_advice(circuit.witness(101));
+
+        assert_eq!(parallel.1, sequential.1);
+        assert_eq!(parallel.2, sequential.2);
+        assert_eq!(sorted(parallel.0), sorted(sequential.0));
+    }
+
+    #[test]
+    fn test_scheduled_assignment_without_threads() {
+        let sc = circuit::<Fr, u64, u64, _>("grouped", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.pragma_step_schedule(StepSchedule::Grouped);
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, steps| {
+                for i in 0..steps {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let one = circuit.synthesize_advice(circuit.witness(5));
+        // a grouped schedule is translated by the parallel path whatever the number of threads
+        circuit.assignment_threads = 0;
+        let none = circuit.synthesize_advice(circuit.witness(5));
+
+        assert_eq!(format!("{:?}", none), format!("{:?}", one));
+    }
+
+    #[test]
+    fn test_streaming_assignment() {
+        let sc = circuit::<Fr, u64, u64, _>("counter", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.transition(eq(a + 1, a.next())));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                });
+            });
+            ctx.trace(move |ctx, steps| {
+                for i in 0..steps {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.check_constraints = true;
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let streamed = circuit.stream_advice(50, Value::unknown());
+        let materialized = circuit.synthesize_advice(circuit.witness(50));
+
+        assert_eq!(streamed.1, materialized.1);
+        assert_eq!(streamed.2, materialized.2);
+        let sorted = |assignments: Vec<Assignment<Fr, Advice>>| {
+            let mut assignments: Vec<String> = assignments
+                .iter()
+                .map(|assignment| format!("{:?}", assignment))
+                .collect();
+            assignments.sort();
+            assignments
+        };
+        assert_eq!(sorted(streamed.0), sorted(materialized.0));
+    }
+
+    #[test]
+    fn test_streaming_trace_context() {
+        let sc = circuit::<Fr, (), u64, _>("planned", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.pragma_num_steps(3);
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, _| {
+                let steps = ctx.remaining().expect("num_steps should be known");
+                for i in 0..steps {
+                    ctx.add(&step, i as u64);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+
+        let streamed = circuit.stream_advice((), Value::unknown());
+        assert_eq!(streamed.2.len(), 3);
+    }
+
+    #[test]
+    fn test_step_group_regions() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let imported = meta.advice_column();
+        let sc = circuit::<Fr, (), u64, _>("grouped", |ctx| {
+            let a = ctx.forward("a");
+            let h = ctx.import_halo2_advice("h", imported);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.transition(eq(a + 1, a.next())));
+                ctx.wg(move |ctx, v: u64| {
+                    ctx.assign(a, v.field());
+                    // b and h are left unassigned in some steps
+                    if v % 2 == 0 {
+                        ctx.assign(b, 1.field());
+                    }
+                    if v == 0 {
+                        ctx.assign(h, 2.field());
+                    }
+                });
+            });
+            ctx.trace(move |ctx, _| {
+                for i in 0..5 {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut meta);
+
+        let (single, height, step_offsets) = circuit.synthesize_advice(circuit.witness(()));
+        let regions = circuit.step_group_regions(&single, height, &step_offsets, 2);
+        assert_eq!(
+            regions
+                .iter()
+                .map(|(start, end, _)| (*start, *end))
+                .collect::<Vec<_>>(),
+            vec![(0, 2), (2, 4), (4, 5)]
+        );
+
+        let debug = |assignment: &Assignment<Fr, Advice>| format!("{:?}", assignment);
+        let mut grouped: Vec<String> = Vec::new();
+        let mut columns: Vec<Column<Advice>> = circuit.advice_columns.values().cloned().collect();
+        columns.sort_by_key(|column| column.index());
+        columns.dedup();
+        // a, b, the step selector and h
+        assert_eq!(columns.len(), 4);
+        for (_, end, rows) in regions.iter() {
+            // every advice column, the imported one too, is assigned in the last row
+            for column in columns.iter() {
+                assert!(
+                    rows.iter()
+                        .any(|(c, offset, _)| c == column && *offset == end - 1),
+                    "column {:?} not assigned in row {}",
+                    column,
+                    end - 1
+                );
+            }
+            grouped.extend(rows.iter().map(debug));
+        }
+
+        // the regions have the assignments of the single region, and zeros as padding
+        let single: Vec<String> = single.iter().map(debug).collect();
+        for assignment in single.iter() {
+            assert_eq!(grouped.iter().filter(|g| *g == assignment).count(), 1);
+        }
+        for (column, offset, value) in regions.iter().flat_map(|(_, _, rows)| rows.iter()) {
+            let assignment = debug(&(*column, *offset, *value));
+            if !single.contains(&assignment) {
+                assert_eq!(
+                    assignment,
+                    debug(&(*column, *offset, Value::known(Fr::ZERO)))
+                );
+            }
+        }
+        assert!(grouped.len() > single.len());
+    }
+
+    #[test]
+    fn test_try_witness() {
+        let sc = circuit::<Fr, &str, u64, _>("parsed", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.try_trace(move |ctx, steps: &str| {
+                for i in 0..steps.parse::<u64>()? {
+                    ctx.add(&step, i);
+                }
+
+                Ok::<_, std::num::ParseIntError>(())
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let circuit = chiquito2Halo2(compiled);
+
+        let witness = circuit.try_witness("3").unwrap().unwrap();
+        assert_eq!(witness.step_instances.len(), 3);
+        assert_eq!(circuit.witness("3"), Some(witness));
+
+        let err = circuit.try_witness("three").unwrap_err();
+        assert_eq!(err.to_string(), "invalid digit found in string");
+    }
+
+    #[test]
+    fn test_challenge_assignment() {
+        let sc = circuit::<Fr, u64, u64, _>("rlc", |ctx| {
+            let a = ctx.forward("a");
+            let acc = ctx.forward_with_phase("acc", 1);
+            let r = ctx.challenge("r", 0);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.constr(eq(a * r, acc)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign_with_challenges(acc, move |challenges| {
+                        Fr::from(v) * challenges.get(r)
+                    });
+                });
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let acc = compiled
+            .column_infos()
+            .into_iter()
+            .find(|column| column.annotation == "srcm forward acc")
+            .unwrap();
+        assert_eq!(acc.phase, 1);
+        let r = compiled.challenges[0];
+
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut ConstraintSystem::default());
+        let acc = circuit.advice_columns[&acc.uuid];
+
+        let witness = circuit.witness(3).unwrap();
+        assert_eq!(witness.step_instances[2].assignments.len(), 1);
+        assert_eq!(witness.step_instances[2].challenge_assignments.len(), 1);
+
+        let acc_values = |challenges: Value<ChallengeValues<Fr>>| {
+            circuit
+                .synthesize_advice_with_challenges(Some(witness.clone()), challenges)
+                .0
+                .into_iter()
+                .filter(|(column, _, _)| *column == acc)
+                .map(|(_, offset, value)| format!("{} {:?}", offset, value))
+                .collect::<Vec<String>>()
+        };
+
+        let mut challenges = ChallengeValues::new();
+        challenges.insert(r, Fr::from(5));
+        let expected: Vec<String> = (0..3)
+            .map(|i| format!("{} {:?}", i, Value::known(Fr::from(i * 5))))
+            .collect();
+        assert_eq!(acc_values(Value::known(challenges)), expected);
+
+        let unknown: Vec<String> = (0..3)
+            .map(|i| format!("{} {:?}", i, Value::<Fr>::unknown()))
+            .collect();
+        assert_eq!(acc_values(Value::unknown()), unknown);
+    }
+
+    #[test]
+    fn test_challenge_assignment_phase() {
+        let sc = circuit::<Fr, bool, (), _>("rlc", |ctx| {
+            let a = ctx.forward("a");
+            let r = ctx.challenge("r", 0);
+            let internal = ctx.step_type("internal");
+            let early = ctx.step_type("early");
+
+            ctx.step_type_def(internal, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.wg(move |ctx, _| ctx.assign_with_challenges(b, move |c| c.get(r)));
+            });
+            ctx.step_type_def(early, |ctx| {
+                ctx.wg(move |ctx, _| ctx.assign_with_challenges(a, move |c| c.get(r)));
+            });
+            ctx.trace(move |ctx, use_internal| {
+                if use_internal {
+                    ctx.add(&internal, ());
+                } else {
+                    ctx.add(&early, ());
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let circuit = chiquito2Halo2(compiled);
+
+        let message = catch(|| circuit.witness(true)).unwrap_err();
+        assert!(
+            message.contains("cannot assign b with challenges, it is in phase 0"),
+            "{}",
+            message
+        );
+        assert!(message.contains("of type internal"), "{}", message);
+        let message = catch(|| circuit.witness(false)).unwrap_err();
+        assert!(
+            message.contains("cannot assign a with challenges, it is in phase 0"),
+            "{}",
+            message
+        );
+    }
+}
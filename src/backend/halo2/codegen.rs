@@ -0,0 +1,429 @@
+use std::fmt::Write;
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::ir::{
+    Circuit, Column,
+    ColumnType::{Advice, Fixed, Halo2Advice, Halo2Fixed},
+    PolyExpr,
+};
+
+/// Emits the Rust source of a standalone halo2 `Circuit` equivalent to the compiled circuit,
+/// named `name`, which must be a valid Rust identifier. The generated code only depends on
+/// `halo2_proofs`.
+///
+/// The generated `configure` creates the same columns, gate and lookups than `ChiquitoHalo2`.
+/// Witness generation is written in Rust closures that cannot be emitted, so the generated
+/// circuit takes the value of every advice and fixed column as tables, in the order of the
+/// columns listed in the generated doc comment. `q_enable`, `q_first` and `q_last` are assigned
+/// by the generated `synthesize` from the height of the trace.
+pub fn halo2_source<F: PrimeField, TraceArgs, StepArgs>(
+    name: &str,
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+) -> String {
+    if name.is_empty()
+        || name.starts_with(|c: char| c.is_ascii_digit())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        panic!("{} is not a valid circuit name", name);
+    }
+
+    let codegen = Codegen::new(circuit);
+    let mut out = String::new();
+
+    codegen.header(&mut out, name);
+    codegen.configure(&mut out);
+    codegen.synthesize(&mut out);
+    out.push_str("}\n\n");
+    out.push_str(CONSTANT_HELPER);
+
+    out
+}
+
+const CONSTANT_HELPER: &str = "#[allow(dead_code)]
+fn constant<F: PrimeField>(bytes: &[u8]) -> F {
+    let mut repr = F::Repr::default();
+    repr.as_mut()[..bytes.len()].copy_from_slice(bytes);
+    Option::from(F::from_repr(repr)).expect(\"constant is not a field element\")
+}
+";
+
+struct Codegen<'a, F, TraceArgs, StepArgs> {
+    circuit: &'a Circuit<F, TraceArgs, StepArgs>,
+    advice: Vec<&'a Column>,
+    fixed: Vec<&'a Column>,
+}
+
+impl<'a, F: PrimeField, TraceArgs, StepArgs> Codegen<'a, F, TraceArgs, StepArgs> {
+    fn new(circuit: &'a Circuit<F, TraceArgs, StepArgs>) -> Self {
+        let advice = circuit
+            .columns
+            .iter()
+            .filter(|column| matches!(column.ctype, Advice | Halo2Advice))
+            .collect();
+        let fixed = circuit
+            .columns
+            .iter()
+            .filter(|column| matches!(column.ctype, Fixed | Halo2Fixed))
+            .collect();
+
+        Self {
+            circuit,
+            advice,
+            fixed,
+        }
+    }
+
+    fn header(&self, out: &mut String, name: &str) {
+        out.push_str(
+            "// Generated by chiquito, do not edit.
+
+use halo2_proofs::{
+    circuit::{Cell, Layouter, SimpleFloorPlanner, Value},
+    halo2curves::group::ff::PrimeField,
+    plonk::{
+        self, Advice, Circuit, Column, ConstraintSystem, Error, Expression, Fixed, Instance,
+    },
+    poly::Rotation,
+};
+
+#[derive(Clone, Debug)]
+",
+        );
+        writeln!(out, "pub struct {}Config {{", name).unwrap();
+        out.push_str(
+            "    pub advice: Vec<Column<Advice>>,
+    pub fixed: Vec<Column<Fixed>>,
+    pub instance: Option<Column<Instance>>,
+}
+
+",
+        );
+
+        out.push_str("/// Advice columns:\n");
+        for (i, column) in self.advice.iter().enumerate() {
+            writeln!(out, "/// - {}: {}", i, column.annotation).unwrap();
+        }
+        out.push_str("///\n/// Fixed columns:\n");
+        for (i, column) in self.fixed.iter().enumerate() {
+            writeln!(out, "/// - {}: {}", i, column.annotation).unwrap();
+        }
+        out.push_str(
+            "///
+/// `exposed` lists the advice column index and row of each public input, in instance order.
+#[derive(Clone, Default)]
+",
+        );
+        writeln!(out, "pub struct {}<F: PrimeField> {{", name).unwrap();
+        out.push_str(
+            "    pub height: usize,
+    pub advice: Vec<Vec<Value<F>>>,
+    pub fixed: Vec<Vec<F>>,
+    pub exposed: Vec<(usize, usize)>,
+}
+
+",
+        );
+        writeln!(out, "impl<F: PrimeField> Circuit<F> for {}<F> {{", name).unwrap();
+        writeln!(out, "    type Config = {}Config;", name).unwrap();
+        out.push_str(
+            "    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            height: self.height,
+            advice: self
+                .advice
+                .iter()
+                .map(|column| vec![Value::unknown(); column.len()])
+                .collect(),
+            fixed: self.fixed.clone(),
+            exposed: self.exposed.clone(),
+        }
+    }
+
+",
+        );
+    }
+
+    fn configure(&self, out: &mut String) {
+        out.push_str("    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {\n");
+
+        out.push_str("        let advice = vec![\n");
+        for column in self.advice.iter() {
+            let phase = match column.phase {
+                0 => "FirstPhase",
+                1 => "SecondPhase",
+                2 => "ThirdPhase",
+                _ => panic!("jarll wrong phase"),
+            };
+            writeln!(out, "            meta.advice_column_in(plonk::{}),", phase).unwrap();
+        }
+        out.push_str("        ];\n");
+
+        out.push_str("        let fixed = vec![\n");
+        for _ in self.fixed.iter() {
+            out.push_str("            meta.fixed_column(),\n");
+        }
+        out.push_str("        ];\n\n");
+
+        if self.circuit.exposed.is_empty() {
+            out.push_str("        let instance = None;\n");
+        } else {
+            for (column, _, _) in self.circuit.exposed.iter() {
+                writeln!(
+                    out,
+                    "        meta.enable_equality(advice[{}]);",
+                    self.advice_index(column)
+                )
+                .unwrap();
+            }
+            out.push_str("        let instance = Some(meta.instance_column());\n");
+        }
+
+        if !self.circuit.polys.is_empty() {
+            out.push_str("\n        meta.create_gate(\"main\", |meta| {\n            vec![\n");
+            for poly in self.circuit.polys.iter() {
+                writeln!(
+                    out,
+                    "                ({:?}, {}),",
+                    poly.annotation,
+                    self.expr(&poly.expr)
+                )
+                .unwrap();
+            }
+            out.push_str("            ]\n        });\n");
+        }
+
+        for lookup in self.circuit.lookups.iter() {
+            writeln!(
+                out,
+                "\n        meta.lookup_any({:?}, |meta| {{\n            vec![",
+                lookup.annotation
+            )
+            .unwrap();
+            for (src, dest) in lookup.exprs.iter() {
+                writeln!(
+                    out,
+                    "                ({}, {}),",
+                    self.expr(src),
+                    self.expr(dest)
+                )
+                .unwrap();
+            }
+            out.push_str("            ]\n        });\n");
+        }
+
+        out.push_str(
+            "
+        Self::Config {
+            advice,
+            fixed,
+            instance,
+        }
+    }
+
+",
+        );
+    }
+
+    fn synthesize(&self, out: &mut String) {
+        out.push_str(
+            "    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<F>,
+    ) -> Result<(), Error> {
+        let cells = layouter.assign_region(
+            || \"circuit\",
+            |mut region| {
+                for (column, values) in config.fixed.iter().zip(self.fixed.iter()) {
+                    for (row, value) in values.iter().enumerate() {
+                        region.assign_fixed(|| \"\", *column, row, || Value::known(*value))?;
+                    }
+                }
+
+",
+        );
+
+        writeln!(
+            out,
+            "                for row in 0..self.height {{
+                    region.assign_fixed(|| \"q_enable=1\", config.fixed[{}], row, || {{
+                        Value::known(F::ONE)
+                    }})?;
+                }}",
+            self.fixed_index(&self.circuit.q_enable)
+        )
+        .unwrap();
+        if let Some(q_first) = &self.circuit.q_first {
+            writeln!(
+                out,
+                "                region.assign_fixed(|| \"q_first=1\", config.fixed[{}], 0, || {{
+                    Value::known(F::ONE)
+                }})?;",
+                self.fixed_index(q_first)
+            )
+            .unwrap();
+        }
+        if let Some(q_last) = &self.circuit.q_last {
+            writeln!(
+                out,
+                "                if self.height > 0 {{
+                    region.assign_fixed(
+                        || \"q_last=1\",
+                        config.fixed[{}],
+                        self.height - 1,
+                        || Value::known(F::ONE),
+                    )?;
+                }}",
+                self.fixed_index(q_last)
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "
+                let mut cells: Vec<Option<Cell>> = vec![None; self.exposed.len()];
+                for (i, (column, values)) in config.advice.iter().zip(self.advice.iter()).enumerate()
+                {
+                    for (row, value) in values.iter().enumerate() {
+                        let cell = region.assign_advice(|| \"\", *column, row, || *value)?;
+
+                        for (exposed, position) in cells.iter_mut().zip(self.exposed.iter()) {
+                            if *position == (i, row) {
+                                *exposed = Some(cell.cell());
+                            }
+                        }
+                    }
+                }
+
+                Ok(cells)
+            },
+        )?;
+
+        if let Some(instance) = config.instance {
+            for (row, cell) in cells.into_iter().enumerate() {
+                let cell = cell.expect(\"exposed cell not assigned\");
+                layouter.constrain_instance(cell, instance, row)?;
+            }
+        }
+
+        Ok(())
+    }
+",
+        );
+    }
+
+    fn expr(&self, expr: &PolyExpr<F>) -> String {
+        match expr {
+            PolyExpr::Const(c) => format!("Expression::Constant({})", constant(c)),
+            PolyExpr::Query(column, rotation, _) => match column.ctype {
+                Advice | Halo2Advice => format!(
+                    "meta.query_advice(advice[{}], Rotation({}))",
+                    self.advice_index(column),
+                    rotation
+                ),
+                Fixed | Halo2Fixed => format!(
+                    "meta.query_fixed(fixed[{}], Rotation({}))",
+                    self.fixed_index(column),
+                    rotation
+                ),
+            },
+            PolyExpr::Sum(es) => format!("({})", self.join(es, " + ")),
+            PolyExpr::Mul(es) => format!("({})", self.join(es, " * ")),
+            PolyExpr::Neg(e) => format!("(-{})", self.expr(e)),
+            PolyExpr::Pow(e, n) => {
+                if *n == 0 {
+                    "Expression::Constant(F::ONE)".to_string()
+                } else {
+                    let e = self.expr(e);
+                    format!("({})", vec![e; *n as usize].join(" * "))
+                }
+            }
+            PolyExpr::Halo2Expr(_) => {
+                panic!("cannot generate source for a circuit with halo2 expressions")
+            }
+            PolyExpr::Challenge(_) => {
+                panic!("cannot generate source for a circuit with challenges")
+            }
+        }
+    }
+
+    fn join(&self, es: &[PolyExpr<F>], sep: &str) -> String {
+        es.iter()
+            .map(|e| self.expr(e))
+            .collect::<Vec<_>>()
+            .join(sep)
+    }
+
+    fn advice_index(&self, column: &Column) -> usize {
+        self.advice
+            .iter()
+            .position(|c| *c == column)
+            .unwrap_or_else(|| panic!("column not found {}", column.annotation))
+    }
+
+    fn fixed_index(&self, column: &Column) -> usize {
+        self.fixed
+            .iter()
+            .position(|c| *c == column)
+            .unwrap_or_else(|| panic!("column not found {}", column.annotation))
+    }
+}
+
+/// Small constants are emitted as `F::from(n)`, others as their little-endian representation.
+fn constant<F: PrimeField>(value: &F) -> String {
+    let repr = value.to_repr();
+    let bytes = repr.as_ref();
+    let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+
+    if len <= 8 {
+        let mut small = [0u8; 8];
+        small[..len].copy_from_slice(&bytes[..len]);
+
+        format!("F::from({})", u64::from_le_bytes(small))
+    } else {
+        format!("constant::<F>(&{:?})", &bytes[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_halo2_source() {
+        let sc = circuit::<Fr, (), (), _>("counter", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.pragma_first_step(step);
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a + 1, a.next())));
+            });
+            ctx.expose(a, crate::ast::ExposeOffset::First);
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let source = halo2_source("Counter", &compiled);
+        if let Err(err) = syn::parse_file(&source) {
+            panic!("generated source does not parse: {}\n{}", err, source);
+        }
+
+        assert!(source.contains("impl<F: PrimeField> Circuit<F> for Counter<F> {"));
+        assert!(source.contains("meta.enable_equality(advice[0]);"));
+        assert!(source.contains("meta.query_advice(advice[0], Rotation(1))"));
+        assert!(source.contains("F::from(1)"));
+        assert!(source.contains("/// - 0: q_enable"));
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt;
+
+use crate::{ast::ExposeOffset, ir::Circuit, wit_gen::TraceWitness};
+
+/// An equality constraint between an advice cell and a row of the instance column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyConstraint {
+    pub column: String,
+    pub offset: ExposeOffset,
+    /// Row of the advice cell, `None` when the exposed step is not in the witness.
+    pub row: Option<usize>,
+    pub instance_row: usize,
+}
+
+/// The equality constraints that `ChiquitoHalo2` adds to the permutation argument. The only
+/// copies are from the exposed signals to the instance column, so a permutation failure is either
+/// a wrong public input or an exposed cell in an unexpected row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyReport {
+    /// Advice columns with equality enabled, in the permutation argument.
+    pub equality_columns: Vec<String>,
+    pub constraints: Vec<CopyConstraint>,
+}
+
+impl CopyReport {
+    /// The equality constraints of `circuit`, with the rows of the cells in `witness` when given.
+    pub fn new<F, TraceArgs, StepArgs>(
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        witness: Option<&TraceWitness<F>>,
+    ) -> Self {
+        let step_offsets: Option<Vec<usize>> = witness.map(|witness| {
+            let mut offset = 0;
+            witness
+                .step_instances
+                .iter()
+                .map(|instance| {
+                    let step_offset = offset;
+                    let step = circuit
+                        .step_types
+                        .get(&instance.step_type_uuid)
+                        .expect("step type not found");
+                    offset += circuit.placement.step_height(step) as usize;
+
+                    step_offset
+                })
+                .collect()
+        });
+
+        let mut equality_columns: Vec<String> = Vec::new();
+        for (column, _, _) in circuit.exposed.iter() {
+            if !equality_columns.contains(&column.annotation) {
+                equality_columns.push(column.annotation.clone());
+            }
+        }
+
+        let constraints = circuit
+            .exposed
+            .iter()
+            .enumerate()
+            .map(
+                |(instance_row, (column, rotation, offset))| CopyConstraint {
+                    column: column.annotation.clone(),
+                    offset: *offset,
+                    row: step_offsets
+                        .as_ref()
+                        .and_then(|offsets| cell_row(offsets, *rotation, offset)),
+                    instance_row,
+                },
+            )
+            .collect();
+
+        Self {
+            equality_columns,
+            constraints,
+        }
+    }
+
+    /// The constraints as a graphviz graph, with an edge from each advice cell to its instance
+    /// row.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph copies {\n    rankdir=LR;\n");
+
+        for constraint in self.constraints.iter() {
+            dot.push_str(&format!(
+                "    \"{}\" -> \"instance[{}]\";\n",
+                constraint.cell(),
+                constraint.instance_row
+            ));
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+impl CopyConstraint {
+    fn cell(&self) -> String {
+        match self.row {
+            Some(row) => format!("{}[{}]", self.column, row),
+            None => format!("{}[{:?}]", self.column, self.offset),
+        }
+    }
+}
+
+fn cell_row(step_offsets: &[usize], rotation: i32, offset: &ExposeOffset) -> Option<usize> {
+    let step_offset = match offset {
+        ExposeOffset::First => step_offsets.first(),
+        ExposeOffset::Last => step_offsets.last(),
+        ExposeOffset::Step(step) => step_offsets.get(*step),
+    }?;
+
+    Some((*step_offset as i32 + rotation) as usize)
+}
+
+/// A row of the instance column, with the advice cell that is copied to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstanceRow<F> {
+    pub instance_row: usize,
+    pub column: String,
+    pub row: usize,
+    /// Value of the advice cell, `None` when the witness does not assign it.
+    pub value: Option<F>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstanceLayoutError<F> {
+    /// The number of public inputs is not the number of exposed signals.
+    Length { expected: usize, found: usize },
+    /// The advice cell copied to the instance row does not hold the public input of the row.
+    Misaligned {
+        instance_row: usize,
+        column: String,
+        row: usize,
+        expected: F,
+        found: Option<F>,
+    },
+}
+
+impl<F: fmt::Debug> fmt::Display for InstanceLayoutError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceLayoutError::Length { expected, found } => write!(
+                f,
+                "{} public inputs for {} exposed signals",
+                found, expected
+            ),
+            InstanceLayoutError::Misaligned {
+                instance_row,
+                column,
+                row,
+                expected,
+                found,
+            } => write!(
+                f,
+                "instance[{}] is copied from {}[{}] holding {:?}, expected {:?}",
+                instance_row, column, row, found, expected
+            ),
+        }
+    }
+}
+
+impl<F: fmt::Debug> std::error::Error for InstanceLayoutError<F> {}
+
+impl fmt::Display for CopyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "equality columns: {}", self.equality_columns.join(", "))?;
+        for constraint in self.constraints.iter() {
+            writeln!(
+                f,
+                "{} == instance[{}]",
+                constraint.cell(),
+                constraint.instance_row
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_copy_report() {
+        let sc = circuit::<Fr, (), u64, _>("copies", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, v.field());
+                })
+            });
+            ctx.expose(a, ExposeOffset::First);
+            ctx.expose(b, ExposeOffset::Last);
+            ctx.trace(move |ctx, _| {
+                for i in 0..3 {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let report = CopyReport::new(&compiled, Some(&witness));
+        assert_eq!(report.equality_columns.len(), 2);
+        assert_eq!(report.constraints[0].row, Some(0));
+        assert_eq!(report.constraints[1].row, Some(2));
+        assert_eq!(report.constraints[1].instance_row, 1);
+        assert!(report.to_dot().contains("[2]\" -> \"instance[1]\""));
+
+        let unplaced = CopyReport::new(&compiled, None);
+        assert_eq!(unplaced.constraints[1].row, None);
+        assert!(unplaced.to_string().contains("[Last] == instance[1]"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::wit_gen::TraceWitness;
+
+use super::ChiquitoHalo2;
+
+/// A witness that does not fit in the usable rows of a circuit of `2^k` rows, see
+/// `ChiquitoHalo2::check_rows`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowsError {
+    pub k: u32,
+    /// Rows left for the assignments once halo2 reserves the rows of the blinding factors.
+    pub usable_rows: usize,
+    /// Rows of the steps of the witness, laid out one after the other.
+    pub advice_rows: usize,
+    /// Rows of the fixed assignments, including the selectors enabled up to the height of the
+    /// witness.
+    pub fixed_rows: usize,
+    pub steps: usize,
+    /// Steps of the witness, from the first, whose rows fit in the usable rows.
+    pub steps_that_fit: usize,
+}
+
+impl RowsError {
+    pub fn rows(&self) -> usize {
+        self.advice_rows.max(self.fixed_rows)
+    }
+}
+
+impl fmt::Display for RowsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the witness needs {} rows ({} advice rows for {} steps, {} fixed rows), but only {} \
+             of the 2^{} rows are usable after the blinding rows: {} of the steps fit",
+            self.rows(),
+            self.advice_rows,
+            self.steps,
+            self.fixed_rows,
+            self.usable_rows,
+            self.k,
+            self.steps_that_fit
+        )
+    }
+}
+
+impl std::error::Error for RowsError {}
+
+impl<F: Field + From<u64> + Hash, TraceArgs, StepArgs: Clone>
+    ChiquitoHalo2<F, TraceArgs, StepArgs>
+{
+    /// Rows of a circuit of `2^k` rows that the assignments can use, the last rows are reserved
+    /// by halo2 for the blinding factors of the constraint system set up by `configure`.
+    pub fn usable_rows(&self, k: u32) -> usize {
+        let blinding_factors = self
+            .blinding_factors
+            .expect("the circuit must be configured to know its blinding factors");
+
+        (1usize << k).saturating_sub(blinding_factors + 1)
+    }
+
+    /// Checks that `witness` fits in the usable rows of a circuit of `2^k` rows before it is
+    /// synthesized, where halo2 would fail with `NotEnoughRowsAvailable` instead.
+    pub fn check_rows(&self, k: u32, witness: &TraceWitness<F>) -> Result<(), RowsError> {
+        let usable_rows = self.usable_rows(k);
+
+        let mut advice_rows = 0;
+        let mut steps_that_fit = 0;
+        for instance in witness.step_instances.iter() {
+            let step = self
+                .circuit
+                .step_types
+                .get(&instance.step_type_uuid)
+                .expect("step type not found");
+            advice_rows += self.circuit.placement.step_height(step) as usize;
+
+            if advice_rows <= usable_rows {
+                steps_that_fit += 1;
+            }
+        }
+
+        let fixed_rows = self
+            .synthesize_fixed()
+            .iter()
+            .map(|(_, offset, _)| offset + 1)
+            .chain([witness.height])
+            .max()
+            .unwrap_or_default();
+
+        if advice_rows <= usable_rows && fixed_rows <= usable_rows {
+            return Ok(());
+        }
+
+        Err(RowsError {
+            k,
+            usable_rows,
+            advice_rows,
+            fixed_rows,
+            steps: witness.step_instances.len(),
+            steps_that_fit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{halo2curves::bn256::Fr, plonk::ConstraintSystem};
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+    };
+
+    #[test]
+    fn test_check_rows() {
+        let sc = circuit::<Fr, u64, u64, _>("rows", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut chiquito = ChiquitoHalo2::new(compiled);
+        let mut meta = ConstraintSystem::<Fr>::default();
+        chiquito.configure(&mut meta);
+
+        let usable_rows = chiquito.usable_rows(5);
+        assert_eq!(usable_rows, 32 - meta.blinding_factors() - 1);
+
+        let fits = chiquito.witness(usable_rows as u64).unwrap();
+        assert_eq!(chiquito.check_rows(5, &fits), Ok(()));
+
+        let too_long = chiquito.witness(40).unwrap();
+        let err = chiquito.check_rows(5, &too_long).unwrap_err();
+        assert_eq!(err.steps, 40);
+        assert_eq!(err.steps_that_fit, usable_rows);
+        assert_eq!(err.advice_rows, 40);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::query::Queriable,
+    compiler::{AssignmentError, FixedGenContext},
+    wit_gen::public_inputs::{
+        decode_public_inputs, encode_public_inputs, PublicInputError, WORD_SIZE,
+    },
+};
+
+#[derive(Debug)]
+pub enum TableFileError {
+    Io(io::Error),
+    /// The file does not have a whole number of rows.
+    Length {
+        bytes: usize,
+        row_size: usize,
+    },
+    Value {
+        row: usize,
+        err: PublicInputError,
+    },
+    Assignment {
+        row: usize,
+        err: AssignmentError,
+    },
+}
+
+impl fmt::Display for TableFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableFileError::Io(err) => write!(f, "cannot read table file: {}", err),
+            TableFileError::Length { bytes, row_size } => write!(
+                f,
+                "table file has {} bytes, not a multiple of its rows of {} bytes",
+                bytes, row_size
+            ),
+            TableFileError::Value { row, err } => {
+                write!(f, "invalid value in row {} of table file: {}", row, err)
+            }
+            TableFileError::Assignment { row, err } => {
+                write!(f, "cannot assign row {} of table file: {}", row, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TableFileError {}
+
+/// Assigns the rows of a lookup table stored in a binary file to the fixed `columns`, the first
+/// row at `offset`, and returns the number of rows. Call it from the `fixed_gen` of the circuit,
+/// so that large tables are read during synthesis instead of being generated or kept in the
+/// circuit. The file is the rows one after the other, each the values of the columns in order as
+/// `encode_public_inputs` words, see `write_table_file`.
+///
+/// With the `mmap` feature the file is memory mapped, otherwise it is read a row at a time. The
+/// file must not be modified while it is loaded.
+pub fn load_table_file<F: PrimeField, P: AsRef<Path>>(
+    ctx: &mut dyn FixedGenContext<F>,
+    path: P,
+    columns: &[Queriable<F>],
+    offset: usize,
+) -> Result<usize, TableFileError> {
+    if columns.is_empty() {
+        panic!("table file loaded into no columns");
+    }
+
+    let row_size = columns.len() * WORD_SIZE;
+    let file = File::open(path).map_err(TableFileError::Io)?;
+    let bytes = file.metadata().map_err(TableFileError::Io)?.len() as usize;
+    if bytes % row_size != 0 {
+        return Err(TableFileError::Length { bytes, row_size });
+    }
+    let rows = bytes / row_size;
+
+    #[cfg(feature = "mmap")]
+    {
+        // SAFETY: the map is only read during this call, and the file must not be modified
+        // meanwhile, as documented.
+        let map = unsafe { memmap2::Mmap::map(&file) }.map_err(TableFileError::Io)?;
+        for (row, word) in map.chunks_exact(row_size).take(rows).enumerate() {
+            assign_row(ctx, columns, offset, row, word)?;
+        }
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    {
+        use std::io::{BufReader, Read};
+
+        let mut reader = BufReader::new(file);
+        let mut words = vec![0; row_size];
+        for row in 0..rows {
+            reader.read_exact(&mut words).map_err(TableFileError::Io)?;
+            assign_row(ctx, columns, offset, row, &words)?;
+        }
+    }
+
+    Ok(rows)
+}
+
+fn assign_row<F: PrimeField>(
+    ctx: &mut dyn FixedGenContext<F>,
+    columns: &[Queriable<F>],
+    offset: usize,
+    row: usize,
+    words: &[u8],
+) -> Result<(), TableFileError> {
+    let values: Vec<F> =
+        decode_public_inputs(words).map_err(|err| TableFileError::Value { row, err })?;
+
+    for (column, value) in columns.iter().zip(values) {
+        ctx.try_assign(offset + row, *column, value)
+            .map_err(|err| TableFileError::Assignment { row, err })?;
+    }
+
+    Ok(())
+}
+
+/// Writes the rows of a lookup table in the format of `load_table_file`, for example to
+/// precompute it once. All the rows must have the same number of values.
+pub fn write_table_file<F: PrimeField, P: AsRef<Path>>(path: P, rows: &[Vec<F>]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    for row in rows.iter() {
+        if row.len() != rows[0].len() {
+            panic!(
+                "table rows have {} and {} values, but must have the same number",
+                rows[0].len(),
+                row.len()
+            );
+        }
+
+        writer.write_all(&encode_public_inputs(row))?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{halo2curves::bn256::Fr, plonk::ConstraintSystem};
+
+    use super::*;
+    use crate::{
+        ast::ImportedHalo2Fixed,
+        backend::halo2::chiquito2Halo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+    };
+
+    #[test]
+    fn test_table_file() {
+        let path = std::env::temp_dir().join(format!("chiquito-table-{}.bin", std::process::id()));
+        let rows: Vec<Vec<Fr>> = (0..100)
+            .map(|i| vec![Fr::from(i), Fr::from(i * i)])
+            .collect();
+        write_table_file(&path, &rows).unwrap();
+
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let input = meta.fixed_column();
+        let output = meta.fixed_column();
+
+        let table_path = path.clone();
+        let sc = circuit::<Fr, (), (), _>("squares", |ctx| {
+            let input = ctx.import_halo2_fixed("input", input);
+            let output = ctx.import_halo2_fixed("output", output);
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |_| {});
+            let table_path = table_path.clone();
+            ctx.fixed_gen(move |ctx| {
+                let rows = load_table_file(ctx, &table_path, &[input, output], 2).unwrap();
+                assert_eq!(rows, 100);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut circuit = chiquito2Halo2(compiled);
+        circuit.configure(&mut meta);
+
+        let assignments = circuit.synthesize_fixed();
+        assert_eq!(assignments.len(), 200);
+        assert_eq!(assignments[0].0, input);
+        assert_eq!(assignments[0].1, 2);
+        assert_eq!(assignments[199].0, output);
+        assert_eq!(assignments[199].1, 101);
+
+        std::fs::write(&path, [0; WORD_SIZE * 3]).unwrap();
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let columns = [
+            Queriable::Halo2FixedQuery(
+                ImportedHalo2Fixed::new(meta.fixed_column(), "a".to_string()),
+                0,
+            ),
+            Queriable::Halo2FixedQuery(
+                ImportedHalo2Fixed::new(meta.fixed_column(), "b".to_string()),
+                0,
+            ),
+        ];
+        assert!(matches!(
+            load_table_file(&mut Recorder, &path, &columns, 0),
+            Err(TableFileError::Length {
+                bytes: 96,
+                row_size: 64
+            })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    struct Recorder;
+
+    impl FixedGenContext<Fr> for Recorder {
+        fn assign(&mut self, _: usize, _: Queriable<Fr>, _: Fr) {}
+    }
+}
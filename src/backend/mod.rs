@@ -1 +1,28 @@
 pub mod halo2;
+pub mod proof;
+pub mod workspace;
+
+/// Features of the constraint system of a backend. The compiler rejects circuits that use a
+/// feature the backend cannot express, instead of failing when the backend builds the circuit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackendCapabilities {
+    /// Maximum degree of a polynomial constraint, or `None` when it is not bounded.
+    pub max_degree: Option<usize>,
+    /// Signals in phases after the first, which can depend on challenges.
+    pub challenges: bool,
+    /// Lookups into tables of advice columns, assigned by the witness.
+    pub dynamic_lookups: bool,
+    pub shuffles: bool,
+}
+
+impl Default for BackendCapabilities {
+    /// No restriction, every feature of the compiler is supported.
+    fn default() -> Self {
+        Self {
+            max_degree: None,
+            challenges: true,
+            dynamic_lookups: true,
+            shuffles: true,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+
+use crate::{
+    ir::Circuit,
+    wit_gen::{public_inputs::LAYOUT_VERSION, replay::fingerprint},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    BackendMismatch { proof: String, expected: String },
+    CircuitMismatch { proof: String, expected: String },
+    LayoutVersionMismatch { proof: u32, expected: u32 },
+    Malformed,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::BackendMismatch { proof, expected } => {
+                write!(f, "proof is for backend {}, expected {}", proof, expected)
+            }
+            ProofError::CircuitMismatch { proof, expected } => {
+                write!(f, "proof is for circuit {}, expected {}", proof, expected)
+            }
+            ProofError::LayoutVersionMismatch { proof, expected } => write!(
+                f,
+                "proof uses public input layout version {}, expected {}",
+                proof, expected
+            ),
+            ProofError::Malformed => write!(f, "malformed proof"),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+/// A proof together with what is needed to know whether a verifier can check it: the backend
+/// that produced it, the fingerprint of the compiled circuit and the version of the public input
+/// layout of `encode_public_inputs`. Serializable with serde when the `serde` feature is enabled,
+/// and with `to_bytes` otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Proof {
+    pub backend: String,
+    pub circuit_fingerprint: String,
+    pub layout_version: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Proof {
+    pub fn new<F: fmt::Debug, TraceArgs, StepArgs>(
+        backend: &str,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        bytes: Vec<u8>,
+    ) -> Self {
+        Self {
+            backend: backend.to_string(),
+            circuit_fingerprint: fingerprint(circuit),
+            layout_version: LAYOUT_VERSION,
+            bytes,
+        }
+    }
+
+    /// Checks that the proof was produced by `backend` for `circuit`, with the current public
+    /// input layout, before attempting to verify it.
+    pub fn check_compatible<F: fmt::Debug, TraceArgs, StepArgs>(
+        &self,
+        backend: &str,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+    ) -> Result<(), ProofError> {
+        if self.backend != backend {
+            return Err(ProofError::BackendMismatch {
+                proof: self.backend.clone(),
+                expected: backend.to_string(),
+            });
+        }
+
+        let expected = fingerprint(circuit);
+        if self.circuit_fingerprint != expected {
+            return Err(ProofError::CircuitMismatch {
+                proof: self.circuit_fingerprint.clone(),
+                expected,
+            });
+        }
+
+        if self.layout_version != LAYOUT_VERSION {
+            return Err(ProofError::LayoutVersionMismatch {
+                proof: self.layout_version,
+                expected: LAYOUT_VERSION,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the proof as length-prefixed fields, all integers little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        for field in [self.backend.as_bytes(), self.circuit_fingerprint.as_bytes()] {
+            out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            out.extend_from_slice(field);
+        }
+        out.extend_from_slice(&self.layout_version.to_le_bytes());
+        out.extend_from_slice(&(self.bytes.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.bytes);
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofError> {
+        let mut rest = bytes;
+
+        let backend = take_string(&mut rest)?;
+        let circuit_fingerprint = take_string(&mut rest)?;
+        let layout_version = take_u32(&mut rest)?;
+        let len = take_u32(&mut rest)? as usize;
+        let proof_bytes = take(&mut rest, len)?.to_vec();
+
+        if !rest.is_empty() {
+            return Err(ProofError::Malformed);
+        }
+
+        Ok(Self {
+            backend,
+            circuit_fingerprint,
+            layout_version,
+            bytes: proof_bytes,
+        })
+    }
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], ProofError> {
+    if rest.len() < len {
+        return Err(ProofError::Malformed);
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+
+    Ok(head)
+}
+
+fn take_u32(rest: &mut &[u8]) -> Result<u32, ProofError> {
+    Ok(u32::from_le_bytes(take(rest, 4)?.try_into().unwrap()))
+}
+
+fn take_string(rest: &mut &[u8]) -> Result<String, ProofError> {
+    let len = take_u32(rest)? as usize;
+
+    String::from_utf8(take(rest, len)?.to_vec()).map_err(|_| ProofError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_proof_compatibility() {
+        let define = |offset: u64| {
+            let sc = circuit::<Fr, (), (), _>("proof", |ctx| {
+                let a = ctx.forward("a");
+                let step = ctx.step_type("step");
+
+                ctx.step_type_def(step, |ctx| {
+                    ctx.setup(move |ctx| ctx.transition(eq(a + offset, a.next())));
+                });
+            });
+
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc)
+        };
+
+        let compiled = define(1);
+        let proof = Proof::new("halo2", &compiled, vec![1, 2, 3]);
+
+        let decoded = Proof::from_bytes(&proof.to_bytes()).expect("should decode");
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.check_compatible("halo2", &define(1)), Ok(()));
+
+        assert!(matches!(
+            decoded.check_compatible("plonky2", &compiled),
+            Err(ProofError::BackendMismatch { .. })
+        ));
+        assert!(matches!(
+            decoded.check_compatible("halo2", &define(2)),
+            Err(ProofError::CircuitMismatch { .. })
+        ));
+        assert_eq!(
+            Proof::from_bytes(&proof.to_bytes()[1..]),
+            Err(ProofError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,755 @@
+use std::{any::Any, cell::RefCell, collections::HashMap, fmt, hash::Hash, rc::Rc};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::Circuit as astCircuit,
+    compiler::{cell_manager::CellManager, step_selector::StepSelectorBuilder, Compiler},
+    ir::{Circuit, ColumnType},
+    metrics,
+    wit_gen::{
+        public_inputs::{encode_public_inputs, keccak256},
+        GenericTraceContext, TraceOptions, TraceWitness,
+    },
+};
+
+use super::proof::{Proof, ProofError};
+
+/// A backend that creates and checks proofs for the circuits of a `Workspace`. `k` is the same
+/// for every circuit of the workspace, so that they share the parameters, like the SRS, of size
+/// `2^k`.
+pub trait ProvingBackend<F> {
+    /// Name of the backend, recorded in the proofs.
+    fn name(&self) -> String;
+
+    /// Smallest `k` such that a circuit of `rows` rows fits in `2^k` rows.
+    fn min_k(&self, rows: usize) -> u32 {
+        rows.max(1).next_power_of_two().trailing_zeros()
+    }
+
+    /// Commitment to the values of a shared table for parameters of size `2^k`. The default is a
+    /// keccak256 digest of the values, enough to check that two circuits use the same table, a
+    /// backend that commits to the fixed columns should commit to the values instead.
+    fn commit_table(&self, k: u32, values: &[F]) -> Vec<u8>
+    where
+        F: PrimeField,
+    {
+        let mut input = k.to_be_bytes().to_vec();
+        input.extend(encode_public_inputs(values));
+
+        keccak256(&input).to_vec()
+    }
+
+    /// Proves `witness`. The fixed columns of `circuit` bound to the shared `tables` are already
+    /// committed to, so the backend can leave them out of the verifying key of the circuit.
+    fn prove<TraceArgs, StepArgs: Clone>(
+        &self,
+        k: u32,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        witness: TraceWitness<F>,
+        public_inputs: &[F],
+        tables: &[SharedTable<F>],
+    ) -> Result<Vec<u8>, String>;
+
+    fn verify<TraceArgs, StepArgs: Clone>(
+        &self,
+        k: u32,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        public_inputs: &[F],
+        tables: &[SharedTable<F>],
+        proof: &[u8],
+    ) -> Result<bool, String>;
+}
+
+/// A fixed table generated once and committed once for all the circuits of a `Workspace` that
+/// use it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedTable<F> {
+    pub name: String,
+    /// Annotation of the fixed column of the circuit that holds the table.
+    pub column: String,
+    /// The uuid of the column, resolved when the circuit is registered, so the backend does not
+    /// have to look the column up by its annotation.
+    pub column_uuid: u32,
+    pub values: Rc<Vec<F>>,
+    pub commitment: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkspaceError {
+    UnknownCircuit(String),
+    DuplicateCircuit(String),
+    UnknownTable(String),
+    DuplicateTable(String),
+    /// A column bound to a shared table is not a fixed column of the circuit.
+    UnknownColumn {
+        circuit: String,
+        column: String,
+    },
+    /// Several fixed columns of the circuit have the annotation of a column bound to a shared
+    /// table, for example two fixed tables of the same name.
+    AmbiguousColumn {
+        circuit: String,
+        column: String,
+        matches: usize,
+    },
+    /// The trace arguments are not of the type of the circuit.
+    WrongArgs(String),
+    NoTrace(String),
+    Proof(ProofError),
+    Backend(String),
+}
+
+impl fmt::Display for WorkspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkspaceError::UnknownCircuit(name) => write!(f, "unknown circuit {}", name),
+            WorkspaceError::DuplicateCircuit(name) => {
+                write!(f, "circuit {} is already registered", name)
+            }
+            WorkspaceError::WrongArgs(name) => {
+                write!(f, "wrong type of trace arguments for circuit {}", name)
+            }
+            WorkspaceError::UnknownTable(name) => write!(f, "unknown table {}", name),
+            WorkspaceError::DuplicateTable(name) => {
+                write!(f, "table {} is already added", name)
+            }
+            WorkspaceError::UnknownColumn { circuit, column } => {
+                write!(f, "circuit {} has no fixed column {}", circuit, column)
+            }
+            WorkspaceError::AmbiguousColumn {
+                circuit,
+                column,
+                matches,
+            } => write!(
+                f,
+                "circuit {} has {} fixed columns {}, the shared table cannot be bound to one",
+                circuit, matches, column
+            ),
+            WorkspaceError::NoTrace(name) => write!(f, "circuit {} has no trace", name),
+            WorkspaceError::Proof(err) => write!(f, "{}", err),
+            WorkspaceError::Backend(message) => write!(f, "backend error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for WorkspaceError {}
+
+impl From<ProofError> for WorkspaceError {
+    fn from(err: ProofError) -> Self {
+        WorkspaceError::Proof(err)
+    }
+}
+
+type ProveFn<F> = dyn Fn(
+    Box<dyn Any>,
+    u32,
+    &[SharedTable<F>],
+    TraceOptions,
+) -> Result<(Proof, Vec<F>), WorkspaceError>;
+type VerifyFn<F> = dyn Fn(&Proof, &[F], u32, &[SharedTable<F>]) -> Result<bool, WorkspaceError>;
+type CommitFn<F> = dyn Fn(u32, &[F]) -> Vec<u8>;
+
+struct Entry<F> {
+    /// Name of the backend of the circuit, which commits to its shared tables.
+    backend: String,
+    rows: usize,
+    min_k: u32,
+    /// Shared tables of the circuit, by fixed column annotation and uuid, and table name.
+    tables: Vec<(String, u32, String)>,
+    commit: Box<CommitFn<F>>,
+    prove: Box<ProveFn<F>>,
+    verify: Box<VerifyFn<F>>,
+}
+
+/// Circuits of a project registered by name, each compiled with its own compiler and proved with
+/// its own backend instance, behind a single prove and verify interface. All the circuits share
+/// `k`, the smallest that fits every circuit, so a single set of parameters serves the workspace.
+///
+/// Big fixed tables can be added once with `add_table` and bound to a fixed column of each
+/// circuit that uses them, they are then generated and committed to only once.
+pub struct Workspace<F, B> {
+    entries: HashMap<String, Entry<F>>,
+    names: Vec<String>,
+    tables: HashMap<String, Rc<Vec<F>>>,
+    /// Commitments of the tables, by backend name, table name and `k`.
+    commitments: RefCell<HashMap<(String, String, u32), Vec<u8>>>,
+    trace_options: TraceOptions,
+    _backend: std::marker::PhantomData<B>,
+}
+
+impl<F, B> Default for Workspace<F, B> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            names: Vec::new(),
+            tables: HashMap::new(),
+            commitments: RefCell::new(HashMap::new()),
+            trace_options: TraceOptions::default(),
+            _backend: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<F: PrimeField + Hash + 'static, B: ProvingBackend<F> + 'static> Workspace<F, B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generates the witnesses of `prove` with the checks of `options`, like the
+    /// `check_constraints` and `strict_assignments` options of `ChiquitoHalo2`.
+    pub fn with_trace_options(mut self, options: TraceOptions) -> Self {
+        self.trace_options = options;
+
+        self
+    }
+
+    /// Generates the values of the shared table `name`. The fixed generators of the circuits that
+    /// use it get the values with `table`.
+    pub fn add_table<G: FnOnce() -> Vec<F>>(
+        &mut self,
+        name: &str,
+        gen: G,
+    ) -> Result<Rc<Vec<F>>, WorkspaceError> {
+        if self.tables.contains_key(name) {
+            return Err(WorkspaceError::DuplicateTable(name.to_string()));
+        }
+
+        let values = Rc::new(gen());
+        self.tables.insert(name.to_string(), Rc::clone(&values));
+
+        Ok(values)
+    }
+
+    /// The values of the shared table `name`.
+    pub fn table(&self, name: &str) -> Option<Rc<Vec<F>>> {
+        self.tables.get(name).cloned()
+    }
+
+    /// Compiles `sc` with `compiler` and registers it as `name`, to be proved with `backend`.
+    pub fn register<CM, SSB, TraceArgs, StepArgs>(
+        &mut self,
+        name: &str,
+        compiler: &Compiler<CM, SSB>,
+        sc: astCircuit<F, TraceArgs, StepArgs>,
+        backend: B,
+    ) -> Result<(), WorkspaceError>
+    where
+        CM: CellManager,
+        SSB: StepSelectorBuilder,
+        TraceArgs: 'static,
+        StepArgs: Clone + 'static,
+    {
+        self.register_with_tables(name, compiler, sc, backend, &[])
+    }
+
+    /// Like `register`, binding the fixed columns of the circuit to shared tables, as pairs of
+    /// column annotation and table name. The fixed generator of the circuit still assigns the
+    /// columns, with the values of `table`. Each annotation is resolved in the compiled circuit
+    /// when it is registered, and must be the annotation of exactly one of its fixed columns, so
+    /// that circuits with tables of the same name are not bound to the wrong column.
+    pub fn register_with_tables<CM, SSB, TraceArgs, StepArgs>(
+        &mut self,
+        name: &str,
+        compiler: &Compiler<CM, SSB>,
+        sc: astCircuit<F, TraceArgs, StepArgs>,
+        backend: B,
+        tables: &[(&str, &str)],
+    ) -> Result<(), WorkspaceError>
+    where
+        CM: CellManager,
+        SSB: StepSelectorBuilder,
+        TraceArgs: 'static,
+        StepArgs: Clone + 'static,
+    {
+        if self.entries.contains_key(name) {
+            return Err(WorkspaceError::DuplicateCircuit(name.to_string()));
+        }
+        if let Some((_, table)) = tables
+            .iter()
+            .find(|(_, table)| !self.tables.contains_key(*table))
+        {
+            return Err(WorkspaceError::UnknownTable(table.to_string()));
+        }
+
+        let compiled = Rc::new(compiler.compile(&sc));
+        let bound_tables = tables
+            .iter()
+            .map(|(column, table)| {
+                let uuid = resolve_fixed_column(name, &compiled, column)?;
+
+                Ok((column.to_string(), uuid, table.to_string()))
+            })
+            .collect::<Result<Vec<_>, WorkspaceError>>()?;
+        let backend = Rc::new(backend);
+
+        let step_height = compiled
+            .step_types
+            .values()
+            .map(|step| compiled.placement.step_height(step) as usize)
+            .max()
+            .unwrap_or(1);
+        let rows = compiled.num_steps * step_height;
+
+        let prove = {
+            let name = name.to_string();
+            let compiled = Rc::clone(&compiled);
+            let backend = Rc::clone(&backend);
+
+            move |args: Box<dyn Any>, k: u32, tables: &[SharedTable<F>], options: TraceOptions| {
+                let args = args
+                    .downcast::<TraceArgs>()
+                    .map_err(|_| WorkspaceError::WrongArgs(name.clone()))?;
+                let trace = compiled
+                    .trace
+                    .as_ref()
+                    .ok_or_else(|| WorkspaceError::NoTrace(name.clone()))?;
+
+                let timer = metrics::Timer::start(metrics::WITNESS_SECONDS);
+                let mut ctx = GenericTraceContext::for_circuit(&compiled, options);
+                trace(&mut ctx, *args);
+                let witness = ctx.get_witness();
+                timer.stop();
+                metrics::increment_counter(
+                    metrics::STEPS_GENERATED,
+                    witness.step_instances.len() as u64,
+                );
+
+                let public_inputs = witness.public_values.clone();
+                let timer = metrics::Timer::start(metrics::PROVE_SECONDS);
+                let bytes = backend
+                    .prove(k, &compiled, witness, &public_inputs, tables)
+                    .map_err(WorkspaceError::Backend)?;
+                timer.stop();
+
+                Ok((Proof::new(&backend.name(), &compiled, bytes), public_inputs))
+            }
+        };
+
+        let verify = {
+            let backend = Rc::clone(&backend);
+
+            move |proof: &Proof, public_inputs: &[F], k: u32, tables: &[SharedTable<F>]| {
+                proof.check_compatible(&backend.name(), &compiled)?;
+
+                backend
+                    .verify(k, &compiled, public_inputs, tables, &proof.bytes)
+                    .map_err(WorkspaceError::Backend)
+            }
+        };
+
+        self.entries.insert(
+            name.to_string(),
+            Entry {
+                backend: backend.name(),
+                rows,
+                min_k: backend.min_k(rows),
+                tables: bound_tables,
+                commit: Box::new(move |k, values| backend.commit_table(k, values)),
+                prove: Box::new(prove),
+                verify: Box::new(verify),
+            },
+        );
+        self.names.push(name.to_string());
+
+        Ok(())
+    }
+
+    /// Names of the registered circuits, in registration order.
+    pub fn circuits(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Rows of the circuit `name`, from its `num_steps`.
+    pub fn rows(&self, name: &str) -> Option<usize> {
+        self.entries.get(name).map(|entry| entry.rows)
+    }
+
+    /// The `k` shared by all the circuits.
+    pub fn k(&self) -> u32 {
+        self.entries
+            .values()
+            .map(|entry| entry.min_k)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Generates the witness of the circuit `name` with `args`, which must be of its `TraceArgs`
+    /// type, and proves it. Returns the proof and the public inputs to verify it with.
+    pub fn prove<TraceArgs: 'static>(
+        &self,
+        name: &str,
+        args: TraceArgs,
+    ) -> Result<(Proof, Vec<F>), WorkspaceError> {
+        let entry = self.entry(name)?;
+        let k = self.k();
+
+        (entry.prove)(
+            Box::new(args),
+            k,
+            &self.shared_tables(entry, k),
+            self.trace_options,
+        )
+    }
+
+    /// Verifies a proof of the circuit `name`, after checking that it was produced by the backend
+    /// of the circuit for the same compiled circuit.
+    pub fn verify(
+        &self,
+        name: &str,
+        proof: &Proof,
+        public_inputs: &[F],
+    ) -> Result<bool, WorkspaceError> {
+        let entry = self.entry(name)?;
+        let k = self.k();
+
+        (entry.verify)(proof, public_inputs, k, &self.shared_tables(entry, k))
+    }
+
+    /// Commitment to the shared table `table` at the `k` of the workspace by the backend of the
+    /// circuit `circuit`, computed once for each backend. `None` if there is no such table or
+    /// circuit.
+    pub fn table_commitment(&self, circuit: &str, table: &str) -> Option<Vec<u8>> {
+        let values = self.tables.get(table)?;
+        let entry = self.entries.get(circuit)?;
+
+        Some(self.commitment(entry, table, values, self.k()))
+    }
+
+    fn shared_tables(&self, entry: &Entry<F>, k: u32) -> Vec<SharedTable<F>> {
+        entry
+            .tables
+            .iter()
+            .map(|(column, column_uuid, name)| {
+                let values = &self.tables[name];
+
+                SharedTable {
+                    name: name.clone(),
+                    column: column.clone(),
+                    column_uuid: *column_uuid,
+                    values: Rc::clone(values),
+                    commitment: self.commitment(entry, name, values, k),
+                }
+            })
+            .collect()
+    }
+
+    fn commitment(&self, entry: &Entry<F>, name: &str, values: &[F], k: u32) -> Vec<u8> {
+        self.commitments
+            .borrow_mut()
+            .entry((entry.backend.clone(), name.to_string(), k))
+            .or_insert_with(|| (entry.commit)(k, values))
+            .clone()
+    }
+
+    fn entry(&self, name: &str) -> Result<&Entry<F>, WorkspaceError> {
+        self.entries
+            .get(name)
+            .ok_or_else(|| WorkspaceError::UnknownCircuit(name.to_string()))
+    }
+}
+
+/// The uuid of the only fixed column of `circuit`, registered as `name`, annotated `column`.
+fn resolve_fixed_column<F, TraceArgs, StepArgs>(
+    name: &str,
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    column: &str,
+) -> Result<u32, WorkspaceError> {
+    let matches: Vec<u32> = circuit
+        .columns
+        .iter()
+        .filter(|c| matches!(c.ctype, ColumnType::Fixed | ColumnType::Halo2Fixed))
+        .filter(|c| c.annotation == column)
+        .map(|c| c.uuid())
+        .collect();
+
+    match matches.as_slice() {
+        [uuid] => Ok(*uuid),
+        [] => Err(WorkspaceError::UnknownColumn {
+            circuit: name.to_string(),
+            column: column.to_string(),
+        }),
+        _ => Err(WorkspaceError::AmbiguousColumn {
+            circuit: name.to_string(),
+            column: column.to_string(),
+            matches: matches.len(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::{ExposeOffset, ToField},
+        compiler::{cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder},
+        dsl::{cb::eq, circuit},
+    };
+
+    /// Proves by encoding the public inputs, enough to exercise the workspace. Backends of
+    /// different salts have different names and table commitments.
+    struct EchoBackend(u8);
+
+    impl ProvingBackend<Fr> for EchoBackend {
+        fn name(&self) -> String {
+            format!("echo {}", self.0)
+        }
+
+        fn commit_table(&self, k: u32, values: &[Fr]) -> Vec<u8> {
+            let mut input = vec![self.0, k as u8];
+            input.extend(encode_public_inputs(values));
+
+            keccak256(&input).to_vec()
+        }
+
+        fn prove<TraceArgs, StepArgs: Clone>(
+            &self,
+            k: u32,
+            _circuit: &Circuit<Fr, TraceArgs, StepArgs>,
+            _witness: TraceWitness<Fr>,
+            public_inputs: &[Fr],
+            tables: &[SharedTable<Fr>],
+        ) -> Result<Vec<u8>, String> {
+            Ok(echo(k, public_inputs, tables))
+        }
+
+        fn verify<TraceArgs, StepArgs: Clone>(
+            &self,
+            k: u32,
+            _circuit: &Circuit<Fr, TraceArgs, StepArgs>,
+            public_inputs: &[Fr],
+            tables: &[SharedTable<Fr>],
+            proof: &[u8],
+        ) -> Result<bool, String> {
+            Ok(proof == echo(k, public_inputs, tables))
+        }
+    }
+
+    fn echo(k: u32, public_inputs: &[Fr], tables: &[SharedTable<Fr>]) -> Vec<u8> {
+        let mut bytes = vec![k as u8];
+        bytes.extend(encode_public_inputs(public_inputs));
+        for table in tables {
+            bytes.extend(table.commitment.iter());
+        }
+
+        bytes
+    }
+
+    fn counter(num_steps: usize, increment: u64) -> astCircuit<Fr, u64, u64> {
+        counter_with_tables(num_steps, increment, &[])
+    }
+
+    /// A counter with a fixed table of bytes for each name of `tables`.
+    fn counter_with_tables(
+        num_steps: usize,
+        increment: u64,
+        tables: &[&str],
+    ) -> astCircuit<Fr, u64, u64> {
+        circuit::<Fr, u64, u64, _>("counter", |ctx| {
+            for table in tables {
+                ctx.fixed_table_from_iter(table, (0..256).map(|byte| [Fr::from(byte)]));
+            }
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a + increment, a.next())));
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.pragma_num_steps(num_steps);
+            ctx.expose(a, ExposeOffset::First);
+            ctx.trace(move |ctx, start| {
+                for i in 0..num_steps as u64 {
+                    ctx.add(&step, start + i * increment);
+                }
+            });
+        })
+    }
+
+    #[test]
+    fn test_workspace_trace_context() {
+        let fill = || {
+            circuit::<Fr, u64, u64, _>("fill", |ctx| {
+                let a = ctx.forward("a");
+                let step = ctx.step_type("step");
+
+                ctx.step_type_def(step, |ctx| {
+                    ctx.setup(move |ctx| ctx.constr(eq(a * (a - 1), 0)));
+                    ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+                });
+                ctx.pragma_num_steps(3);
+                ctx.expose(a, ExposeOffset::Last);
+                // the trace fills the steps reported by the context, as with ChiquitoHalo2
+                ctx.trace(move |ctx, last| {
+                    while ctx.remaining() > Some(1) {
+                        ctx.add(&step, 0);
+                    }
+                    ctx.add(&step, last);
+                });
+            })
+        };
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {});
+
+        let mut workspace = Workspace::<Fr, EchoBackend>::new();
+        workspace
+            .register("fill", &compiler, fill(), EchoBackend(0))
+            .unwrap();
+        let (_, public_inputs) = workspace.prove("fill", 1u64).unwrap();
+        assert_eq!(public_inputs, vec![Fr::from(1)]);
+        // the echo backend does not check the constraints
+        assert!(workspace.prove("fill", 2u64).is_ok());
+
+        let mut checked = Workspace::<Fr, EchoBackend>::new().with_trace_options(TraceOptions {
+            check_constraints: true,
+            ..Default::default()
+        });
+        checked
+            .register("fill", &compiler, fill(), EchoBackend(0))
+            .unwrap();
+        assert!(checked.prove("fill", 1u64).is_ok());
+        assert!(crate::ci::catch(|| checked.prove("fill", 2u64)).is_err());
+    }
+
+    #[test]
+    fn test_workspace() {
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {});
+
+        let mut workspace = Workspace::<Fr, EchoBackend>::new();
+        workspace
+            .register("small", &compiler, counter(4, 1), EchoBackend(0))
+            .unwrap();
+        workspace
+            .register("large", &compiler, counter(100, 2), EchoBackend(0))
+            .unwrap();
+
+        assert_eq!(workspace.circuits(), &["small", "large"]);
+        assert_eq!(workspace.k(), 7);
+        assert_eq!(
+            workspace.register("small", &compiler, counter(4, 1), EchoBackend(0)),
+            Err(WorkspaceError::DuplicateCircuit("small".to_string()))
+        );
+
+        let (proof, public_inputs) = workspace.prove("small", 5u64).unwrap();
+        assert_eq!(public_inputs, vec![Fr::from(5)]);
+        assert_eq!(workspace.verify("small", &proof, &public_inputs), Ok(true));
+        assert!(matches!(
+            workspace.verify("large", &proof, &public_inputs),
+            Err(WorkspaceError::Proof(ProofError::CircuitMismatch { .. }))
+        ));
+
+        assert_eq!(
+            workspace.prove("small", 5u32).err(),
+            Some(WorkspaceError::WrongArgs("small".to_string()))
+        );
+        assert_eq!(
+            workspace.prove("missing", 5u64).err(),
+            Some(WorkspaceError::UnknownCircuit("missing".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_shared_tables() {
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {});
+        let generated = std::cell::Cell::new(0);
+
+        let mut workspace = Workspace::<Fr, EchoBackend>::new();
+        let bytes = workspace
+            .add_table("bytes", || {
+                generated.set(generated.get() + 1);
+                (0..256).map(Fr::from).collect()
+            })
+            .unwrap();
+        for (name, increment) in [("a", 1), ("b", 2)] {
+            workspace
+                .register_with_tables(
+                    name,
+                    &compiler,
+                    counter_with_tables(4, increment, &["bytes"]),
+                    EchoBackend(0),
+                    &[("table bytes[0]", "bytes")],
+                )
+                .unwrap();
+        }
+
+        assert_eq!(generated.get(), 1);
+        assert!(Rc::ptr_eq(&bytes, &workspace.table("bytes").unwrap()));
+        assert_eq!(
+            workspace.register_with_tables(
+                "c",
+                &compiler,
+                counter(4, 3),
+                EchoBackend(0),
+                &[("table bytes[0]", "words")],
+            ),
+            Err(WorkspaceError::UnknownTable("words".to_string()))
+        );
+        assert_eq!(
+            workspace.register_with_tables(
+                "c",
+                &compiler,
+                counter(4, 3),
+                EchoBackend(0),
+                &[("table bytes[0]", "bytes")],
+            ),
+            Err(WorkspaceError::UnknownColumn {
+                circuit: "c".to_string(),
+                column: "table bytes[0]".to_string()
+            })
+        );
+        // two tables of the same name, in different parts of the circuit
+        assert_eq!(
+            workspace.register_with_tables(
+                "c",
+                &compiler,
+                counter_with_tables(4, 3, &["bytes", "bytes"]),
+                EchoBackend(0),
+                &[("table bytes[0]", "bytes")],
+            ),
+            Err(WorkspaceError::AmbiguousColumn {
+                circuit: "c".to_string(),
+                column: "table bytes[0]".to_string(),
+                matches: 2
+            })
+        );
+
+        let commitment = workspace.table_commitment("a", "bytes").unwrap();
+        for name in ["a", "b"] {
+            let (proof, public_inputs) = workspace.prove(name, 1u64).unwrap();
+            assert!(proof.bytes.ends_with(&commitment));
+            assert_eq!(workspace.verify(name, &proof, &public_inputs), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_shared_tables_mixed_backends() {
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {});
+
+        let mut workspace = Workspace::<Fr, EchoBackend>::new();
+        workspace
+            .add_table("bytes", || (0..256).map(Fr::from).collect())
+            .unwrap();
+        for (name, salt) in [("a", 0), ("b", 1)] {
+            workspace
+                .register_with_tables(
+                    name,
+                    &compiler,
+                    counter_with_tables(4, 1, &["bytes"]),
+                    EchoBackend(salt),
+                    &[("table bytes[0]", "bytes")],
+                )
+                .unwrap();
+        }
+
+        let first = workspace.table_commitment("a", "bytes").unwrap();
+        let second = workspace.table_commitment("b", "bytes").unwrap();
+        assert_ne!(first, second);
+        for (name, commitment) in [("a", first), ("b", second)] {
+            let (proof, public_inputs) = workspace.prove(name, 1u64).unwrap();
+            assert!(proof.bytes.ends_with(&commitment));
+            assert_eq!(workspace.verify(name, &proof, &public_inputs), Ok(true));
+        }
+        assert_eq!(workspace.table_commitment("c", "bytes"), None);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use halo2_proofs::{
+    circuit::{Layouter, SimpleFloorPlanner},
+    dev::MockProver,
+    halo2curves::bn256::Fr,
+    plonk::{Circuit as halo2Circuit, ConstraintSystem, Error},
+};
+
+use crate::{
+    ast::Circuit as astCircuit,
+    backend::halo2::{chiquito2Halo2, ChiquitoHalo2},
+    compiler::config::CompilerConfig,
+    wit_gen::TraceWitness,
+};
+
+/// Limits checked by `smoke_test_with_budgets`, unlimited when `None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SmokeBudgets {
+    pub max_columns: Option<usize>,
+    pub max_degree: Option<usize>,
+    pub max_rows: Option<usize>,
+}
+
+/// Outcome of a smoke test. The stages run in order and stop at the first that fails, so the
+/// stats of the later stages are `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SmokeReport {
+    pub k: u32,
+    pub compiled: bool,
+    pub columns: Option<usize>,
+    pub max_degree: Option<usize>,
+    pub witness: bool,
+    pub rows: Option<usize>,
+    pub verified: bool,
+    /// Why the test failed: panics of the compiler or of the witness generation, failures of the
+    /// mock prover and exceeded budgets.
+    pub failures: Vec<String>,
+}
+
+impl SmokeReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The report as a JSON object, for CI tools to read.
+    pub fn to_json(&self) -> String {
+        let option = |value: Option<usize>| match value {
+            Some(value) => value.to_string(),
+            None => "null".to_string(),
+        };
+        let failures: Vec<String> = self
+            .failures
+            .iter()
+            .map(|failure| format!("\"{}\"", escape_json(failure)))
+            .collect();
+
+        format!(
+            "{{\"passed\":{},\"k\":{},\"compiled\":{},\"columns\":{},\"max_degree\":{},\"witness\":{},\"rows\":{},\"verified\":{},\"failures\":[{}]}}",
+            self.passed(),
+            self.k,
+            self.compiled,
+            option(self.columns),
+            option(self.max_degree),
+            self.witness,
+            option(self.rows),
+            self.verified,
+            failures.join(",")
+        )
+    }
+}
+
+impl fmt::Display for SmokeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "smoke test {} with k = {}",
+            if self.passed() { "passed" } else { "failed" },
+            self.k
+        )?;
+        if let (Some(columns), Some(max_degree)) = (self.columns, self.max_degree) {
+            writeln!(f, "{} columns, max degree {}", columns, max_degree)?;
+        }
+        if let Some(rows) = self.rows {
+            writeln!(f, "{} rows", rows)?;
+        }
+        for failure in self.failures.iter() {
+            writeln!(f, "FAILED {}", failure)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compiles `sc` with the default configuration, generates the witness of `args`, runs the
+/// `MockProver` with `2^k` rows and reports the outcome, to be called from a CI test:
+///
+/// ```ignore
+/// let report = smoke_test(&fibo_circuit(), (), 7);
+/// assert!(report.passed(), "{}", report);
+/// ```
+pub fn smoke_test<TraceArgs: 'static, StepArgs: Clone + 'static>(
+    sc: &astCircuit<Fr, TraceArgs, StepArgs>,
+    args: TraceArgs,
+    k: u32,
+) -> SmokeReport {
+    smoke_test_with_budgets(sc, args, k, &SmokeBudgets::default())
+}
+
+/// Like `smoke_test`, also failing when the compiled circuit exceeds `budgets`.
+pub fn smoke_test_with_budgets<TraceArgs: 'static, StepArgs: Clone + 'static>(
+    sc: &astCircuit<Fr, TraceArgs, StepArgs>,
+    args: TraceArgs,
+    k: u32,
+    budgets: &SmokeBudgets,
+) -> SmokeReport {
+    let mut report = SmokeReport {
+        k,
+        compiled: false,
+        columns: None,
+        max_degree: None,
+        witness: false,
+        rows: None,
+        verified: false,
+        failures: Vec::new(),
+    };
+
+    let compiled = match catch(|| CompilerConfig::default().compile(sc)) {
+        Ok(compiled) => compiled,
+        Err(message) => {
+            report.failures.push(format!("compilation: {}", message));
+            return report;
+        }
+    };
+    report.compiled = true;
+
+    let columns = compiled.columns.len();
+    let max_degree = compiled.profile(0).max_degree;
+    report.columns = Some(columns);
+    report.max_degree = Some(max_degree);
+    check_budget(&mut report, "columns", columns, budgets.max_columns);
+    check_budget(&mut report, "degree", max_degree, budgets.max_degree);
+
+    let step_heights: HashMap<u32, usize> = compiled
+        .step_types
+        .values()
+        .map(|step| (step.uuid(), compiled.placement.step_height(step) as usize))
+        .collect();
+
+    let mut circuit = chiquito2Halo2(compiled);
+    let witness = match catch(|| circuit.witness(args)) {
+        Ok(Some(witness)) => witness,
+        Ok(None) => {
+            report
+                .failures
+                .push("witness: circuit has no trace".to_string());
+            return report;
+        }
+        Err(message) => {
+            report.failures.push(format!("witness: {}", message));
+            return report;
+        }
+    };
+    report.witness = true;
+
+    let rows = if witness.height > 0 {
+        witness.height
+    } else {
+        witness
+            .step_instances
+            .iter()
+            .map(|instance| step_heights[&instance.step_type_uuid])
+            .sum()
+    };
+    report.rows = Some(rows);
+    check_budget(&mut report, "rows", rows, budgets.max_rows);
+    // the last rows are reserved for the blinding factors, which are only known once the
+    // circuit is configured, the MockProver configures it again in its own constraint system
+    circuit.configure(&mut ConstraintSystem::default());
+    let usable_rows = circuit.usable_rows(k);
+    if rows > usable_rows {
+        report.failures.push(format!(
+            "rows: {} rows do not fit in the {} usable rows of 2^{} rows",
+            rows, usable_rows, k
+        ));
+        return report;
+    }
+
+    let public_inputs = witness.public_inputs(sc);
+    let instance = if public_inputs.is_empty() {
+        Vec::new()
+    } else {
+        vec![public_inputs]
+    };
+
+    PENDING.with(|pending| *pending.borrow_mut() = Some(Box::new(circuit)));
+    let smoke_circuit = SmokeCircuit::<TraceArgs, StepArgs> {
+        witness: RefCell::new(Some(witness)),
+        _args: std::marker::PhantomData,
+    };
+    let result = catch(|| MockProver::<Fr>::run(k, &smoke_circuit, instance));
+    PENDING.with(|pending| pending.borrow_mut().take());
+
+    match result {
+        Ok(Ok(prover)) => match prover.verify_par() {
+            Ok(()) => report.verified = true,
+            Err(failures) => report.failures.extend(
+                failures
+                    .iter()
+                    .map(|failure| format!("mock prover: {}", failure)),
+            ),
+        },
+        Ok(Err(err)) => report.failures.push(format!("mock prover: {:?}", err)),
+        Err(message) => report.failures.push(format!("mock prover: {}", message)),
+    }
+
+    report
+}
+
+fn check_budget(report: &mut SmokeReport, stat: &str, value: usize, budget: Option<usize>) {
+    if let Some(budget) = budget {
+        if value > budget {
+            report
+                .failures
+                .push(format!("budget: {} {} above {}", stat, value, budget));
+        }
+    }
+}
+
+/// Runs `f`, returning the message of its panic if it panics.
+pub(crate) fn catch<T, G: FnOnce() -> T>(f: G) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else {
+            "panic".to_string()
+        }
+    })
+}
+
+pub(crate) fn escape_json(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+thread_local! {
+    /// The circuit that the next `SmokeCircuit::configure` configures. halo2 configures circuits
+    /// without an instance, so the circuit under test is passed through here.
+    static PENDING: RefCell<Option<Box<dyn Any>>> = RefCell::new(None);
+}
+
+struct SmokeCircuit<TraceArgs, StepArgs: Clone> {
+    witness: RefCell<Option<TraceWitness<Fr>>>,
+    _args: std::marker::PhantomData<(TraceArgs, StepArgs)>,
+}
+
+impl<TraceArgs: 'static, StepArgs: Clone + 'static> halo2Circuit<Fr>
+    for SmokeCircuit<TraceArgs, StepArgs>
+{
+    type Config = Rc<ChiquitoHalo2<Fr, TraceArgs, StepArgs>>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        Self {
+            witness: RefCell::new(None),
+            _args: std::marker::PhantomData,
+        }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        let circuit = PENDING
+            .with(|pending| pending.borrow_mut().take())
+            .expect("smoke test circuit configured outside of smoke_test");
+        let mut circuit = circuit
+            .downcast::<ChiquitoHalo2<Fr, TraceArgs, StepArgs>>()
+            .expect("smoke test circuit of another type");
+
+        circuit.configure(meta);
+
+        Rc::new(*circuit)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        mut layouter: impl Layouter<Fr>,
+    ) -> Result<(), Error> {
+        if let Some(witness) = self.witness.borrow_mut().take() {
+            config.synthesize_witness(&mut layouter, witness)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::{ExposeOffset, ToField},
+        dsl::{cb::eq, circuit},
+    };
+
+    fn squares() -> astCircuit<Fr, u64, u64> {
+        circuit::<Fr, u64, u64, _>("squares", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a * a, b)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * v).field());
+                });
+            });
+            ctx.expose(a, ExposeOffset::First);
+            ctx.trace(move |ctx, steps| {
+                if steps > 8 {
+                    panic!("too many steps");
+                }
+                for i in 0..steps {
+                    ctx.add(&step, i);
+                }
+            });
+        })
+    }
+
+    #[test]
+    fn test_smoke_test() {
+        let report = smoke_test(&squares(), 4, 4);
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.rows, Some(4));
+        assert!(report.to_json().starts_with("{\"passed\":true,\"k\":4,"));
+
+        let report = smoke_test_with_budgets(
+            &squares(),
+            4,
+            4,
+            &SmokeBudgets {
+                max_degree: Some(1),
+                ..Default::default()
+            },
+        );
+        assert!(!report.passed());
+        assert!(report.failures[0].starts_with("budget: degree "));
+
+        // 3 rows fit in 2^3 rows, but not in the rows left by the blinding factors
+        let report = smoke_test(&squares(), 3, 3);
+        assert!(report.witness);
+        assert!(!report.verified);
+        assert_eq!(report.failures.len(), 1);
+        assert!(
+            report.failures[0].starts_with("rows: 3 rows do not fit in the "),
+            "{}",
+            report
+        );
+        assert!(smoke_test(&squares(), 3, 4).passed());
+
+        let report = smoke_test(&squares(), 9, 4);
+        assert!(!report.witness);
+        assert_eq!(report.failures, vec!["witness: too many steps".to_string()]);
+    }
+}
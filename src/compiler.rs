@@ -1,5 +1,5 @@
 use core::fmt::Debug;
-use std::{collections::HashMap, rc::Rc};
+use std::{any::Any, collections::HashMap, fmt, ops::Range, rc::Rc, sync::Arc};
 
 use halo2_proofs::{
     arithmetic::Field,
@@ -8,33 +8,251 @@ use halo2_proofs::{
 
 use crate::{
     ast::{
-        query::Queriable, Circuit as astCircuit, Expr, ForwardSignal, ImportedHalo2Advice,
-        ImportedHalo2Fixed, StepType,
+        query::Queriable, Circuit as astCircuit, Constraint, ExposeOffset, Expr, ForwardSignal,
+        ImportedHalo2Advice, ImportedHalo2Fixed, Invariant, StepFixedGen, StepSchedule, StepType,
+        Trace, TransitionConstraint,
     },
-    dsl::StepTypeHandler,
-    ir::{Circuit, Column, ColumnType, Poly, PolyExpr, PolyLookup},
+    backend::BackendCapabilities,
+    dsl::{cb, StepTypeHandler},
+    ir::{Circuit, Column, ColumnType, FixedTable, Poly, PolyExpr, PolyLookup, UnassignedPolicy},
+    metrics,
     util::uuid,
+    wit_gen::{
+        assignments::Assignments,
+        challenge::{ChallengeAssignment, ChallengeValues},
+    },
 };
 
 use self::{
     cell_manager::{CellManager, Placement},
+    degree::reduce_degree,
+    expr_limits::{check_expr_limits, ExprLimits},
     step_selector::{StepSelector, StepSelectorBuilder},
 };
 
 pub mod cell_manager;
+pub mod config;
+pub mod degree;
+pub mod expr_limits;
+pub mod split;
 pub mod step_selector;
 
 pub trait TraceContext<StepArgs> {
     fn add(&mut self, step: &StepTypeHandler, args: StepArgs);
     fn set_height(&mut self, height: usize);
+
+    /// Number of steps added so far, the index of the next step added.
+    fn steps(&self) -> usize;
+
+    /// Number of steps the circuit is planned for, see `pragma_num_steps`, when the context
+    /// knows it.
+    fn num_steps(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<'a, StepArgs> dyn TraceContext<StepArgs> + 'a {
+    /// Index of the next step added, for example to tell which step a trace is at.
+    pub fn current_offset(&self) -> usize {
+        self.steps()
+    }
+
+    /// Steps left to add before the trace has `num_steps` steps, for example to know when to add
+    /// the last one. `None` when the number of steps is not known, and 0 past it.
+    pub fn remaining(&self) -> Option<usize> {
+        self.num_steps()
+            .map(|num_steps| num_steps.saturating_sub(self.steps()))
+    }
+
+    /// Runs a reusable trace generator, defined with the step types of this circuit, as part of
+    /// this trace. Its steps are added after the steps already in the trace, and the range of
+    /// their indices is returned, for example to constrain or look them up later. The height set
+    /// by the sub-trace is ignored, the height of the witness is the one set by the parent trace.
+    pub fn sub_trace<TraceArgs>(
+        &mut self,
+        trace: &Trace<TraceArgs, StepArgs>,
+        args: TraceArgs,
+    ) -> Range<usize> {
+        self.sub_trace_with(|ctx| trace(ctx, args))
+    }
+
+    /// Like `sub_trace`, for a closure.
+    pub fn sub_trace_with<T: FnOnce(&mut dyn TraceContext<StepArgs>)>(
+        &mut self,
+        trace: T,
+    ) -> Range<usize> {
+        let start = self.steps();
+        trace(&mut SubTraceContext { parent: self });
+
+        start..self.steps()
+    }
+}
+
+/// Trace context of a sub-trace, adding its steps to the parent trace.
+struct SubTraceContext<'p, 'a, StepArgs> {
+    parent: &'p mut (dyn TraceContext<StepArgs> + 'a),
+}
+
+impl<'p, 'a, StepArgs> TraceContext<StepArgs> for SubTraceContext<'p, 'a, StepArgs> {
+    fn add(&mut self, step: &StepTypeHandler, args: StepArgs) {
+        self.parent.add(step, args);
+    }
+
+    fn set_height(&mut self, _height: usize) {}
+
+    fn steps(&self) -> usize {
+        self.parent.steps()
+    }
+
+    fn num_steps(&self) -> Option<usize> {
+        self.parent.num_steps()
+    }
 }
 
 /// A trait that represents a witness generation context. It provides an interface for assigning
 /// values to witness columns in a circuit.
+///
+/// The assignment methods track their caller, so that contexts can record the source location of
+/// each assignment, see `GenericTraceContext::with_provenance`.
 pub trait WitnessGenContext<F> {
     /// Takes a `Queriable` object representing the witness column (lhs) and the value (rhs) to be
     /// assigned.
+    #[track_caller]
     fn assign(&mut self, lhs: Queriable<F>, rhs: F);
+
+    /// Assigns every pair of `assignments`, like calling `assign` for each of them. Contexts that
+    /// store the assignments can reserve room for all of them at once. See also `assign_many`.
+    #[track_caller]
+    fn assign_slice(&mut self, assignments: &[(Queriable<F>, F)])
+    where
+        F: Clone,
+    {
+        for (lhs, rhs) in assignments.iter() {
+            self.assign(*lhs, rhs.clone());
+        }
+    }
+
+    /// Like `assign_slice`, for assignments computed by an iterator. Witness generation functions
+    /// call it with `assign_many`.
+    #[track_caller]
+    fn assign_iter(&mut self, assignments: &mut dyn Iterator<Item = (Queriable<F>, F)>) {
+        for (lhs, rhs) in assignments {
+            self.assign(lhs, rhs);
+        }
+    }
+
+    /// Like `assign`, returning an error instead of accepting a signal that cannot be assigned in
+    /// the witness of the step. By default it only checks the kind of the signal.
+    #[track_caller]
+    fn try_assign(&mut self, lhs: Queriable<F>, rhs: F) -> Result<(), AssignmentError> {
+        check_witness_signal(&lhs)?;
+        self.assign(lhs, rhs);
+
+        Ok(())
+    }
+
+    /// Reports that a check of the witness generation failed, see `witness_assert!`. The trace
+    /// context adds the step and its assignments so far to the message. By default it panics
+    /// with `message`.
+    fn fail(&mut self, message: String) {
+        panic!("{}", message);
+    }
+
+    /// The signals assigned so far in the step, checked by `assert`. Contexts that do not keep
+    /// them return `None`, and their assertions are not checked.
+    fn assignments(&self) -> Option<&Assignments<F>> {
+        None
+    }
+
+    /// Adds an assignment computed from the challenges, see `assign_with_challenges`. By default
+    /// it panics, the context does not support challenges.
+    fn push_challenge_assignment(&mut self, assignment: ChallengeAssignment<F>) {
+        panic!(
+            "cannot assign {} with challenges in this context",
+            assignment.signal.annotation()
+        );
+    }
+}
+
+impl<'a, F> dyn WitnessGenContext<F> + 'a {
+    /// Assigns all the pairs of `assignments` in one call, see `WitnessGenContext::assign_iter`.
+    #[track_caller]
+    pub fn assign_many<I: IntoIterator<Item = (Queriable<F>, F)>>(&mut self, assignments: I) {
+        self.assign_iter(&mut assignments.into_iter());
+    }
+
+    /// Assigns a signal of a later phase with the value of `value`, computed when the backend
+    /// knows the challenges of the verifier. The values of the earlier phases that it needs must
+    /// be captured by the closure:
+    ///
+    /// ```ignore
+    /// ctx.wg(move |ctx, (value, acc_value): (u64, u64)| {
+    ///     ctx.assign(a, value.field());
+    ///     ctx.assign_with_challenges(acc, move |challenges| {
+    ///         Fr::from(acc_value) * challenges.get(r) + Fr::from(value)
+    ///     });
+    /// });
+    /// ```
+    ///
+    /// The signal must be a forward signal of a phase after the challenges it uses, the trace
+    /// fails the step otherwise. It has no value in the witness returned by the trace, so the
+    /// constraint checks during witness generation do not see it.
+    pub fn assign_with_challenges<V>(&mut self, lhs: Queriable<F>, value: V)
+    where
+        V: Fn(&ChallengeValues<F>) -> F + Send + Sync + 'static,
+    {
+        self.push_challenge_assignment(ChallengeAssignment::new(lhs, Arc::new(value)));
+    }
+}
+
+impl<'a, F: Field> dyn WitnessGenContext<F> + 'a {
+    /// In debug builds, checks that `constraint` holds with the signals assigned so far, and
+    /// fails like `witness_assert!` with `message`, the constraint and the location of the call
+    /// when it does not:
+    ///
+    /// ```ignore
+    /// ctx.assign(c, (a_value + b_value).field());
+    /// ctx.assert(eq(a + b, c), "c is the sum");
+    /// ```
+    ///
+    /// A constraint querying a signal that is not assigned yet fails too, and so do the next
+    /// step queries of transitions, which have no value during witness generation. Release
+    /// builds do not check anything.
+    #[track_caller]
+    pub fn assert<C: Into<cb::Constraint<F>>>(&mut self, constraint: C, message: &str) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+
+        let constraint = constraint.into();
+        let location = std::panic::Location::caller();
+        let failure = match self.assignments() {
+            Some(assignments) => {
+                match constraint
+                    .expr
+                    .eval(&|signal| assignments.get(signal).copied())
+                {
+                    Ok(value) if value == F::ZERO => None,
+                    Ok(value) => Some(format!(
+                        "{}: {} evaluates to {:?}, at {}",
+                        message, constraint.annotation, value, location
+                    )),
+                    Err(signal) => Some(format!(
+                        "{}: {} queries {}, which has no value, at {}",
+                        message,
+                        constraint.annotation,
+                        signal.annotation(),
+                        location
+                    )),
+                }
+            }
+            None => None,
+        };
+
+        if let Some(failure) = failure {
+            self.fail(failure);
+        }
+    }
 }
 
 /// A trait that represents a fixed column generation context. It provides an interface for
@@ -43,6 +261,271 @@ pub trait FixedGenContext<F> {
     /// Takes a `Queriable` object representing the fixed column (lhs) and the value (rhs) to be
     /// assigned.
     fn assign(&mut self, offset: usize, lhs: Queriable<F>, rhs: F);
+
+    /// Like `assign`, returning an error instead of panicking on a signal that is not a fixed
+    /// column or an offset outside of the circuit.
+    fn try_assign(
+        &mut self,
+        offset: usize,
+        lhs: Queriable<F>,
+        rhs: F,
+    ) -> Result<(), AssignmentError> {
+        check_fixed_column(&lhs)?;
+        self.assign(offset, lhs, rhs);
+
+        Ok(())
+    }
+
+    /// Usable rows of the circuit, when the context knows them.
+    fn rows(&self) -> Option<usize> {
+        None
+    }
+
+    /// Assigns `values` to the fixed column `lhs`, the first one at offset 0, checking the column
+    /// and that all the values fit in the rows of the circuit before assigning any of them. See
+    /// also `assign_from_fn`.
+    fn assign_slice(&mut self, lhs: Queriable<F>, values: &[F]) -> Result<(), AssignmentError>
+    where
+        F: Clone,
+    {
+        check_fixed_column(&lhs)?;
+        check_fixed_rows(&lhs, values.len(), self.rows())?;
+
+        for (offset, value) in values.iter().enumerate() {
+            self.assign(offset, lhs, value.clone());
+        }
+
+        Ok(())
+    }
+
+    /// The rows of the steps in the compiled layout, when the context knows them, see
+    /// `try_assign_step`.
+    fn step_rows(&self) -> Option<StepRows> {
+        None
+    }
+
+    /// Like `try_assign`, at the row `row` of the step `step` instead of an offset, so that the
+    /// fixed values line up with the steps when a step spans more than one row. Fails on a step
+    /// or row outside of the layout, or when the context does not know it, as when the step types
+    /// have different heights.
+    fn try_assign_step(
+        &mut self,
+        step: usize,
+        row: usize,
+        lhs: Queriable<F>,
+        rhs: F,
+    ) -> Result<(), AssignmentError> {
+        let rows = self
+            .step_rows()
+            .ok_or_else(|| AssignmentError::UnknownStepRows {
+                signal: lhs.annotation(),
+            })?;
+
+        if step >= rows.num_steps || row >= rows.step_height {
+            return Err(AssignmentError::StepOutOfBounds {
+                signal: lhs.annotation(),
+                step,
+                row,
+                rows,
+            });
+        }
+
+        self.try_assign(step * rows.step_height + row, lhs, rhs)
+    }
+
+    /// Like `try_assign_step`, panicking on the error.
+    fn assign_step(&mut self, step: usize, row: usize, lhs: Queriable<F>, rhs: F) {
+        if let Err(err) = self.try_assign_step(step, row, lhs, rhs) {
+            panic!("invalid fixed assignment: {}", err);
+        }
+    }
+}
+
+impl<'a, F> dyn FixedGenContext<F> + 'a {
+    /// Assigns the value `gen(offset)` to the fixed column `lhs` at each offset of `0..len`, with
+    /// the checks of `assign_slice`, without collecting the values first.
+    pub fn assign_from_fn<G: FnMut(usize) -> F>(
+        &mut self,
+        lhs: Queriable<F>,
+        len: usize,
+        mut gen: G,
+    ) -> Result<(), AssignmentError> {
+        check_fixed_column(&lhs)?;
+        check_fixed_rows(&lhs, len, self.rows())?;
+
+        for offset in 0..len {
+            self.assign(offset, lhs, gen(offset));
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of steps of a compiled circuit and rows of each step, when all the step types have the
+/// same height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepRows {
+    pub num_steps: usize,
+    pub step_height: usize,
+}
+
+/// An assignment rejected by `WitnessGenContext::try_assign` or `FixedGenContext::try_assign`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssignmentError {
+    WrongKind {
+        signal: String,
+        expected: &'static str,
+    },
+    /// Fixed columns are assigned at an offset, not with a rotation.
+    Rotation {
+        signal: String,
+    },
+    OutOfBounds {
+        signal: String,
+        offset: usize,
+        rows: usize,
+    },
+    NotInStepType {
+        signal: String,
+        step_type: String,
+    },
+    /// A step or a row past the steps of the circuit or the height of a step.
+    StepOutOfBounds {
+        signal: String,
+        step: usize,
+        row: usize,
+        rows: StepRows,
+    },
+    /// The context does not know the rows of the steps.
+    UnknownStepRows {
+        signal: String,
+    },
+    /// The signal already has a value, in a trace context `with_strict_assignments`.
+    Overwritten {
+        signal: String,
+    },
+    /// A signal assigned with challenges is in a phase where no challenge is known yet.
+    ChallengePhase {
+        signal: String,
+        phase: usize,
+    },
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignmentError::WrongKind { signal, expected } => {
+                write!(f, "cannot assign {}, expected a {}", signal, expected)
+            }
+            AssignmentError::Rotation { signal } => {
+                write!(f, "cannot assign {} with a rotation", signal)
+            }
+            AssignmentError::OutOfBounds {
+                signal,
+                offset,
+                rows,
+            } => write!(
+                f,
+                "cannot assign {} at offset {}, the circuit has {} rows",
+                signal, offset, rows
+            ),
+            AssignmentError::NotInStepType { signal, step_type } => {
+                write!(f, "signal {} is not in step type {}", signal, step_type)
+            }
+            AssignmentError::StepOutOfBounds {
+                signal,
+                step,
+                row,
+                rows,
+            } => write!(
+                f,
+                "cannot assign {} at row {} of step {}, the circuit has {} steps of {} rows",
+                signal, row, step, rows.num_steps, rows.step_height
+            ),
+            AssignmentError::UnknownStepRows { signal } => write!(
+                f,
+                "cannot assign {} at a step, the rows of the steps are not known",
+                signal
+            ),
+            AssignmentError::Overwritten { signal } => {
+                write!(f, "signal {} is already assigned", signal)
+            }
+            AssignmentError::ChallengePhase { signal, phase } => write!(
+                f,
+                "cannot assign {} with challenges, it is in phase {}, but a challenge is only \
+                 known in the phases after its own, so the signal must be a forward signal of \
+                 phase 1 or later",
+                signal, phase
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AssignmentError {}
+
+/// Checks that the signal is an imported fixed column without rotation, the signals that the
+/// fixed generation assigns.
+pub(crate) fn check_fixed_column<F>(signal: &Queriable<F>) -> Result<(), AssignmentError> {
+    match signal {
+        Queriable::Halo2FixedQuery(_, 0) => Ok(()),
+        Queriable::Halo2FixedQuery(_, _) => Err(AssignmentError::Rotation {
+            signal: signal.annotation(),
+        }),
+        _ => Err(AssignmentError::WrongKind {
+            signal: signal.annotation(),
+            expected: "fixed column",
+        }),
+    }
+}
+
+/// Checks that `len` values from offset 0 fit in the `rows` of the circuit, when known.
+pub(crate) fn check_fixed_rows<F>(
+    signal: &Queriable<F>,
+    len: usize,
+    rows: Option<usize>,
+) -> Result<(), AssignmentError> {
+    match rows {
+        Some(rows) if len > rows => Err(AssignmentError::OutOfBounds {
+            signal: signal.annotation(),
+            offset: len - 1,
+            rows,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the signal is an internal, forward or imported advice signal without rotation,
+/// the signals that the witness generation of a step assigns.
+pub(crate) fn check_witness_signal<F>(signal: &Queriable<F>) -> Result<(), AssignmentError> {
+    match signal {
+        Queriable::Internal(_) | Queriable::Forward(_, _) | Queriable::Halo2AdviceQuery(_, _) => {
+            Ok(())
+        }
+        _ => Err(AssignmentError::WrongKind {
+            signal: signal.annotation(),
+            expected: "internal, forward or halo2 advice signal",
+        }),
+    }
+}
+
+/// Checks that the signal can be assigned with challenges, a forward signal of a later phase than
+/// the first one. Internal signals are in the columns of phase 0.
+pub(crate) fn check_challenge_signal<F>(signal: &Queriable<F>) -> Result<(), AssignmentError> {
+    match signal {
+        Queriable::Forward(forward, _) if forward.phase() > 0 => Ok(()),
+        Queriable::Forward(forward, _) => Err(AssignmentError::ChallengePhase {
+            signal: signal.annotation(),
+            phase: forward.phase(),
+        }),
+        Queriable::Internal(_) => Err(AssignmentError::ChallengePhase {
+            signal: signal.annotation(),
+            phase: 0,
+        }),
+        _ => Err(AssignmentError::WrongKind {
+            signal: signal.annotation(),
+            expected: "forward signal of a later phase",
+        }),
+    }
 }
 
 #[derive(Debug)]
@@ -57,6 +540,9 @@ pub struct CompilationUnit<F, StepArgs> {
     pub columns: Vec<Column>,
     pub polys: Vec<Poly<F>>,
     pub lookups: Vec<PolyLookup<F>>,
+
+    pub step_fixed: HashMap<u32, Column>,
+    pub invariants: Vec<Invariant<F>>,
 }
 
 impl<F, StepArgs> Default for CompilationUnit<F, StepArgs> {
@@ -72,11 +558,26 @@ impl<F, StepArgs> Default for CompilationUnit<F, StepArgs> {
             columns: Default::default(),
             polys: Default::default(),
             lookups: Default::default(),
+
+            step_fixed: Default::default(),
+            invariants: Default::default(),
         }
     }
 }
 
 impl<F, StepArgs> CompilationUnit<F, StepArgs> {
+    /// The step types in the order they were defined. The iteration order of `step_types` changes
+    /// between runs, the cell managers, step selectors and the compiler go through the step types
+    /// in this order instead, so that the columns and constraints of a compiled circuit, and its
+    /// verifying key, are always the same.
+    pub fn ordered_step_types(&self) -> Vec<Rc<StepType<F, StepArgs>>> {
+        let mut step_types: Vec<Rc<StepType<F, StepArgs>>> =
+            self.step_types.values().cloned().collect();
+        step_types.sort_by_key(|step| step.uuid());
+
+        step_types
+    }
+
     fn find_halo2_advice(&self, to_find: ImportedHalo2Advice) -> Option<Column> {
         for column in self.columns.iter() {
             if let Some(advice) = column.halo2_advice {
@@ -117,6 +618,14 @@ impl<F, StepArgs> CompilationUnit<F, StepArgs> {
 pub struct Compiler<CM: CellManager, SSB: StepSelectorBuilder> {
     cell_manager: CM,
     step_selector_builder: SSB,
+    capabilities: BackendCapabilities,
+    debug_constraints: bool,
+    batch_lookups: bool,
+    default_num_steps: usize,
+    max_degree: Option<usize>,
+    degree_reduction: bool,
+    expr_limits: ExprLimits,
+    unassigned: Option<Box<dyn Any>>,
 }
 
 impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
@@ -124,19 +633,117 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
         Compiler {
             cell_manager,
             step_selector_builder,
+            capabilities: BackendCapabilities::default(),
+            debug_constraints: cfg!(debug_assertions),
+            batch_lookups: false,
+            default_num_steps: 0,
+            max_degree: None,
+            degree_reduction: false,
+            expr_limits: ExprLimits::default(),
+            unassigned: None,
         }
     }
 
+    /// Sets whether the constraints added with `StepTypeSetupContext::debug_only` are compiled.
+    /// By default they are compiled in debug builds only, so that production keys do not include
+    /// them.
+    pub fn with_debug_constraints(mut self, enabled: bool) -> Self {
+        self.debug_constraints = enabled;
+        self
+    }
+
+    /// Sets whether lookups from different step types into the same table are merged into a
+    /// single lookup argument. A row is in a single step type, so the sources of the other step
+    /// types are zero there and the sum of the sources looks up the source of the step type of
+    /// the row, which needs fewer lookup arguments, and their columns, in the backend. Lookups of
+    /// the same step type are active in the same rows and are never merged.
+    pub fn with_lookup_batching(mut self, enabled: bool) -> Self {
+        self.batch_lookups = enabled;
+        self
+    }
+
+    /// Sets the number of steps of the circuits that do not set it with `pragma_num_steps`.
+    pub fn with_default_num_steps(mut self, num_steps: usize) -> Self {
+        self.default_num_steps = num_steps;
+        self
+    }
+
+    /// Sets the maximum degree of the constraints, including the selectors, for example 5 for
+    /// the usual halo2 parameters. Compiling a circuit with a constraint above it panics, unless
+    /// the degree reduction is enabled.
+    pub fn with_max_degree(mut self, max_degree: usize) -> Self {
+        self.max_degree = Some(max_degree);
+        self
+    }
+
+    /// Sets whether the constraints above the maximum degree, the one set with `with_max_degree`
+    /// or the one of the backend capabilities, are rewritten with new internal signals until they
+    /// fit, see `degree::reduce_degree`.
+    pub fn with_degree_reduction(mut self, enabled: bool) -> Self {
+        self.degree_reduction = enabled;
+        self
+    }
+
+    /// Sets the limits on the number of nodes and the nesting depth of the expressions of the
+    /// step types. Compiling a circuit with an expression above them panics, naming the
+    /// constraint, before the compiler walks the expressions. By default they are
+    /// `ExprLimits::default()`, `ExprLimits::none()` removes them. See
+    /// `expr_limits::check_expr_limits` to check a circuit without compiling it.
+    pub fn with_expr_limits(mut self, limits: ExprLimits) -> Self {
+        self.expr_limits = limits;
+        self
+    }
+
+    /// Sets what the backend does with the signals a step instance leaves unassigned. By default
+    /// they are left unassigned, which the prover reads as zero and can hide a missing assignment.
+    /// The policy is for the field of the circuits compiled, compiling a circuit over another field
+    /// panics.
+    pub fn with_unassigned_policy<F: 'static>(mut self, policy: UnassignedPolicy<F>) -> Self {
+        self.unassigned = Some(Box::new(policy));
+        self
+    }
+
+    /// Sets the capabilities of the backend the circuit is compiled for, for example
+    /// `backend::halo2::capabilities()`. Compiling a circuit that uses a feature the backend does
+    /// not support panics.
+    pub fn with_capabilities(mut self, capabilities: BackendCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn capabilities(&self) -> &BackendCapabilities {
+        &self.capabilities
+    }
+
     pub fn compile<F: Field + Clone, TraceArgs, StepArgs>(
         &self,
         sc: &astCircuit<F, TraceArgs, StepArgs>,
     ) -> Circuit<F, TraceArgs, StepArgs> {
+        let _timer = metrics::Timer::start(metrics::COMPILE_SECONDS);
+        if let Err(err) = check_expr_limits(sc, &self.expr_limits) {
+            panic!("{}", err);
+        }
+        let num_steps = self.num_steps(sc);
+        Self::check_budgets(sc, num_steps);
+        Self::check_padding(sc);
+        Self::check_schedule(sc);
+
+        let step_types = self.reduce_step_types(sc);
+        let mut aliases = sc.aliases.clone();
+        for step in step_types.values() {
+            aliases.extend(&step.aliases);
+        }
         let mut unit = CompilationUnit::<F, StepArgs> {
             annotations: {
                 let mut acc = sc.annotations.clone();
-                for step in sc.step_types.values() {
+                for step in step_types.values() {
                     acc.extend(step.annotations.clone());
                 }
+                for (uuid, annotation) in acc.iter_mut() {
+                    if let Some(alias) = aliases.get(*uuid).first() {
+                        *annotation = alias.clone();
+                    }
+                }
 
                 acc
             },
@@ -168,15 +775,27 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
             .collect();
 
         unit.columns = vec![halo2_advice_columns, halo2_fixed_columns].concat();
-        unit.step_types = sc.step_types.clone();
+        unit.step_types = step_types;
         unit.forward_signals = sc.forward_signals.clone();
+        unit.invariants = sc.invariants.clone();
 
         self.cell_manager.place(&mut unit);
+
+        let mut step_fixed = self.add_step_fixed(sc, &mut unit);
+        let fixed_tables = self.add_fixed_tables(sc, &mut unit);
         self.step_selector_builder
             .build::<F, TraceArgs, StepArgs>(&mut unit);
 
-        for step in unit.step_types.clone().values() {
+        let mut lookup_steps = Vec::new();
+        for step in unit.ordered_step_types().iter() {
             self.compile_step(&mut unit, step);
+            lookup_steps.resize(unit.lookups.len(), step.uuid());
+        }
+
+        step_fixed.extend(self.add_trace_constraints(sc, &mut unit, num_steps));
+
+        if self.batch_lookups {
+            unit.lookups = batch_lookups(lookup_steps, std::mem::take(&mut unit.lookups));
         }
 
         let q_enable = Column {
@@ -254,6 +873,25 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
             None
         };
 
+        let exposed_signals: Vec<(ForwardSignal, ExposeOffset)> = sc
+            .exposed
+            .iter()
+            .map(|(signal, offset)| match signal {
+                Queriable::Forward(forward, false) => (*forward, *offset),
+                _ => panic!("can only expose a forward signal without rotation"),
+            })
+            .collect();
+        let exposed = exposed_signals
+            .iter()
+            .map(|(forward, offset)| {
+                let placement = unit.placement.get_forward_placement(forward);
+
+                (placement.column, placement.rotation, *offset)
+            })
+            .collect();
+
+        self.check_capabilities(&unit);
+
         Circuit::<F, TraceArgs, StepArgs> {
             placement: unit.placement,
             selector: unit.selector,
@@ -264,9 +902,401 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
             q_enable,
             q_first,
             q_last,
+            num_steps,
+            step_schedule: sc.step_schedule,
+
+            exposed,
+            exposed_signals,
+            exposed_results: sc.exposed_results.clone(),
+            external_signals: sc.external_signals.clone(),
+            aliases,
 
             trace: sc.trace.as_ref().map(|v| Rc::clone(v)),
+            try_trace: sc.try_trace.as_ref().map(Rc::clone),
             fixed_gen: sc.fixed_gen.as_ref().map(|v| Rc::clone(v)),
+            step_fixed,
+            fixed_tables,
+            challenges: sc.challenges.clone(),
+
+            unassigned: self.unassigned_policy(),
+        }
+    }
+
+    fn add_step_fixed<F, TraceArgs, StepArgs>(
+        &self,
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+        unit: &mut CompilationUnit<F, StepArgs>,
+    ) -> Vec<(Column, Rc<StepFixedGen<F>>)> {
+        if sc.step_fixed.is_empty() {
+            return Vec::new();
+        }
+
+        if !unit.placement.same_height() {
+            panic!("step-indexed fixed signals require all step types to have the same height");
+        }
+        if self.num_steps(sc) == 0 {
+            panic!("step-indexed fixed signals require pragma_num_steps");
+        }
+
+        sc.step_fixed
+            .iter()
+            .map(|(signal, gen)| {
+                let annotation = unit
+                    .annotations
+                    .get(&signal.uuid())
+                    .cloned()
+                    .unwrap_or_default();
+                let column = Column::fixed(&format!("step fixed {}", annotation));
+
+                unit.columns.push(column.clone());
+                unit.step_fixed.insert(signal.uuid(), column.clone());
+
+                (column, Rc::clone(gen))
+            })
+            .collect()
+    }
+
+    /// Places the columns of the fixed tables, which are queried like step-indexed fixed signals.
+    fn add_fixed_tables<F, TraceArgs, StepArgs>(
+        &self,
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+        unit: &mut CompilationUnit<F, StepArgs>,
+    ) -> Vec<FixedTable<F>> {
+        sc.fixed_tables
+            .iter()
+            .map(|table| {
+                let columns = table
+                    .columns
+                    .iter()
+                    .map(|signal| {
+                        let annotation = unit
+                            .annotations
+                            .get(&signal.uuid())
+                            .cloned()
+                            .unwrap_or_default();
+                        let column = Column::fixed(&format!("table {}", annotation));
+
+                        unit.columns.push(column.clone());
+                        unit.step_fixed.insert(signal.uuid(), column.clone());
+
+                        column
+                    })
+                    .collect();
+
+                FixedTable {
+                    columns,
+                    rows: Rc::clone(&table.rows),
+                }
+            })
+            .collect()
+    }
+
+    /// Adds the polynomial of each trace constraint, gated by a fixed column that is one in the
+    /// first row of the steps where it is enforced. The fixed columns are assigned like the
+    /// step-indexed fixed signals.
+    fn add_trace_constraints<F: Field, TraceArgs, StepArgs>(
+        &self,
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+        unit: &mut CompilationUnit<F, StepArgs>,
+        num_steps: usize,
+    ) -> Vec<(Column, Rc<StepFixedGen<F>>)> {
+        if sc.trace_constraints.is_empty() {
+            return Vec::new();
+        }
+
+        if !unit.placement.same_height() {
+            panic!("trace constraints require all step types to have the same height");
+        }
+        if num_steps == 0 {
+            panic!("trace constraints require pragma_num_steps");
+        }
+        let step = match unit.step_types.values().next() {
+            Some(step) => Rc::clone(step),
+            None => panic!("trace constraints require a step type"),
+        };
+
+        sc.trace_constraints
+            .iter()
+            .map(|trace_constraint| {
+                let annotation = &trace_constraint.constraint.annotation;
+                let queries_next = trace_constraint
+                    .constraint
+                    .expr
+                    .queriables()
+                    .iter()
+                    .any(|q| {
+                        matches!(
+                            q,
+                            Queriable::Forward(_, true)
+                                | Queriable::StepFixed(_, true)
+                                | Queriable::StepTypeNext(_)
+                        )
+                    });
+                if queries_next && trace_constraint.rows.contains(num_steps - 1, num_steps) {
+                    panic!(
+                        "trace constraint {} queries the next step at the last step, which has no \
+                         next step",
+                        annotation
+                    );
+                }
+                let column = Column::fixed(&format!("trace constraint {}", annotation));
+                let expr = self.transform_expr(unit, &step, &trace_constraint.constraint.expr);
+
+                unit.columns.push(column.clone());
+                unit.polys.push(Poly {
+                    annotation: format!("trace::{} at {:?}", annotation, trace_constraint.rows),
+                    expr: PolyExpr::Mul(vec![
+                        PolyExpr::Query(column.clone(), 0, annotation.clone()),
+                        expr,
+                    ]),
+                });
+
+                let rows = trace_constraint.rows;
+                let gen: Rc<StepFixedGen<F>> = Rc::new(move |step| {
+                    if rows.contains(step, num_steps) {
+                        F::ONE
+                    } else {
+                        F::ZERO
+                    }
+                });
+
+                (column, gen)
+            })
+            .collect()
+    }
+
+    fn unassigned_policy<F: Field>(&self) -> UnassignedPolicy<F> {
+        match &self.unassigned {
+            Some(policy) => policy
+                .downcast_ref::<UnassignedPolicy<F>>()
+                .expect("unassigned policy is for another field")
+                .clone(),
+            None => UnassignedPolicy::default(),
+        }
+    }
+
+    /// The maximum degree of the constraints, the lowest of the one set in the compiler and the
+    /// one of the backend.
+    fn max_degree(&self) -> Option<usize> {
+        match (self.max_degree, self.capabilities.max_degree) {
+            (Some(target), Some(backend)) => Some(target.min(backend)),
+            (target, backend) => target.or(backend),
+        }
+    }
+
+    fn reduce_step_types<F: Field, TraceArgs, StepArgs>(
+        &self,
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+    ) -> HashMap<u32, Rc<StepType<F, StepArgs>>> {
+        let max_degree = match (self.degree_reduction, self.max_degree()) {
+            (true, Some(max_degree)) => max_degree,
+            _ => return sc.step_types.clone(),
+        };
+
+        sc.step_types
+            .iter()
+            .map(|(uuid, step)| match reduce_degree(step, max_degree) {
+                Some(reduced) => (*uuid, Rc::new(reduced)),
+                None => (*uuid, Rc::clone(step)),
+            })
+            .collect()
+    }
+
+    fn check_capabilities<F: Field, StepArgs>(&self, unit: &CompilationUnit<F, StepArgs>) {
+        if let Some(max_degree) = self.max_degree {
+            for poly in unit.polys.iter() {
+                let degree = poly.expr.degree();
+                if degree > max_degree {
+                    panic!(
+                        "constraint {} has degree {}, but the target degree is {}",
+                        poly.annotation, degree, max_degree
+                    );
+                }
+            }
+        }
+
+        if let Some(max_degree) = self.capabilities.max_degree {
+            for poly in unit.polys.iter() {
+                let degree = poly.expr.degree();
+                if degree > max_degree {
+                    panic!(
+                        "constraint {} has degree {}, but the backend supports up to degree {}",
+                        poly.annotation, degree, max_degree
+                    );
+                }
+            }
+        }
+
+        if !self.capabilities.challenges {
+            if let Some(column) = unit.columns.iter().find(|column| column.phase > 0) {
+                panic!(
+                    "column {} is in phase {}, but the backend does not support challenges",
+                    column.annotation, column.phase
+                );
+            }
+            if let Some(poly) = unit.polys.iter().find(|poly| queries_challenge(&poly.expr)) {
+                panic!(
+                    "constraint {} queries a challenge, but the backend does not support challenges",
+                    poly.annotation
+                );
+            }
+        }
+
+        if !self.capabilities.dynamic_lookups {
+            for lookup in unit.lookups.iter() {
+                let dynamic = lookup.exprs.iter().any(|(_, dest)| queries_advice(dest));
+                if dynamic {
+                    panic!(
+                        "lookup {} has advice columns in its table, but the backend does not support dynamic lookups",
+                        lookup.annotation
+                    );
+                }
+            }
+        }
+    }
+
+    fn num_steps<F, TraceArgs, StepArgs>(&self, sc: &astCircuit<F, TraceArgs, StepArgs>) -> usize {
+        if sc.num_steps > 0 {
+            sc.num_steps
+        } else {
+            self.default_num_steps
+        }
+    }
+
+    fn check_budgets<F, TraceArgs, StepArgs>(
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+        num_steps: usize,
+    ) {
+        if num_steps == 0 {
+            return;
+        }
+
+        let total: usize = sc.step_types.values().filter_map(|step| step.budget).sum();
+
+        if total > num_steps {
+            panic!(
+                "step type budgets add up to {} steps, but the circuit has num_steps {}",
+                total, num_steps
+            );
+        }
+    }
+
+    /// The padding step types must keep the protected signals with a transition equivalent to
+    /// `next(signal) - signal`, or to it only when the next step is a padding step, as the
+    /// exposed results do. A transition that only mentions both could change them.
+    fn check_padding<F: Field, TraceArgs, StepArgs>(sc: &astCircuit<F, TraceArgs, StepArgs>) {
+        let protected = sc.padding_protected_signals();
+        let padding = sc.padding_step_types();
+
+        for handler in padding.iter() {
+            let step = match sc.step_types.get(&handler.uuid()) {
+                Some(step) => step,
+                None => continue,
+            };
+
+            for signal in protected.iter() {
+                let current = Queriable::<F>::Forward(*signal, false);
+                let next = Queriable::<F>::Forward(*signal, true);
+                let kept = next - current;
+                let mut keeping = vec![kept.clone()];
+                keeping.extend(padding.iter().map(|padding| padding.next() * kept.clone()));
+                let keeping: Vec<_> = keeping
+                    .into_iter()
+                    .flat_map(|keep| [keep.canonicalize(), (-keep).canonicalize()])
+                    .collect();
+
+                let keeps = step.transition_constraints.iter().any(|constraint| {
+                    !constraint.expr.contains_halo2_expr()
+                        && keeping.contains(&constraint.expr.canonicalize())
+                });
+
+                if !keeps {
+                    panic!(
+                        "padding step type {} does not constrain next({1}) == {1}, which is \
+                         exposed or carried between steps; add the transition or \
+                         allow_padding_change",
+                        step.name,
+                        current.annotation()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Grouping the steps by type reorders the rows, which is only sound when the constraints of
+    /// every step are on its own rows and nothing depends on the position of a step.
+    fn check_schedule<F: Clone, TraceArgs, StepArgs>(sc: &astCircuit<F, TraceArgs, StepArgs>) {
+        if sc.step_schedule != StepSchedule::Grouped {
+            return;
+        }
+
+        let forbid = |reason: String| -> ! {
+            panic!("step schedule Grouped reorders the steps, but {}", reason)
+        };
+
+        if sc.first_step.is_some() || sc.last_step.is_some() {
+            forbid("the circuit constrains its first or last step type".to_string());
+        }
+        if !sc.exposed.is_empty() || !sc.exposed_results.is_empty() {
+            forbid("the circuit exposes signals of given steps".to_string());
+        }
+        if !sc.step_fixed.is_empty() {
+            forbid("the circuit has step-indexed fixed signals".to_string());
+        }
+        if let Some(constraint) = sc.trace_constraints.first() {
+            forbid(format!(
+                "the circuit has the trace constraint {} at given steps",
+                constraint.constraint.annotation
+            ));
+        }
+        if let Some(invariant) = sc.invariants.iter().find(|invariant| invariant.transition) {
+            forbid(format!(
+                "invariant {} queries the next step",
+                invariant.name
+            ));
+        }
+
+        for step in sc.step_types.values() {
+            if !step.transition_constraints.is_empty()
+                || !step.debug_transition_constraints.is_empty()
+            {
+                forbid(format!(
+                    "step type {} has transition constraints",
+                    step.name
+                ));
+            }
+
+            let exprs = step
+                .constraints
+                .iter()
+                .chain(step.debug_constraints.iter())
+                .map(|constraint| constraint.expr.clone())
+                .chain(step.lookups.iter().flat_map(|lookup| {
+                    lookup
+                        .exprs
+                        .iter()
+                        .flat_map(|(src, dest)| [src.expr.clone(), dest.clone()])
+                        .chain(lookup.enable.iter().map(|enable| enable.expr.clone()))
+                }));
+            for expr in exprs {
+                for queriable in expr.queriables() {
+                    let cross_step = match queriable {
+                        Queriable::Forward(_, next) | Queriable::StepFixed(_, next) => next,
+                        Queriable::Halo2AdviceQuery(_, rotation)
+                        | Queriable::Halo2FixedQuery(_, rotation) => rotation != 0,
+                        Queriable::StepTypeNext(_) => true,
+                        _ => false,
+                    };
+
+                    if cross_step {
+                        forbid(format!(
+                            "step type {} queries {} in another step",
+                            step.name,
+                            queriable.annotation()
+                        ));
+                    }
+                }
+            }
         }
     }
 
@@ -281,7 +1311,50 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
             .unwrap_or(&"??".to_string())
             .to_owned();
 
-        for constr in step.constraints.iter() {
+        for name in step.overridden_invariants.iter() {
+            if !unit
+                .invariants
+                .iter()
+                .any(|invariant| invariant.name == *name)
+            {
+                panic!(
+                    "step type {} overrides unknown invariant {}",
+                    step_annotation, name
+                );
+            }
+        }
+        let invariants: Vec<&Invariant<F>> = unit
+            .invariants
+            .iter()
+            .filter(|invariant| !step.overridden_invariants.contains(&invariant.name))
+            .collect();
+        let invariant_constraints: Vec<Constraint<F>> = invariants
+            .iter()
+            .filter(|invariant| !invariant.transition)
+            .map(|invariant| invariant_constraint(invariant))
+            .collect();
+        let invariant_transitions: Vec<TransitionConstraint<F>> = invariants
+            .iter()
+            .filter(|invariant| invariant.transition)
+            .map(|invariant| {
+                let constraint = invariant_constraint(invariant);
+                TransitionConstraint {
+                    annotation: constraint.annotation,
+                    expr: constraint.expr,
+                }
+            })
+            .collect();
+
+        let debug_constraints = step
+            .debug_constraints
+            .iter()
+            .filter(|_| self.debug_constraints);
+        for constr in step
+            .constraints
+            .iter()
+            .chain(debug_constraints)
+            .chain(invariant_constraints.iter())
+        {
             let constraint = self.transform_expr(unit, step, &constr.expr.clone());
             let poly = unit.selector.select(step, &constraint);
 
@@ -297,7 +1370,16 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
         }
 
         // TODO only transition_constraints should have rotations
-        for constr in step.transition_constraints.iter() {
+        let debug_transitions = step
+            .debug_transition_constraints
+            .iter()
+            .filter(|_| self.debug_constraints);
+        for constr in step
+            .transition_constraints
+            .iter()
+            .chain(debug_transitions)
+            .chain(invariant_transitions.iter())
+        {
             let constraint = self.transform_expr(unit, step, &constr.expr.clone());
             let poly = unit.selector.select(step, &constraint);
 
@@ -313,18 +1395,44 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
         }
 
         for lookup in step.lookups.iter() {
+            let table_step = lookup.table_step.map(|uuid| {
+                Rc::clone(unit.step_types.get(&uuid).unwrap_or_else(|| {
+                    panic!("lookup {} into an unknown step type", lookup.annotation)
+                }))
+            });
+
+            // the rows outside of both step types look up and make up all-zero tuples, so the
+            // selectors are paired first, a row of `step` only matches a row of `table_step`
+            let selectors = table_step.as_ref().map(|table_step| {
+                let src = match &lookup.enable {
+                    Some(enable) => unit
+                        .selector
+                        .select(step, &self.transform_expr(unit, step, &enable.expr)),
+                    None => unit.selector.selector_expr[step].clone(),
+                };
+
+                (
+                    src,
+                    unit.selector.selector_expr[table_step.as_ref()].clone(),
+                )
+            });
+
             let poly_lookup = PolyLookup {
                 annotation: lookup.annotation.clone(),
-                exprs: lookup
-                    .exprs
-                    .iter()
-                    .map(|(src, dest)| {
+                exprs: selectors
+                    .into_iter()
+                    .chain(lookup.exprs.iter().map(|(src, dest)| {
                         let src_poly = self.transform_expr(unit, step, &src.expr);
-                        let dest_poly = self.transform_expr(unit, step, dest);
+                        let dest_poly = match &table_step {
+                            Some(table_step) => unit
+                                .selector
+                                .select(table_step, &self.transform_expr(unit, table_step, dest)),
+                            None => self.transform_expr(unit, step, dest),
+                        };
                         let src_selected = unit.selector.select(step, &src_poly);
 
                         (src_selected, dest_poly)
-                    })
+                    }))
                     .collect(),
             };
 
@@ -415,6 +1523,22 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
                     annotation,
                 )
             }
+            Queriable::StepFixed(signal, next) => {
+                let column = unit
+                    .step_fixed
+                    .get(&signal.uuid())
+                    .expect("step fixed column not found")
+                    .clone();
+                let rotation = if next {
+                    unit.placement.step_height(step) as i32
+                } else {
+                    0
+                };
+                let annotation = format!("{}[{}, {}]", q.annotation(), column.annotation, rotation);
+
+                PolyExpr::Query(column, rotation, annotation)
+            }
+            Queriable::Challenge(challenge) => PolyExpr::Challenge(challenge),
             Queriable::_unaccessible(_) => panic!("jarrl"),
         }
     }
@@ -483,3 +1607,533 @@ impl<CM: CellManager, SSB: StepSelectorBuilder> Compiler<CM, SSB> {
             .collect();
     }
 }
+
+fn queries_advice<F>(expr: &PolyExpr<F>) -> bool {
+    match expr {
+        PolyExpr::Query(column, _, _) => {
+            matches!(column.ctype, ColumnType::Advice | ColumnType::Halo2Advice)
+        }
+        PolyExpr::Sum(v) | PolyExpr::Mul(v) => v.iter().any(queries_advice),
+        PolyExpr::Neg(v) | PolyExpr::Pow(v, _) => queries_advice(v),
+        PolyExpr::Const(_) | PolyExpr::Halo2Expr(_) | PolyExpr::Challenge(_) => false,
+    }
+}
+
+fn queries_challenge<F>(expr: &PolyExpr<F>) -> bool {
+    match expr {
+        PolyExpr::Challenge(_) => true,
+        PolyExpr::Sum(v) | PolyExpr::Mul(v) => v.iter().any(queries_challenge),
+        PolyExpr::Neg(v) | PolyExpr::Pow(v, _) => queries_challenge(v),
+        PolyExpr::Const(_) | PolyExpr::Query(_, _, _) | PolyExpr::Halo2Expr(_) => false,
+    }
+}
+
+fn invariant_constraint<F: Clone>(invariant: &Invariant<F>) -> Constraint<F> {
+    Constraint {
+        annotation: format!(
+            "invariant {}: {}",
+            invariant.name, invariant.constraint.annotation
+        ),
+        expr: invariant.constraint.expr.clone(),
+    }
+}
+
+/// Merges the lookups with the same table from different step types, see
+/// `Compiler::with_lookup_batching`. `steps` is the step type of each lookup.
+fn batch_lookups<F: Clone + Debug>(
+    steps: Vec<u32>,
+    lookups: Vec<PolyLookup<F>>,
+) -> Vec<PolyLookup<F>> {
+    let mut batches: Vec<(String, Vec<u32>, PolyLookup<F>)> = Vec::new();
+
+    for (step, lookup) in steps.into_iter().zip(lookups) {
+        let table = lookup
+            .exprs
+            .iter()
+            .map(|(_, dest)| poly_key(dest))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        match batches
+            .iter_mut()
+            .find(|(other, steps, _)| *other == table && !steps.contains(&step))
+        {
+            Some((_, steps, batch)) => {
+                steps.push(step);
+                batch.annotation = format!("{} | {}", batch.annotation, lookup.annotation);
+                for ((batch_src, _), (src, _)) in batch.exprs.iter_mut().zip(lookup.exprs) {
+                    *batch_src = PolyExpr::Sum(vec![batch_src.clone(), src]);
+                }
+            }
+            None => batches.push((table, vec![step], lookup)),
+        }
+    }
+
+    batches.into_iter().map(|(_, _, lookup)| lookup).collect()
+}
+
+/// Identifies an expression by its columns instead of their annotations.
+fn poly_key<F: Debug>(expr: &PolyExpr<F>) -> String {
+    let join =
+        |v: &Vec<PolyExpr<F>>, sep: &str| v.iter().map(poly_key).collect::<Vec<String>>().join(sep);
+
+    match expr {
+        PolyExpr::Const(c) => format!("{:?}", c),
+        PolyExpr::Query(column, rotation, _) => format!("[{}, {}]", column.uuid(), rotation),
+        PolyExpr::Sum(v) => format!("({})", join(v, " + ")),
+        PolyExpr::Mul(v) => format!("({})", join(v, " * ")),
+        PolyExpr::Neg(v) => format!("(-{})", poly_key(v)),
+        PolyExpr::Pow(v, exp) => format!("({})^{}", poly_key(v), exp),
+        PolyExpr::Halo2Expr(e) => format!("{:?}", e),
+        PolyExpr::Challenge(challenge) => format!("challenge {}", challenge.uuid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::{ToField, TraceRows},
+        ci::smoke_test,
+        compiler::{cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder},
+        dsl::{
+            cb::{eq, is_binary},
+            circuit, CircuitContext,
+        },
+    };
+
+    fn cubic() -> astCircuit<Fr, (), ()> {
+        circuit::<Fr, (), (), _>("cubic", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.constr(eq(a * a * a, 1)));
+            });
+        })
+    }
+
+    #[test]
+    fn test_deterministic_output() {
+        let sc = circuit::<Fr, (), (), _>("steps", |ctx| {
+            let a = ctx.forward("a");
+
+            for name in ["one", "two", "three", "four", "five", "six"] {
+                let step = ctx.step_type(name);
+                ctx.step_type_def(step, |ctx| {
+                    let b = ctx.internal(name);
+                    ctx.setup(move |ctx| ctx.constr(eq(a, b)));
+                });
+            }
+        });
+
+        let layout = || {
+            let compiled =
+                Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+            let columns: Vec<String> = compiled
+                .columns
+                .iter()
+                .map(|column| column.annotation.clone())
+                .collect();
+            let polys: Vec<String> = compiled
+                .polys
+                .iter()
+                .map(|poly| poly.annotation.clone())
+                .collect();
+
+            (columns, polys)
+        };
+
+        let first = layout();
+        for _ in 0..4 {
+            assert_eq!(layout(), first);
+        }
+        assert!(first.1[0].contains("one"));
+    }
+
+    #[test]
+    fn test_capabilities_max_degree() {
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {})
+            .with_capabilities(BackendCapabilities {
+                max_degree: Some(5),
+                ..Default::default()
+            });
+
+        // q_enable and the step selector add two to the degree of the constraint
+        compiler.compile(&cubic());
+    }
+
+    #[test]
+    #[should_panic(expected = "has degree 5, but the backend supports up to degree 4")]
+    fn test_capabilities_max_degree_exceeded() {
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {})
+            .with_capabilities(BackendCapabilities {
+                max_degree: Some(4),
+                ..Default::default()
+            });
+
+        compiler.compile(&cubic());
+    }
+
+    #[test]
+    fn test_debug_constraints() {
+        let sc = circuit::<Fr, (), (), _>("debug", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a, 1));
+                    ctx.debug_only(|ctx| ctx.constr(eq(a * a, 1)));
+                });
+            });
+        });
+
+        let compile = |debug| {
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {})
+                .with_debug_constraints(debug)
+                .compile(&sc)
+        };
+
+        assert_eq!(compile(true).polys.len(), compile(false).polys.len() + 1);
+
+        // the debug only is_binary does not make the release one redundant
+        let sc = circuit::<Fr, (), (), _>("debug booleans", |ctx| {
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let a = ctx.internal("a");
+
+                ctx.setup(move |ctx| {
+                    ctx.debug_only(|ctx| ctx.constr(is_binary(a)));
+                    ctx.constr(is_binary(a));
+                });
+            });
+        });
+        let step = sc.step_types.values().next().unwrap();
+        assert_eq!(step.debug_constraints.len(), 1);
+        assert_eq!(step.constraints.len(), 1);
+        assert_eq!(step.boolean_signals.len(), 1);
+    }
+
+    #[test]
+    fn test_invariants() {
+        let sc = circuit::<Fr, (), (), _>("invariants", |ctx| {
+            let counter = ctx.forward("counter");
+            let increment = ctx.step_type("increment");
+            let reset = ctx.step_type("reset");
+
+            ctx.invariant(
+                "counter step",
+                eq(
+                    (counter.next() - counter) * (counter.next() - counter - 1),
+                    0,
+                ),
+            );
+
+            ctx.step_type_def(increment, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(counter.next(), counter + 1)));
+            });
+            ctx.step_type_def(reset, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.override_invariant("counter step");
+                    ctx.transition(eq(counter.next(), 0));
+                });
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let invariants: Vec<&Poly<Fr>> = compiled
+            .polys
+            .iter()
+            .filter(|poly| poly.annotation.contains("invariant counter step"))
+            .collect();
+
+        assert_eq!(invariants.len(), 1);
+        assert!(invariants[0].annotation.starts_with("increment::"));
+    }
+
+    #[test]
+    fn test_lookup_batching() {
+        let table_column = halo2_proofs::plonk::ConstraintSystem::<Fr>::default().fixed_column();
+
+        let sc = circuit::<Fr, (), (), _>("batching", |ctx| {
+            let a = ctx.forward("a");
+            let table = ctx.import_halo2_fixed("table", table_column);
+            let first = ctx.step_type("first");
+            let second = ctx.step_type("second");
+
+            ctx.step_type_def(first, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.add_lookup(|lookup| {
+                        lookup.add(a, table);
+                    });
+                    ctx.add_lookup(|lookup| {
+                        lookup.add(a + 1, table);
+                    });
+                });
+            });
+            ctx.step_type_def(second, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.add_lookup(|lookup| {
+                        lookup.add(a * 2, table);
+                    });
+                });
+            });
+        });
+
+        let compile = |batching| {
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {})
+                .with_lookup_batching(batching)
+                .compile(&sc)
+        };
+
+        assert_eq!(compile(false).lookups.len(), 3);
+        // the lookup of second is merged with one of the lookups of first
+        assert_eq!(compile(true).lookups.len(), 2);
+    }
+
+    #[test]
+    fn test_step_lookup() {
+        let sc = circuit::<Fr, (), (), _>("calls", |ctx| {
+            let create = ctx.step_type("create");
+            let call = ctx.step_type("call");
+
+            let mut id = None;
+            ctx.step_type_def(create, |ctx| id = Some(ctx.internal("id")));
+            let id = id.unwrap();
+            ctx.step_type_def(call, |ctx| {
+                let callee = ctx.internal("callee");
+                ctx.setup(move |ctx| {
+                    ctx.add_lookup(|lookup| {
+                        lookup.add(callee, id).from_step(create);
+                    });
+                });
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let step_selector = |name: &str| {
+            let step = compiled
+                .step_types
+                .values()
+                .find(|step| step.name == name)
+                .unwrap();
+            compiled
+                .selector
+                .select(step, &PolyExpr::Const(Fr::from(1)))
+        };
+
+        assert_eq!(compiled.lookups.len(), 1);
+        assert!(compiled.lookups[0].annotation.contains("in step create"));
+        let exprs = &compiled.lookups[0].exprs;
+        assert_eq!(exprs.len(), 2);
+        // the selectors of the steps, then the values
+        let selector = |name: &str| match step_selector(name) {
+            PolyExpr::Mul(selector) => format!("{:?}", selector[0]),
+            _ => unreachable!(),
+        };
+        assert!(format!("{:?}", exprs[0].0).contains(&selector("call")));
+        assert!(format!("{:?}", exprs[0].1).contains(&selector("create")));
+        let (src, dest) = &exprs[1];
+        assert!(format!("{:?}", dest).contains("id["));
+        assert!(format!("{:?}", src).contains("callee["));
+        match dest {
+            PolyExpr::Mul(dest) => assert_eq!(format!("{:?}", dest[0]), selector("create")),
+            _ => panic!("table side is not selected"),
+        }
+    }
+
+    #[test]
+    fn test_step_lookup_mock_prover() {
+        let calls = || {
+            circuit::<Fr, u64, u64, _>("calls", |ctx| {
+                let create = ctx.step_type("create");
+                let call = ctx.step_type("call");
+
+                let mut id = None;
+                ctx.step_type_def(create, |ctx| {
+                    let signal = ctx.internal("id");
+                    ctx.wg(move |ctx, v: u64| ctx.assign(signal, v.field()));
+                    id = Some(signal);
+                });
+                let id = id.unwrap();
+                ctx.step_type_def(call, |ctx| {
+                    let callee = ctx.internal("callee");
+                    ctx.setup(move |ctx| {
+                        ctx.add_lookup(|lookup| {
+                            lookup.add(callee, id).from_step(create);
+                        });
+                    });
+                    ctx.wg(move |ctx, v: u64| ctx.assign(callee, v.field()));
+                });
+                ctx.trace(move |ctx, callee| {
+                    ctx.add(&create, 7);
+                    ctx.add(&call, callee);
+                });
+            })
+        };
+
+        let report = smoke_test(&calls(), 7, 4);
+        assert!(report.passed(), "{}", report);
+
+        // a zero callee matches the rows of no step type, but there is no create step with id 0
+        for callee in [0, 5] {
+            let report = smoke_test(&calls(), callee, 4);
+            assert!(!report.verified, "callee {}", callee);
+            assert!(
+                report
+                    .failures
+                    .iter()
+                    .any(|failure| failure.contains("lookup")),
+                "{}",
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn test_trace_constraints() {
+        let sc = circuit::<Fr, (), (), _>("air", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.pragma_num_steps(6);
+            ctx.boundary(0, eq(a, 1));
+            ctx.periodic(2, 1, eq(a, 0));
+            ctx.trace_constraint(TraceRows::Last, eq(a, 32));
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a.next(), a * 2)));
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let trace_polys: Vec<&Poly<Fr>> = compiled
+            .polys
+            .iter()
+            .filter(|poly| poly.annotation.starts_with("trace::"))
+            .collect();
+        assert_eq!(trace_polys.len(), 3);
+        // q_enable, the trace selector and the constraint
+        assert_eq!(trace_polys[0].expr.degree(), 3);
+
+        let rows = |index: usize| -> Vec<usize> {
+            let gen = &compiled.step_fixed[index].1;
+            (0..6).filter(|step| gen(*step) == Fr::from(1)).collect()
+        };
+        assert_eq!(rows(0), vec![0]);
+        assert_eq!(rows(1), vec![1, 3, 5]);
+        assert_eq!(rows(2), vec![5]);
+    }
+
+    fn doubling(num_steps: usize) -> astCircuit<Fr, u64, u64> {
+        circuit::<Fr, u64, u64, _>("doubling", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+            let last = ctx.step_type("last");
+
+            ctx.pragma_num_steps(num_steps);
+            ctx.boundary(0, eq(a, 1));
+            ctx.trace_constraint(TraceRows::Last, eq(a, 1 << (num_steps - 1)));
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a.next(), a * 2)));
+                ctx.wg(move |ctx, v: u64| ctx.assign(a, v.field()));
+            });
+            ctx.step_type_def(last, |ctx| {
+                ctx.wg(move |ctx, v: u64| ctx.assign(a, v.field()));
+            });
+            ctx.trace(move |ctx, first| {
+                for i in 0..num_steps - 1 {
+                    ctx.add(&step, first << i);
+                }
+                ctx.add(&last, first << (num_steps - 1));
+            });
+        })
+    }
+
+    #[test]
+    fn test_trace_constraints_mock_prover() {
+        let report = smoke_test(&doubling(4), 1, 4);
+        assert!(report.passed(), "{}", report);
+
+        // the steps double each other, but the first step is not one
+        let report = smoke_test(&doubling(4), 2, 4);
+        assert!(!report.verified);
+        assert!(
+            report
+                .failures
+                .iter()
+                .any(|failure| failure.contains("trace::") && failure.contains("at Step(0)")),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "queries the next step at the last step")]
+    fn test_trace_constraint_last_next() {
+        let sc = circuit::<Fr, (), (), _>("air", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.pragma_num_steps(4);
+            ctx.trace_constraint(TraceRows::Last, eq(a.next(), a));
+            ctx.step_type_def(step, |_| {});
+        });
+
+        Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+    }
+
+    fn grouped<D: Fn(&mut CircuitContext<Fr, (), ()>, Queriable<Fr>)>(def: D) {
+        let sc = circuit::<Fr, (), (), _>("schedule", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.pragma_num_steps(2);
+            ctx.pragma_step_schedule(StepSchedule::Grouped);
+            ctx.step_type_def(step, |_| {});
+            def(ctx, a);
+        });
+
+        Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "step schedule Grouped reorders the steps, but step type jump has transition \
+                    constraints"
+    )]
+    fn test_step_schedule_transition() {
+        grouped(|ctx, a| {
+            let jump = ctx.step_type("jump");
+            ctx.step_type_def(jump, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a, a.next())));
+            });
+        });
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "step schedule Grouped reorders the steps, but invariant steady queries the \
+                    next step"
+    )]
+    fn test_step_schedule_transition_invariant() {
+        grouped(|ctx, a| ctx.invariant("steady", eq(a, a.next())));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "step schedule Grouped reorders the steps, but the circuit has the trace \
+                    constraint"
+    )]
+    fn test_step_schedule_trace_constraint() {
+        grouped(|ctx, a| ctx.trace_constraint(TraceRows::Step(0), eq(a, 1)));
+    }
+}
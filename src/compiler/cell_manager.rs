@@ -55,6 +55,17 @@ impl<F, StepArgs> Placement<F, StepArgs> {
             .clone()
     }
 
+    /// The placements of the internal signals of `step`, in no particular order.
+    pub fn internal_placements(
+        &self,
+        step: &StepType<F, StepArgs>,
+    ) -> impl Iterator<Item = (&InternalSignal, &SignalPlacement)> {
+        self.steps
+            .get(step)
+            .into_iter()
+            .flat_map(|placement| placement.signals.iter())
+    }
+
     pub fn step_height(&self, step: &StepType<F, StepArgs>) -> u32 {
         self.steps.get(step).expect("step not found").height
     }
@@ -113,7 +124,7 @@ impl CellManager for SingleRowCellManager {
 
         let mut max_internal_width: u32 = 0;
 
-        for step in unit.step_types.values() {
+        for step in unit.ordered_step_types().iter() {
             let mut internal_signals: u32 = 0;
 
             let mut step_placement = StepPlacement {
@@ -161,6 +172,12 @@ pub struct MaxWidthCellManager {
     max_width: usize,
 }
 
+impl MaxWidthCellManager {
+    pub fn new(max_width: usize) -> Self {
+        Self { max_width }
+    }
+}
+
 impl CellManager for MaxWidthCellManager {
     fn place<F, StepArgs>(&self, unit: &mut CompilationUnit<F, StepArgs>) {
         let mut placement = Placement::<F, StepArgs> {
@@ -202,7 +219,7 @@ impl CellManager for MaxWidthCellManager {
             }
         }
 
-        for step in unit.step_types.values() {
+        for step in unit.ordered_step_types().iter() {
             let mut step_placement = StepPlacement {
                 height: if forward_signal_column > 0 {
                     (forward_signal_row + 1) as u32
@@ -0,0 +1,544 @@
+use std::{fmt, fs, path::Path};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::{ast::Circuit as astCircuit, ir::Circuit};
+
+use super::{
+    cell_manager::{CellManager, MaxWidthCellManager, SingleRowCellManager},
+    expr_limits::ExprLimits,
+    step_selector::{SimpleStepSelectorBuilder, StepSelectorBuilder, TwoStepsSelectorBuilder},
+    Compiler,
+};
+
+/// Cell manager strategy selected by a `CompilerConfig`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CellManagerConfig {
+    SingleRow,
+    MaxWidth(usize),
+}
+
+/// Step selector strategy selected by a `CompilerConfig`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepSelectorConfig {
+    Simple,
+    TwoSteps { hint_one: Option<String> },
+}
+
+/// Circuit parameters that can be changed without recompiling the circuit code. `k` is not used
+/// by the compiler itself, it is carried so that the code building the circuit and running the
+/// prover can read it from the same place. `num_steps` is the number of steps of the circuits that
+/// do not set it with `pragma_num_steps`.
+///
+/// The `dev` and `prod` profiles, see `CompilerConfig::profile`, are starting points for the
+/// configuration of development and production builds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompilerConfig {
+    pub cell_manager: CellManagerConfig,
+    pub step_selector: StepSelectorConfig,
+    pub k: Option<u32>,
+    pub num_steps: Option<usize>,
+    /// Whether the debug only constraints are compiled, by default in debug builds only.
+    pub debug_constraints: Option<bool>,
+    pub lookup_batching: bool,
+    /// Maximum degree of the constraints, including the selectors, that the circuit is built for,
+    /// for example 5 for the usual halo2 parameters.
+    pub max_degree: Option<usize>,
+    /// Whether the constraints above `max_degree` are rewritten to fit instead of failing the
+    /// compilation, see `Compiler::with_degree_reduction`.
+    pub degree_reduction: bool,
+    /// Limits on the size of the expressions of the constraints, see `ExprLimits`.
+    pub expr_limits: ExprLimits,
+}
+
+impl Default for CompilerConfig {
+    fn default() -> Self {
+        Self {
+            cell_manager: CellManagerConfig::SingleRow,
+            step_selector: StepSelectorConfig::Simple,
+            k: None,
+            num_steps: None,
+            debug_constraints: None,
+            lookup_batching: false,
+            max_degree: None,
+            degree_reduction: false,
+            expr_limits: ExprLimits::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    UnsupportedFormat(String),
+    Syntax {
+        line: usize,
+        content: String,
+    },
+    UnknownKey(String),
+    /// A key set more than once.
+    DuplicateKey(String),
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+    UnknownProfile(String),
+    /// A `profile` key after the first key, at the given line.
+    MisplacedProfile(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "cannot read config: {}", err),
+            ConfigError::UnsupportedFormat(ext) => {
+                write!(f, "unsupported config format '{}', use toml or yaml", ext)
+            }
+            ConfigError::Syntax { line, content } => {
+                write!(f, "syntax error at line {}: '{}'", line, content)
+            }
+            ConfigError::UnknownKey(key) => write!(f, "unknown config key '{}'", key),
+            ConfigError::DuplicateKey(key) => write!(f, "config key '{}' is set twice", key),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value '{}' for config key '{}'", value, key)
+            }
+            ConfigError::UnknownProfile(name) => {
+                write!(f, "unknown profile '{}', use dev or prod", name)
+            }
+            ConfigError::MisplacedProfile(line) => write!(
+                f,
+                "profile key at line {} has to be the first key of the config",
+                line
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl CompilerConfig {
+    /// The named compilation profile:
+    ///
+    /// * `dev`: the debug only constraints are compiled and the lookups are kept apart, so that a
+    ///   failing lookup points to its step type. Circuits without `pragma_num_steps` get 16 steps,
+    ///   to keep `k` small while testing.
+    /// * `prod`: the debug only constraints are left out and the lookups into the same table are
+    ///   batched, for smaller proving and verifying keys.
+    pub fn profile(name: &str) -> Result<Self, ConfigError> {
+        match name {
+            "dev" => Ok(Self {
+                num_steps: Some(16),
+                debug_constraints: Some(true),
+                lookup_batching: false,
+                ..Default::default()
+            }),
+            "prod" => Ok(Self {
+                debug_constraints: Some(false),
+                lookup_batching: true,
+                ..Default::default()
+            }),
+            _ => Err(ConfigError::UnknownProfile(name.to_string())),
+        }
+    }
+
+    /// Loads the configuration from a `.toml`, `.yaml` or `.yml` file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&source),
+            Some("yaml") | Some("yml") => Self::from_yaml_str(&source),
+            ext => Err(ConfigError::UnsupportedFormat(
+                ext.unwrap_or_default().to_string(),
+            )),
+        }
+    }
+
+    /// Parses a flat TOML document of `key = value` lines. A `profile` key, which has to come
+    /// first, starts from that profile instead of the default configuration. Comments start with
+    /// a `#` outside quoted values, and each key can be set once.
+    pub fn from_toml_str(source: &str) -> Result<Self, ConfigError> {
+        Self::parse(source, '=')
+    }
+
+    /// Parses a flat YAML document of `key: value` lines.
+    pub fn from_yaml_str(source: &str) -> Result<Self, ConfigError> {
+        Self::parse(source, ':')
+    }
+
+    /// Compiles the circuit with the cell manager and step selector given by this configuration.
+    pub fn compile<F: Field + Clone, TraceArgs, StepArgs>(
+        &self,
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+    ) -> Circuit<F, TraceArgs, StepArgs> {
+        match self.cell_manager {
+            CellManagerConfig::SingleRow => self.compile_with(SingleRowCellManager {}, sc),
+            CellManagerConfig::MaxWidth(max_width) => {
+                self.compile_with(MaxWidthCellManager::new(max_width), sc)
+            }
+        }
+    }
+
+    fn compile_with<CM: CellManager, F: Field + Clone, TraceArgs, StepArgs>(
+        &self,
+        cell_manager: CM,
+        sc: &astCircuit<F, TraceArgs, StepArgs>,
+    ) -> Circuit<F, TraceArgs, StepArgs> {
+        match &self.step_selector {
+            StepSelectorConfig::Simple => self
+                .configure(Compiler::new(cell_manager, SimpleStepSelectorBuilder {}))
+                .compile(sc),
+            StepSelectorConfig::TwoSteps { hint_one } => self
+                .configure(Compiler::new(
+                    cell_manager,
+                    TwoStepsSelectorBuilder {
+                        halo2_column: None,
+                        hint_one: hint_one.clone(),
+                    },
+                ))
+                .compile(sc),
+        }
+    }
+
+    fn configure<CM: CellManager, SSB: StepSelectorBuilder>(
+        &self,
+        compiler: Compiler<CM, SSB>,
+    ) -> Compiler<CM, SSB> {
+        let mut compiler = compiler
+            .with_lookup_batching(self.lookup_batching)
+            .with_default_num_steps(self.num_steps.unwrap_or_default())
+            .with_degree_reduction(self.degree_reduction)
+            .with_expr_limits(self.expr_limits);
+        if let Some(max_degree) = self.max_degree {
+            compiler = compiler.with_max_degree(max_degree);
+        }
+
+        match self.debug_constraints {
+            Some(enabled) => compiler.with_debug_constraints(enabled),
+            None => compiler,
+        }
+    }
+
+    fn parse(source: &str, separator: char) -> Result<Self, ConfigError> {
+        let mut config = CompilerConfig::default();
+        let mut cell_manager: Option<String> = None;
+        let mut max_width: Option<usize> = None;
+        let mut step_selector: Option<String> = None;
+        let mut hint_one: Option<String> = None;
+        let first = source
+            .lines()
+            .position(|line| {
+                let line = strip_comment(line).trim();
+                !line.is_empty() && line != "---"
+            })
+            .unwrap_or_default();
+
+        let mut keys: Vec<&str> = Vec::new();
+        for (n, line) in source.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() || line == "---" {
+                continue;
+            }
+
+            let (key, value) = line.split_once(separator).ok_or(ConfigError::Syntax {
+                line: n + 1,
+                content: line.to_string(),
+            })?;
+            let key = key.trim();
+            let value = unquote(key, value.trim())?;
+            if keys.contains(&key) {
+                return Err(ConfigError::DuplicateKey(key.to_string()));
+            }
+            keys.push(key);
+
+            match key {
+                "profile" if n == first => config = Self::profile(value)?,
+                "profile" => return Err(ConfigError::MisplacedProfile(n + 1)),
+                "k" => config.k = Some(parse_value(key, value)?),
+                "num_steps" => config.num_steps = Some(parse_value(key, value)?),
+                "cell_manager" => cell_manager = Some(value.to_string()),
+                "max_width" => max_width = Some(parse_value(key, value)?),
+                "step_selector" => step_selector = Some(value.to_string()),
+                "hint_one" => hint_one = Some(value.to_string()),
+                "debug_constraints" => config.debug_constraints = Some(parse_value(key, value)?),
+                "lookup_batching" => config.lookup_batching = parse_value(key, value)?,
+                "max_degree" => config.max_degree = Some(parse_value(key, value)?),
+                "degree_reduction" => config.degree_reduction = parse_value(key, value)?,
+                "max_expr_nodes" => config.expr_limits.max_nodes = Some(parse_value(key, value)?),
+                "max_expr_depth" => config.expr_limits.max_depth = Some(parse_value(key, value)?),
+                _ => return Err(ConfigError::UnknownKey(key.to_string())),
+            }
+        }
+
+        config.cell_manager = match (cell_manager.as_deref(), max_width) {
+            (None | Some("single_row"), None) => CellManagerConfig::SingleRow,
+            (None | Some("max_width"), Some(max_width)) => CellManagerConfig::MaxWidth(max_width),
+            (Some("max_width"), None) => {
+                return Err(ConfigError::InvalidValue {
+                    key: "max_width".to_string(),
+                    value: "".to_string(),
+                })
+            }
+            (Some(value), _) => {
+                return Err(ConfigError::InvalidValue {
+                    key: "cell_manager".to_string(),
+                    value: value.to_string(),
+                })
+            }
+        };
+
+        config.step_selector = match step_selector.as_deref() {
+            None | Some("simple") if hint_one.is_none() => StepSelectorConfig::Simple,
+            None | Some("two_steps") => StepSelectorConfig::TwoSteps { hint_one },
+            Some(value) => {
+                return Err(ConfigError::InvalidValue {
+                    key: "step_selector".to_string(),
+                    value: value.to_string(),
+                })
+            }
+        };
+
+        Ok(config)
+    }
+}
+
+/// The part of `line` before its comment, a `#` that is not in a quoted value.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (c, quote) {
+            ('#', None) => return &line[..i],
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// The value without its quotes, if it starts and ends with the same quote character.
+fn unquote<'a>(key: &str, value: &'a str) -> Result<&'a str, ConfigError> {
+    let is_quote = |c: char| c == '"' || c == '\'';
+    let (first, last) = (value.chars().next(), value.chars().last());
+
+    match (first, last) {
+        (Some(first), Some(last)) if is_quote(first) && first == last && value.len() > 1 => {
+            Ok(&value[1..value.len() - 1])
+        }
+        (Some(c), _) | (_, Some(c)) if is_quote(c) => Err(ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        }),
+        _ => Ok(value),
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        ci::catch,
+        compiler::expr_limits::DEFAULT_MAX_NODES,
+        dsl::{cb::eq, circuit},
+        wit_gen::{step_test::test_step, GenericTraceContext},
+    };
+
+    #[test]
+    fn test_from_toml_str() {
+        let config = CompilerConfig::from_toml_str(
+            r#"
+            # fibonacci deployment
+            k = 7
+            num_steps = 11
+            cell_manager = "max_width"
+            max_width = 2
+            step_selector = "two_steps"
+            hint_one = "last step"
+            "#,
+        )
+        .expect("should parse");
+
+        assert_eq!(config.k, Some(7));
+        assert_eq!(config.num_steps, Some(11));
+        assert_eq!(config.cell_manager, CellManagerConfig::MaxWidth(2));
+        assert_eq!(
+            config.step_selector,
+            StepSelectorConfig::TwoSteps {
+                hint_one: Some("last step".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_yaml_str() {
+        let config = CompilerConfig::from_yaml_str(
+            "---\nk: 10\nstep_selector: simple\nmax_expr_depth: 64\n",
+        )
+        .expect("should parse");
+
+        assert_eq!(config.k, Some(10));
+        assert_eq!(config.num_steps, None);
+        assert_eq!(config.cell_manager, CellManagerConfig::SingleRow);
+        assert_eq!(config.step_selector, StepSelectorConfig::Simple);
+        assert_eq!(
+            config.expr_limits,
+            ExprLimits {
+                max_nodes: Some(DEFAULT_MAX_NODES),
+                max_depth: Some(64),
+            }
+        );
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(matches!(
+            CompilerConfig::from_toml_str("backend = \"plonky2\""),
+            Err(ConfigError::UnknownKey(key)) if key == "backend"
+        ));
+        assert!(matches!(
+            CompilerConfig::from_toml_str("k = seven"),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "k"
+        ));
+        assert!(matches!(
+            CompilerConfig::from_toml_str("[compiler]"),
+            Err(ConfigError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            CompilerConfig::from_toml_str("cell_manager = \"max_width\""),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "max_width"
+        ));
+        assert!(matches!(
+            CompilerConfig::from_toml_str("k = 7\nnum_steps = 4\nk = 8"),
+            Err(ConfigError::DuplicateKey(key)) if key == "k"
+        ));
+        for value in ["\"abc'", "'abc", "abc\"", "\""] {
+            assert!(matches!(
+                CompilerConfig::from_toml_str(&format!("hint_one = {}", value)),
+                Err(ConfigError::InvalidValue { key, value: invalid })
+                    if key == "hint_one" && invalid == value
+            ));
+        }
+        assert!(matches!(
+            CompilerConfig::from_yaml_str("hint_one: 'last \"step\"'").unwrap().step_selector,
+            StepSelectorConfig::TwoSteps { hint_one: Some(hint) } if hint == "last \"step\""
+        ));
+    }
+
+    #[test]
+    fn test_config_comments() {
+        let config = CompilerConfig::from_toml_str(
+            "step_selector = \"two_steps\" # comment\nhint_one = \"step #1\" # the 'first'\n",
+        )
+        .expect("should parse");
+        assert_eq!(
+            config.step_selector,
+            StepSelectorConfig::TwoSteps {
+                hint_one: Some("step #1".to_string())
+            }
+        );
+
+        let config = CompilerConfig::from_yaml_str("hint_one: 'a # b'\n# k: 4\n").unwrap();
+        assert_eq!(config.k, None);
+        assert_eq!(
+            config.step_selector,
+            StepSelectorConfig::TwoSteps {
+                hint_one: Some("a # b".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dev = CompilerConfig::profile("dev").unwrap();
+        assert_eq!(dev.debug_constraints, Some(true));
+        assert_eq!(dev.num_steps, Some(16));
+        assert!(matches!(
+            CompilerConfig::profile("staging"),
+            Err(ConfigError::UnknownProfile(name)) if name == "staging"
+        ));
+
+        let config =
+            CompilerConfig::from_toml_str("profile = \"prod\"\nlookup_batching = false\nk = 12")
+                .expect("should parse");
+        assert_eq!(config.debug_constraints, Some(false));
+        assert!(!config.lookup_batching);
+        assert_eq!(config.k, Some(12));
+        assert!(matches!(
+            CompilerConfig::from_toml_str("k = 12\nprofile = \"prod\""),
+            Err(ConfigError::MisplacedProfile(2))
+        ));
+
+        let sc = circuit::<Fr, (), (), _>("profiles", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a, a));
+                    ctx.debug_only(|ctx| ctx.constr(eq(a, 1)));
+                });
+            });
+        });
+
+        let dev = dev.compile(&sc);
+        let prod = CompilerConfig::profile("prod").unwrap().compile(&sc);
+        assert_eq!(dev.num_steps, 16);
+        assert_eq!(prod.num_steps, 0);
+        assert_eq!(dev.polys.len(), prod.polys.len() + 1);
+    }
+
+    #[test]
+    fn test_max_degree() {
+        let sc = circuit::<Fr, (), u64, _>("degree", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a * a * a * a * a, b)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, v.pow(5).field());
+                });
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 3));
+        });
+
+        let config = CompilerConfig::from_toml_str("max_degree = 5\ndegree_reduction = true")
+            .expect("should parse");
+        let compiled = config.compile(&sc);
+        assert!(compiled.polys.iter().all(|poly| poly.expr.degree() <= 5));
+
+        // the signals added by the reduction are derived during trace generation
+        let step = compiled.step_types.values().next().unwrap();
+        assert!(step.signals.len() > 1);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+        assert!(test_step(step, &witness.step_instances[0].assignments, None).is_empty());
+
+        let strict = CompilerConfig {
+            max_degree: Some(5),
+            ..Default::default()
+        };
+        let message = catch(|| strict.compile(&sc)).unwrap_err();
+        assert!(
+            message.contains("but the target degree is 5"),
+            "{}",
+            message
+        );
+    }
+}
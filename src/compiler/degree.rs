@@ -0,0 +1,168 @@
+use halo2_proofs::arithmetic::Field;
+
+use crate::ast::{query::Queriable, Constraint, Expr, InternalSignal, StepType};
+
+/// Degree added to every constraint of a step type by `q_enable` and the step selector.
+pub const SELECTOR_DEGREE: usize = 2;
+
+/// Rewrites the constraints of `step` with a degree above `max_degree`, once the selectors are
+/// added, so that they fit. Groups of factors of a product are replaced by a new internal signal,
+/// constrained to be equal to the group, which the trace generation derives from the other
+/// signals of the step, see `derive_assignments`. Only factors that query the signals of the step
+/// itself are grouped, so constraints whose degree comes from the next step or from halo2 columns
+/// are left as they are. Returns `None` when no constraint has to be rewritten.
+pub fn reduce_degree<F: Field, Args>(
+    step: &StepType<F, Args>,
+    max_degree: usize,
+) -> Option<StepType<F, Args>> {
+    let budget = max_degree.saturating_sub(SELECTOR_DEGREE);
+    let exceeded = |expr: &Expr<F>| expr.degree() > budget;
+
+    if !step
+        .constraints
+        .iter()
+        .chain(step.debug_constraints.iter())
+        .map(|constr| &constr.expr)
+        .chain(
+            step.transition_constraints
+                .iter()
+                .chain(step.debug_transition_constraints.iter())
+                .map(|constr| &constr.expr),
+        )
+        .any(exceeded)
+    {
+        return None;
+    }
+
+    if budget < 2 {
+        panic!(
+            "cannot reduce the constraints of step type {} to degree {}, the selectors already add degree {}",
+            step.name, max_degree, SELECTOR_DEGREE
+        );
+    }
+
+    let mut reduced = step.clone();
+    let mut reducer = Reducer {
+        budget,
+        signals: Vec::new(),
+        constraints: Vec::new(),
+    };
+
+    for constr in reduced
+        .constraints
+        .iter_mut()
+        .chain(reduced.debug_constraints.iter_mut())
+        .filter(|constr| exceeded(&constr.expr))
+    {
+        constr.expr = reducer.reduce(constr.expr.clone());
+    }
+    for constr in reduced
+        .transition_constraints
+        .iter_mut()
+        .chain(reduced.debug_transition_constraints.iter_mut())
+        .filter(|constr| exceeded(&constr.expr))
+    {
+        constr.expr = reducer.reduce(constr.expr.clone());
+    }
+
+    for (signal, name) in reducer.signals {
+        reduced.signals.push(signal);
+        reduced.annotations.insert(signal.uuid(), name);
+    }
+    reduced.constraints.extend(reducer.constraints);
+
+    Some(reduced)
+}
+
+struct Reducer<F> {
+    budget: usize,
+    signals: Vec<(InternalSignal, String)>,
+    constraints: Vec<Constraint<F>>,
+}
+
+impl<F: Field> Reducer<F> {
+    fn reduce(&mut self, expr: Expr<F>) -> Expr<F> {
+        if expr.degree() <= self.budget {
+            return expr;
+        }
+
+        match expr {
+            Expr::Sum(exprs) => {
+                Expr::Sum(exprs.into_iter().map(|expr| self.reduce(expr)).collect())
+            }
+            Expr::Neg(expr) => Expr::Neg(Box::new(self.reduce(*expr))),
+            Expr::Pow(expr, exp) => self.reduce_product(vec![*expr; exp as usize]),
+            Expr::Mul(exprs) => self.reduce_product(exprs),
+            expr => expr,
+        }
+    }
+
+    fn reduce_product(&mut self, factors: Vec<Expr<F>>) -> Expr<F> {
+        let mut factors: Vec<Expr<F>> = factors
+            .into_iter()
+            .map(|factor| self.reduce(factor))
+            .collect();
+
+        while factors.iter().map(Expr::degree).sum::<usize>() > self.budget {
+            let mut grouped = Vec::new();
+            let mut group: Vec<Expr<F>> = Vec::new();
+            let mut changed = false;
+
+            for factor in factors {
+                if !is_local(&factor) {
+                    grouped.push(factor);
+                    continue;
+                }
+
+                let degree: usize = group.iter().map(Expr::degree).sum();
+                if !group.is_empty() && degree + factor.degree() > self.budget {
+                    changed |= self.push_group(&mut grouped, std::mem::take(&mut group));
+                }
+                group.push(factor);
+            }
+            changed |= self.push_group(&mut grouped, group);
+
+            factors = grouped;
+            if !changed {
+                break;
+            }
+        }
+
+        Expr::Mul(factors)
+    }
+
+    /// Replaces a group of factors of degree above one by a new signal, returning whether it did.
+    fn push_group(&mut self, grouped: &mut Vec<Expr<F>>, group: Vec<Expr<F>>) -> bool {
+        let product = Expr::Mul(group);
+        if product.degree() <= 1 {
+            if let Expr::Mul(group) = product {
+                grouped.extend(group);
+            }
+            return false;
+        }
+
+        let name = format!("reduced factor {}", self.signals.len());
+        let signal = InternalSignal::new(name.clone());
+        self.signals.push((signal, name));
+        let query = Expr::Query(Queriable::Internal(signal));
+
+        self.constraints.push(Constraint {
+            annotation: format!("{:?} == {:?}", query, product),
+            expr: query.clone() - product,
+        });
+        grouped.push(query);
+
+        true
+    }
+}
+
+/// Whether the expression only queries signals that the witness of the step assigns.
+fn is_local<F>(expr: &Expr<F>) -> bool {
+    match expr {
+        Expr::Const(_) => true,
+        Expr::Sum(exprs) | Expr::Mul(exprs) => exprs.iter().all(is_local),
+        Expr::Neg(expr) | Expr::Pow(expr, _) => is_local(expr),
+        Expr::Query(Queriable::Internal(_)) | Expr::Query(Queriable::Forward(_, false)) => true,
+        Expr::Query(_) | Expr::Halo2Expr(_) => false,
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use crate::ast::{Circuit, Expr};
+
+/// Default maximum number of nodes of an expression, see `ExprLimits`.
+pub const DEFAULT_MAX_NODES: usize = 1 << 20;
+/// Default maximum nesting depth of an expression, see `ExprLimits`.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Limits on the size of the expressions of the constraints, transitions and lookups of the step
+/// types, checked when a constraint is added to a step type, see
+/// `CircuitContext::expr_limits`, and before the compiler walks them, see
+/// `Compiler::with_expr_limits`. An expression generated by code can grow without bound, for
+/// example a sum folded over every element of a large array, and then only fails much later,
+/// with a stack overflow in one of the recursive passes or a compilation that never ends. By
+/// default the limits are `DEFAULT_MAX_NODES` and `DEFAULT_MAX_DEPTH`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExprLimits {
+    /// Maximum number of nodes of an expression, counting every sum, product, negation, power,
+    /// query and constant.
+    pub max_nodes: Option<usize>,
+    /// Maximum nesting depth of an expression, a query or constant alone has depth 1.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for ExprLimits {
+    fn default() -> Self {
+        Self {
+            max_nodes: Some(DEFAULT_MAX_NODES),
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+        }
+    }
+}
+
+impl ExprLimits {
+    /// No limits, for circuits whose expressions are known to fit the stack.
+    pub fn none() -> Self {
+        Self {
+            max_nodes: None,
+            max_depth: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_nodes.is_none() && self.max_depth.is_none()
+    }
+}
+
+/// An expression above the limits of `check_expr_limits`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExprLimitError {
+    Nodes {
+        step_type: String,
+        constraint: String,
+        nodes: usize,
+        max_nodes: usize,
+    },
+    Depth {
+        step_type: String,
+        constraint: String,
+        depth: usize,
+        max_depth: usize,
+    },
+}
+
+impl fmt::Display for ExprLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprLimitError::Nodes {
+                step_type,
+                constraint,
+                nodes,
+                max_nodes,
+            } => write!(
+                f,
+                "constraint {} of step type {} has {} expression nodes, but the limit is {}; \
+                 split it by constraining parts of the expression to new internal signals and \
+                 using the signals instead",
+                constraint, step_type, nodes, max_nodes
+            ),
+            ExprLimitError::Depth {
+                step_type,
+                constraint,
+                depth,
+                max_depth,
+            } => write!(
+                f,
+                "constraint {} of step type {} nests expressions {} deep, but the limit is {}; \
+                 introduce intermediate internal signals for the inner expressions, or build \
+                 sums and products of many terms with a single sum or product instead of \
+                 chaining them",
+                constraint, step_type, depth, max_depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExprLimitError {}
+
+/// Checks the expression of the constraint `constraint` of the step type `step_type` against
+/// `limits`. Measures the expression without recursion, so it can be called on any expression.
+pub fn check_expr<F>(
+    limits: &ExprLimits,
+    step_type: &str,
+    constraint: &str,
+    expr: &Expr<F>,
+) -> Result<(), ExprLimitError> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let (nodes, depth) = expr.size();
+    match (limits.max_nodes, limits.max_depth) {
+        (Some(max_nodes), _) if nodes > max_nodes => Err(ExprLimitError::Nodes {
+            step_type: step_type.to_string(),
+            constraint: constraint.to_string(),
+            nodes,
+            max_nodes,
+        }),
+        (_, Some(max_depth)) if depth > max_depth => Err(ExprLimitError::Depth {
+            step_type: step_type.to_string(),
+            constraint: constraint.to_string(),
+            depth,
+            max_depth,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the expressions of every step type of `sc` against `limits`, in the order the step
+/// types were declared. Returns the first expression above a limit.
+pub fn check_expr_limits<F, TraceArgs, StepArgs>(
+    sc: &Circuit<F, TraceArgs, StepArgs>,
+    limits: &ExprLimits,
+) -> Result<(), ExprLimitError> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let mut steps: Vec<_> = sc.step_types.values().collect();
+    steps.sort_by_key(|step| step.uuid());
+
+    for step in steps {
+        let check =
+            |constraint: &str, expr: &Expr<F>| check_expr(limits, &step.name, constraint, expr);
+
+        for constraint in step.constraints.iter().chain(step.debug_constraints.iter()) {
+            check(&constraint.annotation, &constraint.expr)?;
+        }
+        for transition in step
+            .transition_constraints
+            .iter()
+            .chain(step.debug_transition_constraints.iter())
+        {
+            check(&transition.annotation, &transition.expr)?;
+        }
+        for lookup in step.lookups.iter() {
+            for (source, table) in lookup.exprs.iter() {
+                check(&lookup.annotation, &source.expr)?;
+                check(&lookup.annotation, table)?;
+            }
+            if let Some(enable) = &lookup.enable {
+                check(&lookup.annotation, &enable.expr)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToExpr,
+        ci::catch,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_expr_limits() {
+        let sc = circuit::<Fr, (), (), _>("generated", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a, b));
+                    // a sum folded one term at a time nests as deep as it has terms
+                    let mut sum = a.expr();
+                    for _ in 0..20 {
+                        sum = Expr::Sum(vec![sum, b.expr()]);
+                    }
+                    ctx.transition(eq(sum, a.next()));
+                });
+            });
+        });
+
+        let limits = ExprLimits {
+            max_nodes: Some(100),
+            max_depth: Some(10),
+        };
+        let err = check_expr_limits(&sc, &limits).unwrap_err();
+        assert!(matches!(
+            &err,
+            ExprLimitError::Depth { step_type, depth: 21, .. } if step_type == "step"
+        ));
+        assert!(format!("{}", err).contains("intermediate internal signals"));
+
+        let nodes = ExprLimits {
+            max_nodes: Some(20),
+            max_depth: None,
+        };
+        assert!(matches!(
+            check_expr_limits(&sc, &nodes),
+            Err(ExprLimitError::Nodes { nodes: 43, .. })
+        ));
+        assert_eq!(check_expr_limits(&sc, &ExprLimits::default()), Ok(()));
+        assert_eq!(check_expr_limits(&sc, &ExprLimits::none()), Ok(()));
+
+        let message = catch(|| {
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {})
+                .with_expr_limits(limits)
+                .compile(&sc)
+        })
+        .unwrap_err();
+        assert!(message.contains("nests expressions 21 deep"), "{}", message);
+    }
+}
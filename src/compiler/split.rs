@@ -0,0 +1,420 @@
+use std::{fmt, hash::Hash, rc::Rc};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::{query::Queriable, Circuit as astCircuit, ExposeOffset, StepSchedule},
+    backend::workspace::ProvingBackend,
+    ir::Circuit,
+    metrics,
+    wit_gen::TraceWitness,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    /// The backend failed to prove the chunk.
+    Prove { chunk: usize, err: String },
+    /// The inputs of the chunk are not the outputs of the previous one.
+    Unlinked { chunk: usize },
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitError::Prove { chunk, err } => write!(f, "cannot prove chunk {}: {}", chunk, err),
+            SplitError::Unlinked { chunk } => write!(
+                f,
+                "inputs of chunk {} are not the outputs of the previous chunk",
+                chunk
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+/// A part of the trace of a split circuit, with the circuit that proves it.
+pub struct Chunk<F, StepArgs> {
+    pub index: usize,
+    /// Index in the trace of the first step of the chunk.
+    pub start: usize,
+    pub steps: usize,
+    pub circuit: astCircuit<F, (), StepArgs>,
+    /// The steps of the chunk, followed by the first step of the next chunk, if any.
+    pub witness: TraceWitness<F>,
+    /// Values of the forward signals at the first step, none for the first chunk.
+    pub inputs: Vec<F>,
+    /// Values of the forward signals at the first step of the next chunk, none for the last.
+    pub outputs: Vec<F>,
+}
+
+/// Proof of a chunk, to be aggregated with the proofs of the other chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkProof<F> {
+    pub index: usize,
+    pub public_inputs: Vec<F>,
+    pub inputs: Vec<F>,
+    pub outputs: Vec<F>,
+    pub proof: Vec<u8>,
+}
+
+/// Experimental. Splits a circuit whose trace does not fit in a row budget in chunks of at most
+/// `chunk_steps` steps, each proved by its own circuit, so that the proofs can be aggregated.
+///
+/// The chunks are connected by their forward signals. The witness of a chunk ends with a copy of
+/// the first step of the next chunk, the boundary step, in rows where the constraints are not
+/// enabled, so that the transition constraints of the last step of the chunk hold. The forward
+/// signals are exposed at the first step of each chunk but the first, its inputs, and at the
+/// boundary step of each chunk but the last, its outputs, after the signals that the circuit
+/// exposes itself. `check_links` checks that the outputs of each chunk are the inputs of the next.
+///
+/// Only the first chunk constrains the first step type, and only the last the last step type.
+/// The step type of the boundary step is not linked, only its forward signals. Circuits with
+/// step-indexed fixed signals, trace constraints or a schedule other than `InOrder` cannot be
+/// split, and the forward signals must be assigned at the first step of every chunk.
+pub struct CircuitSplit<'a, F, TraceArgs, StepArgs> {
+    circuit: &'a astCircuit<F, TraceArgs, StepArgs>,
+    step_height: usize,
+    chunk_steps: usize,
+}
+
+impl<'a, F: PrimeField + Hash, TraceArgs, StepArgs> CircuitSplit<'a, F, TraceArgs, StepArgs> {
+    /// Splits `circuit`, compiled as `compiled`, in chunks with at most `row_budget` rows,
+    /// including the rows of the boundary step. The step types must have the same height.
+    pub fn new(
+        circuit: &'a astCircuit<F, TraceArgs, StepArgs>,
+        compiled: &Circuit<F, TraceArgs, StepArgs>,
+        row_budget: usize,
+    ) -> Self {
+        if !circuit.step_fixed.is_empty() || !circuit.trace_constraints.is_empty() {
+            panic!("circuit with step-indexed fixed signals or trace constraints cannot be split");
+        }
+        if circuit.step_schedule != StepSchedule::InOrder {
+            panic!(
+                "circuit with a {:?} step schedule cannot be split",
+                circuit.step_schedule
+            );
+        }
+        if !compiled.placement.same_height() {
+            panic!("circuit with step types of different heights cannot be split");
+        }
+
+        let step_height = compiled
+            .step_types
+            .values()
+            .map(|step| compiled.placement.step_height(step) as usize)
+            .max()
+            .unwrap_or(1);
+        let chunk_steps = (row_budget / step_height).saturating_sub(1);
+        if chunk_steps == 0 {
+            panic!(
+                "row budget {} cannot fit a step and a boundary step of {} rows",
+                row_budget, step_height
+            );
+        }
+
+        Self {
+            circuit,
+            step_height,
+            chunk_steps,
+        }
+    }
+
+    /// Maximum number of steps of a chunk.
+    pub fn chunk_steps(&self) -> usize {
+        self.chunk_steps
+    }
+
+    /// Splits `witness`, a witness of the whole trace, in chunks.
+    pub fn split(&self, witness: &TraceWitness<F>) -> Vec<Chunk<F, StepArgs>> {
+        let total = witness.step_instances.len();
+        let count = (total + self.chunk_steps - 1) / self.chunk_steps;
+
+        (0..count)
+            .map(|index| {
+                let start = index * self.chunk_steps;
+                let steps = self.chunk_steps.min(total - start);
+                let boundary = start + steps < total;
+
+                let circuit = self.chunk_circuit(index, count, start, steps, boundary);
+                let end = if boundary {
+                    start + steps + 1
+                } else {
+                    start + steps
+                };
+                let mut chunk_witness = TraceWitness {
+                    step_instances: witness.step_instances[start..end].to_vec(),
+                    height: steps * self.step_height,
+                    public_values: Vec::new(),
+                };
+                chunk_witness.public_values = chunk_witness.public_inputs(&circuit);
+
+                // the public values are the exposed signals of the circuit, the inputs and the
+                // outputs
+                let state = self.circuit.forward_signals.len();
+                let outputs = chunk_witness.public_values.len() - if boundary { state } else { 0 };
+                let inputs = outputs - if index > 0 { state } else { 0 };
+
+                Chunk {
+                    index,
+                    start,
+                    steps,
+                    inputs: chunk_witness.public_values[inputs..outputs].to_vec(),
+                    outputs: chunk_witness.public_values[outputs..].to_vec(),
+                    circuit,
+                    witness: chunk_witness,
+                }
+            })
+            .collect()
+    }
+
+    fn chunk_circuit(
+        &self,
+        index: usize,
+        count: usize,
+        start: usize,
+        steps: usize,
+        boundary: bool,
+    ) -> astCircuit<F, (), StepArgs> {
+        let sc = self.circuit;
+        let last = index + 1 == count;
+
+        let mut exposed: Vec<(Queriable<F>, ExposeOffset)> = sc
+            .exposed
+            .iter()
+            .filter_map(|(signal, offset)| match offset {
+                ExposeOffset::First if index == 0 => Some((*signal, ExposeOffset::First)),
+                ExposeOffset::Last if last => Some((*signal, ExposeOffset::Last)),
+                ExposeOffset::Step(step) if (start..start + steps).contains(step) => {
+                    Some((*signal, ExposeOffset::Step(step - start)))
+                }
+                _ => None,
+            })
+            .collect();
+        let state = |offset: ExposeOffset| {
+            sc.forward_signals
+                .iter()
+                .map(move |signal| (Queriable::Forward(*signal, false), offset))
+        };
+        if index > 0 {
+            exposed.extend(state(ExposeOffset::First));
+        }
+        if boundary {
+            exposed.extend(state(ExposeOffset::Step(steps)));
+        }
+
+        astCircuit {
+            forward_signals: sc.forward_signals.clone(),
+            external_signals: sc.external_signals.clone(),
+            halo2_advice: sc.halo2_advice.clone(),
+            halo2_fixed: sc.halo2_fixed.clone(),
+            step_types: sc.step_types.clone(),
+            trace: None,
+            try_trace: None,
+            fixed_gen: sc.fixed_gen.as_ref().map(Rc::clone),
+            annotations: sc.annotations.clone(),
+            aliases: sc.aliases.clone(),
+            first_step: sc.first_step.filter(|_| index == 0),
+            last_step: sc.last_step.filter(|_| last),
+            num_steps: 0,
+            step_schedule: StepSchedule::InOrder,
+            exposed,
+            exposed_results: sc.exposed_results.clone(),
+            derived_values: sc.derived_values.clone(),
+            padding_steps: sc.padding_steps.clone(),
+            padding_allowed: sc.padding_allowed.clone(),
+            signal_defaults: sc.signal_defaults.clone(),
+            signal_widths: sc.signal_widths.clone(),
+            step_fixed: Vec::new(),
+            fixed_tables: sc.fixed_tables.clone(),
+            challenges: sc.challenges.clone(),
+            invariants: sc.invariants.clone(),
+            trace_constraints: Vec::new(),
+        }
+    }
+}
+
+/// Compiles the circuit of each chunk with `compile` and proves its witness with `backend`, for
+/// parameters of size `2^k`.
+pub fn prove_chunks<F, StepArgs, B, C>(
+    backend: &B,
+    k: u32,
+    chunks: &[Chunk<F, StepArgs>],
+    compile: C,
+) -> Result<Vec<ChunkProof<F>>, SplitError>
+where
+    F: Clone,
+    StepArgs: Clone,
+    B: ProvingBackend<F>,
+    C: Fn(&astCircuit<F, (), StepArgs>) -> Circuit<F, (), StepArgs>,
+{
+    chunks
+        .iter()
+        .map(|chunk| {
+            let compiled = compile(&chunk.circuit);
+            let public_inputs = chunk.witness.public_values.clone();
+            let timer = metrics::Timer::start(metrics::PROVE_SECONDS);
+            let proof = backend
+                .prove(k, &compiled, chunk.witness.clone(), &public_inputs, &[])
+                .map_err(|err| SplitError::Prove {
+                    chunk: chunk.index,
+                    err,
+                })?;
+            timer.stop();
+
+            Ok(ChunkProof {
+                index: chunk.index,
+                public_inputs,
+                inputs: chunk.inputs.clone(),
+                outputs: chunk.outputs.clone(),
+                proof,
+            })
+        })
+        .collect()
+}
+
+/// Checks that the outputs of each chunk are the inputs of the next, the proofs in order.
+pub fn check_links<F: PartialEq>(proofs: &[ChunkProof<F>]) -> Result<(), SplitError> {
+    for pair in proofs.windows(2) {
+        if pair[0].outputs != pair[1].inputs {
+            return Err(SplitError::Unlinked {
+                chunk: pair[1].index,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{halo2curves::bn256::Fr, plonk::ConstraintSystem};
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::{halo2::ChiquitoHalo2, workspace::SharedTable},
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+        wit_gen::{public_inputs::encode_public_inputs, step_test::test_step, GenericTraceContext},
+    };
+
+    struct EchoBackend;
+
+    impl ProvingBackend<Fr> for EchoBackend {
+        fn name(&self) -> String {
+            "echo".to_string()
+        }
+
+        fn prove<TraceArgs, StepArgs: Clone>(
+            &self,
+            _: u32,
+            _: &Circuit<Fr, TraceArgs, StepArgs>,
+            _: TraceWitness<Fr>,
+            public_inputs: &[Fr],
+            _: &[SharedTable<Fr>],
+        ) -> Result<Vec<u8>, String> {
+            Ok(encode_public_inputs(public_inputs))
+        }
+
+        fn verify<TraceArgs, StepArgs: Clone>(
+            &self,
+            _: u32,
+            _: &Circuit<Fr, TraceArgs, StepArgs>,
+            public_inputs: &[Fr],
+            _: &[SharedTable<Fr>],
+            proof: &[u8],
+        ) -> Result<bool, String> {
+            Ok(proof == encode_public_inputs(public_inputs))
+        }
+    }
+
+    #[test]
+    fn test_circuit_split() {
+        let sc = circuit::<Fr, (), u64, _>("counter", |ctx| {
+            let a = ctx.forward("a");
+            let inc = ctx.step_type("inc");
+            let halt = ctx.step_type("halt");
+
+            ctx.step_type_def(inc, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a + 1, a.next())));
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.step_type_def(halt, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.pragma_first_step(inc);
+            ctx.pragma_last_step(halt);
+            ctx.expose(a, ExposeOffset::Last);
+            ctx.trace(move |ctx, _| {
+                for i in 0..7 {
+                    ctx.add(&inc, i);
+                }
+                ctx.add(&halt, 7);
+            });
+        });
+        let compiler = Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {});
+        let compiled = compiler.compile(&sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let split = CircuitSplit::new(&sc, &compiled, 4);
+        assert_eq!(split.chunk_steps(), 3);
+        let chunks = split.split(&witness);
+
+        let steps: Vec<(usize, usize, usize)> = chunks
+            .iter()
+            .map(|chunk| {
+                (
+                    chunk.steps,
+                    chunk.witness.step_instances.len(),
+                    chunk.witness.height,
+                )
+            })
+            .collect();
+        assert_eq!(steps, vec![(3, 4, 3), (3, 4, 3), (2, 2, 2)]);
+        let links: Vec<(Vec<Fr>, Vec<Fr>)> = chunks
+            .iter()
+            .map(|chunk| (chunk.inputs.clone(), chunk.outputs.clone()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                (vec![], vec![Fr::from(3)]),
+                (vec![Fr::from(3)], vec![Fr::from(6)]),
+                (vec![Fr::from(6)], vec![]),
+            ]
+        );
+        assert_eq!(
+            chunks[2].witness.public_values,
+            vec![Fr::from(7), Fr::from(6)]
+        );
+        assert!(chunks[0].circuit.first_step.is_some() && chunks[0].circuit.last_step.is_none());
+        assert!(chunks[2].circuit.first_step.is_none() && chunks[2].circuit.last_step.is_some());
+
+        for chunk in chunks.iter() {
+            let instances = &chunk.witness.step_instances;
+            for (i, instance) in instances.iter().take(chunk.steps).enumerate() {
+                let step = &chunk.circuit.step_types[&instance.step_type_uuid];
+                assert_eq!(
+                    test_step(step, &instance.assignments, instances.get(i + 1)),
+                    vec![]
+                );
+            }
+
+            let mut chunk_circuit = ChiquitoHalo2::new(compiler.compile(&chunk.circuit));
+            chunk_circuit.configure(&mut ConstraintSystem::default());
+            assert_eq!(
+                chunk_circuit
+                    .verify_instance_layout(chunk.witness.clone(), &chunk.witness.public_values),
+                Ok(())
+            );
+        }
+
+        let mut proofs = prove_chunks(&EchoBackend, 4, &chunks, |sc| compiler.compile(sc)).unwrap();
+        assert_eq!(check_links(&proofs), Ok(()));
+        proofs[1].inputs = vec![Fr::from(4)];
+        assert_eq!(check_links(&proofs), Err(SplitError::Unlinked { chunk: 1 }));
+    }
+}
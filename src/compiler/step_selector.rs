@@ -66,7 +66,7 @@ impl StepSelectorBuilder for SimpleStepSelectorBuilder {
             columns: Vec::new(),
         };
 
-        for step in unit.step_types.values() {
+        for step in unit.ordered_step_types().iter() {
             let annotation = if let Some(annotation) = unit.annotations.get(&step.uuid()) {
                 format!("'step selector for {}'", annotation)
             } else {
@@ -136,12 +136,9 @@ impl StepSelectorBuilder for TwoStepsSelectorBuilder {
             let step_zero = other_step_type(unit, one_uuid).expect("step not found");
             (step_zero, step_one.clone())
         } else {
-            let mut iter = unit.step_types.values();
+            let step_types = unit.ordered_step_types();
 
-            (
-                Rc::clone(iter.next().expect("step not found")),
-                Rc::clone(iter.next().expect("step not found")),
-            )
+            (Rc::clone(&step_types[0]), Rc::clone(&step_types[1]))
         };
 
         let column = match self.halo2_column {
@@ -214,7 +211,7 @@ fn other_step_type<F, StepArgs>(
     unit: &CompilationUnit<F, StepArgs>,
     uuid: u32,
 ) -> Option<Rc<StepType<F, StepArgs>>> {
-    for step_type in unit.step_types.values() {
+    for step_type in unit.ordered_step_types().iter() {
         if step_type.uuid() != uuid {
             return Some(Rc::clone(step_type));
         }
@@ -1,14 +1,31 @@
 use crate::{
-    ast::{query::Queriable, Circuit, StepType, StepTypeUUID},
-    compiler::{FixedGenContext, TraceContext, WitnessGenContext},
+    ast::{
+        self, query::Queriable, Circuit, ConstraintGroup, DerivedValue, ExposeOffset,
+        ExposedResult, Expr, ForwardSignal, InternalSignal, SignalDefault, SignalWidth,
+        StepSchedule, StepType, StepTypeLibrary, StepTypeUUID, TraceError, TraceRows,
+    },
+    compiler::{
+        expr_limits::{check_expr, ExprLimits},
+        FixedGenContext, TraceContext, WitnessGenContext,
+    },
     util::uuid,
 };
 
-use halo2_proofs::plonk::{Advice, Column as Halo2Column, Fixed};
+use halo2_proofs::{
+    halo2curves::group::ff::PrimeField,
+    plonk::{Advice, Column as Halo2Column, Fixed},
+};
 
 use core::fmt::Debug;
+use std::rc::Rc;
 
-use self::cb::{lookup, Constraint, LookupBuilder, Typing};
+use self::{
+    cb::{
+        annotate, binary_signal, eq, is_binary, lookup, when, BooleanUse, Constraint,
+        LookupBuilder, Typing,
+    },
+    signals::{BoolSignal, ByteSignal, FieldSignal},
+};
 
 /// A generic structure designed to handle the context of a circuit for generic types `F`,
 /// `TraceArgs` and `StepArgs`. The struct contains a `Circuit` instance and implements
@@ -18,6 +35,7 @@ use self::cb::{lookup, Constraint, LookupBuilder, Typing};
 /// representing the arguments passed to the `step_type_def` function.
 pub struct CircuitContext<F, TraceArgs, StepArgs> {
     sc: Circuit<F, TraceArgs, StepArgs>,
+    expr_limits: ExprLimits,
 }
 
 impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
@@ -33,6 +51,31 @@ impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
         Queriable::Forward(self.sc.add_forward(name, phase), false)
     }
 
+    /// Adds a challenge of the verifier, usable after the advice columns of `phase` are committed.
+    /// The constraints query it like a signal, and the signals of the later phases are assigned
+    /// with its value by `assign_with_challenges`:
+    ///
+    /// ```ignore
+    /// let a = ctx.forward("a");
+    /// let acc = ctx.forward_with_phase("acc", 1);
+    /// let r = ctx.challenge("r", 0);
+    ///
+    /// ctx.step_type_def(step, |ctx| {
+    ///     ctx.setup(move |ctx| ctx.transition(eq(acc * r + a.next(), acc.next())));
+    ///     // ...
+    /// });
+    /// ```
+    pub fn challenge(&mut self, name: &str, phase: usize) -> Queriable<F> {
+        Queriable::Challenge(self.sc.add_challenge(name, phase))
+    }
+
+    /// Adds a forward signal whose full column of values is supplied externally, for example by
+    /// another prover component, instead of assigned by the witness generation of the step types.
+    /// The values are given to the witness generation with `ExternalColumns`, one for each step.
+    pub fn forward_external(&mut self, name: &str) -> Queriable<F> {
+        Queriable::Forward(self.sc.add_external(name), false)
+    }
+
     /// Imports a halo2 advice column with a name string into the circuit and returns a
     /// `Queriable` instance representing the imported column.
     pub fn import_halo2_advice(&mut self, name: &str, column: Halo2Column<Advice>) -> Queriable<F> {
@@ -45,6 +88,16 @@ impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
         Queriable::Halo2FixedQuery(self.sc.add_halo2_fixed(name, column), 0)
     }
 
+    /// Adds a fixed signal whose value in the step at index `i` is `gen(i)`, and returns a
+    /// `Queriable` for it. The compiler places it in a fixed column, which requires all step
+    /// types to have the same height and `pragma_num_steps` to be set.
+    pub fn step_fixed<G>(&mut self, name: &str, gen: G) -> Queriable<F>
+    where
+        G: Fn(usize) -> F + 'static,
+    {
+        Queriable::StepFixed(self.sc.add_step_fixed(name, gen), false)
+    }
+
     /// Adds a new step type with the specified name to the circuit and returns a
     /// `StepTypeHandler` instance. The `StepTypeHandler` instance can be used to define the
     /// step type using the `step_type_def` function.
@@ -65,8 +118,10 @@ impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
     {
         let mut context =
             StepTypeContext::<F, StepArgs>::new(handler.uuid(), handler.annotation.to_string());
+        context.expr_limits = self.expr_limits;
 
         def(&mut context);
+        context.record_unasserted_conditions();
 
         self.sc.add_step_type_def(context.step_type);
     }
@@ -83,6 +138,29 @@ impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
         self.sc.set_trace(def);
     }
 
+    /// Like `trace`, for a trace that returns an error instead of panicking, see
+    /// `ChiquitoHalo2::try_witness`:
+    ///
+    /// ```ignore
+    /// ctx.try_trace(move |ctx, input: String| {
+    ///     let n: u64 = input.parse()?;
+    ///     for i in 0..n {
+    ///         ctx.add(&step, i);
+    ///     }
+    ///
+    ///     Ok::<_, std::num::ParseIntError>(())
+    /// });
+    /// ```
+    pub fn try_trace<D, E>(&mut self, def: D)
+    where
+        D: Fn(&mut dyn TraceContext<StepArgs>, TraceArgs) -> Result<(), E> + 'static,
+        E: Into<TraceError>,
+        TraceArgs: 'static,
+        StepArgs: 'static,
+    {
+        self.sc.set_try_trace(def);
+    }
+
     /// Sets the fixed generation function for the circuit. The fixed generation function is
     /// responsible for assigning fixed values to fixed column `Queriable`. It is entirely left
     /// for the user to implement and is Turing complete. Users typically generate cell values and
@@ -105,6 +183,502 @@ impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
     pub fn pragma_last_step(&mut self, step_type: StepTypeHandler) {
         self.sc.last_step = Some(step_type);
     }
+
+    /// Sets the limits on the size of the expressions of the constraints of the step types,
+    /// checked when a constraint is added, before the expression is walked. It must be called
+    /// before the step types are defined. By default they are `ExprLimits::default()`.
+    pub fn expr_limits(&mut self, limits: ExprLimits) {
+        if !self.sc.step_types.is_empty() {
+            panic!("expr_limits must be set before the step types are defined");
+        }
+
+        self.expr_limits = limits;
+    }
+
+    /// Sets the number of steps the circuit is planned for. The compiler checks that the budgets
+    /// of the step types fit in it.
+    pub fn pragma_num_steps(&mut self, num_steps: usize) {
+        self.sc.num_steps = num_steps;
+    }
+
+    /// Declares how the trace orders the step instances, see `StepSchedule`.
+    pub fn pragma_step_schedule(&mut self, schedule: StepSchedule) {
+        self.sc.step_schedule = schedule;
+    }
+
+    /// Declares a step type that pads the trace. Padding step types, and the padding step types of
+    /// `expose_result`, must keep the exposed signals and the signals carried between steps
+    /// unchanged, which the compiler checks for each one of them.
+    pub fn pragma_padding_step(&mut self, step_type: StepTypeHandler) {
+        self.sc.padding_steps.push(step_type);
+    }
+
+    /// Allows padding steps to change a forward signal that is exposed or carried between steps.
+    pub fn allow_padding_change(&mut self, signal: Queriable<F>) {
+        match signal {
+            Queriable::Forward(forward, false) => self.sc.padding_allowed.push(forward),
+            _ => panic!("can only allow padding changes on a forward signal without rotation"),
+        }
+    }
+
+    /// Sets what the witness generation assigns to `signal` in the steps that query it without
+    /// assigning it, see `SignalDefault`. Applies to every step type of the circuit, whether
+    /// defined before or after, and without a default the signal is left unassigned, see
+    /// `Compiler::with_unassigned_policy`.
+    ///
+    /// ```ignore
+    /// let counter = ctx.forward("counter");
+    /// ctx.signal_default(counter, SignalDefault::Carry);
+    /// ```
+    pub fn signal_default(&mut self, signal: Queriable<F>, default: SignalDefault) {
+        let forward = match signal {
+            Queriable::Forward(forward, false) => forward,
+            _ => panic!("can only set the default of a forward signal without rotation"),
+        };
+        if self
+            .sc
+            .signal_defaults
+            .iter()
+            .any(|(other, _)| *other == forward)
+        {
+            panic!("signal {} already has a default", signal.annotation());
+        }
+
+        self.sc.signal_defaults.push((forward, default));
+    }
+
+    /// Declares another name of a forward signal, for example its name before a rename, so that
+    /// witnesses serialized and schemas published with it stay valid. See `Aliases`.
+    pub fn alias(&mut self, signal: Queriable<F>, name: &str) {
+        match signal {
+            Queriable::Forward(forward, false) => self.add_alias(forward.uuid(), name),
+            _ => panic!("can only alias a forward signal without rotation"),
+        }
+    }
+
+    /// Declares another name of a step type, like `alias`.
+    pub fn step_type_alias(&mut self, step_type: StepTypeHandler, name: &str) {
+        self.add_alias(step_type.uuid(), name);
+    }
+
+    fn add_alias(&mut self, uuid: u32, name: &str) {
+        if let Some((other, _)) = self
+            .sc
+            .annotations
+            .iter()
+            .find(|(other, annotation)| **other != uuid && annotation.as_str() == name)
+        {
+            panic!(
+                "alias {} is already the name of {}",
+                name, self.sc.annotations[other]
+            );
+        }
+
+        self.sc.aliases.add(uuid, name);
+    }
+
+    /// Exposes a forward signal as a public input. The value is read at the step given by
+    /// `offset` and constrained to be equal to the next row of the instance column, in the order
+    /// in which signals are exposed. Each signal can only be exposed once.
+    pub fn expose(&mut self, signal: Queriable<F>, offset: ExposeOffset) {
+        self.sc.expose(signal, offset);
+    }
+
+    /// Links a step type library into the circuit. The forward signals and step types of the
+    /// library can then be used as if they were defined in this circuit, with
+    /// `StepTypeLibrary::forward` and `StepTypeLibrary::step_type` returning them by name.
+    pub fn link(&mut self, library: &StepTypeLibrary<F, StepArgs>) {
+        self.sc.link(library);
+    }
+}
+
+/// Context to define a `StepTypeLibrary`. It has the same functions as `CircuitContext` to add
+/// forward signals and step types, but no trace, so the library can be shared by circuits with
+/// different `TraceArgs`.
+pub struct LibraryContext<F, StepArgs> {
+    library: StepTypeLibrary<F, StepArgs>,
+}
+
+impl<F, StepArgs> LibraryContext<F, StepArgs> {
+    /// Adds a forward signal to the library and returns it.
+    pub fn forward(&mut self, name: &str) -> Queriable<F> {
+        self.forward_with_phase(name, 0)
+    }
+
+    /// Adds a forward signal with a specified phase to the library and returns it.
+    pub fn forward_with_phase(&mut self, name: &str, phase: usize) -> Queriable<F> {
+        let signal = ForwardSignal::new_with_phase(phase, name.to_string());
+
+        self.library.forward_signals.push(signal);
+        self.library
+            .annotations
+            .insert(signal.uuid(), name.to_string());
+
+        Queriable::Forward(signal, false)
+    }
+
+    /// Adds a new step type with the specified name to the library and returns its handler.
+    pub fn step_type(&mut self, name: &str) -> StepTypeHandler {
+        let handler = StepTypeHandler::new(name.to_string());
+
+        self.library.handlers.push(handler);
+        self.library
+            .annotations
+            .insert(handler.uuid(), name.to_string());
+
+        handler
+    }
+
+    /// Defines a step type of the library, like `CircuitContext::step_type_def`.
+    pub fn step_type_def<D>(&mut self, handler: StepTypeHandler, def: D)
+    where
+        D: FnOnce(&mut StepTypeContext<F, StepArgs>),
+    {
+        let mut context =
+            StepTypeContext::<F, StepArgs>::new(handler.uuid(), handler.annotation.to_string());
+
+        def(&mut context);
+        context.record_unasserted_conditions();
+
+        self.library
+            .step_types
+            .insert(handler.uuid(), Rc::new(context.step_type));
+    }
+}
+
+impl<F: PrimeField, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
+    /// Adds a lookup table of `N` fixed columns with the rows of `rows`, from the first row of
+    /// the circuit, and returns its handle for `LookupBuilder::table`. The rows are deduplicated
+    /// and sorted, and a row of zeros is added when missing, as the lookups look up zeros in the
+    /// rows where they are not enabled:
+    ///
+    /// ```ignore
+    /// let xor = ctx.fixed_table_from_iter(
+    ///     "xor",
+    ///     (0..16u64).flat_map(|a| (0..16u64).map(move |b| [a.field(), b.field(), (a ^ b).field()])),
+    /// );
+    ///
+    /// ctx.step_type_def(step, |ctx| {
+    ///     ctx.setup(move |ctx| ctx.add_lookup(|lookup| {
+    ///         lookup.table(&xor, [a, b, c]);
+    ///     }));
+    /// });
+    /// ```
+    pub fn fixed_table_from_iter<I, const N: usize>(
+        &mut self,
+        name: &str,
+        rows: I,
+    ) -> FixedTableHandle<F, N>
+    where
+        I: IntoIterator<Item = [F; N]>,
+    {
+        if N == 0 {
+            panic!("fixed table {} has no columns", name);
+        }
+
+        let key = |row: &[F; N]| {
+            row.iter()
+                .map(|value| value.to_repr().as_ref().iter().rev().cloned().collect())
+                .collect::<Vec<Vec<u8>>>()
+        };
+        let mut rows: Vec<[F; N]> = rows.into_iter().collect();
+        rows.sort_by_cached_key(key);
+        rows.dedup();
+        if rows.first() != Some(&[F::ZERO; N]) {
+            rows.insert(0, [F::ZERO; N]);
+        }
+
+        let len = rows.len();
+        let columns = self
+            .sc
+            .add_fixed_table(name, rows.into_iter().map(Vec::from).collect());
+
+        FixedTableHandle {
+            columns: std::array::from_fn(|i| Queriable::StepFixed(columns[i], false)),
+            len,
+        }
+    }
+
+    /// Adds a forward signal whose values fit in `bits` bits. Every step type of the circuit,
+    /// including the ones defined before and the ones linked from a library, decomposes the
+    /// signal in `bits` binary internal signals, which are assigned during trace generation.
+    /// Assigning a value that does not fit panics, and `WitnessLinter` reports it. `bits` must be
+    /// between 1 and `F::NUM_BITS - 1`.
+    pub fn forward_width(&mut self, name: &str, bits: u32) -> Queriable<F> {
+        self.add_forward_width(name, bits, true)
+    }
+
+    /// Adds a forward signal whose values fit in `bits` bits without range checking it, for
+    /// signals already range checked by other constraints or lookups. Only `WitnessLinter` checks
+    /// the width.
+    pub fn forward_width_unchecked(&mut self, name: &str, bits: u32) -> Queriable<F> {
+        self.add_forward_width(name, bits, false)
+    }
+
+    /// Adds a forward signal range checked to be 0 or 1 in every step type, which
+    /// `signals::ifx` accepts as a condition.
+    pub fn forward_bool(&mut self, name: &str) -> BoolSignal<F> {
+        BoolSignal::new(self.forward_width(name, 1))
+    }
+
+    /// Adds a forward signal range checked to 8 bits.
+    pub fn forward_byte(&mut self, name: &str) -> ByteSignal<F> {
+        ByteSignal::new(self.forward_width(name, 8))
+    }
+
+    /// Adds a forward signal without constraints on its values, which cannot be used as a
+    /// condition in `signals::ifx`.
+    pub fn forward_field(&mut self, name: &str) -> FieldSignal<F> {
+        FieldSignal::new(self.forward(name))
+    }
+
+    fn add_forward_width(&mut self, name: &str, bits: u32, checked: bool) -> Queriable<F> {
+        // with NUM_BITS bits or more the sum of the bits wraps around the modulus, and several
+        // values of the bits decompose the same signal value
+        if bits == 0 || bits >= F::NUM_BITS {
+            panic!(
+                "signal {} has a width of {} bits, but widths must be between 1 and {} bits",
+                name,
+                bits,
+                F::NUM_BITS - 1
+            );
+        }
+
+        let signal = self.sc.add_forward(name, 0);
+        let width = bits as usize;
+
+        let decompose = move |value: &F| {
+            let repr = value.to_repr();
+            let bytes = repr.as_ref();
+            let bit = |i: usize| bytes[i / 8] >> (i % 8) & 1 == 1;
+
+            if (width..bytes.len() * 8).any(bit) {
+                return None;
+            }
+
+            Some(
+                (0..width)
+                    .map(|i| if bit(i) { F::ONE } else { F::ZERO })
+                    .collect(),
+            )
+        };
+
+        let powers: Vec<F> = std::iter::successors(Some(F::ONE), |power| Some(power.double()))
+            .take(width)
+            .collect();
+        let constraints = move |bits: &[InternalSignal]| {
+            let value = Queriable::<F>::Forward(signal, false);
+            let mut constraints: Vec<(String, Expr<F>)> = bits
+                .iter()
+                .map(|bit| {
+                    let constraint = is_binary(Queriable::<F>::Internal(*bit));
+                    (constraint.annotation, constraint.expr)
+                })
+                .collect();
+
+            let sum = Expr::Sum(
+                bits.iter()
+                    .zip(powers.iter())
+                    .map(|(bit, power)| Expr::Const(*power) * Queriable::Internal(*bit))
+                    .collect(),
+            );
+            constraints.push((
+                format!("{} fits in {} bits", value.annotation(), width),
+                value - sum,
+            ));
+
+            constraints
+        };
+
+        self.sc.signal_widths.push(SignalWidth {
+            signal,
+            bits,
+            checked,
+            decompose: Rc::new(decompose),
+            constraints: Rc::new(constraints),
+        });
+
+        Queriable::Forward(signal, false)
+    }
+}
+
+impl<F: Clone, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
+    /// Exposes the value that `signal` takes in the last step that is not of the `padding` step
+    /// type. Exposing the signal at `ExposeOffset::Last` would read a padding step instead when
+    /// the trace is padded. An auto-generated forward signal carries the value through the
+    /// padding steps and is exposed at the last step; it is assigned automatically during trace
+    /// generation and returned so it can be referenced in other constraints.
+    pub fn expose_result(
+        &mut self,
+        signal: Queriable<F>,
+        padding: StepTypeHandler,
+    ) -> Queriable<F> {
+        if !matches!(signal, Queriable::Forward(_, false)) {
+            panic!(
+                "can only expose the result of a forward signal without rotation, not {}",
+                signal.annotation()
+            );
+        }
+        if self
+            .sc
+            .exposed_results
+            .iter()
+            .any(|result| result.signal.uuid() == signal.uuid())
+        {
+            panic!("result {} is already exposed", signal.annotation());
+        }
+
+        let carrier = self.forward(&format!("result({})", signal.annotation()));
+
+        self.sc.exposed_results.push(ExposedResult {
+            signal,
+            carrier,
+            padding,
+        });
+        self.sc.expose(carrier, ExposeOffset::Last);
+
+        carrier
+    }
+
+    /// Exposes at `offset` a new forward signal `name` that takes the value of `expr` in every
+    /// step, so the public values are derived from the witness by the circuit definition instead
+    /// of an instance builder kept apart. `expr` can only query forward signals of the current
+    /// step. The signal is assigned automatically during trace generation and returned so it can
+    /// be referenced in other constraints, or passed to `expose_result` to expose its value in the
+    /// last step that is not padding.
+    pub fn expose_derived<E: Into<Expr<F>>>(
+        &mut self,
+        name: &str,
+        expr: E,
+        offset: ExposeOffset,
+    ) -> Queriable<F> {
+        let expr = expr.into();
+        if let Some(queriable) = expr
+            .queriables()
+            .into_iter()
+            .find(|queriable| !matches!(queriable, Queriable::Forward(_, false)))
+        {
+            panic!(
+                "derived value {} can only query the forward signals of its step, not {}",
+                name,
+                queriable.annotation()
+            );
+        }
+
+        let signal = self.forward(name);
+        self.sc.derived_values.push(DerivedValue { signal, expr });
+        self.sc.expose(signal, offset);
+
+        signal
+    }
+
+    /// Adds an invariant on forward signals, for example that a counter increases by 0 or 1, that
+    /// the compiler enforces in every step type unless the step type calls
+    /// `StepTypeSetupContext::override_invariant` with its `name`. An invariant that queries the
+    /// next step is enforced as a transition constraint.
+    pub fn invariant<C: Into<Constraint<F>>>(&mut self, name: &str, constraint: C) {
+        let constraint = constraint.into();
+        if constraint.typing != Typing::AntiBooly {
+            panic!(
+                "Expected AntiBooly constraint, got {:?} (invariant: {})",
+                constraint.typing, name
+            );
+        }
+        if self
+            .sc
+            .invariants
+            .iter()
+            .any(|invariant| invariant.name == name)
+        {
+            panic!("invariant {} is already declared", name);
+        }
+
+        let queriables = constraint.expr.queriables();
+        if let Some(internal) = queriables
+            .iter()
+            .find(|q| matches!(q, Queriable::Internal(_)))
+        {
+            panic!(
+                "invariant {} queries internal signal {}, which belongs to a step type",
+                name,
+                internal.annotation()
+            );
+        }
+        let transition = queriables.iter().any(|q| {
+            matches!(
+                q,
+                Queriable::Forward(_, true)
+                    | Queriable::StepFixed(_, true)
+                    | Queriable::StepTypeNext(_)
+            )
+        });
+
+        self.sc.invariants.push(ast::Invariant {
+            name: name.to_string(),
+            constraint: ast::Constraint {
+                annotation: constraint.annotation,
+                expr: constraint.expr,
+            },
+            transition,
+        });
+    }
+
+    /// Adds a constraint on forward signals enforced at the steps given by `rows`, whatever
+    /// their step type, for example a boundary constraint on the first step or a constraint
+    /// repeated every few steps. Like `step_fixed`, it requires all step types to have the same
+    /// height and `pragma_num_steps` to be set. A constraint that queries the next step cannot
+    /// be enforced at the last step, the compiler rejects it.
+    pub fn trace_constraint<C: Into<Constraint<F>>>(&mut self, rows: TraceRows, constraint: C) {
+        let constraint = constraint.into();
+        if constraint.typing != Typing::AntiBooly {
+            panic!(
+                "Expected AntiBooly constraint, got {:?} (trace constraint: {})",
+                constraint.typing, constraint.annotation
+            );
+        }
+        if let TraceRows::Periodic { period: 0, .. } = rows {
+            panic!(
+                "trace constraint {} has a period of zero",
+                constraint.annotation
+            );
+        }
+        if let Some(internal) = constraint
+            .expr
+            .queriables()
+            .iter()
+            .find(|q| matches!(q, Queriable::Internal(_)))
+        {
+            panic!(
+                "trace constraint {} queries internal signal {}, which belongs to a step type",
+                constraint.annotation,
+                internal.annotation()
+            );
+        }
+
+        self.sc.trace_constraints.push(ast::TraceConstraint {
+            constraint: ast::Constraint {
+                annotation: constraint.annotation,
+                expr: constraint.expr,
+            },
+            rows,
+        });
+    }
+
+    /// Adds a constraint enforced only at the step at `index`, see `trace_constraint`.
+    pub fn boundary<C: Into<Constraint<F>>>(&mut self, index: usize, constraint: C) {
+        self.trace_constraint(TraceRows::Step(index), constraint);
+    }
+
+    /// Adds a constraint enforced every `period` steps starting at `offset`, see
+    /// `trace_constraint`.
+    pub fn periodic<C: Into<Constraint<F>>>(
+        &mut self,
+        period: usize,
+        offset: usize,
+        constraint: C,
+    ) {
+        self.trace_constraint(TraceRows::Periodic { period, offset }, constraint);
+    }
 }
 
 /// A generic structure designed to handle the context of a step type for generic types `F` and
@@ -114,12 +688,17 @@ impl<F, TraceArgs, StepArgs> CircuitContext<F, TraceArgs, StepArgs> {
 /// the step type.
 pub struct StepTypeContext<F, Args> {
     step_type: StepType<F, Args>,
+    /// Signals used as conditions, with the annotation of the constraint using them.
+    conditions: Vec<(Queriable<F>, String)>,
+    expr_limits: ExprLimits,
 }
 
 impl<F, Args> StepTypeContext<F, Args> {
     pub fn new(uuid: u32, name: String) -> Self {
         Self {
             step_type: StepType::new(uuid, name),
+            conditions: Vec::new(),
+            expr_limits: ExprLimits::default(),
         }
     }
 
@@ -129,6 +708,22 @@ impl<F, Args> StepTypeContext<F, Args> {
         Queriable::Internal(self.step_type.add_signal(name))
     }
 
+    /// Declares another name of an internal signal of the step type, like
+    /// `CircuitContext::alias`.
+    pub fn alias(&mut self, signal: Queriable<F>, name: &str) {
+        match signal {
+            Queriable::Internal(internal) if self.step_type.signals.contains(&internal) => {
+                self.step_type.aliases.add(internal.uuid(), name)
+            }
+            _ => panic!("can only alias an internal signal of the step type"),
+        }
+    }
+
+    /// Adds an internal signal without constraints on its values.
+    pub fn internal_field(&mut self, name: &str) -> FieldSignal<F> {
+        FieldSignal::new(self.internal(name))
+    }
+
     /// DEPRECATED
     pub fn constr<C: Into<Constraint<F>>>(&mut self, constraint: C) {
         println!("DEPRECATED constr: use setup for constraints in step types");
@@ -149,6 +744,13 @@ impl<F, Args> StepTypeContext<F, Args> {
             .add_transition(constraint.annotation, constraint.expr);
     }
 
+    /// Sets the expected maximum number of instances of the step type in the trace. The
+    /// compiler checks that the budgets of all step types fit in the circuit's `num_steps`, and
+    /// `TraceWitness::budget_report` compares the budget with the actual instances.
+    pub fn budget(&mut self, max_instances: usize) {
+        self.step_type.budget = Some(max_instances);
+    }
+
     /// Define step constraints.
     pub fn setup<D>(&mut self, def: D)
     where
@@ -156,6 +758,10 @@ impl<F, Args> StepTypeContext<F, Args> {
     {
         let mut ctx = StepTypeSetupContext {
             step_type: &mut self.step_type,
+            conditions: &mut self.conditions,
+            groups: Vec::new(),
+            debug_only: false,
+            expr_limits: self.expr_limits,
         };
 
         def(&mut ctx);
@@ -172,6 +778,35 @@ impl<F, Args> StepTypeContext<F, Args> {
     {
         self.step_type.set_wg(def);
     }
+
+    /// Like `wg`, with a witness generation function that can run in other threads, so that
+    /// `generate_parallel` runs the witness generation of the steps of this type concurrently.
+    pub fn wg_sync<D>(&mut self, def: D)
+    where
+        D: Fn(&mut dyn WitnessGenContext<F>, Args) + Send + Sync + 'static,
+        F: 'static,
+        Args: 'static,
+    {
+        self.step_type.set_sync_wg(def);
+    }
+
+    /// Records in the step type the signals used as conditions that are not asserted boolean,
+    /// which `WitnessLinter` reports.
+    fn record_unasserted_conditions(&mut self) {
+        for (signal, annotation) in self.conditions.iter() {
+            if !self.step_type.boolean_signals.contains(signal)
+                && !self
+                    .step_type
+                    .unasserted_conditions
+                    .iter()
+                    .any(|(other, _)| other == signal)
+            {
+                self.step_type
+                    .unasserted_conditions
+                    .push((*signal, annotation.clone()));
+            }
+        }
+    }
 }
 
 impl<F: Debug + Clone, Args> StepTypeContext<F, Args> {
@@ -181,22 +816,54 @@ impl<F: Debug + Clone, Args> StepTypeContext<F, Args> {
 
         self.step_type.lookups.push(lookup_builder.lookup.clone());
     }
+
+    /// Adds an internal signal constrained to be 0 or 1, which `signals::ifx` accepts as a
+    /// condition.
+    pub fn internal_bool(&mut self, name: &str) -> BoolSignal<F>
+    where
+        F: From<u64>,
+    {
+        let signal = self.internal(name);
+        self.setup(move |ctx| ctx.constr(is_binary(signal)));
+
+        BoolSignal::new(signal)
+    }
 }
 
 pub struct StepTypeSetupContext<'a, F, Args> {
     step_type: &'a mut StepType<F, Args>,
+    conditions: &'a mut Vec<(Queriable<F>, String)>,
+    /// Enclosing groups, as their index in `StepType::constraint_groups` and the function that
+    /// enables a constraint with the group condition.
+    groups: Vec<(usize, Box<EnableConstraint<'a, F>>)>,
+    debug_only: bool,
+    expr_limits: ExprLimits,
 }
 
+type EnableConstraint<'a, F> = dyn Fn(Constraint<F>) -> Constraint<F> + 'a;
+
 impl<'a, F, Args> StepTypeSetupContext<'a, F, Args> {
     /// Adds a constraint to the step type. Involves internal signal(s) and forward signals without
     /// SuperRotation only. Chiquito provides syntax sugar for defining complex constraints.
     /// Refer to the `cb` (constraint builder) module for more information.
     pub fn constr<C: Into<Constraint<F>>>(&mut self, constraint: C) {
         let constraint = constraint.into();
+        self.enforce_expr_limits(&constraint);
         Self::enforce_constraint_typing(&constraint);
+        let constraint = self.enable_in_groups(constraint, false);
+        if self.track_booleans(&constraint, false) {
+            return;
+        }
 
-        self.step_type
-            .add_constr(constraint.annotation, constraint.expr);
+        if self.debug_only {
+            self.step_type.debug_constraints.push(ast::Constraint {
+                annotation: constraint.annotation,
+                expr: constraint.expr,
+            });
+        } else {
+            self.step_type
+                .add_constr(constraint.annotation, constraint.expr);
+        }
     }
 
     /// Adds a transition constraint to the step type. It’s the same as a regular constraint except
@@ -205,10 +872,95 @@ impl<'a, F, Args> StepTypeSetupContext<'a, F, Args> {
     /// for more information.
     pub fn transition<C: Into<Constraint<F>>>(&mut self, constraint: C) {
         let constraint = constraint.into();
+        self.enforce_expr_limits(&constraint);
         Self::enforce_constraint_typing(&constraint);
+        let constraint = self.enable_in_groups(constraint, true);
+        if self.track_booleans(&constraint, true) {
+            return;
+        }
 
-        self.step_type
-            .add_transition(constraint.annotation, constraint.expr);
+        if self.debug_only {
+            self.step_type
+                .debug_transition_constraints
+                .push(ast::TransitionConstraint {
+                    annotation: constraint.annotation,
+                    expr: constraint.expr,
+                });
+        } else {
+            self.step_type
+                .add_transition(constraint.annotation, constraint.expr);
+        }
+    }
+
+    /// Adds the constraints and transitions of `def` as sanity checks for development, which are
+    /// compiled in debug builds and left out of release builds, see
+    /// `Compiler::with_debug_constraints`.
+    pub fn debug_only<D: FnOnce(&mut StepTypeSetupContext<'a, F, Args>)>(&mut self, def: D) {
+        let debug_only = self.debug_only;
+        self.debug_only = true;
+        def(self);
+        self.debug_only = debug_only;
+    }
+
+    /// Applies the conditions of the enclosing groups, innermost first, and counts the constraint
+    /// in each group.
+    fn enable_in_groups(
+        &mut self,
+        mut constraint: Constraint<F>,
+        transition: bool,
+    ) -> Constraint<F> {
+        for (index, enable) in self.groups.iter().rev() {
+            constraint = enable(constraint);
+
+            let group = &mut self.step_type.constraint_groups[*index];
+            if transition {
+                group.transitions += 1;
+            } else {
+                group.constraints += 1;
+            }
+        }
+
+        constraint
+    }
+
+    /// Records the signals the constraint uses as conditions or asserts boolean. Returns true when
+    /// the constraint is an `is_binary` of a signal already asserted boolean in the step type, so
+    /// that it is not added twice.
+    ///
+    /// Only a constraint that is an `is_binary` itself asserts its signal, an `is_binary` nested
+    /// in `when` or another operator only holds in some rows. Transitions do not assert signals
+    /// either, as they are not enforced in the last step, and neither do the debug only
+    /// constraints, which release builds leave out.
+    fn track_booleans(&mut self, constraint: &Constraint<F>, transition: bool) -> bool {
+        let mut redundant = false;
+
+        for (signal, usage) in constraint.booleans.iter() {
+            match usage {
+                BooleanUse::Condition => self
+                    .conditions
+                    .push((*signal, constraint.annotation.clone())),
+                BooleanUse::Asserted if self.step_type.boolean_signals.contains(signal) => {
+                    redundant = binary_signal(constraint) == Some(*signal);
+                }
+                BooleanUse::Asserted if !transition && !self.debug_only => {
+                    self.step_type.boolean_signals.push(*signal)
+                }
+                BooleanUse::Asserted => {}
+            }
+        }
+
+        redundant
+    }
+
+    fn enforce_expr_limits(&self, constraint: &Constraint<F>) {
+        if let Err(err) = check_expr(
+            &self.expr_limits,
+            &self.step_type.name,
+            &constraint.annotation,
+            &constraint.expr,
+        ) {
+            panic!("{}", err);
+        }
     }
 
     fn enforce_constraint_typing(constraint: &Constraint<F>) {
@@ -221,6 +973,71 @@ impl<'a, F, Args> StepTypeSetupContext<'a, F, Args> {
     }
 }
 
+impl<'a, F, Args> StepTypeSetupContext<'a, F, Args> {
+    /// Excludes the circuit invariant `name` from this step type, for step types that reset or
+    /// otherwise change the signals it constrains. The step type should add its own constraints
+    /// for them.
+    pub fn override_invariant(&mut self, name: &str) {
+        self.step_type.overridden_invariants.push(name.to_string());
+    }
+}
+
+impl<'a, F: From<u64> + Clone, Args> StepTypeSetupContext<'a, F, Args> {
+    /// Adds the constraints and transitions of `def` in a group named `name`, which only applies
+    /// them when `enable` is 1, as in `when(enable, constraint)`. Useful for opcode-style circuits
+    /// where one selector enables many constraints. Groups can be nested, and the number of
+    /// constraints in each group is recorded in `StepType::constraint_groups`.
+    ///
+    /// An `enable` that is not a single signal is assigned once to a new internal signal, derived
+    /// by the trace generation, which gates the constraints of the group instead, so that they
+    /// only gain one degree from the group.
+    pub fn group<E, D>(&mut self, name: &str, enable: E, def: D)
+    where
+        E: Into<Constraint<F>>,
+        D: FnOnce(&mut StepTypeSetupContext<F, Args>),
+    {
+        let enable: Constraint<F> = enable.into();
+        self.step_type.constraint_groups.push(ConstraintGroup {
+            name: name.to_string(),
+            enable: enable.annotation.clone(),
+            constraints: 0,
+            transitions: 0,
+        });
+
+        let enable = match enable.expr {
+            // when rejects an AntiBooly enable
+            Expr::Query(_) => enable,
+            _ if enable.typing == Typing::AntiBooly => enable,
+            _ => {
+                let signal =
+                    Queriable::Internal(self.step_type.add_signal(&format!("{} enable", name)));
+                let annotation = enable.annotation.clone();
+                let definition = eq(signal, enable);
+                self.track_booleans(&definition, false);
+                self.step_type
+                    .add_constr(definition.annotation, definition.expr);
+
+                annotate(annotation, signal, Typing::Boolean)
+            }
+        };
+
+        let group = name.to_string();
+        let enable_constraint = move |constraint: Constraint<F>| {
+            let mut constraint = when(enable.clone(), constraint);
+            constraint.annotation = format!("{}: {}", group, constraint.annotation);
+
+            constraint
+        };
+
+        self.groups.push((
+            self.step_type.constraint_groups.len() - 1,
+            Box::new(enable_constraint),
+        ));
+        def(self);
+        self.groups.pop();
+    }
+}
+
 impl<'a, F: Debug + Clone, Args> StepTypeSetupContext<'a, F, Args> {
     /// Adds a lookup to the step type.
     pub fn add_lookup<D>(&mut self, lookup_handler: D)
@@ -229,7 +1046,7 @@ impl<'a, F: Debug + Clone, Args> StepTypeSetupContext<'a, F, Args> {
     {
         let mut lookup_builder = lookup();
         lookup_handler(&mut lookup_builder);
-        self.step_type.lookups.push(lookup_builder.lookup.clone());
+        self.step_type.lookups.push(lookup_builder.lookup);
     }
 }
 
@@ -247,6 +1064,16 @@ impl StepTypeHandler {
         }
     }
 
+    /// The handler of an existing step type. The annotation is leaked to give it the lifetime of
+    /// the handlers of the DSL, so build one handler per step type and reuse it, not one per
+    /// step.
+    pub(crate) fn with_uuid(id: StepTypeUUID, annotation: &str) -> Self {
+        Self {
+            id,
+            annotation: Box::leak(annotation.to_string().into_boxed_str()),
+        }
+    }
+
     pub fn uuid(&self) -> u32 {
         self.id
     }
@@ -256,6 +1083,29 @@ impl StepTypeHandler {
     }
 }
 
+/// A lookup table of `N` fixed columns, see `CircuitContext::fixed_table_from_iter`.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedTableHandle<F, const N: usize> {
+    columns: [Queriable<F>; N],
+    len: usize,
+}
+
+impl<F, const N: usize> FixedTableHandle<F, N> {
+    pub fn columns(&self) -> [Queriable<F>; N] {
+        self.columns
+    }
+
+    /// Number of rows of the table, including its row of zeros.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Always false, a table has at least its row of zeros.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
 pub struct ForwardSignalHandler {
     // fs: ForwardSignal,
 }
@@ -265,18 +1115,404 @@ pub struct ForwardSignalHandler {
 /// functions. This is the main function that users call to define a Chiquito circuit. Currently,
 /// the name is not used for annotation within the function, but it may be used in future
 /// implementations.
-pub fn circuit<F, TraceArgs, StepArgs, D>(_name: &str, def: D) -> Circuit<F, TraceArgs, StepArgs>
+pub fn circuit<F: Clone, TraceArgs, StepArgs, D>(
+    _name: &str,
+    def: D,
+) -> Circuit<F, TraceArgs, StepArgs>
 where
     D: Fn(&mut CircuitContext<F, TraceArgs, StepArgs>),
 {
     // TODO annotate circuit
     let mut context = CircuitContext {
         sc: Circuit::default(),
+        expr_limits: ExprLimits::default(),
     };
 
     def(&mut context);
 
+    context.sc.lower_signal_widths();
+    context.sc.lower_signal_defaults();
+    context.sc.lower_exposed_results();
+    context.sc.lower_derived_values();
+
     context.sc
 }
 
+/// Creates a `StepTypeLibrary` by providing a name and a definition closure that is applied to a
+/// mutable `LibraryContext`. The library is defined once and can be linked into several circuits
+/// with `CircuitContext::link`.
+pub fn library<F, StepArgs, D>(name: &str, def: D) -> StepTypeLibrary<F, StepArgs>
+where
+    D: FnOnce(&mut LibraryContext<F, StepArgs>),
+{
+    let mut context = LibraryContext {
+        library: StepTypeLibrary::new(name),
+    };
+
+    def(&mut context);
+
+    context.library
+}
+
 pub mod cb;
+pub mod signals;
+pub mod skeleton;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::{ToExpr, ToField},
+        ci::catch,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::cb::eq,
+        wit_gen::{eval_expr, GenericTraceContext, TraceWitness},
+    };
+
+    pub(crate) fn witness<TraceArgs, StepArgs>(
+        sc: &Circuit<Fr, TraceArgs, StepArgs>,
+        args: TraceArgs,
+    ) -> TraceWitness<Fr> {
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, args);
+
+        ctx.get_witness()
+    }
+
+    /// Like `witness`, with the exposed results of the compiled circuit carried through the
+    /// steps.
+    fn compiled_witness<TraceArgs>(
+        sc: &Circuit<Fr, TraceArgs, u64>,
+        args: TraceArgs,
+    ) -> TraceWitness<Fr> {
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types)
+            .with_exposed_results(&compiled.exposed_results);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, args);
+
+        ctx.get_witness()
+    }
+
+    /// Declares the step type `name`, whose steps assign their argument to `a`.
+    pub(crate) fn assigning<TraceArgs>(
+        ctx: &mut CircuitContext<Fr, TraceArgs, u64>,
+        name: &str,
+        a: Queriable<Fr>,
+    ) -> StepTypeHandler {
+        let step = ctx.step_type(name);
+        ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+
+        step
+    }
+
+    #[test]
+    fn test_signal_declarations_apply_to_every_step_type() {
+        let lib = library::<Fr, u64, _>("lib", |ctx| {
+            let b = ctx.forward("b");
+            let linked = ctx.step_type("linked");
+
+            ctx.step_type_def(linked, |ctx| ctx.wg(move |ctx, v| ctx.assign(b, v.field())));
+        });
+        let linked = lib.step_type("linked");
+
+        let sc = circuit::<Fr, (), u64, _>("order", |ctx| {
+            ctx.link(&lib);
+            let b = lib.forward("b");
+            let before = ctx.step_type("before");
+
+            ctx.step_type_def(before, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(b.next(), b)));
+            });
+
+            // declared after the step types
+            ctx.forward_width("a", 4);
+            ctx.signal_default(b, SignalDefault::Carry);
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&linked, 3);
+                ctx.add(&before, 0);
+            });
+        });
+
+        for step in sc.step_types.values() {
+            assert_eq!(step.range_checks.len(), 1, "step type {}", step.name);
+        }
+        let before = sc
+            .step_types
+            .values()
+            .find(|step| step.name == "before")
+            .unwrap();
+        assert_eq!(before.signal_defaults.len(), 1);
+
+        let witness = witness(&sc, ());
+        let b = Queriable::Forward(lib.forward_signals[0], false);
+        assert_eq!(
+            witness.step_instances[1].assignments.get(&b),
+            Some(&3.field())
+        );
+        // the library is left unchanged
+        assert!(lib
+            .step_types
+            .values()
+            .all(|step| step.range_checks.is_empty()));
+    }
+
+    #[test]
+    fn test_forward_bool_in_step_types_defined_before() {
+        let sc = circuit::<Fr, (), u64, _>("bool", |ctx| {
+            let a = ctx.forward("a");
+            let before = ctx.step_type("before");
+            let after = ctx.step_type("after");
+
+            // the next step can query the flag in this step, which is not declared yet
+            ctx.step_type_def(before, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a.next(), a)));
+            });
+
+            let flag = ctx.forward_bool("flag");
+            ctx.step_type_def(after, |ctx| {
+                ctx.setup(move |ctx| ctx.constr(signals::ifx_then(flag, eq(a, 1))));
+            });
+        });
+
+        let flag = Queriable::Forward(sc.forward_signals[1], false);
+        for step in sc.step_types.values() {
+            assert_eq!(step.range_checks.len(), 1, "step type {}", step.name);
+            assert_eq!(step.range_checks[0].bits.len(), 1);
+            assert_eq!(step.boolean_signals, vec![flag], "step type {}", step.name);
+            assert!(step.unasserted_conditions.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_expr_limits_when_constraint_added() {
+        let deep = |limits: Option<ExprLimits>| {
+            catch(|| {
+                circuit::<Fr, (), u64, _>("deep", |ctx| {
+                    if let Some(limits) = limits {
+                        ctx.expr_limits(limits);
+                    }
+                    let a = ctx.forward("a");
+                    let step = ctx.step_type("step");
+
+                    ctx.step_type_def(step, |ctx| {
+                        ctx.setup(move |ctx| {
+                            let mut e = a.expr();
+                            for _ in 0..5000 {
+                                e = -(e + a);
+                            }
+                            ctx.constr(eq(e, a));
+                        });
+                    });
+                })
+            })
+            .unwrap_err()
+        };
+
+        let message = deep(None);
+        assert!(
+            message.contains("of step type step nests expressions"),
+            "{}",
+            message
+        );
+        assert!(message.contains("but the limit is 256"), "{}", message);
+
+        let message = deep(Some(ExprLimits {
+            max_nodes: None,
+            max_depth: Some(1000),
+        }));
+        assert!(message.contains("but the limit is 1000"), "{}", message);
+    }
+
+    #[test]
+    fn test_forward_width_range_check() {
+        let sc = circuit::<Fr, (), u64, _>("width", |ctx| {
+            let a = ctx.forward_width("a", 4);
+            let step = assigning(ctx, "step", a);
+
+            ctx.trace(move |ctx, _| ctx.add(&step, 6));
+        });
+
+        let step = sc.step_types.values().next().unwrap();
+        assert_eq!(step.signals.len(), 4);
+        assert_eq!(step.constraints.len(), 5);
+
+        let witness = witness(&sc, ());
+        let bits: Vec<Fr> = step
+            .signals
+            .iter()
+            .map(|bit| witness.step_instances[0].assignments[&Queriable::Internal(*bit)])
+            .collect();
+        assert_eq!(bits, vec![0.field(), 1.field(), 1.field(), 0.field()]);
+
+        for constraint in step.constraints.iter() {
+            assert_eq!(eval_expr(&constraint.expr, &witness, 0), Ok(0.field()));
+        }
+    }
+
+    #[test]
+    fn test_forward_width_bounds() {
+        let width = |bits: u32| {
+            catch(|| {
+                circuit::<Fr, (), u64, _>("width", |ctx| {
+                    ctx.forward_width("a", bits);
+                })
+            })
+            .map(|_| ())
+        };
+
+        assert_eq!(width(1), Ok(()));
+        assert_eq!(width(Fr::NUM_BITS - 1), Ok(()));
+        assert_eq!(
+            width(0),
+            Err(format!(
+                "signal a has a width of 0 bits, but widths must be between 1 and {} bits",
+                Fr::NUM_BITS - 1
+            ))
+        );
+        let message = width(Fr::NUM_BITS).unwrap_err();
+        assert!(
+            message.contains("widths must be between 1 and"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_exposed_result_carried_through_padding() {
+        let sc = circuit::<Fr, (), u64, _>("counter", |ctx| {
+            let a = ctx.forward("a");
+            let step = assigning(ctx, "step", a);
+            let padding = assigning(ctx, "padding", a);
+
+            ctx.expose_result(a, padding);
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 1);
+                ctx.add(&step, 5);
+                ctx.add(&padding, 0);
+                ctx.add(&padding, 0);
+            });
+        });
+        let carrier = sc.exposed_results[0].carrier;
+
+        // one carry constraint per step type plus the equality in the non-padding step type
+        let constraints: usize = sc
+            .step_types
+            .values()
+            .map(|step| step.constraints.len() + step.transition_constraints.len())
+            .sum();
+        assert_eq!(constraints, 3);
+
+        let carried: Vec<Fr> = compiled_witness(&sc, ())
+            .step_instances
+            .iter()
+            .map(|step| *step.assignments.get(&carrier).unwrap())
+            .collect();
+        assert_eq!(carried, vec![1.field(), 5.field(), 5.field(), 5.field()]);
+    }
+
+    #[test]
+    fn test_expose_derived_values() {
+        let sc = circuit::<Fr, (), u64, _>("derived public values", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("step");
+            let padding = ctx.step_type("padding");
+
+            let sum = ctx.expose_derived("sum", a + b * 2, ExposeOffset::First);
+            let product = ctx.expose_derived("product", a * b, ExposeOffset::Step(1));
+            ctx.expose_result(product, padding);
+            // read before the padding, which only carries the result
+            ctx.allow_padding_change(sum);
+            ctx.allow_padding_change(product);
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v + 1).field());
+                })
+            });
+            ctx.step_type_def(padding, |ctx| {
+                ctx.wg(move |ctx, _| {
+                    ctx.assign(a, 0.field());
+                    ctx.assign(b, 0.field());
+                })
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 1);
+                ctx.add(&step, 3);
+                ctx.add(&padding, 0);
+            });
+
+            assert!(
+                catch(|| ctx.expose_derived("next", a.next() + sum, ExposeOffset::Last)).is_err()
+            );
+        });
+
+        // sum in the first step, product in the second and carried to the last
+        assert_eq!(
+            compiled_witness(&sc, ()).public_inputs(&sc),
+            vec![5.field(), 12.field(), 12.field()]
+        );
+    }
+
+    #[test]
+    fn test_linked_library() {
+        let lib = library::<Fr, u64, _>("counter", |ctx| {
+            let a = ctx.forward("a");
+            let inc = ctx.step_type("inc");
+
+            ctx.step_type_def(inc, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a + 1, a.next())));
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+        });
+
+        let inc = lib.step_type("inc");
+        let a = lib.forward("a");
+
+        for n in 0..2 {
+            let sc = circuit::<Fr, (), u64, _>("linked", |ctx| {
+                ctx.link(&lib);
+
+                ctx.trace(move |ctx, _| {
+                    for i in 0..n + 1 {
+                        ctx.add(&inc, i);
+                    }
+                });
+            });
+
+            let compiled =
+                Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+            assert_eq!(compiled.step_types.len(), 1);
+
+            let witness = witness(&sc, ());
+            assert_eq!(witness.step_instances.len(), n as usize + 1);
+            assert_eq!(
+                witness.step_instances[n as usize].assignments.get(&a),
+                Some(&Fr::from(n))
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "can only expose the result of a forward signal without rotation, not b"
+    )]
+    fn test_expose_result_of_internal_signal() {
+        circuit::<Fr, (), u64, _>("internal result", |ctx| {
+            let step = ctx.step_type("step");
+            let padding = ctx.step_type("padding");
+
+            let mut b = None;
+            ctx.step_type_def(step, |ctx| b = Some(ctx.internal("b")));
+            ctx.expose_result(b.unwrap(), padding);
+        });
+    }
+}
@@ -1,10 +1,13 @@
 use std::{fmt::Debug, vec};
 
-use halo2_proofs::arithmetic::Field;
+use halo2_proofs::{arithmetic::Field, halo2curves::group::ff::PrimeField};
 
-use crate::ast::{query::Queriable, Expr, Lookup, ToExpr};
+use crate::{
+    ast::{constant::Constant, query::Queriable, Expr, Lookup, ToExpr},
+    compiler::expr_limits::DEFAULT_MAX_DEPTH,
+};
 
-use super::StepTypeHandler;
+use super::{FixedTableHandle, StepTypeHandler};
 
 /// Represents a constraint with an associated annotation and expression.
 #[derive(Clone)]
@@ -12,28 +15,48 @@ pub struct Constraint<F> {
     pub annotation: String,
     pub expr: Expr<F>,
     pub typing: Typing,
+    /// Signals used as conditions or asserted boolean by the constraint or its operands.
+    pub booleans: Vec<(Queriable<F>, BooleanUse)>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Typing {
     Unknown,
     Boolean,
     AntiBooly,
 }
 
+/// How a constraint relies on a signal being 0 or 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BooleanUse {
+    /// The signal is used as a selector or an operand of a logic operator.
+    Condition,
+    /// The signal is constrained to be 0 or 1 by `is_binary`.
+    Asserted,
+}
+
 impl<F: Debug> From<Expr<F>> for Constraint<F> {
     fn from(expr: Expr<F>) -> Self {
-        let annotation = format!("{:?}", &expr);
+        // formatting recurses into the expression, an expression too deep for the stack is left
+        // for the expression limits to report
+        let (nodes, depth) = expr.size();
+        let annotation = if depth > DEFAULT_MAX_DEPTH {
+            format!("<expression of {} nodes nested {} deep>", nodes, depth)
+        } else {
+            format!("{:?}", &expr)
+        };
         match expr {
             Expr::Query(Queriable::StepTypeNext(_)) => Self {
                 expr,
                 annotation,
                 typing: Typing::Boolean,
+                booleans: vec![],
             },
             _ => Self {
                 expr,
                 annotation,
                 typing: Typing::Unknown,
+                booleans: vec![],
             },
         }
     }
@@ -56,6 +79,12 @@ impl<F: Field + From<u64> + Debug> From<i32> for Constraint<F> {
     }
 }
 
+impl<F: PrimeField> From<Constant> for Constraint<F> {
+    fn from(constant: Constant) -> Self {
+        Expr::from(constant).into()
+    }
+}
+
 macro_rules! impl_cb_like {
     ($type:ty) => {
         impl<F: From<u64> + Debug> From<$type> for Constraint<F> {
@@ -79,6 +108,53 @@ impl<F> Debug for Constraint<F> {
     }
 }
 
+/// Signals a constraint built from `constraint` relies on to be boolean. The signals asserted
+/// boolean by `constraint` are left out, as the constraint built from it, for example with
+/// `when`, no longer enforces them in every row.
+pub(crate) fn operand_booleans<F>(constraint: &Constraint<F>) -> Vec<(Queriable<F>, BooleanUse)> {
+    constraint
+        .booleans
+        .iter()
+        .filter(|(_, usage)| *usage == BooleanUse::Condition)
+        .map(|(signal, usage)| (*signal, *usage))
+        .collect()
+}
+
+/// The signal asserted boolean by `constraint` when it is the `is_binary` of a single signal,
+/// recognized by its expression rather than its annotation, which other signals can share.
+pub(crate) fn binary_signal<F>(constraint: &Constraint<F>) -> Option<Queriable<F>> {
+    let signal = match &constraint.expr {
+        Expr::Mul(factors) => match factors.as_slice() {
+            [Expr::Query(signal), Expr::Sum(terms)] => match terms.as_slice() {
+                [Expr::Const(_), Expr::Neg(negated)] if matches!(&**negated, Expr::Query(query) if query == signal) => {
+                    *signal
+                }
+                _ => return None,
+            },
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    constraint
+        .booleans
+        .contains(&(signal, BooleanUse::Asserted))
+        .then_some(signal)
+}
+
+/// Signals the constraint relies on to be boolean when used as a condition, including the
+/// constraint itself when it is a single signal of unknown typing.
+fn conditions<F>(constraint: &Constraint<F>) -> Vec<(Queriable<F>, BooleanUse)> {
+    let mut booleans = operand_booleans(constraint);
+    if constraint.typing == Typing::Unknown {
+        if let Expr::Query(query) = &constraint.expr {
+            booleans.push((*query, BooleanUse::Condition));
+        }
+    }
+
+    booleans
+}
+
 /// Takes an iterator of input constraints and returns a new constraint representing the logical AND
 /// of all input constraints. In practice, multiplies all input constraints together, i.e. A * B * C
 /// * … = 0.
@@ -87,11 +163,13 @@ pub fn and<F: From<u64>, E: Into<Constraint<F>>, I: IntoIterator<Item = E>>(
 ) -> Constraint<F> {
     let mut annotations: Vec<String> = vec![];
     let mut expr: Expr<F> = 1u64.expr();
+    let mut booleans = vec![];
 
     for constraint in inputs.into_iter() {
         let constraint = constraint.into();
         match constraint.typing {
             Typing::Boolean | Typing::Unknown => {
+                booleans.extend(conditions(&constraint));
                 annotations.push(constraint.annotation);
                 expr = expr * constraint.expr;
             }
@@ -106,6 +184,7 @@ pub fn and<F: From<u64>, E: Into<Constraint<F>>, I: IntoIterator<Item = E>>(
         annotation: format!("({})", annotations.join(" AND ")),
         expr,
         typing: Typing::Boolean,
+        booleans,
     }
 }
 
@@ -121,11 +200,13 @@ pub fn or<
 ) -> Constraint<F> {
     let mut annotations: Vec<String> = vec![];
     let mut exprs: Vec<Expr<F>> = vec![];
+    let mut booleans = vec![];
 
     for constraint in inputs.into_iter() {
         let constraint = constraint.into();
         match constraint.typing {
             Typing::Boolean | Typing::Unknown => {
+                booleans.extend(conditions(&constraint));
                 annotations.push(constraint.annotation);
                 exprs.push(constraint.expr);
             }
@@ -142,6 +223,7 @@ pub fn or<
         annotation: format!("({})", annotations.join(" OR ")),
         expr: result.expr,
         typing: Typing::Boolean,
+        booleans,
     }
 }
 
@@ -155,6 +237,8 @@ pub fn xor<F: From<u64> + Clone, LHS: Into<Constraint<F>>, RHS: Into<Constraint<
 
     let lhs: Constraint<F> = lhs.into();
     let rhs: Constraint<F> = rhs.into();
+    let mut booleans = conditions(&lhs);
+    booleans.extend(conditions(&rhs));
 
     let expr = match (lhs.typing, rhs.typing) {
         (Typing::Boolean | Typing::Unknown, Typing::Boolean | Typing::Unknown) => {
@@ -169,6 +253,7 @@ pub fn xor<F: From<u64> + Clone, LHS: Into<Constraint<F>>, RHS: Into<Constraint<
         annotation: format!("({})", annotations.join(" XOR ")),
         expr,
         typing: Typing::Boolean,
+        booleans,
     }
 }
 
@@ -178,13 +263,16 @@ pub fn eq<F, LHS: Into<Constraint<F>>, RHS: Into<Constraint<F>>>(
     lhs: LHS,
     rhs: RHS,
 ) -> Constraint<F> {
-    let lhs = lhs.into();
-    let rhs = rhs.into();
+    let lhs: Constraint<F> = lhs.into();
+    let rhs: Constraint<F> = rhs.into();
+    let mut booleans = operand_booleans(&lhs);
+    booleans.extend(operand_booleans(&rhs));
 
     Constraint {
         annotation: format!("{} == {}", lhs.annotation, rhs.annotation),
         expr: lhs.expr - rhs.expr,
         typing: Typing::AntiBooly,
+        booleans,
     }
 }
 
@@ -212,6 +300,10 @@ pub fn select<
         )
     }
 
+    let mut booleans = conditions(&selector);
+    booleans.extend(operand_booleans(&when_true));
+    booleans.extend(operand_booleans(&when_false));
+
     let typing = if when_true.typing == when_false.typing {
         when_true.typing
     } else {
@@ -226,6 +318,7 @@ pub fn select<
         expr: selector.expr.clone() * when_true.expr
             + (1u64.expr() - selector.expr) * when_false.expr,
         typing,
+        booleans,
     }
 }
 
@@ -245,10 +338,14 @@ pub fn when<F: From<u64> + Clone, T1: Into<Constraint<F>>, T2: Into<Constraint<F
         )
     }
 
+    let mut booleans = conditions(&selector);
+    booleans.extend(operand_booleans(&when_true));
+
     Constraint {
         annotation: format!("if({})then({})", selector.annotation, when_true.annotation),
         expr: selector.expr * when_true.expr,
         typing: when_true.typing,
+        booleans,
     }
 }
 
@@ -268,6 +365,9 @@ pub fn unless<F: From<u64> + Clone, T1: Into<Constraint<F>>, T2: Into<Constraint
         )
     }
 
+    let mut booleans = conditions(&selector);
+    booleans.extend(operand_booleans(&when_false));
+
     Constraint {
         annotation: format!(
             "unless({})then({})",
@@ -275,6 +375,7 @@ pub fn unless<F: From<u64> + Clone, T1: Into<Constraint<F>>, T2: Into<Constraint
         ),
         expr: (1u64.expr() - selector.expr) * when_false.expr,
         typing: when_false.typing,
+        booleans,
     }
 }
 
@@ -288,6 +389,7 @@ pub fn not<F: From<u64>, T: Into<Constraint<F>>>(constraint: T) -> Constraint<F>
             constraint.annotation
         );
     }
+    let booleans = conditions(&constraint);
     let annotation = format!("NOT({})", constraint.annotation);
     let expr = 1u64.expr() - constraint.expr;
 
@@ -295,6 +397,7 @@ pub fn not<F: From<u64>, T: Into<Constraint<F>>>(constraint: T) -> Constraint<F>
         annotation,
         expr,
         typing: Typing::Boolean,
+        booleans,
     }
 }
 
@@ -307,6 +410,7 @@ pub fn isz<F, T: Into<Constraint<F>>>(constraint: T) -> Constraint<F> {
         annotation: format!("0 == {}", constraint.annotation),
         expr: constraint.expr,
         typing: Typing::AntiBooly,
+        booleans: constraint.booleans,
     }
 }
 
@@ -324,6 +428,7 @@ pub fn if_next_step<F: Clone, T: Into<Constraint<F>>>(
     );
 
     Constraint {
+        booleans: operand_booleans(&constraint),
         expr: step_type.next() * constraint.expr,
         annotation,
         typing: constraint.typing,
@@ -357,6 +462,25 @@ pub fn annotate<F, E: Into<Expr<F>>>(annotation: String, expr: E, typing: Typing
         annotation,
         expr: expr.into(),
         typing,
+        booleans: vec![],
+    }
+}
+
+/// Takes a constraint and returns a new constraint that requires it to be either 0 or 1. When the
+/// input is a single signal, it can then be used as a condition without warnings, and asserting it
+/// again in the same step type adds no other constraint.
+pub fn is_binary<F: From<u64> + Clone, T: Into<Constraint<F>>>(constraint: T) -> Constraint<F> {
+    let constraint = constraint.into();
+    let mut booleans = operand_booleans(&constraint);
+    if let Expr::Query(query) = &constraint.expr {
+        booleans.push((*query, BooleanUse::Asserted));
+    }
+
+    Constraint {
+        annotation: format!("is_binary({})", constraint.annotation),
+        expr: constraint.expr.clone() * (1u64.expr() - constraint.expr),
+        typing: Typing::AntiBooly,
+        booleans,
     }
 }
 
@@ -404,6 +528,34 @@ impl<F: Debug + Clone> LookupBuilder<F> {
         self
     }
 
+    /// Looks up `inputs` in the rows of `table`, each input in the column at its position.
+    pub fn table<C: Into<Constraint<F>>, const N: usize>(
+        &mut self,
+        table: &FixedTableHandle<F, N>,
+        inputs: [C; N],
+    ) -> &mut Self {
+        for (input, column) in inputs.into_iter().zip(table.columns()) {
+            self.add(input, column);
+        }
+
+        self
+    }
+
+    /// Looks up into the rows where the step type `step` is active: the table side of every pair
+    /// is placed in `step`, and the step selectors of both step types are looked up first, so the
+    /// all-zero rows of other step types match no input of the lookup. For example,
+    /// every call step can be required to match a create step. The table is made of advice
+    /// columns, which requires a backend with dynamic lookups.
+    pub fn from_step(&mut self, step: StepTypeHandler) -> &mut Self {
+        if self.lookup.table_step.is_some() {
+            panic!("lookup already has a table step type");
+        }
+
+        self.lookup.table_step = Some(step.uuid());
+        self.lookup.annotation += &format!("in step {} ", step.annotation);
+        self
+    }
+
     /// Adds a selector column specific to the lookup table. Because the function returns a mutable
     /// reference to the `LookupBuilder<F>`, it can an chain multiple `add` and `enable` function
     /// calls to build the lookup table. Requires calling `lookup` to create an
@@ -426,7 +578,7 @@ mod tests {
     use halo2_proofs::halo2curves::bn256::Fr;
 
     use super::*;
-    use crate::ast::{ToExpr, ToField};
+    use crate::ast::{InternalSignal, ToExpr, ToField};
 
     #[test]
     fn test_and_empty() {
@@ -732,4 +884,217 @@ mod tests {
                 matches!(v[1], Expr::Const(c) if c == 40u64.field())) &&
             matches!(v[1], Expr::Const(c) if c == 10u64.field())));
     }
+
+    #[test]
+    fn test_is_binary_tracks_booleans() {
+        let a: Queriable<Fr> = Queriable::Internal(InternalSignal::new("a".to_string()));
+        let b: Queriable<Fr> = Queriable::Internal(InternalSignal::new("b".to_string()));
+
+        let binary = is_binary(a);
+        assert_eq!(binary.annotation, "is_binary(a)");
+        assert_eq!(binary.typing, Typing::AntiBooly);
+        assert_eq!(binary.booleans, vec![(a, BooleanUse::Asserted)]);
+
+        let selected = when(a, eq(b, 1));
+        assert_eq!(selected.booleans, vec![(a, BooleanUse::Condition)]);
+
+        let circuit = crate::dsl::circuit::<Fr, (), (), _>("booleans", |ctx| {
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, move |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.constr(is_binary(a));
+                    ctx.constr(is_binary(a));
+                    ctx.constr(when(a, eq(b, 1)));
+                });
+            });
+        });
+
+        let step = circuit.step_types.values().next().unwrap();
+        assert_eq!(step.constraints.len(), 2);
+        assert_eq!(step.boolean_signals, vec![a]);
+        assert!(step.unasserted_conditions.is_empty());
+    }
+
+    #[test]
+    fn test_is_binary_deduplicated_by_signal() {
+        // two signals with the same name
+        let a: Queriable<Fr> = Queriable::Internal(InternalSignal::new("flag".to_string()));
+        let b: Queriable<Fr> = Queriable::Internal(InternalSignal::new("flag".to_string()));
+
+        assert_eq!(binary_signal(&is_binary(a)), Some(a));
+        assert_eq!(binary_signal(&is_binary(a + b)), None);
+
+        let circuit = crate::dsl::circuit::<Fr, (), (), _>("booleans", |ctx| {
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, move |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.constr(is_binary(a));
+                    ctx.constr(is_binary(b));
+                    // the same signal under another name is still redundant
+                    ctx.constr(is_binary(annotate(
+                        "renamed".to_string(),
+                        a,
+                        Typing::Unknown,
+                    )));
+                    // a constraint on b named like the is_binary of a is not
+                    ctx.constr(Constraint {
+                        annotation: "is_binary(flag)".to_string(),
+                        booleans: vec![(a, BooleanUse::Asserted)],
+                        ..is_binary(b)
+                    });
+                });
+            });
+        });
+
+        let step = circuit.step_types.values().next().unwrap();
+        let annotations: Vec<_> = step.constraints.iter().map(|c| &c.annotation).collect();
+        assert_eq!(annotations, vec!["is_binary(flag)"; 3]);
+        assert_eq!(step.boolean_signals, vec![a, b]);
+    }
+
+    #[test]
+    fn test_conditional_is_binary_does_not_assert() {
+        let a: Queriable<Fr> = Queriable::Internal(InternalSignal::new("a".to_string()));
+        let q: Queriable<Fr> = Queriable::Internal(InternalSignal::new("q".to_string()));
+
+        let conditional = when(q, is_binary(a));
+        assert_eq!(conditional.booleans, vec![(q, BooleanUse::Condition)]);
+
+        let circuit = crate::dsl::circuit::<Fr, (), (), _>("booleans", |ctx| {
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, move |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.constr(when(q, is_binary(a)));
+                    // a is only boolean when q is 1, this constraint is not redundant
+                    ctx.constr(is_binary(a));
+                    ctx.transition(is_binary(q));
+                });
+            });
+        });
+
+        let step = circuit.step_types.values().next().unwrap();
+        assert_eq!(
+            step.constraints
+                .iter()
+                .map(|constraint| constraint.annotation.as_str())
+                .collect::<Vec<_>>(),
+            vec!["if(q)then(is_binary(a))", "is_binary(a)"]
+        );
+        // a transition does not hold in the last step
+        assert_eq!(step.boolean_signals, vec![a]);
+        assert_eq!(
+            step.unasserted_conditions,
+            vec![(q, "if(q)then(is_binary(a))".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_constraint_group() {
+        let circuit = crate::dsl::circuit::<Fr, (), (), _>("groups", |ctx| {
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, move |ctx| {
+                let op = ctx.internal("op");
+                let a = ctx.internal("a");
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(is_binary(op));
+                    ctx.group("add", op, |ctx| {
+                        ctx.constr(eq(a + 1, b));
+                        ctx.group("inner", a, |ctx| ctx.constr(eq(b, 2)));
+                    });
+                });
+            });
+        });
+
+        let step = circuit.step_types.values().next().unwrap();
+        assert_eq!(
+            step.constraint_groups
+                .iter()
+                .map(|group| (
+                    group.name.as_str(),
+                    group.enable.as_str(),
+                    group.constraints
+                ))
+                .collect::<Vec<_>>(),
+            vec![("add", "op", 2), ("inner", "a", 1)]
+        );
+        assert_eq!(
+            step.constraints[1].annotation,
+            "add: if(op)then((a + 0x1) == b)"
+        );
+        assert_eq!(
+            step.constraints[2].annotation,
+            "add: if(op)then(inner: if(a)then(b == 0x2))"
+        );
+    }
+
+    #[test]
+    fn test_constraint_group_enable_degree() {
+        let circuit = crate::dsl::circuit::<Fr, (), u64, _>("groups", |ctx| {
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, move |ctx| {
+                let p = ctx.internal("p");
+                let q = ctx.internal("q");
+                let a = ctx.internal("a");
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(is_binary(p));
+                    ctx.constr(is_binary(q));
+                    ctx.group("both", and(vec![p, q]), |ctx| {
+                        ctx.constr(eq(a, b));
+                        ctx.constr(eq(a + 1, b + 1));
+                        ctx.constr(is_binary(a));
+                    });
+                    // the is_binary of the group only holds when p and q are 1
+                    ctx.constr(is_binary(a));
+                });
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(p, 1.field());
+                    ctx.assign(q, 1.field());
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, v.field());
+                });
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 1));
+        });
+
+        let step = circuit.step_types.values().next().unwrap();
+        let enable = step
+            .signals
+            .iter()
+            .find(|signal| Queriable::<Fr>::Internal(**signal).annotation() == "both enable")
+            .copied()
+            .expect("enable signal");
+        assert_eq!(step.constraints[2].annotation, "both enable == (p AND q)");
+        // the enable of degree 2 adds a single degree to the constraints of the group
+        let gated: Vec<&crate::ast::Constraint<Fr>> = step
+            .constraints
+            .iter()
+            .filter(|constraint| constraint.annotation.starts_with("both: "))
+            .collect();
+        assert_eq!(gated.len(), 3);
+        assert_eq!(gated[0].expr.degree(), 2);
+        assert_eq!(gated[2].expr.degree(), 3);
+        assert_eq!(gated[0].annotation, "both: if((p AND q))then(a == b)");
+        assert_eq!(step.constraints.len(), 7);
+        assert!(step.unasserted_conditions.is_empty());
+
+        // the enable signal is derived by the trace generation
+        let compiled = crate::compiler::Compiler::new(
+            crate::compiler::cell_manager::SingleRowCellManager {},
+            crate::compiler::step_selector::SimpleStepSelectorBuilder {},
+        )
+        .compile(&circuit);
+        let mut ctx = crate::wit_gen::GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+        assert_eq!(
+            witness.step_instances[0]
+                .assignments
+                .get(&Queriable::Internal(enable)),
+            Some(&1.field())
+        );
+    }
 }
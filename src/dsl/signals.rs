@@ -0,0 +1,206 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::ast::{query::Queriable, Expr, ToExpr};
+
+use super::cb::{annotate, select, when, Constraint, Typing};
+
+macro_rules! typed_signal {
+    ($name:ident, $typing:expr) => {
+        #[derive(Debug)]
+        pub struct $name<F>(Queriable<F>);
+
+        impl<F> Clone for $name<F> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<F> Copy for $name<F> {}
+
+        impl<F> $name<F> {
+            /// The signal, to assign it in the witness generation.
+            pub fn queriable(&self) -> Queriable<F> {
+                self.0
+            }
+
+            /// The signal in the next step, see `Queriable::next`.
+            pub fn next(&self) -> Self {
+                $name(self.0.next())
+            }
+        }
+
+        impl<F> From<$name<F>> for Queriable<F> {
+            fn from(signal: $name<F>) -> Self {
+                signal.0
+            }
+        }
+
+        impl<F> From<$name<F>> for Expr<F> {
+            fn from(signal: $name<F>) -> Self {
+                Expr::Query(signal.0)
+            }
+        }
+
+        impl<F> From<$name<F>> for Constraint<F> {
+            fn from(signal: $name<F>) -> Self {
+                annotate(signal.0.annotation(), Expr::Query(signal.0), $typing)
+            }
+        }
+
+        impl<F: Clone> ToExpr<F> for $name<F> {
+            fn expr(&self) -> Expr<F> {
+                Expr::Query(self.0)
+            }
+        }
+
+        impl<F: Clone, RHS: Into<Expr<F>>> Add<RHS> for $name<F> {
+            type Output = Expr<F>;
+
+            fn add(self, rhs: RHS) -> Self::Output {
+                self.expr() + rhs
+            }
+        }
+
+        impl<F: Clone, RHS: Into<Expr<F>>> Sub<RHS> for $name<F> {
+            type Output = Expr<F>;
+
+            fn sub(self, rhs: RHS) -> Self::Output {
+                self.expr() - rhs
+            }
+        }
+
+        impl<F: Clone, RHS: Into<Expr<F>>> Mul<RHS> for $name<F> {
+            type Output = Expr<F>;
+
+            fn mul(self, rhs: RHS) -> Self::Output {
+                self.expr() * rhs
+            }
+        }
+
+        impl<F: Clone> Neg for $name<F> {
+            type Output = Expr<F>;
+
+            fn neg(self) -> Self::Output {
+                self.expr().neg()
+            }
+        }
+    };
+}
+
+typed_signal!(BoolSignal, Typing::Boolean);
+typed_signal!(ByteSignal, Typing::Unknown);
+typed_signal!(FieldSignal, Typing::Unknown);
+
+impl<F> BoolSignal<F> {
+    /// Only for signals already constrained to be 0 or 1, the declaration helpers constrain them.
+    pub(crate) fn new(signal: Queriable<F>) -> Self {
+        BoolSignal(signal)
+    }
+}
+
+impl<F> ByteSignal<F> {
+    /// Only for signals already range checked to 8 bits.
+    pub(crate) fn new(signal: Queriable<F>) -> Self {
+        ByteSignal(signal)
+    }
+}
+
+impl<F> FieldSignal<F> {
+    pub fn new(signal: Queriable<F>) -> Self {
+        FieldSignal(signal)
+    }
+}
+
+/// Like `select`, with a condition that must be a `BoolSignal`, so that a signal not constrained
+/// to be boolean cannot be used as the condition.
+///
+/// ```compile_fail
+/// use chiquito::dsl::{circuit, signals::ifx};
+/// use halo2_proofs::halo2curves::bn256::Fr;
+///
+/// circuit::<Fr, (), (), _>("misuse", |ctx| {
+///     let value = ctx.forward_field("value");
+///     let a = ctx.forward("a");
+///
+///     ifx(value, a, 0);
+/// });
+/// ```
+pub fn ifx<F: From<u64> + Clone, T1: Into<Constraint<F>>, T2: Into<Constraint<F>>>(
+    condition: BoolSignal<F>,
+    when_true: T1,
+    when_false: T2,
+) -> Constraint<F> {
+    select(condition, when_true, when_false)
+}
+
+/// Like `when`, with a condition that must be a `BoolSignal`.
+pub fn ifx_then<F: From<u64> + Clone, T: Into<Constraint<F>>>(
+    condition: BoolSignal<F>,
+    when_true: T,
+) -> Constraint<F> {
+    when(condition, when_true)
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+        wit_gen::{step_test::test_step, GenericTraceContext},
+    };
+
+    #[test]
+    fn test_typed_signals() {
+        let sc = circuit::<Fr, (), (bool, u64), _>("typed", |ctx| {
+            let value = ctx.forward_field("value");
+            let byte = ctx.forward_byte("byte");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let flag = ctx.internal_bool("flag");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(ifx(flag, byte, value), byte * 2));
+                    ctx.constr(ifx_then(flag, eq(byte, 3)));
+                });
+                ctx.wg(move |ctx, (f, v)| {
+                    ctx.assign(flag.queriable(), f.field());
+                    ctx.assign(byte.queriable(), v.field());
+                    ctx.assign(value.queriable(), (v * 2).field());
+                });
+            });
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, (false, 3));
+                ctx.add(&step, (true, 3));
+            });
+        });
+
+        let step = sc.step_types.values().next().unwrap();
+        assert_eq!(step.constraints[0].annotation, "is_binary(flag)");
+        assert!(step.constraints[1]
+            .annotation
+            .starts_with("if(flag)then(byte)else(value)"));
+        // the byte range check is added to the step type with its bits
+        assert!(step
+            .constraints
+            .iter()
+            .any(|constraint| constraint.annotation == "byte fits in 8 bits"));
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let step = &compiled.step_types[&witness.step_instances[0].step_type_uuid];
+        assert!(test_step(step, &witness.step_instances[0].assignments, None).is_empty());
+        assert!(!test_step(step, &witness.step_instances[1].assignments, None).is_empty());
+    }
+}
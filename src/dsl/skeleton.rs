@@ -0,0 +1,231 @@
+use std::fmt::Write;
+
+use crate::ast::{query::Queriable, Circuit, Expr, ForwardSignal, StepType};
+
+/// Language of a witness skeleton, see `witness_skeleton`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkeletonLanguage {
+    Rust,
+    /// The Python frontend, where the signals are attributes of the step type.
+    Python,
+}
+
+/// Emits the witness generation function of the step type `step_type` of `circuit`, with a
+/// placeholder assignment for every signal that the step has to assign: its internal signals and
+/// the forward signals it queries in the current row. The bits of range checks are left out, they
+/// are assigned from the checked signal. Each placeholder says whether the signal is boolean or has
+/// a bit width, and forward signals only queried in the next row are listed as assigned by the next
+/// step.
+pub fn witness_skeleton<F: Clone, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    step_type: &StepType<F, StepArgs>,
+    language: SkeletonLanguage,
+) -> String {
+    let range_bits: Vec<_> = step_type
+        .range_checks
+        .iter()
+        .flat_map(|check| check.bits.iter())
+        .collect();
+    let internal = step_type
+        .signals
+        .iter()
+        .filter(|signal| !range_bits.contains(signal))
+        .map(|signal| Queriable::Internal(*signal));
+
+    let queried = queried_forward(step_type);
+    let forward = circuit
+        .forward_signals
+        .iter()
+        .filter(|signal| queried.contains(&(**signal, false)))
+        .map(|signal| Queriable::Forward(*signal, false));
+    let next: Vec<String> = circuit
+        .forward_signals
+        .iter()
+        .filter(|signal| {
+            !queried.contains(&(**signal, false)) && queried.contains(&(**signal, true))
+        })
+        .map(|signal| Queriable::<F>::Forward(*signal, false).annotation())
+        .collect();
+
+    let assignments: Vec<(String, String)> = internal
+        .chain(forward)
+        .map(|signal| {
+            (
+                signal.annotation(),
+                signal_type(circuit, step_type, &signal),
+            )
+        })
+        .collect();
+
+    let mut out = String::new();
+    match language {
+        SkeletonLanguage::Rust => {
+            writeln!(out, "// witness of step type {}", step_type.name).unwrap();
+            writeln!(out, "ctx.wg(move |ctx, args| {{").unwrap();
+            for (name, ty) in assignments.iter() {
+                writeln!(
+                    out,
+                    "    ctx.assign({}, todo!(\"{}: {}\"));",
+                    identifier(name),
+                    name,
+                    ty
+                )
+                .unwrap();
+            }
+            for name in next.iter() {
+                writeln!(out, "    // {} is assigned by the next step", name).unwrap();
+            }
+            writeln!(out, "}});").unwrap();
+        }
+        SkeletonLanguage::Python => {
+            writeln!(out, "# witness of step type {}", step_type.name).unwrap();
+            writeln!(out, "def wg(self, args):").unwrap();
+            for (name, ty) in assignments.iter() {
+                writeln!(
+                    out,
+                    "    self.assign(self.{}, F(0))  # TODO {}: {}",
+                    identifier(name),
+                    name,
+                    ty
+                )
+                .unwrap();
+            }
+            for name in next.iter() {
+                writeln!(out, "    # {} is assigned by the next step", name).unwrap();
+            }
+            if assignments.is_empty() {
+                writeln!(out, "    pass").unwrap();
+            }
+        }
+    }
+
+    out
+}
+
+/// Forward signals queried by the step type, with whether they are queried in the next row.
+fn queried_forward<F: Clone, StepArgs>(step: &StepType<F, StepArgs>) -> Vec<(ForwardSignal, bool)> {
+    let exprs = step
+        .constraints
+        .iter()
+        .chain(step.debug_constraints.iter())
+        .map(|constraint| &constraint.expr)
+        .chain(
+            step.transition_constraints
+                .iter()
+                .chain(step.debug_transition_constraints.iter())
+                .map(|constraint| &constraint.expr),
+        )
+        .chain(step.lookups.iter().flat_map(|lookup| {
+            lookup
+                .exprs
+                .iter()
+                .map(|(src, dest)| [&src.expr, dest])
+                .flat_map(|exprs: [&Expr<F>; 2]| exprs.into_iter())
+                .chain(lookup.enable.iter().map(|enable| &enable.expr))
+        }));
+
+    let mut queried: Vec<(ForwardSignal, bool)> = step
+        .range_checks
+        .iter()
+        .map(|check| (check.signal, false))
+        .collect();
+    for expr in exprs {
+        for queriable in expr.queriables() {
+            if let Queriable::Forward(signal, next) = queriable {
+                queried.push((signal, next));
+            }
+        }
+    }
+
+    queried
+}
+
+fn signal_type<F, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    step: &StepType<F, StepArgs>,
+    signal: &Queriable<F>,
+) -> String {
+    if step.boolean_signals.contains(signal) {
+        return "boolean, 0 or 1".to_string();
+    }
+
+    if let Queriable::Forward(forward, _) = signal {
+        if let Some(check) = step
+            .range_checks
+            .iter()
+            .find(|check| check.signal == *forward)
+        {
+            return format!("{}-bit value", check.bits.len());
+        }
+        if let Some(width) = circuit
+            .signal_widths
+            .iter()
+            .find(|width| width.signal == *forward)
+        {
+            return format!("{}-bit value", width.bits);
+        }
+    }
+
+    "field element".to_string()
+}
+
+fn identifier(name: &str) -> String {
+    let identifier: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", identifier)
+    } else {
+        identifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::dsl::{
+        cb::{eq, is_binary},
+        circuit,
+    };
+
+    #[test]
+    fn test_witness_skeleton() {
+        let sc = circuit::<Fr, (), (), _>("skeleton", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("fibo step");
+
+            ctx.step_type_def(step, |ctx| {
+                let c = ctx.internal("c sum");
+                let flag = ctx.internal("flag");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + b, c));
+                    ctx.constr(is_binary(flag));
+                    ctx.transition(eq(c, b.next()));
+                });
+            });
+        });
+        let step = sc.step_types.values().next().unwrap();
+
+        let rust = witness_skeleton(&sc, step, SkeletonLanguage::Rust);
+        assert!(rust.contains("ctx.assign(c_sum, todo!(\"c sum: field element\"));"));
+        assert!(rust.contains("ctx.assign(flag, todo!(\"flag: boolean, 0 or 1\"));"));
+        assert!(rust.contains("ctx.assign(a, todo!(\"a: field element\"));"));
+        assert!(rust.contains("ctx.assign(b, "));
+
+        let python = witness_skeleton(&sc, step, SkeletonLanguage::Python);
+        assert!(python.starts_with("# witness of step type fibo step\ndef wg(self, args):\n"));
+        assert!(python.contains("self.assign(self.flag, F(0))  # TODO flag: boolean, 0 or 1"));
+    }
+}
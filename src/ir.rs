@@ -1,13 +1,20 @@
 use std::{collections::HashMap, fmt::Debug, rc::Rc};
 
-use halo2_proofs::plonk::Expression;
+use halo2_proofs::{arithmetic::Field, plonk::Expression};
 
 use crate::{
-    ast::{FixedGen, ImportedHalo2Advice, ImportedHalo2Fixed, StepType, Trace},
+    ast::{
+        query::Queriable, Aliases, Challenge, ExposeOffset, ExposedResult, FixedGen, ForwardSignal,
+        ImportedHalo2Advice, ImportedHalo2Fixed, StepFixedGen, StepSchedule, StepType, Trace,
+        TryTrace,
+    },
     compiler::{cell_manager::Placement, step_selector::StepSelector},
     util::uuid,
 };
 
+pub mod introspect;
+pub mod profile;
+
 #[derive(Clone)]
 pub struct Circuit<F, TraceArgs, StepArgs> {
     pub placement: Placement<F, StepArgs>,
@@ -17,13 +24,33 @@ pub struct Circuit<F, TraceArgs, StepArgs> {
     pub q_enable: Column,
     pub q_first: Option<Column>,
     pub q_last: Option<Column>,
+    pub num_steps: usize,
+    pub step_schedule: StepSchedule,
 
     pub columns: Vec<Column>,
     pub polys: Vec<Poly<F>>,
     pub lookups: Vec<PolyLookup<F>>,
 
+    pub exposed: Vec<(Column, i32, ExposeOffset)>, // column, rotation, step offset
+    /// The signals of `exposed`, in the same order.
+    pub exposed_signals: Vec<(ForwardSignal, ExposeOffset)>,
+    pub exposed_results: Vec<ExposedResult<F>>,
+    /// Forward signals whose values are supplied with the witness, see `ExternalColumns`.
+    pub external_signals: Vec<ForwardSignal>,
+    /// Aliases of the signals and step types, see `Aliases`.
+    pub aliases: Aliases,
+
     pub trace: Option<Rc<Trace<TraceArgs, StepArgs>>>,
+    pub try_trace: Option<Rc<TryTrace<TraceArgs, StepArgs>>>,
     pub fixed_gen: Option<Rc<FixedGen<F>>>,
+    /// Fixed columns of step-indexed fixed signals, with the value at each step index.
+    pub step_fixed: Vec<(Column, Rc<StepFixedGen<F>>)>,
+    /// Fixed columns of the tables of `fixed_table_from_iter`, with their rows.
+    pub fixed_tables: Vec<FixedTable<F>>,
+    /// Challenges queried by `PolyExpr::Challenge`, in the order they are created.
+    pub challenges: Vec<Challenge>,
+
+    pub unassigned: UnassignedPolicy<F>,
 }
 
 impl<F: Debug, TraceArgs, StepArgs: Debug> Debug for Circuit<F, TraceArgs, StepArgs> {
@@ -33,11 +60,39 @@ impl<F: Debug, TraceArgs, StepArgs: Debug> Debug for Circuit<F, TraceArgs, StepA
             .field("columns", &self.columns)
             .field("polys", &self.polys)
             .field("lookups", &self.lookups)
+            .field("exposed", &self.exposed)
             .finish()
     }
 }
 
+/// What the backend does with the internal and forward signals that a step instance does not
+/// assign.
+#[derive(Clone, Debug)]
+pub enum UnassignedPolicy<F> {
+    /// The cells are left unassigned, and the prover reads them as zero.
+    Implicit,
+    /// Panics on the first unassigned signal.
+    Error,
+    /// Assigns zero.
+    Zero,
+    /// Assigns the default given for the signal, and panics on signals without one.
+    Defaults(HashMap<Queriable<F>, F>),
+}
+
+impl<F> Default for UnassignedPolicy<F> {
+    fn default() -> Self {
+        UnassignedPolicy::Implicit
+    }
+}
+
+/// The fixed columns of a lookup table and the values of its rows.
 #[derive(Clone, Debug)]
+pub struct FixedTable<F> {
+    pub columns: Vec<Column>,
+    pub rows: Rc<Vec<Vec<F>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ColumnType {
     Advice,
     Fixed,
@@ -143,6 +198,7 @@ pub enum PolyExpr<F> {
     Neg(Box<PolyExpr<F>>),
     Pow(Box<PolyExpr<F>>, u32),
     Halo2Expr(Expression<F>),
+    Challenge(Challenge),
 }
 
 impl<F: Debug> Debug for PolyExpr<F> {
@@ -173,6 +229,7 @@ impl<F: Debug> Debug for PolyExpr<F> {
             Self::Neg(arg0) => write!(f, "(-{:?})", arg0),
             Self::Pow(arg0, arg1) => f.debug_tuple("Pow").field(arg0).field(arg1).finish(),
             Self::Halo2Expr(expr) => write!(f, "{:?}", expr),
+            Self::Challenge(challenge) => write!(f, "`{}`", challenge.annotation()),
         }
     }
 }
@@ -180,7 +237,7 @@ impl<F: Debug> Debug for PolyExpr<F> {
 impl<F: Clone> PolyExpr<F> {
     pub fn rotate(&self, rot: i32) -> PolyExpr<F> {
         match self {
-            PolyExpr::Const(_) => (*self).clone(),
+            PolyExpr::Const(_) | PolyExpr::Challenge(_) => (*self).clone(),
             PolyExpr::Query(c, orig_rot, annotation) => PolyExpr::Query(
                 c.clone(),
                 orig_rot + rot,
@@ -195,6 +252,20 @@ impl<F: Clone> PolyExpr<F> {
     }
 }
 
+impl<F: Field> PolyExpr<F> {
+    pub fn degree(&self) -> usize {
+        match self {
+            PolyExpr::Const(_) | PolyExpr::Challenge(_) => 0,
+            PolyExpr::Query(_, _, _) => 1,
+            PolyExpr::Sum(v) => v.iter().map(|e| e.degree()).max().unwrap_or(0),
+            PolyExpr::Mul(v) => v.iter().map(|e| e.degree()).sum(),
+            PolyExpr::Neg(v) => v.degree(),
+            PolyExpr::Pow(v, exp) => v.degree() * *exp as usize,
+            PolyExpr::Halo2Expr(e) => e.degree(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PolyLookup<F> {
     pub annotation: String,
@@ -0,0 +1,278 @@
+use halo2_proofs::arithmetic::Field;
+
+use crate::{
+    ast::query::Queriable, compiler::cell_manager::SignalPlacement, wit_gen::TraceWitness,
+};
+
+use super::{Circuit, ColumnType, PolyExpr};
+
+/// A signal placed in a column, see `ColumnInfo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignalOrigin {
+    Forward {
+        signal: String,
+        rotation: i32,
+    },
+    Internal {
+        step_type: String,
+        signal: String,
+        rotation: i32,
+    },
+    /// The column is one of the columns of the step selector.
+    StepSelector,
+}
+
+/// A column of a compiled circuit and the signals placed in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub uuid: u32,
+    pub annotation: String,
+    pub ctype: ColumnType,
+    pub phase: usize,
+    /// Sorted by step type and signal name, forward signals first.
+    pub signals: Vec<SignalOrigin>,
+}
+
+/// A polynomial constraint of a compiled circuit and the cells it queries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GateInfo {
+    pub annotation: String,
+    pub degree: usize,
+    /// The annotations and rotations of the queried columns, in the order they are first queried.
+    pub queries: Vec<(String, i32)>,
+}
+
+/// The signal of a step instance assigned to a cell, see `Circuit::locate`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CellOrigin {
+    pub step: usize,
+    pub step_type: String,
+    pub signal: String,
+}
+
+impl<F, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
+    /// The columns of the circuit with the signals placed in them, for tools that show the
+    /// layout of a circuit without parsing its `Debug` output.
+    pub fn column_infos(&self) -> Vec<ColumnInfo> {
+        self.columns
+            .iter()
+            .map(|column| {
+                let mut forward: Vec<SignalOrigin> = self
+                    .placement
+                    .forward
+                    .iter()
+                    .filter(|(_, placement)| placement.column == *column)
+                    .map(|(signal, placement)| SignalOrigin::Forward {
+                        signal: Queriable::<F>::Forward(*signal, false).annotation(),
+                        rotation: placement.rotation,
+                    })
+                    .collect();
+                forward.sort_by_key(|origin| format!("{:?}", origin));
+
+                let mut internal: Vec<SignalOrigin> = self
+                    .step_types
+                    .values()
+                    .flat_map(|step| {
+                        self.placement
+                            .internal_placements(step)
+                            .filter(|(_, placement)| placement.column == *column)
+                            .map(|(signal, placement)| SignalOrigin::Internal {
+                                step_type: step.name.clone(),
+                                signal: Queriable::<F>::Internal(*signal).annotation(),
+                                rotation: placement.rotation,
+                            })
+                    })
+                    .collect();
+                internal.sort_by_key(|origin| format!("{:?}", origin));
+
+                let mut signals = forward;
+                signals.extend(internal);
+                if self.selector.columns.contains(column) {
+                    signals.push(SignalOrigin::StepSelector);
+                }
+
+                ColumnInfo {
+                    uuid: column.uuid(),
+                    annotation: column.annotation.clone(),
+                    ctype: column.ctype.clone(),
+                    phase: column.phase,
+                    signals,
+                }
+            })
+            .collect()
+    }
+
+    /// The signal assigned to the cell of `column` at `row` of the `witness`, with the steps laid
+    /// out one after the other from the first row. `None` for the cells of no signal, like the
+    /// selectors or the fixed columns, and for the rows past the last step.
+    pub fn locate(&self, witness: &TraceWitness<F>, column: u32, row: usize) -> Option<CellOrigin> {
+        let mut offset = 0;
+        for (index, instance) in witness.step_instances.iter().enumerate() {
+            let step = self.step_types.get(&instance.step_type_uuid)?;
+            let height = self.placement.step_height(step) as usize;
+            if row >= offset + height {
+                offset += height;
+                continue;
+            }
+
+            let rotation = (row - offset) as i32;
+            let placed = |placement: &SignalPlacement| {
+                placement.column.uuid() == column && placement.rotation == rotation
+            };
+
+            let forward = self
+                .placement
+                .forward
+                .iter()
+                .find(|(_, placement)| placed(placement))
+                .map(|(signal, _)| Queriable::<F>::Forward(*signal, false));
+            let internal = self
+                .placement
+                .internal_placements(step)
+                .find(|(_, placement)| placed(placement))
+                .map(|(signal, _)| Queriable::<F>::Internal(*signal));
+
+            return forward.or(internal).map(|signal| CellOrigin {
+                step: index,
+                step_type: step.name.clone(),
+                signal: signal.annotation(),
+            });
+        }
+
+        None
+    }
+}
+
+impl<F: Field, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
+    /// The polynomial constraints of the circuit, in the order of the constraint system.
+    pub fn gates(&self) -> Vec<GateInfo> {
+        self.polys
+            .iter()
+            .map(|poly| {
+                let mut queries = Vec::new();
+                collect_queries(&poly.expr, &mut queries);
+
+                GateInfo {
+                    annotation: poly.annotation.clone(),
+                    degree: poly.expr.degree(),
+                    queries,
+                }
+            })
+            .collect()
+    }
+}
+
+fn collect_queries<F>(expr: &PolyExpr<F>, queries: &mut Vec<(String, i32)>) {
+    match expr {
+        PolyExpr::Query(column, rotation, _) => {
+            let query = (column.annotation.clone(), *rotation);
+            if !queries.contains(&query) {
+                queries.push(query);
+            }
+        }
+        PolyExpr::Sum(v) | PolyExpr::Mul(v) => {
+            for expr in v.iter() {
+                collect_queries(expr, queries);
+            }
+        }
+        PolyExpr::Neg(v) | PolyExpr::Pow(v, _) => collect_queries(v, queries),
+        PolyExpr::Const(_) | PolyExpr::Halo2Expr(_) | PolyExpr::Challenge(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_introspection() {
+        let sc = circuit::<Fr, u64, u64, _>("inspected", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a * 2, b));
+                    ctx.transition(eq(a + 1, a.next()));
+                });
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                });
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let columns = compiled.column_infos();
+        let a = columns
+            .iter()
+            .find(|column| column.annotation == "srcm forward a")
+            .unwrap();
+        assert_eq!(a.ctype, ColumnType::Advice);
+        assert_eq!(
+            a.signals,
+            vec![SignalOrigin::Forward {
+                signal: "a".to_string(),
+                rotation: 0
+            }]
+        );
+        let b = columns
+            .iter()
+            .find(|column| column.annotation == "srcm internal signal b")
+            .unwrap();
+        assert_eq!(
+            b.signals,
+            vec![SignalOrigin::Internal {
+                step_type: "step".to_string(),
+                signal: "b".to_string(),
+                rotation: 0
+            }]
+        );
+        assert!(columns
+            .iter()
+            .any(|column| column.signals == vec![SignalOrigin::StepSelector]));
+
+        let gates = compiled.gates();
+        let transition = gates
+            .iter()
+            .find(|gate| gate.annotation.contains("next(a)"))
+            .unwrap();
+        assert_eq!(transition.degree, 3);
+        assert!(transition
+            .queries
+            .contains(&("srcm forward a".to_string(), 1)));
+
+        let a_uuid = a.uuid;
+        let b_uuid = b.uuid;
+        let witness = ChiquitoHalo2::new(compiled.clone()).witness(3).unwrap();
+        assert_eq!(
+            compiled.locate(&witness, b_uuid, 2),
+            Some(CellOrigin {
+                step: 2,
+                step_type: "step".to_string(),
+                signal: "b".to_string()
+            })
+        );
+        assert_eq!(compiled.locate(&witness, a_uuid, 0).unwrap().signal, "a");
+        assert_eq!(compiled.locate(&witness, a_uuid, 3), None);
+    }
+}
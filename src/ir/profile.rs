@@ -0,0 +1,157 @@
+use std::fmt;
+
+use halo2_proofs::arithmetic::Field;
+
+use super::{Circuit, PolyExpr};
+
+/// Estimated cost of evaluating a constraint in the quotient polynomial.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintCost {
+    pub annotation: String,
+    /// Number of additions, multiplications and negations of the expression.
+    pub size: usize,
+    pub degree: usize,
+    /// `size` times the rows of the extended domain, in which the expression is evaluated.
+    pub cost: usize,
+}
+
+/// The constraints of a circuit ranked by their evaluation cost, most expensive first, see
+/// `Circuit::profile`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintProfile {
+    pub rows: usize,
+    /// Rows of the extended domain, `rows` times the power of two above the maximum degree minus
+    /// one. The constraints with the maximum degree set it for all the others.
+    pub extended_rows: usize,
+    pub max_degree: usize,
+    pub constraints: Vec<ConstraintCost>,
+}
+
+impl ConstraintProfile {
+    pub fn total_cost(&self) -> usize {
+        self.constraints
+            .iter()
+            .map(|constraint| constraint.cost)
+            .sum()
+    }
+}
+
+impl fmt::Display for ConstraintProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} rows, {} extended rows, max degree {}",
+            self.rows, self.extended_rows, self.max_degree
+        )?;
+
+        let total = self.total_cost().max(1);
+        for constraint in self.constraints.iter() {
+            writeln!(
+                f,
+                "{:>5.1}% size {} degree {}{} {}",
+                constraint.cost as f64 * 100.0 / total as f64,
+                constraint.size,
+                constraint.degree,
+                if constraint.degree == self.max_degree {
+                    " (max)"
+                } else {
+                    ""
+                },
+                constraint.annotation
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: Field, TraceArgs, StepArgs> Circuit<F, TraceArgs, StepArgs> {
+    /// Estimates which constraints dominate the evaluation of the quotient polynomial by the
+    /// prover for a circuit of `rows` rows. The prover evaluates every constraint in every row of
+    /// the extended domain, so the cost of a constraint is the size of its expression times those
+    /// rows. The estimate does not run the prover.
+    pub fn profile(&self, rows: usize) -> ConstraintProfile {
+        let max_degree = self
+            .polys
+            .iter()
+            .map(|poly| poly.expr.degree())
+            .max()
+            .unwrap_or(0);
+        // halo2 needs an extended domain of at least degree - 1 times the rows
+        let extended_rows = rows * max_degree.saturating_sub(1).max(1).next_power_of_two();
+
+        let mut constraints: Vec<ConstraintCost> = self
+            .polys
+            .iter()
+            .map(|poly| {
+                let size = expr_size(&poly.expr);
+
+                ConstraintCost {
+                    annotation: poly.annotation.clone(),
+                    size,
+                    degree: poly.expr.degree(),
+                    cost: size * extended_rows,
+                }
+            })
+            .collect();
+        constraints.sort_by(|a, b| b.cost.cmp(&a.cost));
+
+        ConstraintProfile {
+            rows,
+            extended_rows,
+            max_degree,
+            constraints,
+        }
+    }
+}
+
+fn expr_size<F>(expr: &PolyExpr<F>) -> usize {
+    match expr {
+        PolyExpr::Const(_) | PolyExpr::Query(_, _, _) | PolyExpr::Challenge(_) => 0,
+        PolyExpr::Sum(v) | PolyExpr::Mul(v) => {
+            v.len().saturating_sub(1) + v.iter().map(expr_size).sum::<usize>()
+        }
+        PolyExpr::Neg(v) => 1 + expr_size(v),
+        PolyExpr::Pow(v, exp) => (*exp as usize).saturating_sub(1) + expr_size(v),
+        PolyExpr::Halo2Expr(_) => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use crate::{
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_profile() {
+        let sc = circuit::<Fr, (), (), _>("profile", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a, b));
+                    ctx.constr(eq(a * a * a + b * b, a * b + 1));
+                });
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let profile = compiled.profile(16);
+
+        assert_eq!(profile.max_degree, 5);
+        assert_eq!(profile.extended_rows, 64);
+        assert_eq!(profile.constraints.len(), 2);
+        assert!(profile.constraints[0].annotation.contains("a * a * a"));
+        assert!(profile.constraints[0].cost > profile.constraints[1].cost);
+        assert!(profile.to_string().contains("(max)"));
+    }
+}
@@ -1,8 +1,11 @@
 pub mod ast;
 pub mod backend;
+pub mod ci;
 pub mod compiler;
 pub mod dsl;
 pub mod ir;
+pub mod metrics;
 pub mod stdlib;
+pub mod templates;
 mod util;
 pub mod wit_gen;
@@ -0,0 +1,164 @@
+//! Metrics of compilation, witness generation and proving, for services that monitor them, for
+//! example by exporting them to Prometheus. The metrics are only emitted with the `metrics`
+//! feature, to a `MetricsRecorder` installed with `set_recorder`:
+//!
+//! ```ignore
+//! struct Prometheus { /* registry, histograms... */ }
+//!
+//! impl MetricsRecorder for Prometheus {
+//!     fn increment_counter(&self, name: &'static str, value: u64) { /* ... */ }
+//!     fn record_histogram(&self, name: &'static str, value: f64) { /* ... */ }
+//! }
+//!
+//! chiquito::metrics::set_recorder(Prometheus::new());
+//! ```
+//!
+//! Without the feature, or without a recorder, emitting a metric does nothing.
+
+use std::sync::{Arc, RwLock};
+
+/// Seconds spent in `Compiler::compile`, a histogram.
+pub const COMPILE_SECONDS: &str = "chiquito_compile_seconds";
+/// Seconds spent running the trace and the witness generation of its steps, a histogram.
+pub const WITNESS_SECONDS: &str = "chiquito_witness_seconds";
+/// Number of step instances generated, a counter.
+pub const STEPS_GENERATED: &str = "chiquito_steps_generated";
+/// Seconds spent by the backend proving a witness, a histogram.
+pub const PROVE_SECONDS: &str = "chiquito_prove_seconds";
+/// Rows used by the steps of each synthesized witness, a histogram.
+pub const ROWS_USED: &str = "chiquito_rows_used";
+
+/// Receives the metrics emitted by chiquito, by the names of the constants of this module.
+pub trait MetricsRecorder: Send + Sync {
+    fn increment_counter(&self, name: &'static str, value: u64);
+    fn record_histogram(&self, name: &'static str, value: f64);
+}
+
+static RECORDER: RwLock<Option<Arc<dyn MetricsRecorder>>> = RwLock::new(None);
+
+/// Sends the metrics of all the threads to `recorder`, replacing the recorder set before.
+pub fn set_recorder<R: MetricsRecorder + 'static>(recorder: R) {
+    *RECORDER.write().unwrap() = Some(Arc::new(recorder));
+}
+
+/// Stops sending the metrics to the recorder.
+pub fn clear_recorder() {
+    *RECORDER.write().unwrap() = None;
+}
+
+#[cfg(feature = "metrics")]
+fn recorder() -> Option<Arc<dyn MetricsRecorder>> {
+    RECORDER.read().unwrap().clone()
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn increment_counter(name: &'static str, value: u64) {
+    if let Some(recorder) = recorder() {
+        recorder.increment_counter(name, value);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn increment_counter(_name: &'static str, _value: u64) {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_histogram(name: &'static str, value: f64) {
+    if let Some(recorder) = recorder() {
+        recorder.record_histogram(name, value);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_histogram(_name: &'static str, _value: f64) {}
+
+/// Records the seconds from its creation to its drop in a histogram.
+pub(crate) struct Timer {
+    #[cfg(feature = "metrics")]
+    name: &'static str,
+    #[cfg(feature = "metrics")]
+    start: std::time::Instant,
+}
+
+impl Timer {
+    #[cfg(feature = "metrics")]
+    pub(crate) fn start(name: &'static str) -> Self {
+        Self {
+            name,
+            start: std::time::Instant::now(),
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    pub(crate) fn start(_name: &'static str) -> Self {
+        Self {}
+    }
+
+    /// Records the time now instead of when the timer goes out of scope.
+    pub(crate) fn stop(self) {}
+}
+
+#[cfg(feature = "metrics")]
+impl Drop for Timer {
+    fn drop(&mut self) {
+        record_histogram(self.name, self.start.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+    };
+
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<(&'static str, f64)>>>);
+
+    impl MetricsRecorder for Recorded {
+        fn increment_counter(&self, name: &'static str, value: u64) {
+            self.0.lock().unwrap().push((name, value as f64));
+        }
+
+        fn record_histogram(&self, name: &'static str, value: f64) {
+            self.0.lock().unwrap().push((name, value));
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let recorded = Recorded::default();
+        set_recorder(recorded.clone());
+
+        let sc = circuit::<Fr, u64, u64, _>("measured", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        ChiquitoHalo2::new(compiled).witness(1234).unwrap();
+        clear_recorder();
+
+        // other tests may emit metrics meanwhile
+        let recorded = recorded.0.lock().unwrap();
+        let names: Vec<&str> = recorded.iter().map(|(name, _)| *name).collect();
+        assert!(names.contains(&COMPILE_SECONDS));
+        assert!(names.contains(&WITNESS_SECONDS));
+        assert!(recorded.contains(&(STEPS_GENERATED, 1234.0)));
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     ast::{query::Queriable, ToExpr},
     compiler::WitnessGenContext,
     dsl::{
-        cb::{Constraint, Typing},
+        cb::{operand_booleans, Constraint, Typing},
         StepTypeContext,
     },
 };
@@ -29,6 +29,7 @@ impl<F: Field + From<u64>> IsZero<F> {
             expr: is_zero_expression,
             annotation: format!("is_zero({:?})", value),
             typing: Typing::Boolean,
+            booleans: operand_booleans(&value),
         };
 
         IsZero {
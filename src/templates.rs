@@ -0,0 +1,410 @@
+use halo2_proofs::arithmetic::Field;
+
+use crate::{
+    ast::{query::Queriable, Circuit, ExposeOffset, Expr, ToField},
+    compiler::TraceContext,
+    dsl::{cb::eq, circuit, CircuitContext, StepTypeHandler},
+};
+
+/// A counter: `count` starts at a given value in the `first` step and increases by one in every
+/// `step`, and the `last` step ends the count. The step args of the step types are the value of
+/// `count` in the step.
+#[derive(Clone, Copy, Debug)]
+pub struct Counter<F> {
+    pub count: Queriable<F>,
+    pub first: StepTypeHandler,
+    pub step: StepTypeHandler,
+    pub last: StepTypeHandler,
+}
+
+impl<F: Field + From<u64>> Counter<F> {
+    /// Defines the signals and step types of a counter from `start` in `ctx`, which can be
+    /// extended with more constraints and step types.
+    pub fn define<TraceArgs>(ctx: &mut CircuitContext<F, TraceArgs, u64>, start: u64) -> Self {
+        let count = ctx.forward("count");
+        let first = ctx.step_type("first count step");
+        let step = ctx.step_type("count step");
+        let last = ctx.step_type("last count step");
+
+        for handler in [first, step] {
+            ctx.step_type_def(handler, |ctx| {
+                ctx.setup(move |ctx| {
+                    if handler == first {
+                        ctx.constr(eq(count, start));
+                    }
+                    ctx.transition(eq(count + 1, count.next()));
+                });
+                ctx.wg(move |ctx, value| ctx.assign(count, value.field()));
+            });
+        }
+        ctx.step_type_def(last, |ctx| {
+            ctx.wg(move |ctx, value| ctx.assign(count, value.field()));
+        });
+
+        Counter {
+            count,
+            first,
+            step,
+            last,
+        }
+    }
+
+    /// Adds `steps` steps counting from `start`, which must be the start of the counter. The
+    /// count needs at least the `first` and the `last` steps.
+    pub fn trace(&self, ctx: &mut dyn TraceContext<u64>, start: u64, steps: usize) {
+        if steps < 2 {
+            panic!("a counter needs at least two steps, got {}", steps);
+        }
+
+        for i in 0..steps {
+            let step = match i {
+                0 => &self.first,
+                i if i + 1 == steps => &self.last,
+                _ => &self.step,
+            };
+            ctx.add(step, start + i as u64);
+        }
+    }
+}
+
+/// A circuit counting from `start` for the number of steps given as trace args, exposing the
+/// last value of the count.
+pub fn counter<F: Field + From<u64>>(start: u64) -> Circuit<F, usize, u64> {
+    circuit::<F, usize, u64, _>("counter", |ctx| {
+        let counter = Counter::define(ctx, start);
+
+        ctx.pragma_first_step(counter.first);
+        ctx.pragma_last_step(counter.last);
+        ctx.expose(counter.count, ExposeOffset::Last);
+        ctx.trace(move |ctx, steps| counter.trace(ctx, start, steps));
+    })
+}
+
+/// The fibonacci sequence: `a` and `b` are consecutive numbers of the sequence, and every step
+/// computes the next one in its internal signal `c`. The step args are the values of `a` and `b`.
+#[derive(Clone, Copy, Debug)]
+pub struct Fibonacci<F> {
+    pub a: Queriable<F>,
+    pub b: Queriable<F>,
+    pub step: StepTypeHandler,
+    pub last: StepTypeHandler,
+}
+
+impl<F: Field + From<u64>> Fibonacci<F> {
+    /// Defines the signals and step types of the sequence in `ctx`.
+    pub fn define<TraceArgs>(ctx: &mut CircuitContext<F, TraceArgs, (F, F)>) -> Self {
+        let a = ctx.forward("a");
+        let b = ctx.forward("b");
+        let step = ctx.step_type("fibo step");
+        let last = ctx.step_type("last fibo step");
+
+        for handler in [step, last] {
+            ctx.step_type_def(handler, |ctx| {
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + b, c));
+                    if handler == step {
+                        ctx.transition(eq(b, a.next()));
+                        ctx.transition(eq(c, b.next()));
+                    }
+                });
+                ctx.wg(move |ctx, (a_value, b_value)| {
+                    ctx.assign(a, a_value);
+                    ctx.assign(b, b_value);
+                    ctx.assign(c, a_value + b_value);
+                });
+            });
+        }
+
+        Fibonacci { a, b, step, last }
+    }
+
+    /// Adds `steps` steps of the sequence starting with 1, 1, the last one of type `last`.
+    pub fn trace(&self, ctx: &mut dyn TraceContext<(F, F)>, steps: usize) {
+        let (mut a, mut b) = (F::ONE, F::ONE);
+
+        for i in 0..steps {
+            let step = if i + 1 == steps {
+                &self.last
+            } else {
+                &self.step
+            };
+            ctx.add(step, (a, b));
+
+            (a, b) = (b, a + b);
+        }
+    }
+}
+
+/// A circuit of the fibonacci sequence with the number of steps given as trace args.
+pub fn fibonacci<F: Field + From<u64>>() -> Circuit<F, usize, (F, F)> {
+    circuit::<F, usize, (F, F), _>("fibonacci", |ctx| {
+        let fibo = Fibonacci::define(ctx);
+
+        ctx.pragma_first_step(fibo.step);
+        ctx.pragma_last_step(fibo.last);
+        ctx.trace(move |ctx, steps| fibo.trace(ctx, steps));
+    })
+}
+
+/// Operation that an `Accumulator` applies in each step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccumulatorOp {
+    Sum,
+    Product,
+}
+
+impl AccumulatorOp {
+    fn identity<F: Field>(&self) -> F {
+        match self {
+            AccumulatorOp::Sum => F::ZERO,
+            AccumulatorOp::Product => F::ONE,
+        }
+    }
+
+    fn apply<F: Field>(&self, acc: F, value: F) -> F {
+        match self {
+            AccumulatorOp::Sum => acc + value,
+            AccumulatorOp::Product => acc * value,
+        }
+    }
+}
+
+/// Folds a list of values: the `init` step sets `acc` to the identity of the operation, every
+/// `step` combines `acc` with its internal signal `value` into the next `acc`, and the `result`
+/// step holds the final `acc`. The step args are the values of `acc` and `value`.
+#[derive(Clone, Copy, Debug)]
+pub struct Accumulator<F> {
+    pub acc: Queriable<F>,
+    pub value: Queriable<F>,
+    pub init: StepTypeHandler,
+    pub step: StepTypeHandler,
+    pub result: StepTypeHandler,
+    pub op: AccumulatorOp,
+}
+
+impl<F: Field + From<u64>> Accumulator<F> {
+    /// Defines the signals and step types of the accumulator in `ctx`.
+    pub fn define<TraceArgs>(
+        ctx: &mut CircuitContext<F, TraceArgs, (F, F)>,
+        op: AccumulatorOp,
+    ) -> Self {
+        let acc = ctx.forward("acc");
+        let init = ctx.step_type("init step");
+        let step = ctx.step_type("accumulate step");
+        let result = ctx.step_type("result step");
+
+        ctx.step_type_def(init, |ctx| {
+            ctx.setup(move |ctx| {
+                ctx.constr(eq(acc, Expr::Const(op.identity::<F>())));
+                ctx.transition(eq(acc, acc.next()));
+            });
+            ctx.wg(move |ctx, (acc_value, _)| ctx.assign(acc, acc_value));
+        });
+
+        let mut value = None;
+        ctx.step_type_def(step, |ctx| {
+            let step_value = ctx.internal("value");
+            value = Some(step_value);
+
+            ctx.setup(move |ctx| match op {
+                AccumulatorOp::Sum => ctx.transition(eq(acc + step_value, acc.next())),
+                AccumulatorOp::Product => ctx.transition(eq(acc * step_value, acc.next())),
+            });
+            ctx.wg(move |ctx, (acc_value, value)| {
+                ctx.assign(acc, acc_value);
+                ctx.assign(step_value, value);
+            });
+        });
+        ctx.step_type_def(result, |ctx| {
+            ctx.wg(move |ctx, (acc_value, _)| ctx.assign(acc, acc_value));
+        });
+
+        Accumulator {
+            acc,
+            value: value.unwrap(),
+            init,
+            step,
+            result,
+            op,
+        }
+    }
+
+    /// Adds the `init` step, a step for each one of `values` and the `result` step.
+    pub fn trace(&self, ctx: &mut dyn TraceContext<(F, F)>, values: &[F]) {
+        let mut acc = self.op.identity();
+
+        ctx.add(&self.init, (acc, F::ZERO));
+        for value in values {
+            ctx.add(&self.step, (acc, *value));
+            acc = self.op.apply(acc, *value);
+        }
+        ctx.add(&self.result, (acc, F::ZERO));
+    }
+}
+
+/// A circuit folding the values given as trace args with `op`, exposing the final value of the
+/// accumulator.
+pub fn accumulator<F: Field + From<u64>>(op: AccumulatorOp) -> Circuit<F, Vec<F>, (F, F)> {
+    circuit::<F, Vec<F>, (F, F), _>("accumulator", |ctx| {
+        let accumulator = Accumulator::define(ctx, op);
+
+        ctx.pragma_first_step(accumulator.init);
+        ctx.pragma_last_step(accumulator.result);
+        ctx.expose(accumulator.acc, ExposeOffset::Last);
+        ctx.trace(move |ctx, values| accumulator.trace(ctx, &values));
+    })
+}
+
+/// Instruction of a `Vm` program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction<F> {
+    Add(F),
+    Mul(F),
+}
+
+/// Skeleton of a virtual machine with a program counter `pc`, an accumulator register `acc` and a
+/// step type for each instruction, the `halt` step ending the execution. The step args are the
+/// values of `pc` and `acc` before the instruction and its operand.
+///
+/// The operands are not checked against a program, circuits built on the skeleton add a lookup of
+/// `pc` and the operand into their program table, and their own instructions.
+#[derive(Clone, Copy, Debug)]
+pub struct Vm<F> {
+    pub pc: Queriable<F>,
+    pub acc: Queriable<F>,
+    pub add: StepTypeHandler,
+    pub mul: StepTypeHandler,
+    pub halt: StepTypeHandler,
+}
+
+impl<F: Field + From<u64>> Vm<F> {
+    /// Defines the registers and instruction step types of the machine in `ctx`.
+    pub fn define<TraceArgs>(ctx: &mut CircuitContext<F, TraceArgs, (F, F, F)>) -> Self {
+        let pc = ctx.forward("pc");
+        let acc = ctx.forward("acc");
+        let add = ctx.step_type("add");
+        let mul = ctx.step_type("mul");
+        let halt = ctx.step_type("halt");
+
+        for handler in [add, mul] {
+            ctx.step_type_def(handler, |ctx| {
+                let operand = ctx.internal("operand");
+
+                ctx.setup(move |ctx| {
+                    ctx.transition(eq(pc + 1, pc.next()));
+                    if handler == add {
+                        ctx.transition(eq(acc + operand, acc.next()));
+                    } else {
+                        ctx.transition(eq(acc * operand, acc.next()));
+                    }
+                });
+                ctx.wg(move |ctx, (pc_value, acc_value, operand_value)| {
+                    ctx.assign(pc, pc_value);
+                    ctx.assign(acc, acc_value);
+                    ctx.assign(operand, operand_value);
+                });
+            });
+        }
+        ctx.step_type_def(halt, |ctx| {
+            ctx.wg(move |ctx, (pc_value, acc_value, _)| {
+                ctx.assign(pc, pc_value);
+                ctx.assign(acc, acc_value);
+            });
+        });
+
+        Vm {
+            pc,
+            acc,
+            add,
+            mul,
+            halt,
+        }
+    }
+
+    /// Runs `program` from `pc` 0 and `acc` 0, adding a step for each instruction and the `halt`
+    /// step.
+    pub fn trace(&self, ctx: &mut dyn TraceContext<(F, F, F)>, program: &[Instruction<F>]) {
+        let mut acc = F::ZERO;
+
+        for (pc, instruction) in program.iter().enumerate() {
+            let pc = (pc as u64).field();
+            match instruction {
+                Instruction::Add(operand) => {
+                    ctx.add(&self.add, (pc, acc, *operand));
+                    acc += *operand;
+                }
+                Instruction::Mul(operand) => {
+                    ctx.add(&self.mul, (pc, acc, *operand));
+                    acc *= *operand;
+                }
+            }
+        }
+        ctx.add(&self.halt, ((program.len() as u64).field(), acc, F::ZERO));
+    }
+}
+
+/// A circuit running the program given as trace args, exposing the final value of `acc`.
+pub fn vm<F: Field + From<u64>>() -> Circuit<F, Vec<Instruction<F>>, (F, F, F)> {
+    circuit::<F, Vec<Instruction<F>>, (F, F, F), _>("vm", |ctx| {
+        let vm = Vm::define(ctx);
+
+        ctx.pragma_last_step(vm.halt);
+        ctx.expose(vm.acc, ExposeOffset::Last);
+        ctx.trace(move |ctx, program| vm.trace(ctx, &program));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hash;
+
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::wit_gen::{step_test::test_step, GenericTraceContext, StepInstance, TraceWitness};
+
+    fn check<F: Field + Hash, TraceArgs, StepArgs>(
+        sc: &Circuit<F, TraceArgs, StepArgs>,
+        args: TraceArgs,
+    ) -> TraceWitness<F> {
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, args);
+        let witness = ctx.get_witness();
+
+        let instances = &witness.step_instances;
+        for (i, instance) in instances.iter().enumerate() {
+            let step = &sc.step_types[&instance.step_type_uuid];
+            let next: Option<&StepInstance<F>> = instances.get(i + 1);
+
+            let failures = test_step(step, &instance.assignments, next);
+            assert!(failures.is_empty(), "step {}: {:?}", i, failures);
+        }
+
+        witness
+    }
+
+    #[test]
+    fn test_templates() {
+        let sc = counter::<Fr>(3);
+        let witness = check(&sc, 5);
+        assert_eq!(witness.public_inputs(&sc), vec![Fr::from(7)]);
+
+        let witness = check(&fibonacci::<Fr>(), 6);
+        assert_eq!(witness.step_instances.len(), 6);
+
+        let sc = accumulator::<Fr>(AccumulatorOp::Product);
+        let witness = check(&sc, vec![Fr::from(2), Fr::from(3), Fr::from(4)]);
+        assert_eq!(witness.public_inputs(&sc), vec![Fr::from(24)]);
+
+        let sc = vm::<Fr>();
+        let program = vec![
+            Instruction::Add(Fr::from(3)),
+            Instruction::Mul(Fr::from(5)),
+            Instruction::Add(Fr::from(1)),
+        ];
+        let witness = check(&sc, program);
+        assert_eq!(witness.public_inputs(&sc), vec![Fr::from(16)]);
+    }
+}
@@ -1,36 +1,537 @@
-use std::{collections::HashMap, hash::Hash, rc::Rc};
+use std::{
+    collections::HashMap, fmt, hash::Hash, mem, ops::RangeBounds, panic::Location, rc::Rc,
+    sync::Arc,
+};
+
+use halo2_proofs::arithmetic::Field;
 
 use crate::{
-    ast::{query::Queriable, StepType, StepTypeUUID},
-    compiler::{TraceContext, WitnessGenContext},
+    ast::{
+        query::Queriable, ExposeOffset, ExposedResult, Expr, ForwardSignal, InternalSignal,
+        SignalDefault, StepSchedule, StepType, StepTypeUUID, StepWitnessGen,
+    },
+    compiler::{
+        check_challenge_signal, check_witness_signal, AssignmentError, TraceContext,
+        WitnessGenContext,
+    },
     dsl::StepTypeHandler,
+    ir::Circuit,
+};
+
+use self::{
+    assign_log::AssignLog,
+    assignments::{Assignments, SignalIndex},
+    challenge::ChallengeAssignment,
+    external::ExternalColumns,
+    progress::TraceHooks,
+    step_test::{test_step, test_transitions, StepFailure},
 };
 
+pub mod anonymize;
+pub mod assign_log;
+pub mod assignments;
+pub mod cache;
+pub mod challenge;
+pub mod columnar;
+pub mod compress;
+pub mod determinism;
+pub mod dry_run;
+#[cfg(feature = "encryption")]
+pub mod encrypt;
+pub mod exec_trace;
+pub mod external;
+pub mod fill;
+pub mod format;
+pub mod fuzz;
+pub mod import;
+pub mod lint;
+pub mod lookup_report;
+pub mod mutator;
+pub mod parallel;
+pub mod pipeline;
+pub mod progress;
+pub mod public_inputs;
+pub mod replay;
+pub mod split;
+pub mod step_test;
+pub mod timeline;
+pub mod trace_log;
+pub mod transitions;
+
+#[derive(Clone, Debug)]
 pub struct StepInstance<F> {
     pub step_type_uuid: StepTypeUUID,
-    pub assignments: HashMap<Queriable<F>, F>,
+    pub assignments: Assignments<F>,
+    /// Source location of the call in the witness generation function that assigned each signal,
+    /// recorded when the trace context is `with_provenance`. Signals assigned by chiquito, like
+    /// the derived signals and the bits of range checks, have none.
+    pub provenance: HashMap<Queriable<F>, &'static Location<'static>>,
+    /// Signals of the later phases, assigned by the backend once it knows the challenges.
+    pub challenge_assignments: Vec<ChallengeAssignment<F>>,
 }
 
 impl<F> StepInstance<F> {
     pub fn new(step_type_uuid: StepTypeUUID) -> StepInstance<F> {
         StepInstance {
             step_type_uuid,
-            assignments: HashMap::default(),
+            assignments: Assignments::default(),
+            provenance: HashMap::default(),
+            challenge_assignments: Vec::new(),
+        }
+    }
+
+    /// A step instance storing the values of the signals of `index` densely, see `Assignments`.
+    pub fn with_index(step_type_uuid: StepTypeUUID, index: Arc<SignalIndex<F>>) -> StepInstance<F> {
+        StepInstance {
+            step_type_uuid,
+            assignments: Assignments::with_index(index),
+            provenance: HashMap::default(),
+            challenge_assignments: Vec::new(),
         }
     }
 }
 
+/// The provenance is not part of the witness, two step instances with the same assignments are
+/// equal wherever their values come from.
+impl<F: PartialEq> PartialEq for StepInstance<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.step_type_uuid == other.step_type_uuid
+            && self.assignments == other.assignments
+            && self
+                .challenge_assignments
+                .iter()
+                .map(|assignment| assignment.signal)
+                .eq(other
+                    .challenge_assignments
+                    .iter()
+                    .map(|assignment| assignment.signal))
+    }
+}
+
+impl<F: Eq> Eq for StepInstance<F> {}
+
+impl<F: Eq + Hash> StepInstance<F> {
+    /// Assigns all the pairs of `assignments`, reserving room for them first when their number
+    /// is known. The last value of a signal given more than once wins, as with `assign`.
+    pub fn assign_many<I: IntoIterator<Item = (Queriable<F>, F)>>(&mut self, assignments: I) {
+        self.assignments.extend(assignments);
+    }
+
+    /// The value assigned to `signal` in the step, if any.
+    pub fn get(&self, signal: &Queriable<F>) -> Option<&F> {
+        self.assignments.get(signal)
+    }
+
+    /// The value of the signal with `annotation` in the step, for tools that know the signals by
+    /// name, like `"a"` or `"next(a)"`. Annotations are not checked to be unique, with two
+    /// signals of the same name the value of either may be returned.
+    pub fn get_by_annotation(&self, annotation: &str) -> Option<&F> {
+        self.assignments
+            .iter()
+            .find(|(signal, _)| signal.annotation() == annotation)
+            .map(|(_, value)| value)
+    }
+
+    /// The annotation and value of every signal assigned in the step, in the order of
+    /// `assignments`.
+    pub fn named_assignments(&self) -> impl Iterator<Item = (String, &F)> {
+        self.assignments
+            .iter()
+            .map(|(signal, value)| (signal.annotation(), value))
+    }
+}
+
 impl<F: Eq + Hash> WitnessGenContext<F> for StepInstance<F> {
     fn assign(&mut self, lhs: Queriable<F>, rhs: F) {
         self.assignments.insert(lhs, rhs);
     }
+
+    fn assign_slice(&mut self, assignments: &[(Queriable<F>, F)])
+    where
+        F: Clone,
+    {
+        self.assign_many(assignments.iter().cloned());
+    }
+
+    fn assign_iter(&mut self, assignments: &mut dyn Iterator<Item = (Queriable<F>, F)>) {
+        self.assign_many(assignments);
+    }
+
+    fn assignments(&self) -> Option<&Assignments<F>> {
+        Some(&self.assignments)
+    }
+
+    fn push_challenge_assignment(&mut self, assignment: ChallengeAssignment<F>) {
+        self.challenge_assignments.push(assignment);
+    }
+}
+
+/// Checks a condition in a witness generation function. When it is false, witness generation
+/// fails with the formatted message, the index and type of the step and the signals assigned so
+/// far, see `WitnessAssertion`.
+///
+/// ```ignore
+/// ctx.wg(move |ctx, (a_value, b_value)| {
+///     witness_assert!(ctx, a_value <= b_value, "a {} is above b {}", a_value, b_value);
+///     ctx.assign(a, a_value.field());
+/// });
+/// ```
+#[macro_export]
+macro_rules! witness_assert {
+    ($ctx:expr, $cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $ctx.fail(format!($($arg)+));
+        }
+    };
+}
+
+/// A failed `witness_assert!`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WitnessAssertion {
+    pub step: usize,
+    pub step_type: String,
+    pub message: String,
+    /// The signals assigned in the step when the assertion failed, sorted by name.
+    pub assignments: Vec<(String, String)>,
+}
+
+impl fmt::Display for WitnessAssertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "witness assertion failed in step {} of type {}: {}",
+            self.step, self.step_type, self.message
+        )?;
+        for (signal, value) in self.assignments.iter() {
+            write!(f, "\n    {} = {}", signal, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for WitnessAssertion {}
+
+/// A constraint that a step does not satisfy, found as the step is added to a trace context
+/// `with_constraint_checks`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintViolation {
+    pub step: usize,
+    pub step_type: String,
+    pub constraint: String,
+    /// The value of the constraint, which is not zero.
+    pub value: String,
+    /// The signals assigned in the step, sorted by name.
+    pub assignments: Vec<(String, String)>,
+}
+
+impl fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "constraint {} not satisfied in step {} of type {}, evaluates to {}",
+            self.constraint, self.step, self.step_type, self.value
+        )?;
+        for (signal, value) in self.assignments.iter() {
+            write!(f, "\n    {} = {}", signal, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConstraintViolation {}
+
+/// Message of a failed assertion and the assignments of the step when it failed.
+pub(crate) type AssertionFailure = (String, Vec<(String, String)>);
+
+/// How `run_wg` records and checks the assignments of a step. The default records nothing and
+/// lets a signal be assigned again.
+pub(crate) struct WgOptions<'a, F> {
+    /// Records the source location of every assignment in the step instance.
+    pub provenance: bool,
+    /// Fails the step when it assigns a signal that already has a value.
+    pub strict: bool,
+    /// Pushes every assignment here in the order it is made.
+    pub calls: Option<&'a mut Vec<(Queriable<F>, F)>>,
+}
+
+impl<'a, F> Default for WgOptions<'a, F> {
+    fn default() -> Self {
+        Self {
+            provenance: false,
+            strict: false,
+            calls: None,
+        }
+    }
+}
+
+/// Runs the witness generation function `wg` of the step type `step`, returning the step instance
+/// and its first failed assertion. `name` and `signals` are the name and internal signals of the
+/// step type, to check the assignments of `try_assign`, and `index` the signals stored densely in
+/// the step instance.
+pub(crate) fn run_wg<F: Clone + Eq + Hash + fmt::Debug, Args>(
+    step: StepTypeUUID,
+    name: &str,
+    signals: &[InternalSignal],
+    index: Arc<SignalIndex<F>>,
+    wg: &StepWitnessGen<F, Args>,
+    args: Args,
+    options: WgOptions<F>,
+) -> (StepInstance<F>, Option<AssertionFailure>) {
+    let mut witness = StepInstance::with_index(step, index);
+
+    let WgOptions {
+        provenance,
+        strict,
+        calls,
+    } = options;
+    let mut checked = CheckedStepInstance {
+        instance: &mut witness,
+        name,
+        signals,
+        provenance,
+        strict,
+        calls,
+        failure: None,
+    };
+    wg(&mut checked, args);
+    let failure = checked.failure;
+
+    (witness, failure)
+}
+
+/// Context of the witness generation function of a step, which records the first failed
+/// assertion, and the provenance of the assignments when enabled.
+struct CheckedStepInstance<'a, F> {
+    instance: &'a mut StepInstance<F>,
+    name: &'a str,
+    signals: &'a [InternalSignal],
+    provenance: bool,
+    strict: bool,
+    calls: Option<&'a mut Vec<(Queriable<F>, F)>>,
+    failure: Option<AssertionFailure>,
+}
+
+impl<'a, F: Clone + Eq + Hash> CheckedStepInstance<'a, F> {
+    #[track_caller]
+    fn record(&mut self, lhs: Queriable<F>) {
+        if self.provenance {
+            self.instance.provenance.insert(lhs, Location::caller());
+        }
+    }
+
+    fn log(&mut self, lhs: Queriable<F>, rhs: &F) {
+        if let Some(calls) = self.calls.as_mut() {
+            calls.push((lhs, rhs.clone()));
+        }
+    }
+
+    /// In strict mode, fails the step when `lhs` already has a value.
+    fn check_overwrite(&mut self, lhs: Queriable<F>, rhs: &F)
+    where
+        F: fmt::Debug,
+    {
+        if !self.strict {
+            return;
+        }
+
+        if let Some(value) = self.instance.assignments.get(&lhs) {
+            let message = format!(
+                "signal {} assigned twice, {:?} overwritten with {:?}",
+                lhs.annotation(),
+                value,
+                rhs
+            );
+            self.fail(message);
+        }
+    }
+}
+
+impl<'a, F: Clone + Eq + Hash + fmt::Debug> WitnessGenContext<F> for CheckedStepInstance<'a, F> {
+    fn assign(&mut self, lhs: Queriable<F>, rhs: F) {
+        self.check_overwrite(lhs, &rhs);
+        self.record(lhs);
+        self.log(lhs, &rhs);
+        self.instance.assign(lhs, rhs);
+    }
+
+    fn assign_slice(&mut self, assignments: &[(Queriable<F>, F)])
+    where
+        F: Clone,
+    {
+        for (lhs, rhs) in assignments.iter() {
+            self.check_overwrite(*lhs, rhs);
+            self.record(*lhs);
+            self.log(*lhs, rhs);
+        }
+        self.instance.assign_slice(assignments);
+    }
+
+    fn assign_iter(&mut self, assignments: &mut dyn Iterator<Item = (Queriable<F>, F)>) {
+        if self.provenance || self.strict || self.calls.is_some() {
+            for (lhs, rhs) in assignments {
+                self.check_overwrite(lhs, &rhs);
+                self.record(lhs);
+                self.log(lhs, &rhs);
+                self.instance.assign(lhs, rhs);
+            }
+        } else {
+            self.instance.assign_iter(assignments);
+        }
+    }
+
+    fn try_assign(&mut self, lhs: Queriable<F>, rhs: F) -> Result<(), AssignmentError> {
+        check_witness_signal(&lhs)?;
+        if let Queriable::Internal(signal) = lhs {
+            if !self.signals.contains(&signal) {
+                return Err(AssignmentError::NotInStepType {
+                    signal: lhs.annotation(),
+                    step_type: self.name.to_string(),
+                });
+            }
+        }
+        if self.strict && self.instance.assignments.get(&lhs).is_some() {
+            return Err(AssignmentError::Overwritten {
+                signal: lhs.annotation(),
+            });
+        }
+
+        self.record(lhs);
+        self.log(lhs, &rhs);
+        self.instance.assign(lhs, rhs);
+
+        Ok(())
+    }
+
+    fn fail(&mut self, message: String) {
+        if self.failure.is_some() {
+            return;
+        }
+
+        self.failure = Some((message, formatted_assignments(self.instance)));
+    }
+
+    fn assignments(&self) -> Option<&Assignments<F>> {
+        Some(&self.instance.assignments)
+    }
+
+    #[track_caller]
+    fn push_challenge_assignment(&mut self, assignment: ChallengeAssignment<F>) {
+        if let Err(err) = check_challenge_signal(&assignment.signal) {
+            self.fail(err.to_string());
+            return;
+        }
+
+        self.record(assignment.signal);
+        self.instance.push_challenge_assignment(assignment);
+    }
+}
+
+/// The assignments of a step instance formatted, sorted by signal name, with the location that
+/// assigned them when their provenance was recorded.
+fn formatted_assignments<F: Eq + Hash + fmt::Debug>(
+    instance: &StepInstance<F>,
+) -> Vec<(String, String)> {
+    let mut assignments: Vec<(String, String)> = instance
+        .assignments
+        .iter()
+        .map(|(signal, value)| {
+            let value = match instance.provenance.get(signal) {
+                Some(location) => format!("{:?} (assigned at {})", value, location),
+                None => format!("{:?}", value),
+            };
+
+            (signal.annotation(), value)
+        })
+        .collect();
+    assignments.sort();
+
+    assignments
 }
 
 pub type Witness<F> = Vec<StepInstance<F>>;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TraceWitness<F> {
     pub step_instances: Witness<F>,
     pub height: usize,
+    /// Values of the exposed signals in the order they were exposed, the rows of the instance
+    /// column, when the trace context is `with_exposed`.
+    pub public_values: Vec<F>,
+}
+
+/// Number of instances of a step type in a trace witness compared with its budget.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepBudget {
+    pub step_type: String,
+    pub budget: Option<usize>,
+    pub instances: usize,
+}
+
+impl StepBudget {
+    pub fn is_exceeded(&self) -> bool {
+        matches!(self.budget, Some(budget) if self.instances > budget)
+    }
+}
+
+impl<F> TraceWitness<F> {
+    /// Reports the number of instances of each step type against its declared budget, sorted by
+    /// step type name.
+    pub fn budget_report<Args>(
+        &self,
+        step_types: &HashMap<u32, Rc<StepType<F, Args>>>,
+    ) -> Vec<StepBudget> {
+        let mut report: Vec<StepBudget> = step_types
+            .values()
+            .map(|step| StepBudget {
+                step_type: step.name.clone(),
+                budget: step.budget,
+                instances: self
+                    .step_instances
+                    .iter()
+                    .filter(|instance| instance.step_type_uuid == step.uuid())
+                    .count(),
+            })
+            .collect();
+
+        report.sort_by(|a, b| a.step_type.cmp(&b.step_type));
+
+        report
+    }
+
+    /// Adds the steps of `other` after the steps of the witness, to stitch together the traces
+    /// of several generators. The height is the largest of the two heights. The public values
+    /// are cleared, recompute them with `public_inputs` once the witness is complete.
+    pub fn append(&mut self, other: TraceWitness<F>) {
+        self.step_instances.extend(other.step_instances);
+        self.height = self.height.max(other.height);
+        self.public_values.clear();
+    }
+
+    /// Keeps the first `steps` steps of the witness. The height is unchanged, the public values
+    /// are cleared when steps are removed.
+    pub fn truncate(&mut self, steps: usize) {
+        if steps < self.step_instances.len() {
+            self.step_instances.truncate(steps);
+            self.public_values.clear();
+        }
+    }
+}
+
+impl<F: Clone> TraceWitness<F> {
+    /// A witness with the steps of `range`, for example to inspect a window of a long trace or
+    /// to reproduce a failure with fewer steps. The height is unchanged and there are no public
+    /// values. Panics when the range is out of the steps of the witness, like slicing a `Vec`.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> TraceWitness<F> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+
+        TraceWitness {
+            step_instances: self.step_instances[bounds].to_vec(),
+            height: self.height,
+            public_values: Vec::new(),
+        }
+    }
 }
 
 impl<F> Default for TraceWitness<F> {
@@ -38,30 +539,516 @@ impl<F> Default for TraceWitness<F> {
         Self {
             step_instances: Default::default(),
             height: Default::default(),
+            public_values: Default::default(),
         }
     }
 }
 
+/// Evaluates `expr` at the step `step_idx` of the witness. Forward signals with rotation and
+/// `StepTypeNext` queries are resolved with the following step. Returns the first queriable
+/// without a value as error, including rotations past the last step and halo2 queries, which are
+/// not part of the witness.
+pub fn eval_expr<F: Field + Hash>(
+    expr: &Expr<F>,
+    witness: &TraceWitness<F>,
+    step_idx: usize,
+) -> Result<F, Queriable<F>> {
+    let step = witness
+        .step_instances
+        .get(step_idx)
+        .unwrap_or_else(|| panic!("step {} not found in witness", step_idx));
+    let next = witness.step_instances.get(step_idx + 1);
+
+    expr.eval(&|queriable: &Queriable<F>| match queriable {
+        Queriable::Internal(_) | Queriable::Forward(_, false) => {
+            step.assignments.get(queriable).copied()
+        }
+        Queriable::Forward(signal, true) => next
+            .and_then(|next| next.assignments.get(&Queriable::Forward(*signal, false)))
+            .copied(),
+        Queriable::StepTypeNext(handler) => next.map(|next| {
+            if next.step_type_uuid == handler.uuid() {
+                F::ONE
+            } else {
+                F::ZERO
+            }
+        }),
+        _ => None,
+    })
+}
+
 pub struct GenericTraceContext<'a, F, StepArgs> {
     step_types: &'a HashMap<u32, Rc<StepType<F, StepArgs>>>,
+    /// Signal index of each step type added so far.
+    indices: HashMap<StepTypeUUID, Arc<SignalIndex<F>>>,
+    exposed_results: &'a [ExposedResult<F>],
+    last_results: Vec<Option<F>>,
+    exposed: &'a [(ForwardSignal, ExposeOffset)],
+    /// Value of each exposed signal in the steps added so far.
+    public_values: Vec<Option<F>>,
+    step_schedule: StepSchedule,
+    /// Number of steps of the circuit, 0 when not known.
+    num_steps: usize,
+    /// Step types in the order they are first added.
+    scheduled: Vec<StepTypeUUID>,
+    check_constraints: bool,
+    provenance: bool,
+    strict_assignments: bool,
+    external: Option<&'a ExternalColumns<F>>,
+    sink: Option<StepSink<'a, F>>,
+    hooks: Option<TraceHooks<'a, F>>,
+    /// Steps already passed to the sink.
+    streamed: usize,
+    assign_log: Option<AssignLog<F>>,
+    /// Assignments made by the witness generation of the step being added, for `assign_log`.
+    calls: Vec<(Queriable<F>, F)>,
 
     witness: TraceWitness<F>,
 }
 
+type StepSink<'a, F> = Box<dyn FnMut(StepInstance<F>) + 'a>;
+
+/// Checks of the trace contexts built by `GenericTraceContext::for_circuit`, which are not part
+/// of the compiled circuit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// See `GenericTraceContext::with_constraint_checks`.
+    pub check_constraints: bool,
+    /// See `GenericTraceContext::with_strict_assignments`.
+    pub strict_assignments: bool,
+}
+
 impl<'a, F, StepArgs> GenericTraceContext<'a, F, StepArgs> {
+    /// A trace context for the compiled `circuit`, with its exposed signals and results, its step
+    /// schedule and its number of steps, and the checks of `options`. Every witness of a compiled
+    /// circuit should be generated with it, so that the trace sees the same context whatever runs
+    /// it.
+    pub fn for_circuit<TraceArgs>(
+        circuit: &'a Circuit<F, TraceArgs, StepArgs>,
+        options: TraceOptions,
+    ) -> Self {
+        let ctx = Self::new(&circuit.step_types)
+            .with_exposed_results(&circuit.exposed_results)
+            .with_exposed(&circuit.exposed_signals)
+            .with_step_schedule(circuit.step_schedule)
+            .with_num_steps(circuit.num_steps);
+
+        let ctx = if options.strict_assignments {
+            ctx.with_strict_assignments()
+        } else {
+            ctx
+        };
+
+        if options.check_constraints {
+            ctx.with_constraint_checks()
+        } else {
+            ctx
+        }
+    }
+
     pub fn new(step_types: &'a HashMap<u32, Rc<StepType<F, StepArgs>>>) -> Self {
         Self {
             step_types,
+            indices: HashMap::default(),
+            exposed_results: &[],
+            last_results: Vec::new(),
+            exposed: &[],
+            public_values: Vec::new(),
+            step_schedule: StepSchedule::InOrder,
+            num_steps: 0,
+            scheduled: Vec::new(),
+            check_constraints: false,
+            provenance: false,
+            strict_assignments: false,
+            external: None,
+            sink: None,
+            hooks: None,
+            streamed: 0,
+            assign_log: None,
+            calls: Vec::new(),
             witness: TraceWitness::default(),
         }
     }
 
-    pub fn get_witness(self) -> TraceWitness<F> {
+    /// Orders the step instances as declared by `pragma_step_schedule`.
+    pub fn with_step_schedule(mut self, step_schedule: StepSchedule) -> Self {
+        self.step_schedule = step_schedule;
+
+        self
+    }
+
+    /// Reports `num_steps` to the trace as the number of steps of the circuit, see
+    /// `TraceContext::remaining`, 0 when not known.
+    pub fn with_num_steps(mut self, num_steps: usize) -> Self {
+        self.num_steps = num_steps;
+
+        self
+    }
+
+    /// Evaluates the constraints of every step as it is added, and its transition constraints
+    /// when the next step is added, panicking with a `ConstraintViolation` on the first one that
+    /// does not hold. Constraints that query unassigned signals are not reported, and with a
+    /// `Grouped` schedule the transition constraints are not evaluated, as the steps are
+    /// reordered afterwards.
+    pub fn with_constraint_checks(mut self) -> Self {
+        self.check_constraints = true;
+
+        self
+    }
+
+    /// Records in each step instance the source location of the call that assigned each signal,
+    /// see `StepInstance::provenance`. The locations are shown with the assignments of the failed
+    /// assertions and constraint checks, so that a wrong value can be traced to the code that
+    /// wrote it.
+    pub fn with_provenance(mut self) -> Self {
+        self.provenance = true;
+
+        self
+    }
+
+    /// Fails the step, with the signal and the two values, when its witness generation assigns a
+    /// signal that already has a value, instead of keeping the last value. `try_assign` returns
+    /// `AssignmentError::Overwritten` instead.
+    pub fn with_strict_assignments(mut self) -> Self {
+        self.strict_assignments = true;
+
+        self
+    }
+
+    /// Logs every assignment of the witness generation functions, in the order they are made,
+    /// with the assignments of the signals computed by chiquito, see `AssignLog`. The log is
+    /// returned by `get_witness_with_log`.
+    pub fn with_assign_log(mut self) -> Self {
+        self.assign_log = Some(AssignLog::default());
+
+        self
+    }
+
+    /// Passes the step instances to `sink` as the trace adds them instead of keeping them in the
+    /// witness, so that huge traces do not have to fit in memory. Each step is passed when the
+    /// next one is added, once its transition constraints can be checked, and the last one in
+    /// `get_witness`, whose witness then has no step instances. The steps cannot be streamed
+    /// with a `Grouped` schedule, which reorders them after the trace.
+    pub fn with_step_sink<S: FnMut(StepInstance<F>) + 'a>(mut self, sink: S) -> Self {
+        self.sink = Some(Box::new(sink));
+
+        self
+    }
+
+    /// Runs the callbacks of `hooks` as the steps are added, see `TraceHooks`.
+    pub fn with_hooks(mut self, hooks: TraceHooks<'a, F>) -> Self {
+        self.hooks = Some(hooks);
+
+        self
+    }
+
+    /// Assigns the values of the external signals to each step as it is added, the value at the
+    /// index of the step. The witness generation of the step types must not assign them. The
+    /// columns are not checked here, see `ExternalColumns::validate`.
+    pub fn with_external_columns(mut self, external: &'a ExternalColumns<F>) -> Self {
+        self.external = Some(external);
+
+        self
+    }
+
+    /// Assigns the carrier signal of each exposed result after every step is added.
+    pub fn with_exposed_results(mut self, exposed_results: &'a [ExposedResult<F>]) -> Self {
+        self.exposed_results = exposed_results;
+        self.last_results = exposed_results.iter().map(|_| None).collect();
+
+        self
+    }
+
+    /// Records the values of the `exposed` signals as the steps are added, in the
+    /// `public_values` of the witness, so that the instance column does not have to be computed
+    /// from the witness afterwards. This also works when the steps are streamed to a sink.
+    pub fn with_exposed(mut self, exposed: &'a [(ForwardSignal, ExposeOffset)]) -> Self {
+        self.exposed = exposed;
+        self.public_values = exposed.iter().map(|_| None).collect();
+
+        self
+    }
+
+    pub fn get_witness(mut self) -> TraceWitness<F> {
+        if self.step_schedule == StepSchedule::Grouped {
+            let scheduled = &self.scheduled;
+            self.witness.step_instances.sort_by_key(|instance| {
+                scheduled
+                    .iter()
+                    .position(|uuid| *uuid == instance.step_type_uuid)
+            });
+        }
+
+        let steps = self.streamed + self.witness.step_instances.len();
+        self.witness.public_values = self
+            .exposed
+            .iter()
+            .zip(self.public_values.drain(..))
+            .map(|((signal, offset), value)| {
+                value.unwrap_or_else(|| {
+                    let found = match offset {
+                        ExposeOffset::Step(step) => *step < steps,
+                        _ => steps > 0,
+                    };
+                    if !found {
+                        panic!("exposed step {:?} not found in witness", offset);
+                    }
+
+                    panic!(
+                        "exposed signal {} not assigned",
+                        Queriable::<F>::Forward(*signal, false).annotation()
+                    )
+                })
+            })
+            .collect();
+
+        if let Some(sink) = self.sink.as_mut() {
+            for instance in self.witness.step_instances.drain(..) {
+                sink(instance);
+            }
+        }
+
         self.witness
     }
+
+    /// Like `get_witness`, with the log of the assignments of `with_assign_log`, empty without
+    /// it.
+    pub fn get_witness_with_log(mut self) -> (TraceWitness<F>, AssignLog<F>)
+    where
+        F: Clone,
+    {
+        let mut log = self.assign_log.take().unwrap_or_default();
+        let witness = self.get_witness();
+        log.height = witness.height;
+        log.public_values = witness.public_values.clone();
+
+        (witness, log)
+    }
 }
 
-impl<'a, F: Eq + Hash, StepArgs> TraceContext<StepArgs> for GenericTraceContext<'a, F, StepArgs> {
+impl<'a, F: Field + Hash, StepArgs> GenericTraceContext<'a, F, StepArgs> {
+    /// Adds a step instance of `step` whose witness generation already ran, with its first failed
+    /// assertion.
+    pub(crate) fn push_step(
+        &mut self,
+        step: &StepType<F, StepArgs>,
+        mut witness: StepInstance<F>,
+        failure: Option<AssertionFailure>,
+    ) {
+        if self.sink.is_some() && self.step_schedule == StepSchedule::Grouped {
+            panic!("steps cannot be streamed to a sink with a Grouped schedule");
+        }
+
+        if !self.scheduled.contains(&step.uuid()) {
+            self.scheduled.push(step.uuid());
+        } else if self.step_schedule == StepSchedule::Contiguous
+            && self.scheduled.last() != Some(&step.uuid())
+        {
+            panic!(
+                "step type {} added again after other step types, but the steps are scheduled contiguous",
+                step.name
+            );
+        }
+
+        if let Some((message, assignments)) = failure {
+            panic!(
+                "{}",
+                WitnessAssertion {
+                    step: self.steps(),
+                    step_type: step.name.clone(),
+                    message,
+                    assignments,
+                }
+            );
+        }
+
+        if let Some(external) = self.external {
+            external.assign(self.steps(), &step.name, &mut witness);
+        }
+        self.assign_defaults(step, &mut witness);
+        assign_range_checks(step, &mut witness);
+        if let Err(message) = derive_assignments(step, &mut witness) {
+            panic!(
+                "{}",
+                WitnessAssertion {
+                    step: self.steps(),
+                    step_type: step.name.clone(),
+                    message,
+                    assignments: formatted_assignments(&witness),
+                }
+            );
+        }
+        self.assign_derived_values(step, &mut witness);
+
+        for (result, last) in self
+            .exposed_results
+            .iter()
+            .zip(self.last_results.iter_mut())
+        {
+            if step.uuid() != result.padding.uuid() {
+                let value = witness.assignments.get(&result.signal).unwrap_or_else(|| {
+                    panic!("exposed result {} not assigned", result.signal.annotation())
+                });
+
+                *last = Some(*value);
+            }
+
+            let value = last.unwrap_or_else(|| {
+                panic!(
+                    "padding step {} added before result {} was produced",
+                    result.padding.annotation,
+                    result.signal.annotation()
+                )
+            });
+
+            witness.assign(result.carrier, value);
+        }
+
+        if self.check_constraints {
+            self.check_step(step, &witness);
+        }
+        self.record_public_values(self.steps(), &witness);
+
+        if let Some(log) = self.assign_log.as_mut() {
+            log.record_step(&step.name, mem::take(&mut self.calls), &witness);
+        }
+
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.step_added(self.streamed + self.witness.step_instances.len(), &witness);
+        }
+
+        if let Some(sink) = self.sink.as_mut() {
+            if let Some(previous) = self.witness.step_instances.pop() {
+                sink(previous);
+                self.streamed += 1;
+            }
+        }
+
+        self.witness.step_instances.push(witness);
+    }
+
+    /// Assigns the derived values of the step, see `CircuitContext::expose_derived`.
+    fn assign_derived_values(&self, step: &StepType<F, StepArgs>, witness: &mut StepInstance<F>) {
+        for derived in step.derived_values.iter() {
+            let value = derived
+                .expr
+                .eval(&|queriable| witness.assignments.get(queriable).copied())
+                .unwrap_or_else(|queriable| {
+                    panic!(
+                        "derived value {} queries {}, not assigned in step {} of type {}",
+                        derived.signal.annotation(),
+                        queriable.annotation(),
+                        self.steps(),
+                        step.name
+                    )
+                });
+
+            witness.assign(derived.signal, value);
+        }
+    }
+
+    /// Assigns the defaults of the forward signals that the witness generation of the step left
+    /// unassigned, see `SignalDefault`.
+    fn assign_defaults(&self, step: &StepType<F, StepArgs>, witness: &mut StepInstance<F>) {
+        for (signal, default) in step.signal_defaults.iter() {
+            let signal = Queriable::Forward(*signal, false);
+            if witness.assignments.contains_key(&signal) {
+                continue;
+            }
+
+            let value = match default {
+                SignalDefault::Zero => F::ZERO,
+                SignalDefault::Carry => self
+                    .witness
+                    .step_instances
+                    .last()
+                    .and_then(|previous| previous.assignments.get(&signal))
+                    .copied()
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "signal {} not assigned in step {} of type {}, and the previous step \
+                             has no value to carry",
+                            signal.annotation(),
+                            self.steps(),
+                            step.name
+                        )
+                    }),
+                SignalDefault::Error => panic!(
+                    "signal {} not assigned in step {} of type {}",
+                    signal.annotation(),
+                    self.steps(),
+                    step.name
+                ),
+            };
+
+            witness.assign(signal, value);
+        }
+    }
+
+    /// Records the values of the exposed signals at the step `index`.
+    fn record_public_values(&mut self, index: usize, instance: &StepInstance<F>) {
+        for ((signal, offset), value) in self.exposed.iter().zip(self.public_values.iter_mut()) {
+            let exposed = match offset {
+                ExposeOffset::First => index == 0,
+                ExposeOffset::Last => true,
+                ExposeOffset::Step(step) => index == *step,
+            };
+
+            if exposed {
+                *value = instance
+                    .assignments
+                    .get(&Queriable::Forward(*signal, false))
+                    .copied();
+            }
+        }
+    }
+
+    /// Checks the constraints of the step being added, and the transition constraints of the
+    /// previous step, whose next step is now known.
+    fn check_step(&self, step: &StepType<F, StepArgs>, witness: &StepInstance<F>) {
+        let index = self.steps();
+        let violation = |index: usize, step: &StepType<F, StepArgs>, instance, failures| {
+            for failure in failures {
+                if let StepFailure::NotSatisfied { constraint, value } = failure {
+                    panic!(
+                        "{}",
+                        ConstraintViolation {
+                            step: index,
+                            step_type: step.name.clone(),
+                            constraint,
+                            value: format!("{:?}", value),
+                            assignments: formatted_assignments(instance),
+                        }
+                    );
+                }
+            }
+        };
+
+        if let (Some(previous), false) = (
+            self.witness.step_instances.last(),
+            self.step_schedule == StepSchedule::Grouped,
+        ) {
+            let previous_step = &self.step_types[&previous.step_type_uuid];
+            violation(
+                index - 1,
+                previous_step,
+                previous,
+                test_transitions(previous_step, &previous.assignments, witness),
+            );
+        }
+
+        violation(
+            index,
+            step,
+            witness,
+            test_step(step, &witness.assignments, None),
+        );
+    }
+}
+
+impl<'a, F: Field + Hash, StepArgs> TraceContext<StepArgs>
+    for GenericTraceContext<'a, F, StepArgs>
+{
     fn add(&mut self, step: &StepTypeHandler, args: StepArgs) {
         let step = Rc::clone(
             self.step_types
@@ -69,14 +1056,937 @@ impl<'a, F: Eq + Hash, StepArgs> TraceContext<StepArgs> for GenericTraceContext<
                 .expect("step type not found"),
         );
 
-        let mut witness = StepInstance::new(step.uuid());
+        let index = Arc::clone(
+            self.indices
+                .entry(step.uuid())
+                .or_insert_with(|| Arc::new(SignalIndex::of_step(&step))),
+        );
 
-        (*step.wg)(&mut witness, args);
+        let calls = self.assign_log.as_ref().map(|_| &mut self.calls);
+        let (witness, failure) = run_wg(
+            step.uuid(),
+            &step.name,
+            &step.signals,
+            index,
+            &*step.wg,
+            args,
+            WgOptions {
+                provenance: self.provenance,
+                strict: self.strict_assignments,
+                calls,
+            },
+        );
 
-        self.witness.step_instances.push(witness);
+        self.push_step(&step, witness, failure);
     }
 
     fn set_height(&mut self, height: usize) {
         self.witness.height = height;
     }
+
+    /// Includes the steps already passed to the sink.
+    fn steps(&self) -> usize {
+        self.streamed + self.witness.step_instances.len()
+    }
+
+    fn num_steps(&self) -> Option<usize> {
+        Some(self.num_steps).filter(|num_steps| *num_steps > 0)
+    }
+}
+
+/// Assigns the bits of the signals range checked by the step type.
+fn assign_range_checks<F: Field + Hash, Args>(
+    step: &StepType<F, Args>,
+    witness: &mut StepInstance<F>,
+) {
+    for check in step.range_checks.iter() {
+        let signal = Queriable::Forward(check.signal, false);
+        if let Some(value) = witness.assignments.get(&signal).copied() {
+            let bits = (check.decompose)(&value).unwrap_or_else(|| {
+                panic!(
+                    "signal {} does not fit in {} bits in step type {}",
+                    signal.annotation(),
+                    check.bits.len(),
+                    step.name
+                )
+            });
+
+            for (bit, value) in check.bits.iter().zip(bits) {
+                witness.assign(Queriable::Internal(*bit), value);
+            }
+        }
+    }
+}
+
+/// Assigns the internal signals left unassigned by the witness generation function, when a
+/// constraint of the step type is linear in the signal and all its other queries are assigned.
+/// Repeats until no more signals can be derived, so chains of derived signals are resolved.
+///
+/// A signal is derived from the first such constraint, and every other constraint that queries
+/// it and can then be evaluated must hold with the derived value, otherwise the constraints
+/// disagree on the signal and the conflict is returned instead of keeping either value.
+fn derive_assignments<F: Field + Hash, Args>(
+    step: &StepType<F, Args>,
+    witness: &mut StepInstance<F>,
+) -> Result<(), String> {
+    let constraints: Vec<(&String, &Expr<F>)> = step
+        .constraints
+        .iter()
+        .map(|constr| (&constr.annotation, &constr.expr))
+        .chain(
+            step.transition_constraints
+                .iter()
+                .map(|constr| (&constr.annotation, &constr.expr)),
+        )
+        .collect();
+
+    let mut derived = Vec::new();
+    let mut progress = true;
+    while progress {
+        progress = false;
+
+        for signal in step.signals.iter() {
+            let signal = Queriable::Internal(*signal);
+            if witness.assignments.contains_key(&signal) {
+                continue;
+            }
+
+            let resolve = |q: &Queriable<F>| witness.assignments.get(q).copied();
+            let value = constraints
+                .iter()
+                .enumerate()
+                .find_map(
+                    |(index, (_, expr))| match eval_linear(expr, &signal, &resolve) {
+                        Some((constant, coefficient)) if coefficient != F::ZERO => {
+                            Some((index, -constant * coefficient.invert().unwrap()))
+                        }
+                        _ => None,
+                    },
+                );
+
+            if let Some((source, value)) = value {
+                witness.assign(signal, value);
+                derived.push((signal, source));
+                progress = true;
+            }
+        }
+    }
+
+    let resolve = |q: &Queriable<F>| witness.assignments.get(q).copied();
+    for (signal, source) in derived {
+        for (index, (annotation, expr)) in constraints.iter().enumerate() {
+            if index == source || !expr.queriables().contains(&signal) {
+                continue;
+            }
+            if let Ok(value) = expr.eval(&resolve) {
+                if value != F::ZERO {
+                    return Err(format!(
+                        "signal {} derived from constraint {} does not satisfy constraint {}",
+                        signal.annotation(),
+                        constraints[source].0,
+                        annotation
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Evaluates `expr` as `constant + coefficient * signal`, with the values of the other queriables
+/// given by `resolve`. Returns `None` if the expression is not linear in `signal` or queries
+/// anything else without a value.
+pub(crate) fn eval_linear<F: Field, R: Fn(&Queriable<F>) -> Option<F>>(
+    expr: &Expr<F>,
+    signal: &Queriable<F>,
+    resolve: &R,
+) -> Option<(F, F)> {
+    match expr {
+        Expr::Const(c) => Some((*c, F::ZERO)),
+        Expr::Query(q) if q == signal => Some((F::ZERO, F::ONE)),
+        Expr::Query(q) => resolve(q).map(|v| (v, F::ZERO)),
+        Expr::Sum(es) => es.iter().try_fold((F::ZERO, F::ZERO), |(c, k), e| {
+            eval_linear(e, signal, resolve).map(|(ec, ek)| (c + ec, k + ek))
+        }),
+        Expr::Mul(es) => es.iter().try_fold((F::ONE, F::ZERO), |(c, k), e| {
+            let (ec, ek) = eval_linear(e, signal, resolve)?;
+            if k != F::ZERO && ek != F::ZERO {
+                None
+            } else {
+                Some((c * ec, c * ek + k * ec))
+            }
+        }),
+        Expr::Neg(e) => eval_linear(e, signal, resolve).map(|(c, k)| (-c, -k)),
+        Expr::Pow(e, exp) => match (eval_linear(e, signal, resolve)?, exp) {
+            (_, 0) => Some((F::ONE, F::ZERO)),
+            (linear, 1) => Some(linear),
+            ((c, k), exp) if k == F::ZERO => Some((c.pow_vartime([*exp as u64]), F::ZERO)),
+            _ => None,
+        },
+        Expr::Halo2Expr(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{
+            cb::eq,
+            circuit,
+            tests::{assigning, witness},
+        },
+    };
+
+    #[test]
+    #[should_panic(expected = "signal a does not fit in 4 bits in step type step")]
+    fn test_forward_width_overflow() {
+        let sc = circuit::<Fr, (), u64, _>("width", |ctx| {
+            let a = ctx.forward_width("a", 4);
+            let step = assigning(ctx, "step", a);
+
+            ctx.trace(move |ctx, _| ctx.add(&step, 16));
+        });
+
+        witness(&sc, ());
+    }
+
+    #[test]
+    fn test_eval_expr() {
+        let sc = circuit::<Fr, (), u64, _>("eval", |ctx| {
+            let a = ctx.forward("a");
+            let step = assigning(ctx, "step", a);
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 3);
+                ctx.add(&step, 5);
+            });
+        });
+
+        let witness = witness(&sc, ());
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+        let expr = a.next() * 2 + a;
+
+        assert_eq!(eval_expr(&expr, &witness, 0), Ok(13.field()));
+        assert_eq!(eval_expr(&expr, &witness, 1), Err(a.next()));
+    }
+
+    #[test]
+    fn test_derive_linear_assignments() {
+        let sc = circuit::<Fr, (), u64, _>("derived", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+                let c = ctx.internal("c");
+                let d = ctx.internal("d");
+
+                ctx.setup(move |ctx| {
+                    // c is derived from a and b, then d from c
+                    ctx.constr(eq(a + b, c));
+                    ctx.constr(eq(d * 2, c * 3 + 1));
+                    // quadratic in b, so b itself is never derived
+                    ctx.constr(eq(b * b, a + 3));
+                });
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, 3.field());
+                });
+            });
+
+            ctx.trace(move |ctx, _| ctx.add(&step, 6));
+        });
+
+        let signals: Vec<Queriable<Fr>> = sc
+            .step_types
+            .values()
+            .next()
+            .unwrap()
+            .signals
+            .iter()
+            .map(|signal| Queriable::Internal(*signal))
+            .collect();
+
+        let witness = witness(&sc, ());
+        let assignments = &witness.step_instances[0].assignments;
+
+        assert_eq!(assignments.get(&signals[0]), Some(&3.field()));
+        assert_eq!(assignments.get(&signals[1]), Some(&9.field()));
+        assert_eq!(assignments.get(&signals[2]), Some(&14.field()));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "witness assertion failed in step 0 of type step: signal c derived from \
+                    constraint"
+    )]
+    fn test_derive_conflicting_assignments() {
+        let sc = circuit::<Fr, (), u64, _>("conflict", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| {
+                    // both linear in c, but they disagree on its value
+                    ctx.constr(eq(a + 1, c));
+                    ctx.constr(eq(c * 2, 7));
+                });
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 2));
+        });
+
+        witness(&sc, ());
+    }
+
+    #[test]
+    fn test_step_schedule() {
+        let sc = circuit::<Fr, (), u64, _>("schedule", |ctx| {
+            let a = ctx.step_type("a");
+            let b = ctx.step_type("b");
+
+            ctx.step_type_def(a, |_| {});
+            ctx.step_type_def(b, |_| {});
+            ctx.pragma_step_schedule(StepSchedule::Grouped);
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&b, 0);
+                ctx.add(&a, 1);
+                ctx.add(&b, 2);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types)
+            .with_step_schedule(compiled.step_schedule);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let names: Vec<String> = ctx
+            .get_witness()
+            .step_instances
+            .iter()
+            .map(|instance| compiled.step_types[&instance.step_type_uuid].name.clone())
+            .collect();
+        assert_eq!(names, vec!["b", "b", "a"]);
+
+        let mut ctx =
+            GenericTraceContext::new(&sc.step_types).with_step_schedule(StepSchedule::Contiguous);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            (sc.trace.as_ref().unwrap())(&mut ctx, ())
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_public_values() {
+        let public_values = |streamed: bool| {
+            let sc = circuit::<Fr, (), u64, _>("public", |ctx| {
+                let a = ctx.forward("a");
+                let b = ctx.forward("b");
+                let first = ctx.step_type("first");
+                let second = ctx.step_type("second");
+
+                for step in [first, second] {
+                    ctx.step_type_def(step, |ctx| {
+                        ctx.wg(move |ctx, v| {
+                            ctx.assign(a, v.field());
+                            ctx.assign(b, (v * 10).field());
+                        })
+                    });
+                }
+                ctx.expose(a, ExposeOffset::Step(1));
+                ctx.expose(b, ExposeOffset::Last);
+
+                ctx.trace(move |ctx, _| {
+                    ctx.add(&first, 1);
+                    ctx.add(&second, 2);
+                    ctx.add(&first, 3);
+                });
+            });
+            let compiled =
+                Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+            let mut ctx = GenericTraceContext::new(&compiled.step_types)
+                .with_exposed(&compiled.exposed_signals)
+                .with_step_schedule(compiled.step_schedule);
+            if streamed {
+                ctx = ctx.with_step_sink(|_| {});
+            }
+            (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+
+            let witness = ctx.get_witness();
+            if !streamed {
+                assert_eq!(witness.public_values, witness.public_inputs(&sc));
+            }
+
+            witness.public_values
+        };
+
+        assert_eq!(public_values(false), vec![Fr::from(2), Fr::from(30)]);
+        assert_eq!(public_values(true), vec![Fr::from(2), Fr::from(30)]);
+    }
+
+    #[test]
+    fn test_budget_report() {
+        let sc = circuit::<Fr, (), (), _>("budgets", |ctx| {
+            let a = ctx.step_type("a");
+            let b = ctx.step_type("b");
+
+            ctx.pragma_num_steps(4);
+            ctx.step_type_def(a, |ctx| ctx.budget(1));
+            ctx.step_type_def(b, |ctx| ctx.budget(3));
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&a, ());
+                ctx.add(&a, ());
+                ctx.add(&b, ());
+            });
+        });
+
+        let report = witness(&sc, ()).budget_report(&sc.step_types);
+
+        assert_eq!(
+            report,
+            vec![
+                StepBudget {
+                    step_type: "a".to_string(),
+                    budget: Some(1),
+                    instances: 2,
+                },
+                StepBudget {
+                    step_type: "b".to_string(),
+                    budget: Some(3),
+                    instances: 1,
+                },
+            ]
+        );
+        assert!(report[0].is_exceeded());
+        assert!(!report[1].is_exceeded());
+    }
+
+    #[test]
+    fn test_witness_append_and_slice() {
+        let sc = circuit::<Fr, (u64, u64), u64, _>("stitched", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, (from, to)| {
+                for i in from..to {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+        let witness = |from, to| {
+            let mut ctx = GenericTraceContext::new(&sc.step_types);
+            (sc.trace.as_ref().unwrap())(&mut ctx, (from, to));
+            ctx.get_witness()
+        };
+
+        let whole = witness(0, 6);
+        let mut stitched = witness(0, 2);
+        stitched.public_values = vec![1.field()];
+        stitched.append(witness(2, 6));
+        assert_eq!(stitched, whole);
+
+        let window = whole.slice(2..4);
+        assert_eq!(window.step_instances, whole.step_instances[2..4]);
+        assert_eq!(whole.slice(..).step_instances, whole.step_instances);
+
+        stitched.truncate(3);
+        assert_eq!(stitched, witness(0, 3));
+        stitched.truncate(5);
+        assert_eq!(stitched.step_instances.len(), 3);
+    }
+
+    #[test]
+    fn test_step_instance_accessors() {
+        let sc = circuit::<Fr, (), u64, _>("named", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v + 1).field());
+                });
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 7));
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+        let step = &witness.step_instances[0];
+
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+        assert_eq!(step.get(&a), Some(&7.field()));
+        assert_eq!(step.get_by_annotation("b"), Some(&8.field()));
+        assert_eq!(step.get_by_annotation("c"), None);
+
+        let mut named: Vec<(String, Fr)> = step
+            .named_assignments()
+            .map(|(name, value)| (name, *value))
+            .collect();
+        named.sort_by(|x, y| x.0.cmp(&y.0));
+        assert_eq!(
+            named,
+            vec![("a".to_string(), 7.field()), ("b".to_string(), 8.field())]
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "witness assertion failed in step 1 of type step: 7 is odd\n    a = "
+    )]
+    fn test_witness_assert() {
+        let sc = circuit::<Fr, (), u64, _>("assert", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    witness_assert!(ctx, v % 2 == 0, "{} is odd", v);
+                });
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 2);
+                ctx.add(&step, 7);
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+    }
+
+    #[test]
+    #[should_panic(expected = "witness assertion failed in step 1 of type step: c is a: ")]
+    fn test_witness_constraint_assert() {
+        let sc = circuit::<Fr, (), u64, _>("assert constraint", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let c = ctx.internal("c");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(c, (v + v % 3).field());
+                    ctx.assert(eq(a, c), "c is a");
+                });
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 3);
+                ctx.add(&step, 4);
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "witness assertion failed in step 0 of type second: signal b is not in step type second"
+    )]
+    fn test_try_assign() {
+        let sc = circuit::<Fr, (), (), _>("try assign", |ctx| {
+            let a = ctx.forward("a");
+            let first = ctx.step_type("first");
+            let second = ctx.step_type("second");
+
+            let mut foreign = None;
+            ctx.step_type_def(first, |ctx| foreign = Some(ctx.internal("b")));
+            let b = foreign.unwrap();
+
+            ctx.step_type_def(second, |ctx| {
+                ctx.wg(move |ctx, _| {
+                    assert_eq!(ctx.try_assign(a, 1.field()), Ok(()));
+                    assert!(matches!(
+                        ctx.try_assign(second.next(), 1.field()),
+                        Err(AssignmentError::WrongKind { .. })
+                    ));
+
+                    if let Err(err) = ctx.try_assign(b, 1.field()) {
+                        ctx.fail(err.to_string());
+                    }
+                });
+            });
+            ctx.trace(move |ctx, _| ctx.add(&second, ()));
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+    }
+
+    #[test]
+    fn test_assign_many() {
+        let sc = circuit::<Fr, (), u64, _>("batch", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let limbs: Vec<Queriable<Fr>> = (0..8)
+                    .map(|i| ctx.internal(&format!("limb{}", i)))
+                    .collect();
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign_slice(&[(a, v.field())]);
+                    ctx.assign_many(
+                        limbs
+                            .iter()
+                            .enumerate()
+                            .map(|(i, limb)| (*limb, ((v >> (8 * i)) & 0xff).field())),
+                    );
+                });
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 0x0102));
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+        let mut instance = StepInstance::new(witness.step_instances[0].step_type_uuid);
+        instance.assign_many(witness.step_instances[0].assignments.clone());
+
+        assert_eq!(instance, witness.step_instances[0]);
+        let mut values: Vec<Fr> = instance.assignments.values().cloned().collect();
+        values.retain(|value| *value != Fr::from(0));
+        values.sort_by_key(|value| format!("{:?}", value));
+        assert_eq!(values, vec![Fr::from(1), Fr::from(2), Fr::from(0x0102)]);
+    }
+
+    #[test]
+    fn test_sub_trace() {
+        let sc = circuit::<Fr, (), u64, _>("nested", |ctx| {
+            let a = ctx.forward("a");
+            let block = ctx.step_type("block");
+            let other = ctx.step_type("other");
+
+            ctx.step_type_def(block, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.step_type_def(other, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+
+            let blocks = move |ctx: &mut dyn TraceContext<u64>, n: u64| {
+                for i in 0..n {
+                    ctx.add(&block, i);
+                }
+                ctx.set_height(1);
+            };
+            ctx.trace(move |ctx, _| {
+                ctx.set_height(10);
+                ctx.add(&other, 7);
+                assert_eq!(ctx.sub_trace(&blocks, 3), 1..4);
+                assert_eq!(ctx.sub_trace_with(|ctx| ctx.add(&other, 8)), 4..5);
+                assert_eq!(ctx.sub_trace(&blocks, 2), 5..7);
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        assert_eq!(witness.height, 10);
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+        let values: Vec<Fr> = witness
+            .step_instances
+            .iter()
+            .map(|instance| instance.assignments[&a])
+            .collect();
+        assert_eq!(
+            values,
+            [7, 0, 1, 2, 8, 0, 1]
+                .iter()
+                .map(|v| Fr::from(*v))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_remaining_steps() {
+        let sc = circuit::<Fr, (), u64, _>("bounded", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+            let last = ctx.step_type("last");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.step_type_def(last, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, _| {
+                assert_eq!(ctx.num_steps(), Some(4));
+                while ctx.remaining() > Some(1) {
+                    let offset = ctx.current_offset();
+                    ctx.add(&step, offset as u64);
+                }
+                ctx.sub_trace_with(|ctx| {
+                    assert_eq!((ctx.current_offset(), ctx.remaining()), (3, Some(1)));
+                    ctx.add(&last, 9);
+                });
+                assert_eq!(ctx.remaining(), Some(0));
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types).with_num_steps(4);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+        let values: Vec<Fr> = witness
+            .step_instances
+            .iter()
+            .map(|instance| instance.assignments[&a])
+            .collect();
+        assert_eq!(
+            values,
+            [0, 1, 2, 9]
+                .iter()
+                .map(|v| Fr::from(*v))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(GenericTraceContext::new(&sc.step_types).num_steps(), None);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let sc = circuit::<Fr, u64, u64, _>("provenance", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign_many([(b, (v * 2).field())]);
+                    witness_assert!(ctx, v < 2, "a {} is too large", v);
+                });
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let witness = |provenance: bool, n| {
+            let mut ctx = GenericTraceContext::new(&sc.step_types);
+            if provenance {
+                ctx = ctx.with_provenance();
+            }
+            (sc.trace.as_ref().unwrap())(&mut ctx, n);
+
+            ctx.get_witness()
+        };
+
+        let recorded = witness(true, 2);
+        assert_eq!(recorded, witness(false, 2));
+        assert!(witness(false, 2).step_instances[0].provenance.is_empty());
+
+        let provenance = &recorded.step_instances[1].provenance;
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+        let (b, b_location) = provenance.iter().find(|(signal, _)| **signal != a).unwrap();
+        assert_eq!(b.annotation(), "b");
+        assert_eq!(provenance[&a].file(), file!());
+        assert_eq!(b_location.line(), provenance[&a].line() + 1);
+
+        let message = std::panic::catch_unwind(AssertUnwindSafe(|| witness(true, 3)))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(
+            message.contains(&format!(
+                "(assigned at {}:{}:",
+                file!(),
+                provenance[&a].line()
+            )),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_strict_assignments() {
+        let sc = circuit::<Fr, u64, u64, _>("strict", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    match v {
+                        1 => {
+                            let rejected = ctx.try_assign(a, 0.field())
+                                == Err(AssignmentError::Overwritten {
+                                    signal: "a".to_string(),
+                                });
+                            ctx.assign(b, (rejected as u64).field());
+                        }
+                        2 => {
+                            ctx.assign(a, 0.field());
+                            ctx.assign(b, 0.field());
+                        }
+                        _ => ctx.assign(b, 0.field()),
+                    }
+                });
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let witness = |strict: bool, n| {
+            let mut ctx = GenericTraceContext::new(&sc.step_types);
+            if strict {
+                ctx = ctx.with_strict_assignments();
+            }
+            (sc.trace.as_ref().unwrap())(&mut ctx, n);
+
+            ctx.get_witness()
+        };
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+
+        // the second value wins unless strict
+        let lenient = witness(false, 3);
+        assert_eq!(
+            lenient.step_instances[1].assignments.get(&a),
+            Some(&0.field())
+        );
+        assert_eq!(
+            lenient.step_instances[2].assignments.get(&a),
+            Some(&0.field())
+        );
+
+        let strict = witness(true, 2);
+        assert_eq!(
+            strict.step_instances[1].assignments.get(&a),
+            Some(&1.field())
+        );
+
+        let message = std::panic::catch_unwind(AssertUnwindSafe(|| witness(true, 3)))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(message.contains("step 2"), "{}", message);
+        assert!(message.contains("signal a assigned twice"), "{}", message);
+    }
+
+    #[test]
+    fn test_constraint_checks() {
+        let sc = circuit::<Fr, u64, u64, _>("checked", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a * 2, b));
+                    ctx.transition(eq(a + 1, a.next()));
+                });
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                });
+            });
+            ctx.trace(move |ctx, skip| {
+                for i in 0..4 {
+                    ctx.add(&step, if i == skip { i + 1 } else { i });
+                }
+            });
+        });
+
+        let run = |skip| {
+            std::panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut ctx = GenericTraceContext::new(&sc.step_types).with_constraint_checks();
+                (sc.trace.as_ref().unwrap())(&mut ctx, skip);
+            }))
+            .map_err(|payload| payload.downcast::<String>().map(|message| *message))
+        };
+
+        assert!(run(10).is_ok());
+        let message = run(2).unwrap_err().unwrap();
+        assert!(
+            message.starts_with(
+                "constraint (a + 0x1) == next(a) not satisfied in step 1 of type step"
+            ),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_signal_defaults() {
+        let sc = circuit::<Fr, bool, u64, _>("defaults", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let c = ctx.forward("c");
+            ctx.signal_default(a, SignalDefault::Carry);
+            ctx.signal_default(b, SignalDefault::Zero);
+            ctx.signal_default(c, SignalDefault::Error);
+            let set = ctx.step_type("set");
+            let pad = ctx.step_type("pad");
+
+            ctx.step_type_def(set, |ctx| {
+                ctx.setup(move |ctx| ctx.constr(eq(a + b, c)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, Fr::from(1));
+                    ctx.assign(c, (v + 1).field());
+                });
+            });
+            ctx.step_type_def(pad, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(a + b, c)));
+                ctx.wg(move |ctx, v| {
+                    if v > 0 {
+                        ctx.assign(c, v.field());
+                    }
+                });
+            });
+            ctx.trace(move |ctx, assign_c| {
+                ctx.add(&set, 6);
+                ctx.add(&pad, 6);
+                ctx.add(&pad, if assign_c { 6 } else { 0 });
+            });
+        });
+
+        let run = |assign_c| {
+            std::panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut ctx = GenericTraceContext::new(&sc.step_types);
+                (sc.trace.as_ref().unwrap())(&mut ctx, assign_c);
+                ctx.get_witness()
+            }))
+        };
+
+        let witness = run(true).unwrap();
+        let value = |step: usize, signal: &str| {
+            witness.step_instances[step]
+                .assignments
+                .iter()
+                .find(|(queriable, _)| queriable.annotation() == signal)
+                .map(|(_, value)| *value)
+        };
+        assert_eq!(value(1, "a"), Some(Fr::from(6)));
+        assert_eq!(value(1, "b"), Some(Fr::from(0)));
+        assert_eq!(value(2, "a"), Some(Fr::from(6)));
+
+        let message = *run(false).unwrap_err().downcast::<String>().unwrap();
+        assert_eq!(message, "signal c not assigned in step 2 of type pad");
+    }
 }
@@ -0,0 +1,209 @@
+use std::{collections::HashMap, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::ast::{query::Queriable, Circuit, Expr};
+
+use super::{eval_expr, public_inputs::keccak256, StepInstance, TraceWitness};
+
+/// A witness with its values replaced, to be shared in bug reports, see
+/// `TraceWitness::anonymize`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AnonymizedWitness<F> {
+    pub witness: TraceWitness<F>,
+    /// The signals, with their step index, that keep their original value because replacing it
+    /// changes whether a constraint holds.
+    pub revealed: Vec<(usize, Queriable<F>)>,
+}
+
+impl<F: PrimeField + Hash> TraceWitness<F> {
+    /// Replaces every value by keccak256 of `salt` and the value, reduced to the field, keeping
+    /// zero and one. Equal values get equal replacements, so equalities, copies and boolean or
+    /// zero checks hold or fail as before, but other arithmetic generally does not. The values of
+    /// the signals of every constraint that would hold or fail differently, in a step or the next
+    /// for transitions, are restored until every constraint of `circuit` holds or fails as in the
+    /// original witness.
+    ///
+    /// Values from a small set, like amounts or indexes, can be recovered by hashing every
+    /// candidate, so `salt` should be random and kept private.
+    pub fn anonymize<TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        salt: &[u8],
+    ) -> AnonymizedWitness<F> {
+        let mut witness = TraceWitness {
+            step_instances: self
+                .step_instances
+                .iter()
+                .map(|instance| StepInstance {
+                    step_type_uuid: instance.step_type_uuid,
+                    assignments: instance
+                        .assignments
+                        .iter()
+                        .map(|(signal, value)| (*signal, pseudonym(value, salt)))
+                        .collect(),
+                    provenance: HashMap::default(),
+                    challenge_assignments: Vec::new(),
+                })
+                .collect(),
+            height: self.height,
+            public_values: self
+                .public_values
+                .iter()
+                .map(|value| pseudonym(value, salt))
+                .collect(),
+        };
+        let mut revealed = Vec::new();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for (step_idx, instance) in self.step_instances.iter().enumerate() {
+                let step = circuit
+                    .step_types
+                    .get(&instance.step_type_uuid)
+                    .expect("step type not found");
+                let has_next = step_idx + 1 < self.step_instances.len();
+
+                let constraints = step.constraints.iter().map(|constraint| &constraint.expr);
+                let transitions = step
+                    .transition_constraints
+                    .iter()
+                    .filter(|_| has_next)
+                    .map(|constraint| &constraint.expr);
+
+                for expr in constraints.chain(transitions) {
+                    if holds(expr, self, step_idx) == holds(expr, &witness, step_idx) {
+                        continue;
+                    }
+
+                    for queriable in expr.queriables() {
+                        let (idx, signal) = match queriable {
+                            Queriable::Internal(_) | Queriable::Forward(_, false) => {
+                                (step_idx, queriable)
+                            }
+                            Queriable::Forward(signal, true) => {
+                                (step_idx + 1, Queriable::Forward(signal, false))
+                            }
+                            _ => continue,
+                        };
+
+                        let original = match self.step_instances[idx].assignments.get(&signal) {
+                            Some(value) => *value,
+                            None => continue,
+                        };
+                        let value = witness.step_instances[idx]
+                            .assignments
+                            .get_mut(&signal)
+                            .expect("anonymized witness has the same signals");
+
+                        if *value != original {
+                            *value = original;
+                            revealed.push((idx, signal));
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        AnonymizedWitness { witness, revealed }
+    }
+}
+
+/// Whether the constraint evaluates to zero, or `None` if it queries an unassigned signal.
+fn holds<F: PrimeField + Hash>(
+    expr: &Expr<F>,
+    witness: &TraceWitness<F>,
+    step_idx: usize,
+) -> Option<bool> {
+    eval_expr(expr, witness, step_idx)
+        .ok()
+        .map(|value| value == F::ZERO)
+}
+
+fn pseudonym<F: PrimeField>(value: &F, salt: &[u8]) -> F {
+    if *value == F::ZERO || *value == F::ONE {
+        return *value;
+    }
+
+    let mut input = salt.to_vec();
+    input.extend_from_slice(value.to_repr().as_ref());
+
+    let base = F::from(256);
+    keccak256(&input)
+        .iter()
+        .fold(F::ZERO, |acc, byte| acc * base + F::from(*byte as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use crate::{
+        ast::ToField,
+        dsl::{cb::eq, circuit},
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_anonymize() {
+        let sc = circuit::<Fr, (), (u64, u64, u64), _>("anonymize", |ctx| {
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let a = ctx.internal("a");
+                let b = ctx.internal("b");
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + 1, b));
+                    ctx.constr(eq(b, c));
+                });
+
+                ctx.wg(move |ctx, (a_value, b_value, c_value)| {
+                    ctx.assign(a, a_value.field());
+                    ctx.assign(b, b_value.field());
+                    ctx.assign(c, c_value.field());
+                });
+            });
+
+            ctx.trace(move |ctx, _| {
+                // a + 1 == b holds and b == c fails
+                ctx.add(&step, (5, 6, 7));
+                // a + 1 == b fails and b == c holds
+                ctx.add(&step, (5, 8, 8));
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let anonymized = witness.anonymize(&sc, b"salt");
+
+        let revealed: Vec<(usize, String)> = anonymized
+            .revealed
+            .iter()
+            .map(|(step, signal)| (*step, signal.annotation()))
+            .collect();
+        assert_eq!(revealed.len(), 2);
+        assert!(revealed.contains(&(0, "a".to_string())));
+        assert!(revealed.contains(&(0, "b".to_string())));
+
+        let values = |step: usize| {
+            let mut values: Vec<(String, Fr)> = anonymized.witness.step_instances[step]
+                .assignments
+                .iter()
+                .map(|(signal, value)| (signal.annotation(), *value))
+                .collect();
+            values.sort_by(|a, b| a.0.cmp(&b.0));
+            values
+        };
+        assert_eq!(values(0)[0].1, Fr::from(5));
+        assert_ne!(values(0)[2].1, Fr::from(7));
+        assert_ne!(values(1)[1].1, Fr::from(8));
+        assert_eq!(values(1)[1].1, values(1)[2].1);
+    }
+}
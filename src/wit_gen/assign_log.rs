@@ -0,0 +1,328 @@
+use std::{collections::HashMap, fs, hash::Hash, path::Path};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::{query::Queriable, StepSchedule},
+    ir::Circuit,
+};
+
+use super::{
+    compress::{compress_values, CompressError, ZeroRunDecoder},
+    replay::{named_witness, put_string, put_u32, take_string, take_u32, ReplayError},
+    StepInstance, TraceWitness,
+};
+
+const HEADER: &str = "chiquito assign log 1";
+
+/// Every assignment made while a trace ran, in the order it was made, recorded by a
+/// `GenericTraceContext` `with_assign_log`. Replaying it rebuilds the exact witness of the trace
+/// without running the trace or the witness generation functions, so that a witness that differs
+/// between two runs can be compared assignment by assignment:
+///
+/// ```ignore
+/// let (witness, log) = chiquito.witness_with_assign_log(args).unwrap();
+/// log.write("run.log")?;
+/// // later, maybe on another machine
+/// let replayed = AssignLog::read("run.log")?.witness(&compiled)?;
+/// ```
+///
+/// Signals and step types are recorded by name, so the names of the signals must be unique
+/// within each step type. The signals assigned with challenges are not recorded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssignLog<F> {
+    pub height: usize,
+    pub public_values: Vec<F>,
+    /// Step type name of each step, in the order they were added.
+    pub steps: Vec<String>,
+    /// Step index, signal name and value of every assignment. The signals that chiquito assigns
+    /// after the witness generation of a step, like the derived signals or the bits of range
+    /// checks, follow the assignments of the step sorted by name.
+    pub assignments: Vec<(usize, String, F)>,
+}
+
+impl<F> Default for AssignLog<F> {
+    fn default() -> Self {
+        Self {
+            height: 0,
+            public_values: Vec::new(),
+            steps: Vec::new(),
+            assignments: Vec::new(),
+        }
+    }
+}
+
+impl<F: Copy + Eq + Hash> AssignLog<F> {
+    /// Records the step `instance` of type `step_type`, with the assignments of its witness
+    /// generation `calls`.
+    pub(crate) fn record_step(
+        &mut self,
+        step_type: &str,
+        calls: Vec<(Queriable<F>, F)>,
+        instance: &StepInstance<F>,
+    ) {
+        let step = self.steps.len();
+        self.steps.push(step_type.to_string());
+
+        let mut last = HashMap::new();
+        for (signal, value) in calls {
+            self.assignments.push((step, signal.annotation(), value));
+            last.insert(signal, value);
+        }
+
+        let mut assigned_after: Vec<(String, F)> = instance
+            .assignments
+            .iter()
+            .filter(|(signal, value)| last.get(*signal) != Some(*value))
+            .map(|(signal, value)| (signal.annotation(), *value))
+            .collect();
+        assigned_after.sort_by(|a, b| a.0.cmp(&b.0));
+        self.assignments.extend(
+            assigned_after
+                .into_iter()
+                .map(|(signal, value)| (step, signal, value)),
+        );
+    }
+}
+
+impl<F: PrimeField + Hash> AssignLog<F> {
+    /// Rebuilds the witness for `circuit`, compiled from the circuit definition the log was
+    /// recorded with. The last assignment of a signal in a step wins, as when the trace ran.
+    pub fn witness<TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+    ) -> Result<TraceWitness<F>, ReplayError> {
+        let mut steps: Vec<(String, Vec<(String, F)>)> = self
+            .steps
+            .iter()
+            .map(|step| (step.clone(), Vec::new()))
+            .collect();
+        for (step, signal, value) in self.assignments.iter() {
+            let (_, assignments) = steps.get_mut(*step).ok_or_else(|| ReplayError::Syntax {
+                line: 0,
+                content: format!("assignment of {} in step {}", signal, step),
+            })?;
+
+            assignments.push((signal.clone(), *value));
+        }
+
+        let mut witness = named_witness(circuit, self.height, &steps)?;
+        if circuit.step_schedule == StepSchedule::Grouped {
+            let mut scheduled = Vec::new();
+            for instance in witness.step_instances.iter() {
+                if !scheduled.contains(&instance.step_type_uuid) {
+                    scheduled.push(instance.step_type_uuid);
+                }
+            }
+            witness.step_instances.sort_by_key(|instance| {
+                scheduled
+                    .iter()
+                    .position(|uuid| *uuid == instance.step_type_uuid)
+            });
+        }
+        witness.public_values = self.public_values.clone();
+
+        Ok(witness)
+    }
+
+    /// Encodes the log in binary, with the names of the step types and signals once each, the
+    /// steps and assignments as indexes into them, and all the values at the end, public values
+    /// first, compressed with `compress_values`. Integers are little-endian `u32`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut step_types: Vec<&str> = Vec::new();
+        let mut signals: Vec<&str> = Vec::new();
+        let steps: Vec<usize> = self
+            .steps
+            .iter()
+            .map(|step| name_index(&mut step_types, step))
+            .collect();
+        let assignments: Vec<(usize, usize)> = self
+            .assignments
+            .iter()
+            .map(|(step, signal, _)| (*step, name_index(&mut signals, signal)))
+            .collect();
+
+        let mut out = Vec::new();
+        put_string(&mut out, HEADER);
+        put_u32(&mut out, self.height);
+        put_u32(&mut out, self.public_values.len());
+        for names in [&step_types, &signals] {
+            put_u32(&mut out, names.len());
+            for name in names.iter() {
+                put_string(&mut out, name);
+            }
+        }
+        put_u32(&mut out, steps.len());
+        for step_type in steps {
+            put_u32(&mut out, step_type);
+        }
+        put_u32(&mut out, assignments.len());
+        for (step, signal) in assignments {
+            put_u32(&mut out, step);
+            put_u32(&mut out, signal);
+        }
+
+        let mut values = self.public_values.clone();
+        values.extend(self.assignments.iter().map(|(_, _, value)| *value));
+        out.extend_from_slice(&compress_values(&values));
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut rest = bytes;
+
+        let header = take_string(&mut rest)?;
+        if header != HEADER {
+            return Err(ReplayError::Syntax {
+                line: 0,
+                content: header,
+            });
+        }
+        let height = take_u32(&mut rest)?;
+        let public = take_u32(&mut rest)?;
+        let mut tables = Vec::new();
+        for _ in 0..2 {
+            let names = (0..take_u32(&mut rest)?)
+                .map(|_| take_string(&mut rest))
+                .collect::<Result<Vec<String>, _>>()?;
+            tables.push(names);
+        }
+        let (step_types, signals) = (&tables[0], &tables[1]);
+        let name = |names: &[String], index: usize| {
+            names
+                .get(index)
+                .cloned()
+                .ok_or_else(|| ReplayError::Syntax {
+                    line: 0,
+                    content: format!("name {} of {}", index, names.len()),
+                })
+        };
+
+        let steps = (0..take_u32(&mut rest)?)
+            .map(|_| name(step_types, take_u32(&mut rest)?))
+            .collect::<Result<Vec<String>, _>>()?;
+        let mut assignments = (0..take_u32(&mut rest)?)
+            .map(|_| {
+                let step = take_u32(&mut rest)?;
+                let signal = name(signals, take_u32(&mut rest)?)?;
+
+                Ok((step, signal, F::ZERO))
+            })
+            .collect::<Result<Vec<(usize, String, F)>, ReplayError>>()?;
+
+        let mut decoder = ZeroRunDecoder::new(rest);
+        let mut public_values = vec![F::ZERO; public];
+        let mut values = vec![F::ZERO; assignments.len()];
+        for buffer in [&mut public_values, &mut values] {
+            decoder
+                .decode_exact(buffer)
+                .map_err(ReplayError::Compressed)?;
+        }
+        if !decoder.is_done() {
+            return Err(ReplayError::Compressed(CompressError::Overflow));
+        }
+        for ((_, _, value), decoded) in assignments.iter_mut().zip(values) {
+            *value = decoded;
+        }
+
+        Ok(Self {
+            height,
+            public_values,
+            steps,
+            assignments,
+        })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        Self::from_bytes(&fs::read(path).map_err(ReplayError::Io)?)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), ReplayError> {
+        fs::write(path, self.to_bytes()).map_err(ReplayError::Io)
+    }
+}
+
+/// The index of `name` in `names`, added at the end when missing.
+fn name_index<'a>(names: &mut Vec<&'a str>, name: &'a str) -> usize {
+    match names.iter().position(|other| *other == name) {
+        Some(index) => index,
+        None => {
+            names.push(name);
+            names.len() - 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_assign_log() {
+        let sc = circuit::<Fr, u64, u64, _>("logged", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| ctx.constr(eq(b + 1, c)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, 0.field());
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                });
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let (witness, log) = ChiquitoHalo2::new(compiled.clone())
+            .witness_with_assign_log(3)
+            .unwrap();
+
+        assert_eq!(log.steps, vec!["step"; 3]);
+        // the second assignment of a overrides the first, c is derived after the wg
+        let step_one: Vec<(&str, Fr)> = log
+            .assignments
+            .iter()
+            .filter(|(step, _, _)| *step == 1)
+            .map(|(_, signal, value)| (signal.as_str(), *value))
+            .collect();
+        assert_eq!(
+            step_one,
+            vec![
+                ("a", Fr::from(0)),
+                ("a", Fr::from(1)),
+                ("b", Fr::from(2)),
+                ("c", Fr::from(3))
+            ]
+        );
+
+        let decoded = AssignLog::from_bytes(&log.to_bytes()).unwrap();
+        assert_eq!(decoded, log);
+        assert_eq!(decoded.witness(&compiled).unwrap(), witness);
+
+        assert!(matches!(
+            AssignLog::<Fr>::from_bytes(&log.to_bytes()[..20]),
+            Err(ReplayError::Compressed(_))
+        ));
+    }
+}
@@ -0,0 +1,435 @@
+use std::{
+    collections::{hash_map, HashMap},
+    fmt,
+    iter::FromIterator,
+    ops::Index,
+    slice,
+    sync::Arc,
+    vec,
+};
+
+use crate::ast::{query::Queriable, StepType};
+
+/// The signals of a step type that have a slot in the dense storage of `Assignments`, compiled
+/// once per step type. Only internal signals and forward signals at the current step are
+/// indexed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignalIndex<F> {
+    signals: Vec<Queriable<F>>,
+    /// The uuid and slot of each signal, sorted by uuid.
+    slots: Vec<(u32, usize)>,
+}
+
+impl<F> SignalIndex<F> {
+    /// Indexes `signals` in order, skipping the repeated ones and the queriables that cannot have
+    /// a slot.
+    pub fn new<I: IntoIterator<Item = Queriable<F>>>(signals: I) -> Self {
+        let mut index = Self {
+            signals: Vec::new(),
+            slots: Vec::new(),
+        };
+
+        for signal in signals {
+            if let Some(uuid) = slot_uuid(&signal) {
+                if let Err(position) = index.slots.binary_search_by_key(&uuid, |(uuid, _)| *uuid) {
+                    index.slots.insert(position, (uuid, index.signals.len()));
+                    index.signals.push(signal);
+                }
+            }
+        }
+
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.signals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signals.is_empty()
+    }
+
+    /// The indexed signals, in the order of their slots.
+    pub fn signals(&self) -> &[Queriable<F>] {
+        &self.signals
+    }
+
+    pub fn slot(&self, signal: &Queriable<F>) -> Option<usize> {
+        let uuid = slot_uuid(signal)?;
+        let position = self
+            .slots
+            .binary_search_by_key(&uuid, |(uuid, _)| *uuid)
+            .ok()?;
+        let slot = self.slots[position].1;
+
+        (self.signals[slot] == *signal).then_some(slot)
+    }
+}
+
+impl<F: Clone> SignalIndex<F> {
+    /// Indexes the internal signals of `step`, then the forward signals that its constraints and
+    /// transitions query.
+    pub fn of_step<Args>(step: &StepType<F, Args>) -> Self {
+        let queried = step
+            .constraints
+            .iter()
+            .map(|constraint| &constraint.expr)
+            .chain(
+                step.transition_constraints
+                    .iter()
+                    .map(|transition| &transition.expr),
+            )
+            .flat_map(|expr| expr.queriables())
+            .filter_map(|queriable| match queriable {
+                Queriable::Forward(signal, _) => Some(Queriable::Forward(signal, false)),
+                _ => None,
+            });
+
+        Self::new(
+            step.signals
+                .iter()
+                .map(|signal| Queriable::Internal(*signal))
+                .chain(queried),
+        )
+    }
+}
+
+fn slot_uuid<F>(signal: &Queriable<F>) -> Option<u32> {
+    match signal {
+        Queriable::Internal(signal) => Some(signal.uuid()),
+        Queriable::Forward(signal, false) => Some(signal.uuid()),
+        _ => None,
+    }
+}
+
+/// The values assigned to the signals of a step instance. The signals of its `SignalIndex` are
+/// stored densely by slot, the others, like the imported halo2 columns or the signals of a step
+/// instance built without index, in a map. It has the API of a `HashMap` from signal to value,
+/// and iterates the indexed signals first, in slot order.
+#[derive(Clone)]
+pub struct Assignments<F> {
+    index: Option<Arc<SignalIndex<F>>>,
+    dense: Vec<Option<F>>,
+    /// Number of assigned slots in `dense`.
+    assigned: usize,
+    overflow: HashMap<Queriable<F>, F>,
+}
+
+impl<F> Default for Assignments<F> {
+    fn default() -> Self {
+        Self {
+            index: None,
+            dense: Vec::new(),
+            assigned: 0,
+            overflow: HashMap::default(),
+        }
+    }
+}
+
+impl<F> Assignments<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_index(index: Arc<SignalIndex<F>>) -> Self {
+        Self {
+            dense: (0..index.len()).map(|_| None).collect(),
+            index: Some(index),
+            ..Self::default()
+        }
+    }
+
+    pub fn index(&self) -> Option<&Arc<SignalIndex<F>>> {
+        self.index.as_ref()
+    }
+
+    fn slot(&self, signal: &Queriable<F>) -> Option<usize> {
+        self.index.as_ref()?.slot(signal)
+    }
+
+    pub fn get(&self, signal: &Queriable<F>) -> Option<&F> {
+        match self.slot(signal) {
+            Some(slot) => self.dense[slot].as_ref(),
+            None => self.overflow.get(signal),
+        }
+    }
+
+    pub fn get_mut(&mut self, signal: &Queriable<F>) -> Option<&mut F> {
+        match self.slot(signal) {
+            Some(slot) => self.dense[slot].as_mut(),
+            None => self.overflow.get_mut(signal),
+        }
+    }
+
+    pub fn contains_key(&self, signal: &Queriable<F>) -> bool {
+        self.get(signal).is_some()
+    }
+
+    /// Assigns `value` to `signal`, returning its previous value.
+    pub fn insert(&mut self, signal: Queriable<F>, value: F) -> Option<F> {
+        match self.slot(&signal) {
+            Some(slot) => {
+                let previous = self.dense[slot].replace(value);
+                if previous.is_none() {
+                    self.assigned += 1;
+                }
+
+                previous
+            }
+            None => self.overflow.insert(signal, value),
+        }
+    }
+
+    pub fn remove(&mut self, signal: &Queriable<F>) -> Option<F> {
+        match self.slot(signal) {
+            Some(slot) => {
+                let previous = self.dense[slot].take();
+                if previous.is_some() {
+                    self.assigned -= 1;
+                }
+
+                previous
+            }
+            None => self.overflow.remove(signal),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.assigned + self.overflow.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, F> {
+        Iter {
+            signals: self.indexed().iter(),
+            dense: self.dense.iter(),
+            overflow: self.overflow.iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, F> {
+        let signals = match self.index.as_ref() {
+            Some(index) => index.signals.iter(),
+            None => [].iter(),
+        };
+
+        IterMut {
+            signals,
+            dense: self.dense.iter_mut(),
+            overflow: self.overflow.iter_mut(),
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Queriable<F>> {
+        self.iter().map(|(signal, _)| signal)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &F> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    fn indexed(&self) -> &[Queriable<F>] {
+        match self.index.as_ref() {
+            Some(index) => &index.signals,
+            None => &[],
+        }
+    }
+}
+
+impl<F> From<HashMap<Queriable<F>, F>> for Assignments<F> {
+    fn from(overflow: HashMap<Queriable<F>, F>) -> Self {
+        Self {
+            overflow,
+            ..Self::default()
+        }
+    }
+}
+
+impl<F> Index<&Queriable<F>> for Assignments<F> {
+    type Output = F;
+
+    fn index(&self, signal: &Queriable<F>) -> &F {
+        self.get(signal)
+            .unwrap_or_else(|| panic!("signal {} is not assigned", signal.annotation()))
+    }
+}
+
+/// Two assignments are equal when they assign the same values to the same signals, whatever
+/// their index.
+impl<F: PartialEq> PartialEq for Assignments<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(signal, value)| other.get(signal) == Some(value))
+    }
+}
+
+impl<F: Eq> Eq for Assignments<F> {}
+
+impl<F: fmt::Debug> fmt::Debug for Assignments<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<F> Extend<(Queriable<F>, F)> for Assignments<F> {
+    fn extend<I: IntoIterator<Item = (Queriable<F>, F)>>(&mut self, assignments: I) {
+        let assignments = assignments.into_iter();
+        if self.index.is_none() {
+            self.overflow.reserve(assignments.size_hint().0);
+        }
+
+        for (signal, value) in assignments {
+            self.insert(signal, value);
+        }
+    }
+}
+
+impl<F> FromIterator<(Queriable<F>, F)> for Assignments<F> {
+    fn from_iter<I: IntoIterator<Item = (Queriable<F>, F)>>(assignments: I) -> Self {
+        let mut result = Self::default();
+        result.extend(assignments);
+
+        result
+    }
+}
+
+pub struct Iter<'a, F> {
+    signals: slice::Iter<'a, Queriable<F>>,
+    dense: slice::Iter<'a, Option<F>>,
+    overflow: hash_map::Iter<'a, Queriable<F>, F>,
+}
+
+impl<'a, F> Iterator for Iter<'a, F> {
+    type Item = (&'a Queriable<F>, &'a F);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (signal, value) in self.signals.by_ref().zip(self.dense.by_ref()) {
+            if let Some(value) = value {
+                return Some((signal, value));
+            }
+        }
+
+        self.overflow.next()
+    }
+}
+
+pub struct IterMut<'a, F> {
+    signals: slice::Iter<'a, Queriable<F>>,
+    dense: slice::IterMut<'a, Option<F>>,
+    overflow: hash_map::IterMut<'a, Queriable<F>, F>,
+}
+
+impl<'a, F> Iterator for IterMut<'a, F> {
+    type Item = (&'a Queriable<F>, &'a mut F);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (signal, value) in self.signals.by_ref().zip(self.dense.by_ref()) {
+            if let Some(value) = value {
+                return Some((signal, value));
+            }
+        }
+
+        self.overflow.next()
+    }
+}
+
+pub type IntoIter<F> =
+    std::iter::Chain<vec::IntoIter<(Queriable<F>, F)>, hash_map::IntoIter<Queriable<F>, F>>;
+
+impl<F> IntoIterator for Assignments<F> {
+    type Item = (Queriable<F>, F);
+    type IntoIter = IntoIter<F>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let dense: Vec<(Queriable<F>, F)> = match self.index.as_ref() {
+            Some(index) => index
+                .signals
+                .iter()
+                .zip(self.dense)
+                .filter_map(|(signal, value)| value.map(|value| (*signal, value)))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        dense.into_iter().chain(self.overflow)
+    }
+}
+
+impl<'a, F> IntoIterator for &'a Assignments<F> {
+    type Item = (&'a Queriable<F>, &'a F);
+    type IntoIter = Iter<'a, F>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, F> IntoIterator for &'a mut Assignments<F> {
+    type Item = (&'a Queriable<F>, &'a mut F);
+    type IntoIter = IterMut<'a, F>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::ast::{ForwardSignal, ImportedHalo2Advice, InternalSignal};
+
+    #[test]
+    fn test_assignments() {
+        let a = Queriable::<Fr>::Forward(ForwardSignal::new_with_phase(0, "a".to_string()), false);
+        let b = Queriable::<Fr>::Internal(InternalSignal::new("b".to_string()));
+        let c = Queriable::<Fr>::Internal(InternalSignal::new("c".to_string()));
+        let index = Arc::new(SignalIndex::new([b, a, a.next(), b]));
+        assert_eq!(index.signals(), &[b, a]);
+        assert_eq!((index.slot(&a), index.slot(&a.next())), (Some(1), None));
+
+        let mut dense = Assignments::with_index(Arc::clone(&index));
+        assert_eq!(dense.insert(a, Fr::from(1)), None);
+        dense.extend([(c, Fr::from(3)), (b, Fr::from(2)), (a, Fr::from(4))]);
+        assert_eq!(dense.len(), 3);
+        assert_eq!(dense[&a], Fr::from(4));
+        assert_eq!(dense.get(&a.next()), None);
+
+        let entries: Vec<(Queriable<Fr>, Fr)> = dense.iter().map(|(s, v)| (*s, *v)).collect();
+        assert_eq!(
+            entries,
+            vec![(b, Fr::from(2)), (a, Fr::from(4)), (c, Fr::from(3))]
+        );
+
+        let mut sparse: Assignments<Fr> = dense.clone().into_iter().collect();
+        assert!(sparse.index().is_none());
+        assert_eq!(sparse, dense);
+
+        for (_, value) in dense.iter_mut() {
+            *value += Fr::from(1);
+        }
+        assert_ne!(sparse, dense);
+        assert_eq!(dense.remove(&b), Some(Fr::from(3)));
+        assert_eq!(sparse.remove(&b), Some(Fr::from(2)));
+        assert_eq!(dense.len(), 2);
+        assert!(!dense.contains_key(&b));
+
+        let advice = Queriable::Halo2AdviceQuery(
+            ImportedHalo2Advice::new(
+                halo2_proofs::plonk::ConstraintSystem::<Fr>::default().advice_column(),
+                "advice".to_string(),
+            ),
+            0,
+        );
+        dense.insert(advice, Fr::from(9));
+        assert_eq!(dense.len(), 3);
+        assert_eq!(dense[&advice], Fr::from(9));
+        assert_eq!(dense.keys().filter(|signal| **signal == advice).count(), 1);
+    }
+}
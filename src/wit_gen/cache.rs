@@ -0,0 +1,228 @@
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hash,
+    io,
+    path::{Path, PathBuf},
+};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::ir::Circuit;
+
+use super::{
+    replay::{fingerprint, ReplayBundle, ReplayError},
+    TraceWitness,
+};
+
+type CacheKey<TraceArgs> = Box<dyn Fn(&TraceArgs) -> u64>;
+
+/// Memoizes the witnesses generated for a circuit, so that tests generating the same witness for
+/// the same trace arguments many times run the trace once:
+///
+/// ```ignore
+/// let mut cache = WitnessCache::new(|(n, seed): &(usize, u64)| (*n as u64) << 32 | *seed)
+///     .on_disk("target/witness-cache");
+/// let witness = chiquito.witness_cached((100, 7), &mut cache)?;
+/// ```
+///
+/// Two trace arguments with the same key must generate the same witness. The witnesses are kept
+/// in memory for each circuit, and with `on_disk` also written to a directory as compressed replay
+/// bundles, see `ReplayBundle::to_compressed`. Later runs reuse them as long as the fingerprint
+/// of the compiled circuit does not change, see `replay::fingerprint`. The fingerprint covers the
+/// constraints but not the witness generation, so the directory must be cleared when the trace
+/// or the witness generation functions change, or the stale witnesses are read back.
+pub struct WitnessCache<F, TraceArgs> {
+    key: CacheKey<TraceArgs>,
+    /// By the circuit, its fingerprint and the UUIDs of its step types, and the key.
+    memory: HashMap<(String, u64), TraceWitness<F>>,
+    dir: Option<PathBuf>,
+    hits: usize,
+    misses: usize,
+}
+
+impl<F, TraceArgs> WitnessCache<F, TraceArgs> {
+    /// A cache of the witnesses generated for the trace arguments, identified by `key`.
+    pub fn new<K: Fn(&TraceArgs) -> u64 + 'static>(key: K) -> Self {
+        Self {
+            key: Box::new(key),
+            memory: HashMap::default(),
+            dir: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Also writes the witnesses to `dir`, created when missing, and reads them from there when
+    /// they are not in memory.
+    pub fn on_disk<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.as_ref().to_path_buf());
+
+        self
+    }
+
+    /// Number of witnesses found in the cache, in memory or on disk.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of witnesses generated because they were not in the cache.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Forgets the witnesses kept in memory, the ones on disk are kept.
+    pub fn clear(&mut self) {
+        self.memory.clear();
+    }
+}
+
+impl<F: PrimeField + Hash, TraceArgs> WitnessCache<F, TraceArgs> {
+    /// Returns the witness of `circuit` cached for `args`, or runs `generate` and caches its
+    /// witness. A circuit without trace generates no witness, and nothing is cached.
+    pub fn get_or_generate<StepArgs, G>(
+        &mut self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        args: TraceArgs,
+        generate: G,
+    ) -> Result<Option<TraceWitness<F>>, ReplayError>
+    where
+        G: FnOnce(TraceArgs) -> Option<TraceWitness<F>>,
+    {
+        let key = (self.key)(&args);
+        let fingerprint = fingerprint(circuit);
+
+        // the witnesses in memory refer to the step types by UUID, which differ between
+        // compilations with the same fingerprint
+        let mut step_types: Vec<u32> = circuit.step_types.keys().copied().collect();
+        step_types.sort();
+        let memory_key = (format!("{}{:?}", fingerprint, step_types), key);
+
+        if let Some(witness) = self.memory.get(&memory_key) {
+            self.hits += 1;
+
+            return Ok(Some(witness.clone()));
+        }
+
+        let path = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}-{:016x}.witness", &fingerprint[..16], key)));
+
+        if let Some(path) = path.as_ref() {
+            match fs::read(path) {
+                Ok(bytes) => {
+                    let witness = ReplayBundle::from_compressed(&bytes)?.witness(circuit)?;
+                    self.hits += 1;
+                    self.memory.insert(memory_key, witness.clone());
+
+                    return Ok(Some(witness));
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(ReplayError::Io(err)),
+            }
+        }
+
+        self.misses += 1;
+        let witness = match generate(args) {
+            Some(witness) => witness,
+            None => return Ok(None),
+        };
+
+        if let Some(path) = path.as_ref() {
+            let bundle = ReplayBundle::new(circuit, &witness, witness.public_values.clone());
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(ReplayError::Io)?;
+            }
+            fs::write(path, bundle.to_compressed()).map_err(ReplayError::Io)?;
+        }
+        self.memory.insert(memory_key, witness.clone());
+
+        Ok(Some(witness))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    fn counter(traces: Rc<Cell<usize>>, offset: u64) -> ChiquitoHalo2<Fr, u64, u64> {
+        let sc = circuit::<Fr, u64, u64, _>("cached", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a + 1, b)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v + 1).field());
+                });
+            });
+            let traces = traces.clone();
+            ctx.trace(move |ctx, n| {
+                traces.set(traces.get() + 1);
+                for i in 0..n {
+                    ctx.add(&step, i + offset);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        ChiquitoHalo2::new(compiled)
+    }
+
+    #[test]
+    fn test_witness_cache() {
+        let traces = Rc::new(Cell::new(0));
+        let chiquito = counter(traces.clone(), 0);
+        let dir =
+            std::env::temp_dir().join(format!("chiquito-witness-cache-{}", std::process::id()));
+
+        let mut cache = WitnessCache::new(|n: &u64| *n).on_disk(&dir);
+        let three = chiquito.witness_cached(3, &mut cache).unwrap().unwrap();
+        assert_eq!(three.step_instances.len(), 3);
+        assert_eq!(
+            chiquito.witness_cached(3, &mut cache).unwrap(),
+            Some(three.clone())
+        );
+        chiquito.witness_cached(4, &mut cache).unwrap();
+        assert_eq!((cache.hits(), cache.misses(), traces.get()), (1, 2, 2));
+
+        // a new cache reads the witnesses written by the first one
+        let mut cache = WitnessCache::new(|n: &u64| *n).on_disk(&dir);
+        assert_eq!(chiquito.witness_cached(3, &mut cache).unwrap(), Some(three));
+        assert_eq!((cache.hits(), cache.misses(), traces.get()), (1, 0, 2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_witness_cache_by_circuit() {
+        let traces = Rc::new(Cell::new(0));
+        let first = counter(traces.clone(), 0);
+        let second = counter(traces.clone(), 10);
+
+        let mut cache = WitnessCache::new(|n: &u64| *n);
+        let witness = first.witness_cached(2, &mut cache).unwrap().unwrap();
+        let other = second.witness_cached(2, &mut cache).unwrap().unwrap();
+        assert_eq!((cache.hits(), cache.misses(), traces.get()), (0, 2, 2));
+        assert_ne!(witness, other);
+        assert_eq!(first.witness_cached(2, &mut cache).unwrap(), Some(witness));
+    }
+}
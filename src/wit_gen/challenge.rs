@@ -0,0 +1,67 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::ast::{query::Queriable, Challenge};
+
+/// Values of the challenges of the verifier, given to the assignments of the signals of the later
+/// phases when the backend synthesizes the witness.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChallengeValues<F> {
+    values: HashMap<u32, F>,
+}
+
+impl<F> Default for ChallengeValues<F> {
+    fn default() -> Self {
+        Self {
+            values: HashMap::new(),
+        }
+    }
+}
+
+impl<F: Clone> ChallengeValues<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, challenge: Challenge, value: F) {
+        self.values.insert(challenge.uuid(), value);
+    }
+
+    /// The value of the challenge `challenge`, as returned by `CircuitContext::challenge`.
+    pub fn get(&self, challenge: Queriable<F>) -> F {
+        match challenge {
+            Queriable::Challenge(c) => self
+                .values
+                .get(&c.uuid())
+                .cloned()
+                .unwrap_or_else(|| panic!("challenge {} has no value", c.annotation())),
+            _ => panic!("{} is not a challenge", challenge.annotation()),
+        }
+    }
+}
+
+pub type ChallengeFn<F> = dyn Fn(&ChallengeValues<F>) -> F + Send + Sync;
+
+/// A signal of a later phase, assigned with a value computed from the challenges once they are
+/// known, see `assign_with_challenges`.
+#[derive(Clone)]
+pub struct ChallengeAssignment<F> {
+    pub signal: Queriable<F>,
+    value: Arc<ChallengeFn<F>>,
+}
+
+impl<F> ChallengeAssignment<F> {
+    pub fn new(signal: Queriable<F>, value: Arc<ChallengeFn<F>>) -> Self {
+        Self { signal, value }
+    }
+
+    pub fn value(&self, challenges: &ChallengeValues<F>) -> F {
+        (self.value)(challenges)
+    }
+}
+
+/// The value is a function, only the signal is printed.
+impl<F> fmt::Debug for ChallengeAssignment<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChallengeAssignment({:?})", self.signal)
+    }
+}
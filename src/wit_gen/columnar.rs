@@ -0,0 +1,340 @@
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::query::Queriable,
+    ir::{Circuit, Column, ColumnType},
+};
+
+use super::{format::FieldFormat, TraceWitness};
+
+/// A witness in long format, one record per assigned signal, stored by column for analytics
+/// tools. With the `arrow` feature it converts to an arrow `RecordBatch`, and with the `parquet`
+/// feature it can be written as a Parquet file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WitnessColumns {
+    pub step: Vec<u64>,
+    pub step_type: Vec<String>,
+    pub signal: Vec<String>,
+    pub column: Vec<String>,
+    /// Row of the cell in the circuit, from the heights of the previous steps.
+    pub row: Vec<u64>,
+    /// The value in decimal, field elements do not fit in 64 bits.
+    pub value: Vec<String>,
+    /// The value when it fits in 64 bits, to compute distributions of small values.
+    pub value_u64: Vec<Option<u64>>,
+}
+
+/// The placement of the signals of a compiled circuit in its columns, one record per signal and
+/// step type. Forward signals have no step type. Columns without signals, like the step
+/// selectors, have no signal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayoutColumns {
+    pub column: Vec<String>,
+    pub kind: Vec<String>,
+    pub phase: Vec<u64>,
+    pub step_type: Vec<Option<String>>,
+    pub signal: Vec<Option<String>>,
+    pub rotation: Vec<i64>,
+}
+
+impl<F: PrimeField> TraceWitness<F> {
+    /// The assignments of the witness in long format, placed in the columns of `circuit`, sorted
+    /// by step and then signal. Signals without a column in the circuit are skipped.
+    pub fn to_columns<TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+    ) -> WitnessColumns {
+        let mut columns = WitnessColumns::default();
+        let mut offset = 0;
+
+        for (step_idx, instance) in self.step_instances.iter().enumerate() {
+            let step = circuit
+                .step_types
+                .get(&instance.step_type_uuid)
+                .expect("step type not found");
+
+            let mut assignments: Vec<(String, &Queriable<F>, &F)> = instance
+                .assignments
+                .iter()
+                .map(|(signal, value)| (signal.annotation(), signal, value))
+                .collect();
+            assignments.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (annotation, signal, value) in assignments {
+                let (column, rotation) = match signal {
+                    Queriable::Internal(internal) => {
+                        let placement = circuit
+                            .placement
+                            .find_internal_signal_placement(step, internal);
+                        (placement.column.annotation, placement.rotation)
+                    }
+                    Queriable::Forward(forward, next) => {
+                        let placement = circuit.placement.get_forward_placement(forward);
+                        let rotation = placement.rotation
+                            + if *next {
+                                circuit.placement.step_height(step) as i32
+                            } else {
+                                0
+                            };
+                        (placement.column.annotation, rotation)
+                    }
+                    Queriable::Halo2AdviceQuery(advice, rotation) => {
+                        match circuit.columns.iter().find(|column| {
+                            column.halo2_advice.map(|a| a.uuid()) == Some(advice.uuid())
+                        }) {
+                            Some(column) => (column.annotation.clone(), *rotation),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+
+                columns.step.push(step_idx as u64);
+                columns.step_type.push(step.name.clone());
+                columns.signal.push(annotation);
+                columns.column.push(column);
+                columns.row.push((offset as i64 + rotation as i64) as u64);
+                columns.value.push(FieldFormat::Decimal.format(value));
+                columns.value_u64.push(to_u64(value));
+            }
+
+            offset += circuit.placement.step_height(step) as usize;
+        }
+
+        columns
+    }
+}
+
+impl LayoutColumns {
+    pub fn new<F, TraceArgs, StepArgs>(circuit: &Circuit<F, TraceArgs, StepArgs>) -> Self {
+        let mut layout = LayoutColumns::default();
+
+        let mut forward: Vec<_> = circuit.placement.forward.iter().collect();
+        forward.sort_by_key(|(signal, _)| signal.uuid());
+        for (signal, placement) in forward {
+            layout.push(
+                &placement.column,
+                None,
+                Some(Queriable::<F>::Forward(*signal, false).annotation()),
+                placement.rotation,
+            );
+        }
+
+        let mut step_types: Vec<_> = circuit.step_types.values().collect();
+        step_types.sort_by_key(|step| step.uuid());
+        for step in step_types {
+            for signal in step.signals.iter() {
+                let placement = circuit
+                    .placement
+                    .find_internal_signal_placement(step, signal);
+                layout.push(
+                    &placement.column,
+                    Some(step.name.clone()),
+                    Some(Queriable::<F>::Internal(*signal).annotation()),
+                    placement.rotation,
+                );
+            }
+        }
+
+        for column in circuit.columns.iter() {
+            if !layout.column.contains(&column.annotation) {
+                layout.push(column, None, None, 0);
+            }
+        }
+
+        layout
+    }
+
+    fn push(
+        &mut self,
+        column: &Column,
+        step_type: Option<String>,
+        signal: Option<String>,
+        rotation: i32,
+    ) {
+        let kind = match column.ctype {
+            ColumnType::Advice => "advice",
+            ColumnType::Fixed => "fixed",
+            ColumnType::Halo2Advice => "halo2 advice",
+            ColumnType::Halo2Fixed => "halo2 fixed",
+        };
+
+        self.column.push(column.annotation.clone());
+        self.kind.push(kind.to_string());
+        self.phase.push(column.phase as u64);
+        self.step_type.push(step_type);
+        self.signal.push(signal);
+        self.rotation.push(rotation as i64);
+    }
+}
+
+fn to_u64<F: PrimeField>(value: &F) -> Option<u64> {
+    let repr = value.to_repr();
+    let bytes = repr.as_ref();
+
+    if bytes[8..].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+
+    Some(u64::from_le_bytes(bytes[..8].try_into().unwrap()))
+}
+
+#[cfg(feature = "arrow")]
+mod arrow_export {
+    use std::{io::Write, sync::Arc};
+
+    use arrow::{
+        array::{ArrayRef, Int64Array, StringArray, UInt64Array},
+        error::ArrowError,
+        ipc::writer::FileWriter,
+        record_batch::RecordBatch,
+    };
+
+    use super::{LayoutColumns, WitnessColumns};
+
+    impl WitnessColumns {
+        pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+            RecordBatch::try_from_iter(vec![
+                (
+                    "step",
+                    Arc::new(UInt64Array::from(self.step.clone())) as ArrayRef,
+                ),
+                (
+                    "step_type",
+                    Arc::new(StringArray::from(self.step_type.clone())) as ArrayRef,
+                ),
+                (
+                    "signal",
+                    Arc::new(StringArray::from(self.signal.clone())) as ArrayRef,
+                ),
+                (
+                    "column",
+                    Arc::new(StringArray::from(self.column.clone())) as ArrayRef,
+                ),
+                (
+                    "row",
+                    Arc::new(UInt64Array::from(self.row.clone())) as ArrayRef,
+                ),
+                (
+                    "value",
+                    Arc::new(StringArray::from(self.value.clone())) as ArrayRef,
+                ),
+                (
+                    "value_u64",
+                    Arc::new(UInt64Array::from(self.value_u64.clone())) as ArrayRef,
+                ),
+            ])
+        }
+    }
+
+    impl LayoutColumns {
+        pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+            RecordBatch::try_from_iter(vec![
+                (
+                    "column",
+                    Arc::new(StringArray::from(self.column.clone())) as ArrayRef,
+                ),
+                (
+                    "kind",
+                    Arc::new(StringArray::from(self.kind.clone())) as ArrayRef,
+                ),
+                (
+                    "phase",
+                    Arc::new(UInt64Array::from(self.phase.clone())) as ArrayRef,
+                ),
+                (
+                    "step_type",
+                    Arc::new(StringArray::from(self.step_type.clone())) as ArrayRef,
+                ),
+                (
+                    "signal",
+                    Arc::new(StringArray::from(self.signal.clone())) as ArrayRef,
+                ),
+                (
+                    "rotation",
+                    Arc::new(Int64Array::from(self.rotation.clone())) as ArrayRef,
+                ),
+            ])
+        }
+    }
+
+    /// Writes `batch` in the Arrow IPC file format.
+    pub fn write_arrow_ipc<W: Write>(batch: &RecordBatch, writer: W) -> Result<(), ArrowError> {
+        let mut writer = FileWriter::try_new(writer, &batch.schema())?;
+        writer.write(batch)?;
+        writer.finish()
+    }
+}
+
+#[cfg(feature = "arrow")]
+pub use arrow_export::write_arrow_ipc;
+
+/// Writes `batch` as a Parquet file.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: std::io::Write + Send>(
+    batch: &arrow::record_batch::RecordBatch,
+    writer: W,
+) -> Result<(), parquet::errors::ParquetError> {
+    let mut writer = parquet::arrow::ArrowWriter::try_new(writer, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_witness_columns() {
+        let sc = circuit::<Fr, (), u64, _>("columns", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, -Fr::from(v));
+                });
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 1);
+                ctx.add(&step, 2);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let columns = ctx.get_witness().to_columns(&compiled);
+
+        assert_eq!(columns.step, vec![0, 0, 1, 1]);
+        assert_eq!(columns.signal, vec!["a", "b", "a", "b"]);
+        assert_eq!(columns.row, vec![0, 0, 1, 1]);
+        assert_eq!(columns.value[2], "2");
+        assert_eq!(columns.value_u64[2], Some(2));
+        assert_eq!(
+            columns.value_u64[3].is_some(),
+            columns.value[3].parse::<u64>().is_ok()
+        );
+
+        let layout = LayoutColumns::new(&compiled);
+        assert_eq!(layout.signal[0], Some("a".to_string()));
+        assert_eq!(layout.step_type[1], Some("step".to_string()));
+        assert!(layout.kind.contains(&"fixed".to_string()));
+    }
+}
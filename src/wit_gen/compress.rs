@@ -0,0 +1,210 @@
+use std::fmt;
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use super::public_inputs::{
+    decode_public_inputs, encode_public_inputs, PublicInputError, WORD_SIZE,
+};
+
+const ZERO_RUN: u8 = 0;
+const LITERAL: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressError {
+    /// The input ends in the middle of a block.
+    Truncated,
+    UnknownBlock(u8),
+    Value(PublicInputError),
+    /// The input has more values than the buffer it is decoded into.
+    Overflow,
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressError::Truncated => write!(f, "compressed values are truncated"),
+            CompressError::UnknownBlock(tag) => write!(f, "unknown block type {}", tag),
+            CompressError::Value(err) => write!(f, "invalid value: {}", err),
+            CompressError::Overflow => write!(f, "more compressed values than expected"),
+        }
+    }
+}
+
+impl std::error::Error for CompressError {}
+
+/// Encodes field elements as blocks of runs of zeros, stored as their length, and of literal
+/// values, stored as `encode_public_inputs` words. The witness of a padded trace is mostly
+/// zeros, so this is much smaller to send from the machine that generates the witness to the
+/// prover.
+pub fn compress_values<F: PrimeField>(values: &[F]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut rest = values;
+
+    while !rest.is_empty() {
+        let zeros = rest
+            .iter()
+            .take_while(|value| bool::from(value.is_zero()))
+            .count();
+        if zeros > 0 {
+            out.push(ZERO_RUN);
+            out.extend_from_slice(&(zeros as u32).to_le_bytes());
+            rest = &rest[zeros..];
+            continue;
+        }
+
+        let literals = rest
+            .iter()
+            .take_while(|value| !bool::from(value.is_zero()))
+            .count();
+        out.push(LITERAL);
+        out.extend_from_slice(&(literals as u32).to_le_bytes());
+        out.extend_from_slice(&encode_public_inputs(&rest[..literals]));
+        rest = &rest[literals..];
+    }
+
+    out
+}
+
+/// Decodes `compress_values` block by block, without expanding the runs of zeros in memory
+/// before they are written to their destination.
+pub struct ZeroRunDecoder<'a> {
+    rest: &'a [u8],
+    zeros: usize,
+    literals: usize,
+}
+
+impl<'a> ZeroRunDecoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            rest: bytes,
+            zeros: 0,
+            literals: 0,
+        }
+    }
+
+    /// Whether every value has been decoded.
+    pub fn is_done(&self) -> bool {
+        self.rest.is_empty() && self.zeros == 0 && self.literals == 0
+    }
+
+    /// Decodes values directly into `buffer` until it is full or the input ends, returning the
+    /// number of values written.
+    pub fn decode_into<F: PrimeField>(&mut self, buffer: &mut [F]) -> Result<usize, CompressError> {
+        let mut written = 0;
+
+        while written < buffer.len() {
+            if self.zeros == 0 && self.literals == 0 && !self.next_block()? {
+                break;
+            }
+
+            if self.zeros > 0 {
+                let len = self.zeros.min(buffer.len() - written);
+                buffer[written..written + len].fill(F::ZERO);
+                self.zeros -= len;
+                written += len;
+            } else {
+                buffer[written] = self.next_literal()?;
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Decodes exactly `buffer.len()` values into `buffer`.
+    pub fn decode_exact<F: PrimeField>(&mut self, buffer: &mut [F]) -> Result<(), CompressError> {
+        if self.decode_into(buffer)? < buffer.len() {
+            return Err(CompressError::Truncated);
+        }
+
+        Ok(())
+    }
+
+    fn next_block(&mut self) -> Result<bool, CompressError> {
+        let (tag, rest) = match self.rest.split_first() {
+            Some(split) => split,
+            None => return Ok(false),
+        };
+        if rest.len() < 4 {
+            return Err(CompressError::Truncated);
+        }
+        let (len, rest) = rest.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+
+        match *tag {
+            ZERO_RUN => self.zeros = len,
+            LITERAL => self.literals = len,
+            tag => return Err(CompressError::UnknownBlock(tag)),
+        }
+        self.rest = rest;
+
+        Ok(true)
+    }
+
+    fn next_literal<F: PrimeField>(&mut self) -> Result<F, CompressError> {
+        if self.rest.len() < WORD_SIZE {
+            return Err(CompressError::Truncated);
+        }
+        let (word, rest) = self.rest.split_at(WORD_SIZE);
+        self.rest = rest;
+        self.literals -= 1;
+
+        decode_public_inputs(word)
+            .map_err(CompressError::Value)
+            .map(|mut values| values.pop().unwrap())
+    }
+}
+
+/// Decodes all the values of `compress_values`.
+pub fn decompress_values<F: PrimeField>(bytes: &[u8]) -> Result<Vec<F>, CompressError> {
+    let mut decoder = ZeroRunDecoder::new(bytes);
+    let mut values = Vec::new();
+    let mut chunk = vec![F::ZERO; 1024];
+
+    loop {
+        let written = decoder.decode_into(&mut chunk)?;
+        values.extend_from_slice(&chunk[..written]);
+        if written < chunk.len() {
+            return Ok(values);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_compress_values() {
+        let mut values = vec![Fr::from(0); 1000];
+        values[3] = Fr::from(7);
+        values[4] = -Fr::from(1);
+        values[999] = Fr::from(9);
+
+        let bytes = compress_values(&values);
+        assert!(bytes.len() < 5 * 5 + 3 * WORD_SIZE);
+        assert_eq!(decompress_values::<Fr>(&bytes), Ok(values.clone()));
+
+        // decoding in small buffers splits the blocks
+        let mut decoder = ZeroRunDecoder::new(&bytes);
+        let mut buffer = [Fr::from(1); 3];
+        let mut decoded = Vec::new();
+        while !decoder.is_done() {
+            let written = decoder.decode_into(&mut buffer).unwrap();
+            decoded.extend_from_slice(&buffer[..written]);
+        }
+        assert_eq!(decoded, values);
+
+        assert_eq!(
+            decompress_values::<Fr>(&bytes[..bytes.len() - 1]),
+            Err(CompressError::Truncated)
+        );
+        let mut exact = vec![Fr::from(0); 1001];
+        assert_eq!(
+            ZeroRunDecoder::new(&bytes).decode_exact(&mut exact),
+            Err(CompressError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fmt, hash::Hash, rc::Rc};
+
+use crate::ast::{query::Queriable, StepType};
+
+use super::TraceWitness;
+
+/// The first difference found between two witnesses, see `first_difference`. Values are printed
+/// with `Debug`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WitnessDifference {
+    Steps {
+        first: usize,
+        second: usize,
+    },
+    StepType {
+        step: usize,
+        first: String,
+        second: String,
+    },
+    /// `None` when the signal is not assigned in one of the witnesses.
+    Assignment {
+        step: usize,
+        step_type: String,
+        signal: String,
+        first: Option<String>,
+        second: Option<String>,
+    },
+    Height {
+        first: usize,
+        second: usize,
+    },
+    PublicValues,
+}
+
+impl fmt::Display for WitnessDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |value: &Option<String>| value.as_deref().unwrap_or("unassigned").to_string();
+        match self {
+            WitnessDifference::Steps { first, second } => {
+                write!(f, "{} steps instead of {}", second, first)
+            }
+            WitnessDifference::StepType {
+                step,
+                first,
+                second,
+            } => write!(
+                f,
+                "step {} is of type {} instead of {}",
+                step, second, first
+            ),
+            WitnessDifference::Assignment {
+                step,
+                step_type,
+                signal,
+                first,
+                second,
+            } => write!(
+                f,
+                "signal {} in step {} of type {} is {} instead of {}",
+                signal,
+                step,
+                step_type,
+                value(second),
+                value(first)
+            ),
+            WitnessDifference::Height { first, second } => {
+                write!(f, "height {} instead of {}", second, first)
+            }
+            WitnessDifference::PublicValues => write!(f, "the public values differ"),
+        }
+    }
+}
+
+/// Two runs of the witness generation with the same arguments gave different witnesses, see
+/// `ChiquitoHalo2::witness_deterministic`. The usual causes are the iteration order of a
+/// `HashMap`, the time or an unseeded random generator in a witness generation function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NondeterminismError {
+    /// Threads of the second run, the first one is serial.
+    pub threads: usize,
+    pub difference: WitnessDifference,
+}
+
+impl fmt::Display for NondeterminismError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "witness generation is not deterministic, the run in {} threads differs from the \
+             serial one: {}",
+            self.threads, self.difference
+        )
+    }
+}
+
+impl std::error::Error for NondeterminismError {}
+
+/// The first difference between the witnesses `first` and `second`, in the order of the steps,
+/// and of the signal annotations within a step. `None` when they are equal.
+pub fn first_difference<F: Eq + Hash + fmt::Debug, Args>(
+    first: &TraceWitness<F>,
+    second: &TraceWitness<F>,
+    step_types: &HashMap<u32, Rc<StepType<F, Args>>>,
+) -> Option<WitnessDifference> {
+    let name = |uuid: u32| {
+        step_types
+            .get(&uuid)
+            .map(|step| step.name.clone())
+            .unwrap_or_else(|| uuid.to_string())
+    };
+
+    for (step, (a, b)) in first
+        .step_instances
+        .iter()
+        .zip(second.step_instances.iter())
+        .enumerate()
+    {
+        if a.step_type_uuid != b.step_type_uuid {
+            return Some(WitnessDifference::StepType {
+                step,
+                first: name(a.step_type_uuid),
+                second: name(b.step_type_uuid),
+            });
+        }
+
+        let mut signals: Vec<Queriable<F>> = a.assignments.iter().map(|(s, _)| *s).collect();
+        signals.extend(
+            b.assignments
+                .iter()
+                .map(|(s, _)| *s)
+                .filter(|s| a.assignments.get(s).is_none()),
+        );
+        signals.sort_by_key(|signal| (signal.annotation(), signal.uuid()));
+
+        for signal in signals {
+            let (value_a, value_b) = (a.assignments.get(&signal), b.assignments.get(&signal));
+            if value_a != value_b {
+                return Some(WitnessDifference::Assignment {
+                    step,
+                    step_type: name(a.step_type_uuid),
+                    signal: signal.annotation(),
+                    first: value_a.map(|value| format!("{:?}", value)),
+                    second: value_b.map(|value| format!("{:?}", value)),
+                });
+            }
+        }
+    }
+
+    if first.step_instances.len() != second.step_instances.len() {
+        return Some(WitnessDifference::Steps {
+            first: first.step_instances.len(),
+            second: second.step_instances.len(),
+        });
+    }
+    if first.height != second.height {
+        return Some(WitnessDifference::Height {
+            first: first.height,
+            second: second.height,
+        });
+    }
+    if first.public_values != second.public_values {
+        return Some(WitnessDifference::PublicValues);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+    };
+
+    #[test]
+    fn test_witness_deterministic() {
+        let sc = circuit::<Fr, u64, u64, _>("deterministic", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.wg_sync(move |ctx, v| ctx.assign(a, v.field()))
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let chiquito = ChiquitoHalo2::new(compiled);
+        assert_eq!(
+            chiquito.witness_deterministic(5, 3).unwrap(),
+            chiquito.witness(5)
+        );
+
+        // the second run sees a different counter
+        let runs = Rc::new(Cell::new(0));
+        let sc = circuit::<Fr, u64, u64, _>("nondeterministic", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            let runs = Rc::clone(&runs);
+            ctx.trace(move |ctx, n| {
+                runs.set(runs.get() + 1);
+                for i in 0..n {
+                    ctx.add(&step, i * runs.get());
+                }
+            });
+        });
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let chiquito = ChiquitoHalo2::new(compiled);
+
+        let err = chiquito.witness_deterministic(3, 1).unwrap_err();
+        assert_eq!(err.threads, 1);
+        assert!(matches!(
+            err.difference,
+            WitnessDifference::Assignment { step: 1, ref signal, .. } if signal == "a"
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    ast::{query::Queriable, StepType, StepTypeUUID},
+    compiler::{TraceContext, WitnessGenContext},
+    dsl::StepTypeHandler,
+    ir::Circuit,
+};
+
+use super::WitnessAssertion;
+
+/// Outcome of a dry run of the trace, see `dry_run`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DryRun {
+    pub steps: usize,
+    /// Number of instances of each step type, in the order the step types are first added.
+    pub step_counts: Vec<(String, usize)>,
+    /// Height set by the trace, 0 when not set.
+    pub height: usize,
+    /// Number of steps the circuit is planned for, 0 when not set.
+    pub num_steps: usize,
+}
+
+impl DryRun {
+    /// Steps left to pad the trace to `num_steps`, `None` when the trace has more steps than
+    /// planned or the circuit has no `num_steps`.
+    pub fn padding(&self) -> Option<usize> {
+        if self.num_steps == 0 {
+            return None;
+        }
+
+        self.num_steps.checked_sub(self.steps)
+    }
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} steps", self.steps)?;
+        if self.num_steps > 0 {
+            write!(f, " of {} planned", self.num_steps)?;
+        }
+        for (step_type, count) in self.step_counts.iter() {
+            write!(f, "\n    {}: {}", step_type, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Trace context that runs the witness generation of every step added, checking its assertions,
+/// but does not keep the assigned values.
+pub struct DryRunTraceContext<'a, F, StepArgs> {
+    step_types: &'a HashMap<u32, Rc<StepType<F, StepArgs>>>,
+
+    steps: usize,
+    step_counts: Vec<(StepTypeUUID, usize)>,
+    height: usize,
+    num_steps: usize,
+    failure: Option<WitnessAssertion>,
+}
+
+impl<'a, F, StepArgs> DryRunTraceContext<'a, F, StepArgs> {
+    pub fn new(step_types: &'a HashMap<u32, Rc<StepType<F, StepArgs>>>) -> Self {
+        Self {
+            step_types,
+            steps: 0,
+            step_counts: Vec::new(),
+            height: 0,
+            num_steps: 0,
+            failure: None,
+        }
+    }
+
+    /// Reports `num_steps` to the trace as the number of steps of the circuit, 0 when not set.
+    pub fn with_num_steps(mut self, num_steps: usize) -> Self {
+        self.num_steps = num_steps;
+
+        self
+    }
+
+    /// The counts of the steps added, or the first failed assertion. The assertion has no
+    /// assignments, as the dry run does not keep them.
+    pub fn get_dry_run(self, num_steps: usize) -> Result<DryRun, WitnessAssertion> {
+        if let Some(failure) = self.failure {
+            return Err(failure);
+        }
+
+        let step_types = self.step_types;
+
+        Ok(DryRun {
+            steps: self.steps,
+            step_counts: self
+                .step_counts
+                .into_iter()
+                .map(|(uuid, count)| (step_types[&uuid].name.clone(), count))
+                .collect(),
+            height: self.height,
+            num_steps,
+        })
+    }
+}
+
+impl<'a, F, StepArgs> TraceContext<StepArgs> for DryRunTraceContext<'a, F, StepArgs> {
+    fn add(&mut self, step: &StepTypeHandler, args: StepArgs) {
+        let step = Rc::clone(
+            self.step_types
+                .get(&step.uuid())
+                .expect("step type not found"),
+        );
+
+        let mut checked = UnrecordedStepInstance { failure: None };
+        (*step.wg)(&mut checked, args);
+        if let (Some(message), None) = (checked.failure, &self.failure) {
+            self.failure = Some(WitnessAssertion {
+                step: self.steps,
+                step_type: step.name.clone(),
+                message,
+                assignments: Vec::new(),
+            });
+        }
+
+        match self
+            .step_counts
+            .iter_mut()
+            .find(|(uuid, _)| *uuid == step.uuid())
+        {
+            Some((_, count)) => *count += 1,
+            None => self.step_counts.push((step.uuid(), 1)),
+        }
+        self.steps += 1;
+    }
+
+    fn set_height(&mut self, height: usize) {
+        self.height = height;
+    }
+
+    fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn num_steps(&self) -> Option<usize> {
+        Some(self.num_steps).filter(|num_steps| *num_steps > 0)
+    }
+}
+
+/// Context of the witness generation function of a step in a dry run, which drops the
+/// assignments and records the first failed assertion.
+struct UnrecordedStepInstance {
+    failure: Option<String>,
+}
+
+impl<F> WitnessGenContext<F> for UnrecordedStepInstance {
+    fn assign(&mut self, _lhs: Queriable<F>, _rhs: F) {}
+
+    fn fail(&mut self, message: String) {
+        if self.failure.is_none() {
+            self.failure = Some(message);
+        }
+    }
+}
+
+/// Runs the trace of `circuit` without allocating the witness, to check the trace logic and the
+/// number of steps of a large trace quickly. The witness generation of every step runs and its
+/// `witness_assert!` checks are reported, but range check bits, derived signals and exposed
+/// results are not computed.
+pub fn dry_run<F, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    args: TraceArgs,
+) -> Option<Result<DryRun, WitnessAssertion>> {
+    circuit.trace.as_ref().map(|trace| {
+        let mut ctx =
+            DryRunTraceContext::new(&circuit.step_types).with_num_steps(circuit.num_steps);
+        trace(&mut ctx, args);
+
+        ctx.get_dry_run(circuit.num_steps)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        witness_assert,
+    };
+
+    #[test]
+    fn test_dry_run() {
+        let sc = circuit::<Fr, u64, u64, _>("dry run", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+            let padding = ctx.step_type("padding");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.wg(move |ctx, v| {
+                    witness_assert!(ctx, v < 5, "value {} too large", v);
+                    ctx.assign(a, v.field());
+                })
+            });
+            ctx.step_type_def(padding, |ctx| {
+                ctx.wg(move |ctx, _| ctx.assign(a, 0.field()))
+            });
+            ctx.pragma_num_steps(8);
+
+            ctx.trace(move |ctx, steps| {
+                for i in 0..steps {
+                    ctx.add(&step, i);
+                }
+                for _ in steps..6 {
+                    ctx.add(&padding, 0);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let report = dry_run(&compiled, 4).unwrap().unwrap();
+        assert_eq!(report.steps, 6);
+        assert_eq!(
+            report.step_counts,
+            vec![("step".to_string(), 4), ("padding".to_string(), 2)]
+        );
+        assert_eq!(report.padding(), Some(2));
+
+        let failure = dry_run(&compiled, 7).unwrap().unwrap_err();
+        assert_eq!(failure.step, 5);
+        assert_eq!(failure.message, "value 5 too large");
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fmt, fs, io, path::Path};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use super::replay::{ReplayBundle, ReplayError};
+
+/// Start of the encrypted files, followed by the format version.
+const MAGIC: &[u8; 4] = b"CHQE";
+const VERSION: u8 = 1;
+const NONCE_SIZE: usize = 12;
+
+#[derive(Debug)]
+pub enum EncryptionError {
+    Io(io::Error),
+    /// The data does not start with the header of an encrypted witness file.
+    Format,
+    /// The data was not encrypted with the key, or was modified.
+    Decrypt,
+    Replay(ReplayError),
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncryptionError::Io(err) => write!(f, "cannot access encrypted file: {}", err),
+            EncryptionError::Format => write!(f, "not an encrypted witness file"),
+            EncryptionError::Decrypt => {
+                write!(f, "cannot decrypt, wrong key or corrupted file")
+            }
+            EncryptionError::Replay(err) => write!(f, "invalid decrypted bundle: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+/// A 256-bit AES-GCM key for the witness files.
+#[derive(Clone, PartialEq, Eq)]
+pub struct WitnessKey([u8; 32]);
+
+impl WitnessKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// A random key from the random number generator of the operating system.
+    pub fn generate() -> Self {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&Aes256Gcm::generate_key(OsRng));
+
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0))
+    }
+}
+
+/// The key is secret, it is not printed.
+impl fmt::Debug for WitnessKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WitnessKey(..)")
+    }
+}
+
+/// Encrypts `plaintext` with AES-256-GCM under a random nonce. The result is the header, the
+/// nonce and the ciphertext with its authentication tag.
+pub fn encrypt(key: &WitnessKey, plaintext: &[u8]) -> Vec<u8> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, plaintext)
+        .expect("AES-GCM encryption failed");
+
+    let mut out = Vec::with_capacity(MAGIC.len() + 1 + NONCE_SIZE + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+
+    out
+}
+
+/// Decrypts the output of `encrypt`, checking that it was not modified.
+pub fn decrypt(key: &WitnessKey, bytes: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+    let header = MAGIC.len() + 1;
+    if bytes.len() < header + NONCE_SIZE
+        || !bytes.starts_with(MAGIC)
+        || bytes[header - 1] != VERSION
+    {
+        return Err(EncryptionError::Format);
+    }
+
+    let nonce = Nonce::from_slice(&bytes[header..header + NONCE_SIZE]);
+    key.cipher()
+        .decrypt(nonce, &bytes[header + NONCE_SIZE..])
+        .map_err(|_| EncryptionError::Decrypt)
+}
+
+/// Writes `contents` encrypted to `path`, for any of the files with witness values written by
+/// chiquito, like the replay bundles or the trace logs.
+pub fn write_encrypted<P: AsRef<Path>>(
+    path: P,
+    key: &WitnessKey,
+    contents: &[u8],
+) -> Result<(), EncryptionError> {
+    fs::write(path, encrypt(key, contents)).map_err(EncryptionError::Io)
+}
+
+pub fn read_encrypted<P: AsRef<Path>>(
+    path: P,
+    key: &WitnessKey,
+) -> Result<Vec<u8>, EncryptionError> {
+    decrypt(key, &fs::read(path).map_err(EncryptionError::Io)?)
+}
+
+impl<F: PrimeField + std::hash::Hash> ReplayBundle<F> {
+    /// Writes the bundle compressed, see `to_compressed`, and encrypted with `key`.
+    pub fn write_encrypted<P: AsRef<Path>>(
+        &self,
+        path: P,
+        key: &WitnessKey,
+    ) -> Result<(), EncryptionError> {
+        write_encrypted(path, key, &self.to_compressed())
+    }
+
+    pub fn read_encrypted<P: AsRef<Path>>(
+        path: P,
+        key: &WitnessKey,
+    ) -> Result<Self, EncryptionError> {
+        Self::from_compressed(&read_encrypted(path, key)?).map_err(EncryptionError::Replay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+
+    #[test]
+    fn test_encrypted_bundle() {
+        let bundle = ReplayBundle {
+            fingerprint: "circuit".to_string(),
+            height: 4,
+            public_inputs: vec![Fr::from(7)],
+            steps: vec![("step".to_string(), vec![("a".to_string(), Fr::from(42))])],
+        };
+        let key = WitnessKey::generate();
+        let path =
+            std::env::temp_dir().join(format!("chiquito-encrypted-{}.bin", std::process::id()));
+
+        bundle.write_encrypted(&path, &key).unwrap();
+        assert_eq!(ReplayBundle::read_encrypted(&path, &key).unwrap(), bundle);
+        assert!(matches!(
+            ReplayBundle::<Fr>::read_encrypted(&path, &WitnessKey::from_bytes([1; 32])),
+            Err(EncryptionError::Decrypt)
+        ));
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(
+            decrypt(&key, &bytes),
+            Err(EncryptionError::Decrypt)
+        ));
+        assert!(matches!(
+            decrypt(&key, &bundle.to_compressed()),
+            Err(EncryptionError::Format)
+        ));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,298 @@
+//! Witnesses from the execution traces of a virtual machine, for example recorded by an emulator,
+//! as a sequence of records of an opcode and named fields. A `RecordMapping` declares the step
+//! type of each opcode and the signals of the fields, so the records are turned into steps
+//! without a trace function written by hand for every opcode. Records can be parsed from text,
+//! one per line:
+//!
+//! ```text
+//! # opcode field=value ...
+//! ADD rs1=1 rs2=2 rd=3
+//! MUL rs1=3 rs2=0x10 rd=48
+//! ```
+//!
+//! Blank lines and lines starting with `#` are skipped. Values are decimal or `0x` hexadecimal
+//! integers, lower than the modulus of the field.
+
+use std::{collections::HashMap, fmt, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::ir::Circuit;
+
+use super::{
+    import::parse_integer,
+    replay::{NamedSignals, ReplayError},
+    TraceWitness,
+};
+
+/// A record of an execution trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExecRecord<F> {
+    pub opcode: String,
+    pub fields: Vec<(String, F)>,
+}
+
+impl<F> ExecRecord<F> {
+    pub fn new<O: Into<String>>(opcode: O) -> Self {
+        Self {
+            opcode: opcode.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_field<N: Into<String>>(mut self, name: N, value: F) -> Self {
+        self.fields.push((name.into(), value));
+
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum ExecTraceError {
+    Syntax {
+        line: usize,
+        content: String,
+    },
+    UnknownOpcode {
+        record: usize,
+        opcode: String,
+    },
+    /// The step type of the opcode or the signal of a field is not in the circuit.
+    Witness {
+        record: usize,
+        err: ReplayError,
+    },
+}
+
+impl fmt::Display for ExecTraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecTraceError::Syntax { line, content } => {
+                write!(f, "syntax error at line {}: '{}'", line, content)
+            }
+            ExecTraceError::UnknownOpcode { record, opcode } => {
+                write!(f, "record {} has the unmapped opcode {}", record, opcode)
+            }
+            ExecTraceError::Witness { record, err } => write!(f, "record {}: {}", record, err),
+        }
+    }
+}
+
+impl std::error::Error for ExecTraceError {}
+
+/// The step type and the signals of the records of an opcode, see `RecordMapping::opcode`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct OpcodeMapping {
+    step_type: String,
+    signals: HashMap<String, String>,
+    ignored: Vec<String>,
+}
+
+/// Maps the records of an execution trace to step types and assignments:
+///
+/// ```ignore
+/// let mapping = RecordMapping::new()
+///     .opcode("ADD", "add", &[("rs1", "a"), ("rs2", "b"), ("rd", "c")])
+///     .opcode("HALT", "halt", &[])
+///     .ignore_fields("ADD", &["pc"]);
+/// let witness = mapping.witness(&compiled, &records)?;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecordMapping {
+    opcodes: HashMap<String, OpcodeMapping>,
+}
+
+impl RecordMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps the records of `opcode` to steps of `step_type`. The fields are assigned to the
+    /// signals given by the pairs of field and signal name of `signals`, and the other fields to
+    /// the signal of their own name. Forward signals are named as in the imported witnesses, see
+    /// `wit_gen::import`.
+    pub fn opcode(mut self, opcode: &str, step_type: &str, signals: &[(&str, &str)]) -> Self {
+        self.opcodes.insert(
+            opcode.to_string(),
+            OpcodeMapping {
+                step_type: step_type.to_string(),
+                signals: signals
+                    .iter()
+                    .map(|(field, signal)| (field.to_string(), signal.to_string()))
+                    .collect(),
+                ignored: Vec::new(),
+            },
+        );
+
+        self
+    }
+
+    /// Skips the `fields` of the records of `opcode`, which must be mapped already, for the
+    /// fields of the trace that are not signals of the circuit.
+    pub fn ignore_fields(mut self, opcode: &str, fields: &[&str]) -> Self {
+        let mapping = self
+            .opcodes
+            .get_mut(opcode)
+            .unwrap_or_else(|| panic!("opcode {} is not mapped", opcode));
+        mapping
+            .ignored
+            .extend(fields.iter().map(|field| field.to_string()));
+
+        self
+    }
+
+    /// The witness of `circuit` with a step for each record, in order. The signals that the
+    /// records leave unassigned but the constraints determine are filled with
+    /// `TraceWitness::fill_derived`.
+    pub fn witness<F: PrimeField + Hash, TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        records: &[ExecRecord<F>],
+    ) -> Result<TraceWitness<F>, ExecTraceError> {
+        let mut names = NamedSignals::new(circuit);
+        let mut witness = TraceWitness::default();
+        for (index, record) in records.iter().enumerate() {
+            let mapping =
+                self.opcodes
+                    .get(&record.opcode)
+                    .ok_or_else(|| ExecTraceError::UnknownOpcode {
+                        record: index,
+                        opcode: record.opcode.clone(),
+                    })?;
+
+            let assignments: Vec<(String, F)> = record
+                .fields
+                .iter()
+                .filter(|(field, _)| !mapping.ignored.contains(field))
+                .map(|(field, value)| {
+                    let signal = mapping.signals.get(field).unwrap_or(field);
+                    (signal.clone(), *value)
+                })
+                .collect();
+
+            let step = names
+                .instance(&mapping.step_type, &assignments)
+                .map_err(|err| ExecTraceError::Witness { record: index, err })?;
+            witness.step_instances.push(step);
+        }
+
+        witness.fill_derived(circuit);
+
+        Ok(witness)
+    }
+}
+
+/// Parses the records of an execution trace in the text format of `wit_gen::exec_trace`.
+pub fn parse_records<F: PrimeField>(text: &str) -> Result<Vec<ExecRecord<F>>, ExecTraceError> {
+    let mut records = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let syntax = || ExecTraceError::Syntax {
+            line: index + 1,
+            content: line.to_string(),
+        };
+        let mut words = line.split_whitespace();
+        let mut record = ExecRecord::new(words.next().ok_or_else(syntax)?);
+        for word in words {
+            let (name, value) = word.split_once('=').ok_or_else(syntax)?;
+            let value = parse_integer(value).ok_or_else(syntax)?;
+
+            record.fields.push((name.to_string(), value));
+        }
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_exec_trace_witness() {
+        let sc = circuit::<Fr, (), (), _>("vm", |ctx| {
+            let pc = ctx.forward("pc");
+            let add = ctx.step_type("add");
+            let mul = ctx.step_type("mul");
+
+            ctx.step_type_def(add, |ctx| {
+                let a = ctx.internal("a");
+                let b = ctx.internal("b");
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + b, c));
+                    ctx.transition(eq(pc + 1, pc.next()));
+                });
+            });
+            ctx.step_type_def(mul, |ctx| {
+                let a = ctx.internal("a");
+                let b = ctx.internal("b");
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a * b, c)));
+            });
+        });
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let records: Vec<ExecRecord<Fr>> = parse_records(
+            "# a trace\n\
+             ADD pc=0 rs1=1 rs2=2 rd=3 cycle=7\n\
+             \n\
+             MUL rs1=3 rs2=0x10\n",
+        )
+        .unwrap();
+        assert_eq!(
+            records[1],
+            ExecRecord::new("MUL")
+                .with_field("rs1", 3.field())
+                .with_field("rs2", 16.field())
+        );
+
+        let mapping = RecordMapping::new()
+            .opcode("ADD", "add", &[("rs1", "a"), ("rs2", "b"), ("rd", "c")])
+            .opcode("MUL", "mul", &[("rs1", "a"), ("rs2", "b"), ("rd", "c")])
+            .ignore_fields("ADD", &["cycle"]);
+        let witness = mapping.witness(&compiled, &records).unwrap();
+
+        assert_eq!(witness.step_instances.len(), 2);
+        let mul_step = &witness.step_instances[1];
+        // c of the mul is derived from its constraint, pc from the transition of the add
+        assert_eq!(mul_step.get_by_annotation("c"), Some(&48.field()));
+        assert_eq!(mul_step.get_by_annotation("pc"), Some(&1.field()));
+
+        assert!(matches!(
+            mapping.witness(&compiled, &[ExecRecord::new("JMP")]),
+            Err(ExecTraceError::UnknownOpcode { record: 0, .. })
+        ));
+        assert!(matches!(
+            RecordMapping::new()
+                .opcode("ADD", "add", &[])
+                .witness(&compiled, &records[..1]),
+            Err(ExecTraceError::Witness {
+                record: 0,
+                err: ReplayError::UnknownSignal { .. }
+            })
+        ));
+        assert!(matches!(
+            parse_records::<Fr>("ADD rs1"),
+            Err(ExecTraceError::Syntax { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+
+use crate::ast::{query::Queriable, ForwardSignal};
+
+use super::StepInstance;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExternalColumnError {
+    /// An external signal of the circuit has no supplied column.
+    Missing { signal: String },
+    /// A column is supplied for a signal that is not an external signal of the circuit.
+    NotExternal { signal: String },
+    /// The column of a signal does not have a value for each step of the trace.
+    Length {
+        signal: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ExternalColumnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExternalColumnError::Missing { signal } => {
+                write!(f, "no column supplied for external signal {}", signal)
+            }
+            ExternalColumnError::NotExternal { signal } => write!(
+                f,
+                "column supplied for signal {}, which is not external",
+                signal
+            ),
+            ExternalColumnError::Length {
+                signal,
+                expected,
+                found,
+            } => write!(
+                f,
+                "column of external signal {} has {} values, the trace has {} steps",
+                signal, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExternalColumnError {}
+
+/// The values of the external signals of a circuit, declared with `forward_external`, one for
+/// each step of the trace in the order the steps are added. They are supplied by the caller of
+/// the witness generation, for example from another prover component or a database dump, and
+/// assigned to each step instance before its derived signals, see
+/// `GenericTraceContext::with_external_columns`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalColumns<F> {
+    columns: Vec<(ForwardSignal, Vec<F>)>,
+}
+
+impl<F> Default for ExternalColumns<F> {
+    fn default() -> Self {
+        Self {
+            columns: Vec::new(),
+        }
+    }
+}
+
+impl<F> ExternalColumns<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Supplies the values of `signal`, replacing the ones supplied before.
+    pub fn with_column(mut self, signal: Queriable<F>, values: Vec<F>) -> Self {
+        let signal = match signal {
+            Queriable::Forward(signal, false) => signal,
+            _ => panic!(
+                "external column supplied for {}, which is not a forward signal",
+                signal.annotation()
+            ),
+        };
+
+        self.columns.retain(|(other, _)| *other != signal);
+        self.columns.push((signal, values));
+
+        self
+    }
+
+    /// Checks that a column is supplied for each of the `external` signals of a circuit, and no
+    /// other. With `steps`, also checks that every column has a value for each step.
+    pub fn validate(
+        &self,
+        external: &[ForwardSignal],
+        steps: Option<usize>,
+    ) -> Result<(), ExternalColumnError> {
+        for signal in external.iter() {
+            if !self.columns.iter().any(|(other, _)| other == signal) {
+                return Err(ExternalColumnError::Missing {
+                    signal: annotation(signal),
+                });
+            }
+        }
+
+        for (signal, values) in self.columns.iter() {
+            if !external.contains(signal) {
+                return Err(ExternalColumnError::NotExternal {
+                    signal: annotation(signal),
+                });
+            }
+
+            match steps {
+                Some(steps) if values.len() != steps => {
+                    return Err(ExternalColumnError::Length {
+                        signal: annotation(signal),
+                        expected: steps,
+                        found: values.len(),
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: Copy + Eq + std::hash::Hash> ExternalColumns<F> {
+    /// Assigns the values of the step `index` in `witness`. A column too short for the step is
+    /// skipped, and reported by `validate` once the trace is complete.
+    pub(crate) fn assign(&self, index: usize, step_type: &str, witness: &mut StepInstance<F>) {
+        for (signal, values) in self.columns.iter() {
+            let queriable = Queriable::Forward(*signal, false);
+            if witness.assignments.contains_key(&queriable) {
+                panic!(
+                    "external signal {} assigned by the witness generation of step type {}",
+                    annotation(signal),
+                    step_type
+                );
+            }
+
+            if let Some(value) = values.get(index) {
+                witness.assignments.insert(queriable, *value);
+            }
+        }
+    }
+}
+
+fn annotation(signal: &ForwardSignal) -> String {
+    Queriable::<()>::Forward(*signal, false).annotation()
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_external_columns() {
+        let sc = circuit::<Fr, u64, u64, _>("external", |ctx| {
+            let a = ctx.forward("a");
+            let hash = ctx.forward_external("hash");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.constr(eq(b, a + hash)));
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+        let hash = Queriable::Forward(sc.external_signals[0], false);
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let chiquito = ChiquitoHalo2::new(compiled);
+
+        let columns = ExternalColumns::new().with_column(hash, vec![Fr::from(10), Fr::from(20)]);
+        let witness = chiquito
+            .witness_with_external(2, &columns)
+            .unwrap()
+            .unwrap();
+        let values: Vec<Vec<Fr>> = witness
+            .step_instances
+            .iter()
+            .map(|instance| {
+                let mut values: Vec<Fr> = instance.assignments.values().copied().collect();
+                values.sort_by_key(|value| format!("{:?}", value));
+                values
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                vec![Fr::from(0), Fr::from(10), Fr::from(10)],
+                vec![Fr::from(1), Fr::from(20), Fr::from(21)]
+            ]
+        );
+
+        assert_eq!(
+            chiquito.witness_with_external(3, &columns),
+            Err(ExternalColumnError::Length {
+                signal: "hash".to_string(),
+                expected: 3,
+                found: 2,
+            })
+        );
+        assert_eq!(
+            chiquito.witness_with_external(2, &ExternalColumns::new()),
+            Err(ExternalColumnError::Missing {
+                signal: "hash".to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::hash::Hash;
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::{ast::query::Queriable, ir::Circuit};
+
+use super::{eval_linear, TraceWitness};
+
+impl<F: Field + Hash> TraceWitness<F> {
+    /// Fills the signals left unassigned by the witness generation that the constraints of
+    /// `circuit` determine, for example `c` from `c == a + b` or `next(a)` from
+    /// `next(a) == a + 1`, so that the witness generation functions only assign the signals that
+    /// are not derived. Signals are solved one at a time from the constraints and transitions
+    /// linear in them whose other queries have values, until no more can be solved. Returns the
+    /// number of signals filled.
+    ///
+    /// The trace context already derives the internal signals of each step as it is added, this
+    /// pass also solves the forward signals, which can depend on the neighbouring steps. The
+    /// public values are not updated, recompute them with `public_inputs`.
+    pub fn fill_derived<TraceArgs, StepArgs>(
+        &mut self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+    ) -> usize {
+        let mut filled = 0;
+        let mut progress = true;
+        while progress {
+            progress = false;
+
+            for index in 0..self.step_instances.len() {
+                while let Some((step, signal, value)) = self.solve_one(circuit, index) {
+                    self.step_instances[step].assignments.insert(signal, value);
+                    filled += 1;
+                    progress = true;
+                }
+            }
+        }
+
+        filled
+    }
+
+    /// An unassigned signal of the step `index`, or of the next step for the forward signals
+    /// queried with rotation, solved from a constraint or transition of the step, with the step
+    /// where it is assigned and its value.
+    fn solve_one<TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        index: usize,
+    ) -> Option<(usize, Queriable<F>, F)> {
+        let instance = &self.step_instances[index];
+        let next = self.step_instances.get(index + 1);
+        let step = circuit
+            .step_types
+            .get(&instance.step_type_uuid)
+            .expect("step type not found");
+
+        // the step and the signal in it of a queriable
+        let locate = |queriable: &Queriable<F>| match *queriable {
+            Queriable::Internal(_) | Queriable::Forward(_, false) => Some((index, *queriable)),
+            Queriable::Forward(signal, true) => {
+                next.map(|_| (index + 1, Queriable::Forward(signal, false)))
+            }
+            _ => None,
+        };
+        let resolve = |queriable: &Queriable<F>| match *queriable {
+            Queriable::StepTypeNext(handler) => next.map(|next| {
+                if next.step_type_uuid == handler.uuid() {
+                    F::ONE
+                } else {
+                    F::ZERO
+                }
+            }),
+            _ => locate(queriable)
+                .and_then(|(step, signal)| self.step_instances[step].assignments.get(&signal))
+                .copied(),
+        };
+
+        let exprs = step
+            .constraints
+            .iter()
+            .map(|constraint| &constraint.expr)
+            .chain(
+                step.transition_constraints
+                    .iter()
+                    .map(|transition| &transition.expr),
+            );
+        for expr in exprs {
+            let mut unknowns: Vec<Queriable<F>> = Vec::new();
+            for queriable in expr.queriables() {
+                if locate(&queriable).is_some()
+                    && resolve(&queriable).is_none()
+                    && !unknowns.contains(&queriable)
+                {
+                    unknowns.push(queriable);
+                }
+            }
+
+            // every other query has a value when the expression is linear in the only unknown
+            if let [signal] = unknowns.as_slice() {
+                if let Some((constant, coefficient)) = eval_linear(expr, signal, &resolve) {
+                    if coefficient != F::ZERO {
+                        let (step, signal) = locate(signal)?;
+                        return Some((step, signal, -constant * coefficient.invert().unwrap()));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_fill_derived() {
+        let sc = circuit::<Fr, u64, u64, _>("filled", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + b, c));
+                    ctx.transition(eq(a + 1, a.next()));
+                });
+                // only the first step assigns a
+                ctx.wg(move |ctx, v| {
+                    if v == 0 {
+                        ctx.assign(a, 0.field());
+                    }
+                    ctx.assign(b, (v * 10).field());
+                });
+            });
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, 3);
+        let mut witness = ctx.get_witness();
+
+        // a and c of the last two steps
+        assert_eq!(witness.fill_derived(&compiled), 4);
+        let values: Vec<(Fr, Fr)> = witness
+            .step_instances
+            .iter()
+            .map(|step| {
+                (
+                    *step.get_by_annotation("a").unwrap(),
+                    *step.get_by_annotation("c").unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (0.field(), 0.field()),
+                (1.field(), 11.field()),
+                (2.field(), 22.field())
+            ]
+        );
+        assert_eq!(witness.fill_derived(&compiled), 0);
+    }
+}
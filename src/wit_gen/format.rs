@@ -0,0 +1,368 @@
+use std::{
+    cell::Cell,
+    collections::{BTreeSet, HashMap},
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::ast::StepType;
+
+use super::{StepInstance, TraceWitness};
+
+/// How field elements are printed by `TraceWitness` and `StepFailure`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldFormat {
+    Decimal,
+    Hex,
+    /// Values above half the modulus are printed as their negative, so that `-1` is printed as
+    /// `-1` instead of `p - 1`.
+    Signed,
+    /// Values that do not fit in this many bits are printed as the hex of their low bits after
+    /// `..`, to keep traces of hashes and other large values readable.
+    Truncated(u32),
+}
+
+impl Default for FieldFormat {
+    fn default() -> Self {
+        FieldFormat::Decimal
+    }
+}
+
+thread_local! {
+    static FIELD_FORMAT: Cell<FieldFormat> = Cell::new(FieldFormat::default());
+}
+
+/// Sets the format used to print field elements in the current thread.
+pub fn set_field_format(format: FieldFormat) {
+    FIELD_FORMAT.with(|current| current.set(format));
+}
+
+pub fn field_format() -> FieldFormat {
+    FIELD_FORMAT.with(|current| current.get())
+}
+
+impl FieldFormat {
+    pub fn format<F: PrimeField>(&self, value: &F) -> String {
+        match self {
+            FieldFormat::Decimal => decimal(&big_endian(value)),
+            FieldFormat::Hex => hex(&big_endian(value)),
+            FieldFormat::Signed => {
+                let positive = big_endian(value);
+                let negative = big_endian(&-*value);
+
+                if negative < positive {
+                    format!("-{}", decimal(&negative))
+                } else {
+                    decimal(&positive)
+                }
+            }
+            FieldFormat::Truncated(bits) => {
+                let bytes = big_endian(value);
+                let bits = *bits as usize;
+                let low_bytes = (bits + 7) / 8;
+
+                let mut low = bytes[bytes.len().saturating_sub(low_bytes)..].to_vec();
+                if let Some(first) = low.first_mut() {
+                    if bits % 8 != 0 {
+                        *first &= (1u8 << (bits % 8)) - 1;
+                    }
+                }
+
+                if bytes[..bytes.len() - low.len()]
+                    .iter()
+                    .all(|byte| *byte == 0)
+                    && low == bytes[bytes.len() - low.len()..]
+                {
+                    decimal(&bytes)
+                } else {
+                    format!("..{}", &hex(&low)[2..])
+                }
+            }
+        }
+    }
+}
+
+/// Formats a field element with the format of the current thread, see `set_field_format`.
+pub fn format_field<F: PrimeField>(value: &F) -> String {
+    field_format().format(value)
+}
+
+fn big_endian<F: PrimeField>(value: &F) -> Vec<u8> {
+    value.to_repr().as_ref().iter().rev().cloned().collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let digits: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let digits = digits.trim_start_matches('0');
+
+    if digits.is_empty() {
+        "0x0".to_string()
+    } else {
+        format!("0x{}", digits)
+    }
+}
+
+fn decimal(bytes: &[u8]) -> String {
+    let mut number = bytes.to_vec();
+    let mut digits = Vec::new();
+
+    while number.iter().any(|byte| *byte != 0) {
+        let mut remainder = 0u32;
+        for byte in number.iter_mut() {
+            let current = (remainder << 8) | *byte as u32;
+            *byte = (current / 10) as u8;
+            remainder = current % 10;
+        }
+        digits.push(char::from(b'0' + remainder as u8));
+    }
+
+    if digits.is_empty() {
+        "0".to_string()
+    } else {
+        digits.iter().rev().collect()
+    }
+}
+
+impl<F: PrimeField> StepInstance<F> {
+    /// The assignments formatted, sorted by signal annotation so that the output does not depend
+    /// on the order of the hash map.
+    fn sorted_assignments(&self) -> Vec<(String, String)> {
+        let mut assignments: Vec<(String, String)> = self
+            .assignments
+            .iter()
+            .map(|(signal, value)| (signal.annotation(), format_field(value)))
+            .collect();
+        assignments.sort();
+
+        assignments
+    }
+
+    fn fmt_assignments(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (signal, value) in self.sorted_assignments() {
+            write!(f, " {} = {}", signal, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: PrimeField> fmt::Display for StepInstance<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step type {}:", self.step_type_uuid)?;
+        self.fmt_assignments(f)
+    }
+}
+
+impl<F: PrimeField> fmt::Display for TraceWitness<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "height {}", self.height)?;
+        for (step, instance) in self.step_instances.iter().enumerate() {
+            writeln!(f, "{}: {}", step, instance)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<F: PrimeField> TraceWitness<F> {
+    /// Writes the witness as CSV, one row per step with one column per signal, headed by the
+    /// signal annotations in order. Values are in decimal, and signals not assigned in a step are
+    /// empty. For one row per assignment, see `to_columns`.
+    pub fn to_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let signals: BTreeSet<String> = self
+            .step_instances
+            .iter()
+            .flat_map(|instance| {
+                instance
+                    .assignments
+                    .keys()
+                    .map(|signal| signal.annotation())
+            })
+            .collect();
+
+        write!(writer, "step")?;
+        for signal in signals.iter() {
+            write!(writer, ",{}", csv_field(signal))?;
+        }
+        writeln!(writer)?;
+
+        for (step, instance) in self.step_instances.iter().enumerate() {
+            let values: HashMap<String, &F> = instance
+                .assignments
+                .iter()
+                .map(|(signal, value)| (signal.annotation(), value))
+                .collect();
+
+            write!(writer, "{}", step)?;
+            for signal in signals.iter() {
+                match values.get(signal) {
+                    Some(value) => write!(writer, ",{}", FieldFormat::Decimal.format(*value))?,
+                    None => write!(writer, ",")?,
+                }
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl<F> TraceWitness<F> {
+    /// Displays the witness with the name of the step type of each step instead of its uuid, so
+    /// that the output can be compared across compilations of the circuit, for snapshot tests.
+    pub fn named<'a, StepArgs>(
+        &'a self,
+        step_types: &'a HashMap<u32, Rc<StepType<F, StepArgs>>>,
+    ) -> NamedTraceWitness<'a, F, StepArgs> {
+        NamedTraceWitness {
+            witness: self,
+            step_types,
+        }
+    }
+}
+
+/// A `TraceWitness` displayed with step type names, see `TraceWitness::named`.
+pub struct NamedTraceWitness<'a, F, StepArgs> {
+    witness: &'a TraceWitness<F>,
+    step_types: &'a HashMap<u32, Rc<StepType<F, StepArgs>>>,
+}
+
+impl<'a, F: PrimeField, StepArgs> fmt::Display for NamedTraceWitness<'a, F, StepArgs> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "height {}", self.witness.height)?;
+        for (step, instance) in self.witness.step_instances.iter().enumerate() {
+            match self.step_types.get(&instance.step_type_uuid) {
+                Some(step_type) => write!(f, "{}: {}:", step, step_type.name)?,
+                None => write!(f, "{}: step type {}:", step, instance.step_type_uuid)?,
+            }
+            instance.fmt_assignments(f)?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_field_format() {
+        let value = Fr::from(300);
+        let minus_one = -Fr::from(1);
+
+        assert_eq!(FieldFormat::Decimal.format(&value), "300");
+        assert_eq!(FieldFormat::Decimal.format(&Fr::from(0)), "0");
+        assert_eq!(FieldFormat::Hex.format(&value), "0x12c");
+        assert_eq!(FieldFormat::Hex.format(&Fr::from(0)), "0x0");
+        assert_eq!(FieldFormat::Signed.format(&minus_one), "-1");
+        assert_eq!(FieldFormat::Signed.format(&value), "300");
+        assert_eq!(FieldFormat::Truncated(16).format(&value), "300");
+        assert_eq!(FieldFormat::Truncated(4).format(&value), "..c");
+
+        set_field_format(FieldFormat::Hex);
+        assert_eq!(format_field(&value), "0x12c");
+        set_field_format(FieldFormat::default());
+    }
+
+    #[test]
+    fn test_named_display() {
+        let sc = circuit::<Fr, u64, u64, _>("named", |ctx| {
+            let z = ctx.forward("z");
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let m = ctx.internal("m");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(z, v.field());
+                    ctx.assign(m, (v * 2).field());
+                    ctx.assign(a, (v + 1).field());
+                })
+            });
+
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let witness = || {
+            let mut ctx = GenericTraceContext::new(&compiled.step_types);
+            (compiled.trace.as_ref().unwrap())(&mut ctx, 2);
+
+            ctx.get_witness()
+        };
+
+        let first = witness();
+        let output = first.named(&compiled.step_types).to_string();
+        assert_eq!(
+            output,
+            "height 0\n0: step: a = 1 m = 0 z = 0\n1: step: a = 2 m = 2 z = 1\n"
+        );
+        for _ in 0..4 {
+            assert_eq!(witness().named(&compiled.step_types).to_string(), output);
+        }
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let sc = circuit::<Fr, (), u64, _>("csv", |ctx| {
+            let a = ctx.forward("a");
+            let first = ctx.step_type("first");
+            let second = ctx.step_type("second");
+
+            ctx.step_type_def(first, |ctx| {
+                let b = ctx.internal("b, c");
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v + 1).field());
+                })
+            });
+            ctx.step_type_def(second, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&first, 1);
+                ctx.add(&second, 5);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+
+        let mut csv = Vec::new();
+        ctx.get_witness().to_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "step,a,\"b, c\"\n0,1,2\n1,5,\n"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Debug},
+    hash::Hash,
+};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::{
+    ast::{Circuit, Expr, StepTypeUUID},
+    ci::catch,
+    compiler::config::CompilerConfig,
+    ir,
+};
+
+use super::{
+    step_test::{test_step, StepFailure},
+    GenericTraceContext, TraceOptions, TraceWitness,
+};
+
+/// Pseudo random numbers for the mutators of a `Fuzzer`, a xorshift generator so that a fuzzing
+/// run is reproducible from its seed.
+#[derive(Clone, Debug)]
+pub struct FuzzRng {
+    state: u64,
+}
+
+impl FuzzRng {
+    pub fn new(seed: u64) -> Self {
+        FuzzRng { state: seed.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        self.state
+    }
+
+    /// A number in `0..n`, `n` must not be zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+/// What a witness exercised, the coverage signal of the fuzzer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Feature {
+    Step(StepTypeUUID),
+    Transition(StepTypeUUID, StepTypeUUID),
+    /// The factors of a constraint that are zero in a step, as a bit mask. A constraint `cond *
+    /// expr` is active in the steps where `cond` is not zero, so the masks are the branches that
+    /// the witness took.
+    Branch(StepTypeUUID, usize, u32),
+}
+
+/// A panic or an unsatisfied constraint found by the fuzzer, with the trace args that cause it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuzzFailure {
+    pub args: String,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuzzReport {
+    pub iterations: usize,
+    pub corpus: usize,
+    /// Number of distinct steps, step transitions and constraint branches covered.
+    pub coverage: usize,
+    pub failures: Vec<FuzzFailure>,
+}
+
+impl fmt::Display for FuzzReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} iterations, {} inputs in corpus, {} features covered",
+            self.iterations, self.corpus, self.coverage
+        )?;
+        for failure in self.failures.iter() {
+            write!(f, "\nFAILED {} with args {}", failure.message, failure.args)?;
+        }
+
+        Ok(())
+    }
+}
+
+type Mutator<'a, TraceArgs> = Box<dyn Fn(&TraceArgs, &mut FuzzRng) -> TraceArgs + 'a>;
+
+/// Coverage guided fuzzer of the trace args of a circuit. Each iteration mutates an input of the
+/// corpus, runs the trace and checks the constraints of every step of the witness. Inputs that
+/// cover a step type, a transition between step types or a branch of a constraint not seen before
+/// are added to the corpus, so that rare step sequences are mutated further.
+///
+/// Panics of the compiler and of the witness generation, and constraints that the witness does
+/// not satisfy, are reported as failures.
+pub struct Fuzzer<'a, F, TraceArgs, StepArgs> {
+    circuit: &'a Circuit<F, TraceArgs, StepArgs>,
+    mutate: Mutator<'a, TraceArgs>,
+    rng: FuzzRng,
+
+    corpus: Vec<TraceArgs>,
+    coverage: HashSet<Feature>,
+    failures: Vec<FuzzFailure>,
+    iterations: usize,
+}
+
+impl<'a, F, TraceArgs, StepArgs> Fuzzer<'a, F, TraceArgs, StepArgs>
+where
+    F: Field + Hash,
+    TraceArgs: Clone + Debug,
+    StepArgs: Clone,
+{
+    /// A fuzzer of `circuit` starting from the `seeds` inputs, which must not be empty.
+    pub fn new<M>(
+        circuit: &'a Circuit<F, TraceArgs, StepArgs>,
+        seeds: Vec<TraceArgs>,
+        mutate: M,
+    ) -> Self
+    where
+        M: Fn(&TraceArgs, &mut FuzzRng) -> TraceArgs + 'a,
+    {
+        if seeds.is_empty() {
+            panic!("fuzzer needs at least one seed input");
+        }
+
+        Fuzzer {
+            circuit,
+            mutate: Box::new(mutate),
+            rng: FuzzRng::new(0x5eed),
+            corpus: seeds,
+            coverage: HashSet::new(),
+            failures: Vec::new(),
+            iterations: 0,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = FuzzRng::new(seed);
+
+        self
+    }
+
+    /// Runs the seed inputs the first time, and then `iterations` mutated inputs.
+    pub fn run(&mut self, iterations: usize) -> FuzzReport {
+        let compiled = match catch(|| CompilerConfig::default().compile(self.circuit)) {
+            Ok(compiled) => compiled,
+            Err(message) => {
+                self.fail("<none>".to_string(), format!("compilation: {}", message));
+                return self.report();
+            }
+        };
+
+        if self.iterations == 0 {
+            for seed in self.corpus.clone() {
+                self.execute(&compiled, seed);
+            }
+        }
+
+        for _ in 0..iterations {
+            let parent = &self.corpus[self.rng.below(self.corpus.len() as u64) as usize];
+            let args = (self.mutate)(parent, &mut self.rng);
+
+            if self.execute(&compiled, args.clone()) {
+                self.corpus.push(args);
+            }
+            self.iterations += 1;
+        }
+
+        self.report()
+    }
+
+    /// The inputs that covered something new, starting with the seeds.
+    pub fn corpus(&self) -> &[TraceArgs] {
+        &self.corpus
+    }
+
+    fn report(&self) -> FuzzReport {
+        FuzzReport {
+            iterations: self.iterations,
+            corpus: self.corpus.len(),
+            coverage: self.coverage.len(),
+            failures: self.failures.clone(),
+        }
+    }
+
+    /// Runs the trace with `args`, returning whether it covered a new feature.
+    fn execute(&mut self, compiled: &ir::Circuit<F, TraceArgs, StepArgs>, args: TraceArgs) -> bool {
+        let trace = match compiled.trace.as_ref() {
+            Some(trace) => trace,
+            None => return false,
+        };
+
+        let described = format!("{:?}", args);
+        let witness = catch(|| {
+            let mut ctx = GenericTraceContext::for_circuit(compiled, TraceOptions::default());
+            trace(&mut ctx, args);
+
+            ctx.get_witness()
+        });
+
+        match witness {
+            Ok(witness) => {
+                let mut new = false;
+                for feature in self.features(compiled, &witness, &described) {
+                    new |= self.coverage.insert(feature);
+                }
+
+                new
+            }
+            Err(message) => {
+                self.fail(described, format!("witness generation: {}", message));
+
+                false
+            }
+        }
+    }
+
+    fn features(
+        &mut self,
+        compiled: &ir::Circuit<F, TraceArgs, StepArgs>,
+        witness: &TraceWitness<F>,
+        args: &str,
+    ) -> Vec<Feature> {
+        let mut features = Vec::new();
+        let instances = &witness.step_instances;
+
+        for (i, instance) in instances.iter().enumerate() {
+            let uuid = instance.step_type_uuid;
+            let step = &compiled.step_types[&uuid];
+            let next = instances.get(i + 1);
+
+            features.push(Feature::Step(uuid));
+            if let Some(next) = next {
+                features.push(Feature::Transition(uuid, next.step_type_uuid));
+            }
+
+            let resolve = |queriable: &_| instance.assignments.get(queriable).copied();
+            for (index, constraint) in step.constraints.iter().enumerate() {
+                if let Expr::Mul(factors) = &constraint.expr {
+                    let mask =
+                        factors
+                            .iter()
+                            .take(32)
+                            .enumerate()
+                            .fold(0, |mask, (bit, factor)| match factor.eval(&resolve) {
+                                Ok(value) if value == F::ZERO => mask | 1 << bit,
+                                _ => mask,
+                            });
+                    features.push(Feature::Branch(uuid, index, mask));
+                }
+            }
+
+            for failure in test_step(step, &instance.assignments, next) {
+                if let StepFailure::NotSatisfied { constraint, .. } = failure {
+                    self.fail(
+                        args.to_string(),
+                        format!(
+                            "constraint {} of step type {} not satisfied",
+                            constraint, step.name
+                        ),
+                    );
+                }
+            }
+        }
+
+        features
+    }
+
+    /// Records a failure, once for each message.
+    fn fail(&mut self, args: String, message: String) {
+        if !self
+            .failures
+            .iter()
+            .any(|failure| failure.message == message)
+        {
+            self.failures.push(FuzzFailure { args, message });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_fuzzer() {
+        let sc = circuit::<Fr, u64, u64, _>("fuzzed", |ctx| {
+            let a = ctx.forward("a");
+            let common = ctx.step_type("common");
+            let rare = ctx.step_type("rare");
+
+            ctx.step_type_def(common, |ctx| {
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.step_type_def(rare, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a * 2, b)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    // wrong for odd values
+                    ctx.assign(b, (v / 2 * 4).field());
+                });
+            });
+
+            ctx.trace(move |ctx, v| {
+                ctx.add(&common, v);
+                if (500..600).contains(&v) {
+                    ctx.add(&rare, v);
+                }
+                if v == 1023 {
+                    panic!("unexpected input");
+                }
+            });
+        });
+
+        let mut fuzzer = Fuzzer::new(&sc, vec![0], |_, rng| rng.below(1024));
+        let report = fuzzer.run(2000);
+
+        assert_eq!(report.iterations, 2000);
+        assert!(report.corpus > 1, "{}", report);
+        assert!(report
+            .failures
+            .iter()
+            .any(|failure| failure.message.ends_with("of step type rare not satisfied")));
+        assert!(report.failures.iter().any(|failure| failure.message
+            == "witness generation: unexpected input"
+            && failure.args == "1023"));
+    }
+}
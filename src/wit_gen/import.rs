@@ -0,0 +1,486 @@
+//! Witnesses produced outside of chiquito, for example by a witness generator written in another
+//! language, in this JSON document:
+//!
+//! ```json
+//! {
+//!   "height": 8,
+//!   "steps": [
+//!     { "step_type": "fibo_step", "assignments": { "a": 1, "b": "1", "c": "0x02" } },
+//!     { "step_type": "fibo_last_step", "assignments": { "a": "1", "next(a)": "2" } }
+//!   ]
+//! }
+//! ```
+//!
+//! `height` is optional, and when it is zero the backend uses the rows of the steps. Step types
+//! and signals are given by their names, which must be unique: the internal signals of the step
+//! type, and the forward signals by their name, or `next(name)` in the next step. Values are
+//! JSON integers or strings with a decimal or `0x` hexadecimal integer, which must be lower than
+//! the modulus of the field. Unknown and repeated keys are errors, so that a typo is not silently
+//! ignored, and so are documents nested deeper than `MAX_DEPTH`.
+
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::ir::Circuit;
+
+use super::{
+    public_inputs::{decode_public_inputs, WORD_SIZE},
+    replay::{named_witness, ReplayError},
+    TraceWitness,
+};
+
+/// Maximum nesting of the arrays and objects of an imported document, which is parsed
+/// recursively.
+pub const MAX_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum ImportError {
+    /// The document is not valid JSON, at the given byte offset.
+    Syntax(usize),
+    /// The document nests arrays and objects deeper than `MAX_DEPTH`, at the given byte offset.
+    Depth(usize),
+    /// The document is valid JSON but does not follow the schema.
+    Schema(String),
+    Value {
+        step: usize,
+        signal: String,
+    },
+    Witness(ReplayError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Syntax(offset) => write!(f, "invalid JSON at byte {}", offset),
+            ImportError::Depth(offset) => write!(
+                f,
+                "JSON nested deeper than {} levels at byte {}",
+                MAX_DEPTH, offset
+            ),
+            ImportError::Schema(message) => write!(f, "invalid witness document: {}", message),
+            ImportError::Value { step, signal } => {
+                write!(f, "invalid value of signal {} in step {}", signal, step)
+            }
+            ImportError::Witness(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl<F: PrimeField + Hash> TraceWitness<F> {
+    /// Parses a witness in the JSON document described in `wit_gen::import` for `circuit`.
+    pub fn from_json<TraceArgs, StepArgs>(
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        json: &str,
+    ) -> Result<Self, ImportError> {
+        let document = Parser::new(json).document()?;
+        let schema = |message: &str| ImportError::Schema(message.to_string());
+
+        let mut height = 0;
+        let mut steps = Vec::new();
+        for (key, value) in document.object().ok_or_else(|| schema("not an object"))? {
+            match (key.as_str(), value) {
+                ("height", Json::Number(number)) => {
+                    height = number.parse().map_err(|_| schema("invalid height"))?
+                }
+                ("steps", Json::Array(values)) => steps = values.iter().collect(),
+                (key, _) => return Err(ImportError::Schema(format!("unexpected key {}", key))),
+            }
+        }
+
+        let steps = steps
+            .into_iter()
+            .enumerate()
+            .map(|(index, step)| parse_step(index, step))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        named_witness(circuit, height, &steps).map_err(ImportError::Witness)
+    }
+}
+
+fn parse_step<F: PrimeField>(
+    index: usize,
+    step: &Json,
+) -> Result<(String, Vec<(String, F)>), ImportError> {
+    let schema = |message: &str| ImportError::Schema(format!("step {} {}", index, message));
+
+    let mut step_type = None;
+    let mut assignments = Vec::new();
+    for (key, value) in step.object().ok_or_else(|| schema("is not an object"))? {
+        match (key.as_str(), value) {
+            ("step_type", Json::String(name)) => step_type = Some(name.clone()),
+            ("assignments", Json::Object(values)) => {
+                for (signal, value) in values {
+                    let parsed = match value {
+                        Json::Number(number) | Json::String(number) => parse_integer(number),
+                        _ => None,
+                    };
+                    let value = parsed.ok_or_else(|| ImportError::Value {
+                        step: index,
+                        signal: signal.clone(),
+                    })?;
+
+                    assignments.push((signal.clone(), value));
+                }
+            }
+            (key, _) => return Err(schema(&format!("has unexpected key {}", key))),
+        }
+    }
+
+    Ok((
+        step_type.ok_or_else(|| schema("has no step_type"))?,
+        assignments,
+    ))
+}
+
+/// Parses a decimal or `0x` hexadecimal integer lower than the modulus.
+pub(crate) fn parse_integer<F: PrimeField>(value: &str) -> Option<F> {
+    let (digits, radix) = match value.strip_prefix("0x") {
+        Some(hex) => (hex, 16),
+        None => (value, 10),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+
+    // big-endian word, multiplied by the radix for every digit
+    let mut word = [0u8; WORD_SIZE];
+    for digit in digits.chars() {
+        let mut carry = digit.to_digit(radix)?;
+        for byte in word.iter_mut().rev() {
+            let value = *byte as u32 * radix + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+
+    decode_public_inputs(&word).ok()?.pop()
+}
+
+/// JSON value, with numbers kept as their text.
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(entries) => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    offset: usize,
+    /// Arrays and objects being parsed.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            offset: 0,
+            depth: 0,
+        }
+    }
+
+    fn document(mut self) -> Result<Json, ImportError> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.offset < self.source.len() {
+            return Err(ImportError::Syntax(self.offset));
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, ImportError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{' | '[') if self.depth == MAX_DEPTH => Err(ImportError::Depth(self.offset)),
+            Some('{') => self.nested(Self::object),
+            Some('[') => self.nested(Self::array),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => Ok(Json::Number(self.number())),
+            _ if self.keyword("true") => Ok(Json::Bool(true)),
+            _ if self.keyword("false") => Ok(Json::Bool(false)),
+            _ if self.keyword("null") => Ok(Json::Null),
+            _ => Err(ImportError::Syntax(self.offset)),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Json, ImportError>,
+    ) -> Result<Json, ImportError> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn object(&mut self) -> Result<Json, ImportError> {
+        let mut entries = Vec::new();
+        self.expect('{')?;
+
+        self.skip_whitespace();
+        if self.eat('}') {
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            if entries.iter().any(|(other, _)| *other == key) {
+                return Err(ImportError::Schema(format!("repeated key {}", key)));
+            }
+            self.skip_whitespace();
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+
+            self.skip_whitespace();
+            if self.eat('}') {
+                return Ok(Json::Object(entries));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, ImportError> {
+        let mut values = Vec::new();
+        self.expect('[')?;
+
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+
+            self.skip_whitespace();
+            if self.eat(']') {
+                return Ok(Json::Array(values));
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ImportError> {
+        let mut value = String::new();
+        self.expect('"')?;
+
+        loop {
+            let c = self.next().ok_or(ImportError::Syntax(self.offset))?;
+            match c {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hex = self
+                                .source
+                                .get(self.offset..self.offset + 4)
+                                .ok_or(ImportError::Syntax(self.offset))?;
+                            self.offset += 4;
+                            u32::from_str_radix(hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or(ImportError::Syntax(self.offset))?
+                        }
+                        _ => return Err(ImportError::Syntax(self.offset)),
+                    };
+                    value.push(escaped);
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> String {
+        let start = self.offset;
+        while let Some('-' | '+' | '.' | 'e' | 'E' | '0'..='9') = self.peek() {
+            self.offset += 1;
+        }
+
+        self.source[start..self.offset].to_string()
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.source[self.offset..].starts_with(keyword) {
+            self.offset += keyword.len();
+            return true;
+        }
+
+        false
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ImportError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(ImportError::Syntax(self.offset))
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.offset += c.len_utf8();
+            return true;
+        }
+
+        false
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+
+        Some(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::{query::Queriable, ToField},
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        wit_gen::{public_inputs::encode_public_inputs, GenericTraceContext},
+    };
+
+    #[test]
+    fn test_from_json() {
+        let sc = circuit::<Fr, (), u64, _>("imported", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                    ctx.assign(a.next(), (v + 1).field());
+                })
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 1);
+                ctx.add(&step, 2);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let imported = TraceWitness::<Fr>::from_json(
+            &compiled,
+            r#"{
+                "steps": [
+                    {"step_type": "step", "assignments": {"a": 1, "b": "2", "next(a)": "0x2"}},
+                    {"step_type": "step", "assignments": {"a": "2", "b": "0x04", "next(a)": 3}}
+                ]
+            }"#,
+        )
+        .expect("should import");
+        assert_eq!(imported, witness);
+
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+        let minus_one: String = encode_public_inputs(&[-Fr::from(1)])
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let imported = TraceWitness::<Fr>::from_json(
+            &compiled,
+            &format!(
+                r#"{{"steps": [{{"step_type": "step", "assignments": {{"a": "0x{}"}}}}]}}"#,
+                minus_one
+            ),
+        )
+        .expect("should import");
+        assert_eq!(imported.step_instances[0].assignments[&a], -Fr::from(1));
+
+        let error = |json| TraceWitness::<Fr>::from_json(&compiled, json).unwrap_err();
+        assert!(matches!(error(r#"{"steps": [}"#), ImportError::Syntax(11)));
+        assert!(matches!(
+            error(r#"{"step": []}"#),
+            ImportError::Schema(message) if message == "unexpected key step"
+        ));
+        assert!(matches!(
+            error(r#"{"steps": [{"step_type": "step", "assignments": {"a": "0x10000000000000000000000000000000000000000000000000000000000000000"}}]}"#),
+            ImportError::Value { step: 0, signal } if signal == "a"
+        ));
+        assert!(matches!(
+            error(r#"{"steps": [{"step_type": "step", "assignments": {"c": 1}}]}"#),
+            ImportError::Witness(ReplayError::UnknownSignal { .. })
+        ));
+        assert!(matches!(
+            error(r#"{"steps": [{"step_type": "step"}], "steps": []}"#),
+            ImportError::Schema(message) if message == "repeated key steps"
+        ));
+        let deep = format!(r#"{{"steps":{}"#, "[".repeat(1_000_000));
+        assert!(matches!(error(&deep), ImportError::Depth(_)));
+        let nested = format!(
+            r#"{{"steps":{}{}}}"#,
+            "[".repeat(MAX_DEPTH - 1),
+            "]".repeat(MAX_DEPTH - 1)
+        );
+        assert!(matches!(error(&nested), ImportError::Schema(_)));
+    }
+
+    #[test]
+    fn test_from_json_ambiguous_step_type() {
+        let sc = circuit::<Fr, (), u64, _>("imported", |ctx| {
+            let first = ctx.step_type("step");
+            let second = ctx.step_type("step");
+
+            ctx.step_type_def(first, |_| {});
+            ctx.step_type_def(second, |_| {});
+        });
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        assert!(matches!(
+            TraceWitness::<Fr>::from_json(&compiled, r#"{"steps": [{"step_type": "step"}]}"#),
+            Err(ImportError::Witness(ReplayError::AmbiguousStepType(name))) if name == "step"
+        ));
+    }
+}
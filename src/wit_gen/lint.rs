@@ -0,0 +1,459 @@
+use std::{collections::HashMap, fmt, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::{query::Queriable, Circuit},
+    dsl::StepTypeHandler,
+};
+
+use super::TraceWitness;
+
+/// A suspicious pattern found in a trace witness.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lint {
+    /// The signal is assigned zero in every step where it is assigned.
+    ZeroSignal { signal: String },
+    /// The signal is never assigned in any instance of the step type.
+    UnassignedSignal { step_type: String, signal: String },
+    /// The value assigned to the signal does not fit in its declared bit-width.
+    BitWidthExceeded {
+        step: usize,
+        signal: String,
+        bits: u32,
+    },
+    /// The step type at this step breaks a declared ordering.
+    StepOrder {
+        step: usize,
+        step_type: String,
+        expected: Vec<String>,
+    },
+    /// A padding step changes a signal that padding must keep, see
+    /// `Circuit::padding_protected_signals`.
+    PaddingChange { step: usize, signal: String },
+    /// The signal is used as a condition by the constraint but is not constrained to be boolean
+    /// in the step type, so the condition can be any value. Reported for the circuit whatever
+    /// the witness.
+    UnassertedCondition {
+        step_type: String,
+        signal: String,
+        constraint: String,
+    },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::ZeroSignal { signal } => write!(f, "signal {} is always zero", signal),
+            Lint::UnassignedSignal { step_type, signal } => write!(
+                f,
+                "signal {} is never assigned in step type {}",
+                signal, step_type
+            ),
+            Lint::BitWidthExceeded { step, signal, bits } => write!(
+                f,
+                "step {}: signal {} does not fit in {} bits",
+                step, signal, bits
+            ),
+            Lint::StepOrder {
+                step,
+                step_type,
+                expected,
+            } => write!(
+                f,
+                "step {}: found step type {}, expected one of [{}]",
+                step,
+                step_type,
+                expected.join(", ")
+            ),
+            Lint::PaddingChange { step, signal } => {
+                write!(f, "step {}: padding changes signal {}", step, signal)
+            }
+            Lint::UnassertedCondition {
+                step_type,
+                signal,
+                constraint,
+            } => write!(
+                f,
+                "signal {} is used as a condition in step type {} but is not constrained to be \
+                 boolean, consider is_binary (constraint: {})",
+                signal, step_type, constraint
+            ),
+        }
+    }
+}
+
+/// Checks a trace witness for patterns that usually point to a bug in the witness generation,
+/// before running the prover. Orderings declared with `pragma_first_step` and `pragma_last_step`
+/// are always checked, and so are the bit-widths of signals declared with `forward_width`. Other
+/// bit-widths and allowed step transitions can be declared on the linter.
+pub struct WitnessLinter<'a, F, TraceArgs, StepArgs> {
+    circuit: &'a Circuit<F, TraceArgs, StepArgs>,
+    bit_widths: HashMap<Queriable<F>, u32>,
+    transitions: HashMap<u32, Vec<StepTypeHandler>>,
+}
+
+impl<'a, F: PrimeField + Hash, TraceArgs, StepArgs> WitnessLinter<'a, F, TraceArgs, StepArgs> {
+    pub fn new(circuit: &'a Circuit<F, TraceArgs, StepArgs>) -> Self {
+        Self {
+            circuit,
+            bit_widths: circuit
+                .signal_widths
+                .iter()
+                .map(|width| (Queriable::Forward(width.signal, false), width.bits))
+                .collect(),
+            transitions: HashMap::default(),
+        }
+    }
+
+    /// Declares that the values of `signal` fit in `bits` bits.
+    pub fn bit_width(mut self, signal: Queriable<F>, bits: u32) -> Self {
+        self.bit_widths.insert(signal, bits);
+        self
+    }
+
+    /// Declares that a step of type `from` can be followed by a step of type `to`. Once a step
+    /// type has allowed transitions, any other following step type is reported.
+    pub fn allow_transition(mut self, from: StepTypeHandler, to: StepTypeHandler) -> Self {
+        self.transitions.entry(from.uuid()).or_default().push(to);
+        self
+    }
+
+    pub fn lint(&self, witness: &TraceWitness<F>) -> Vec<Lint> {
+        let mut lints = Vec::new();
+
+        self.lint_zero_signals(witness, &mut lints);
+        self.lint_unassigned_signals(witness, &mut lints);
+        self.lint_bit_widths(witness, &mut lints);
+        self.lint_step_order(witness, &mut lints);
+        self.lint_padding(witness, &mut lints);
+        self.lint_unasserted_conditions(&mut lints);
+
+        lints
+    }
+
+    fn lint_zero_signals(&self, witness: &TraceWitness<F>, lints: &mut Vec<Lint>) {
+        let mut all_zero: HashMap<Queriable<F>, bool> = HashMap::default();
+        for instance in witness.step_instances.iter() {
+            for (signal, value) in instance.assignments.iter() {
+                *all_zero.entry(*signal).or_insert(true) &= *value == F::ZERO;
+            }
+        }
+
+        let mut zero_signals: Vec<String> = all_zero
+            .into_iter()
+            .filter(|(_, zero)| *zero)
+            .map(|(signal, _)| signal.annotation())
+            .collect();
+        zero_signals.sort();
+
+        lints.extend(
+            zero_signals
+                .into_iter()
+                .map(|signal| Lint::ZeroSignal { signal }),
+        );
+    }
+
+    fn lint_unassigned_signals(&self, witness: &TraceWitness<F>, lints: &mut Vec<Lint>) {
+        let mut step_types: Vec<_> = self.circuit.step_types.values().collect();
+        step_types.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for step in step_types {
+            let instances: Vec<_> = witness
+                .step_instances
+                .iter()
+                .filter(|instance| instance.step_type_uuid == step.uuid())
+                .collect();
+
+            if instances.is_empty() {
+                continue;
+            }
+
+            for signal in step.signals.iter() {
+                let signal = Queriable::Internal(*signal);
+                if !instances
+                    .iter()
+                    .any(|instance| instance.assignments.contains_key(&signal))
+                {
+                    lints.push(Lint::UnassignedSignal {
+                        step_type: step.name.clone(),
+                        signal: signal.annotation(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn lint_unasserted_conditions(&self, lints: &mut Vec<Lint>) {
+        let mut step_types: Vec<_> = self.circuit.step_types.values().collect();
+        step_types.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for step in step_types {
+            lints.extend(
+                step.unasserted_conditions
+                    .iter()
+                    .map(|(signal, constraint)| Lint::UnassertedCondition {
+                        step_type: step.name.clone(),
+                        signal: signal.annotation(),
+                        constraint: constraint.clone(),
+                    }),
+            );
+        }
+    }
+
+    fn lint_bit_widths(&self, witness: &TraceWitness<F>, lints: &mut Vec<Lint>) {
+        for (step, instance) in witness.step_instances.iter().enumerate() {
+            let mut exceeded: Vec<Lint> = instance
+                .assignments
+                .iter()
+                .filter_map(|(signal, value)| match self.bit_widths.get(signal) {
+                    Some(bits) if !fits_in_bits(value, *bits) => Some(Lint::BitWidthExceeded {
+                        step,
+                        signal: signal.annotation(),
+                        bits: *bits,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            exceeded.sort_by_key(|lint| lint.to_string());
+
+            lints.extend(exceeded);
+        }
+    }
+
+    fn lint_step_order(&self, witness: &TraceWitness<F>, lints: &mut Vec<Lint>) {
+        let step_type_name = |uuid: u32| {
+            self.circuit
+                .step_types
+                .get(&uuid)
+                .map(|step| step.name.clone())
+                .unwrap_or_else(|| "??".to_string())
+        };
+
+        let instances = &witness.step_instances;
+
+        if let (Some(first_step), Some(first)) = (self.circuit.first_step, instances.first()) {
+            if first.step_type_uuid != first_step.uuid() {
+                lints.push(Lint::StepOrder {
+                    step: 0,
+                    step_type: step_type_name(first.step_type_uuid),
+                    expected: vec![first_step.annotation.to_string()],
+                });
+            }
+        }
+
+        for (step, pair) in instances.windows(2).enumerate() {
+            if let Some(allowed) = self.transitions.get(&pair[0].step_type_uuid) {
+                if !allowed
+                    .iter()
+                    .any(|handler| handler.uuid() == pair[1].step_type_uuid)
+                {
+                    lints.push(Lint::StepOrder {
+                        step: step + 1,
+                        step_type: step_type_name(pair[1].step_type_uuid),
+                        expected: allowed
+                            .iter()
+                            .map(|handler| handler.annotation.to_string())
+                            .collect(),
+                    });
+                }
+            }
+        }
+
+        if let (Some(last_step), Some(last)) = (self.circuit.last_step, instances.last()) {
+            if last.step_type_uuid != last_step.uuid() {
+                lints.push(Lint::StepOrder {
+                    step: instances.len() - 1,
+                    step_type: step_type_name(last.step_type_uuid),
+                    expected: vec![last_step.annotation.to_string()],
+                });
+            }
+        }
+    }
+}
+
+impl<'a, F: PrimeField + Hash, TraceArgs, StepArgs> WitnessLinter<'a, F, TraceArgs, StepArgs> {
+    fn lint_padding(&self, witness: &TraceWitness<F>, lints: &mut Vec<Lint>) {
+        let padding = self.circuit.padding_step_types();
+        let is_padding = |uuid: u32| padding.iter().any(|handler| handler.uuid() == uuid);
+        let protected = self.circuit.padding_protected_signals();
+
+        for (step, pair) in witness.step_instances.windows(2).enumerate() {
+            if !is_padding(pair[0].step_type_uuid) || !is_padding(pair[1].step_type_uuid) {
+                continue;
+            }
+
+            for signal in protected.iter() {
+                let signal = Queriable::Forward(*signal, false);
+                let before = pair[0].assignments.get(&signal);
+                let after = pair[1].assignments.get(&signal);
+
+                if before.is_some() && after.is_some() && before != after {
+                    lints.push(Lint::PaddingChange {
+                        step: step + 1,
+                        signal: signal.annotation(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Assumes a little-endian representation, as used by the halo2curves fields.
+fn fits_in_bits<F: PrimeField>(value: &F, bits: u32) -> bool {
+    value
+        .to_repr()
+        .as_ref()
+        .iter()
+        .enumerate()
+        .all(|(i, byte)| {
+            let low = i as u32 * 8;
+            if low >= bits {
+                *byte == 0
+            } else if low + 8 > bits {
+                (*byte as u32) >> (bits - low) == 0
+            } else {
+                true
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_lint() {
+        let sc = circuit::<Fr, (), (), _>("lints", |ctx| {
+            let a = ctx.forward("a");
+            let zero = ctx.forward("zero");
+            let first = ctx.step_type("first");
+            let second = ctx.step_type("second");
+
+            ctx.pragma_first_step(first);
+            ctx.pragma_last_step(first);
+
+            ctx.step_type_def(first, |ctx| {
+                ctx.internal("unassigned");
+
+                ctx.wg(move |ctx, _| {
+                    ctx.assign(a, 300.field());
+                    ctx.assign(zero, 0.field());
+                });
+            });
+            ctx.step_type_def(second, |ctx| {
+                ctx.wg(move |ctx, _| {
+                    ctx.assign(a, 1.field());
+                    ctx.assign(zero, 0.field());
+                })
+            });
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&first, ());
+                ctx.add(&second, ());
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let a = sc.forward_signals[0];
+        let lints = WitnessLinter::new(&sc)
+            .bit_width(Queriable::Forward(a, false), 8)
+            .lint(&witness);
+
+        assert_eq!(
+            lints,
+            vec![
+                Lint::ZeroSignal {
+                    signal: "zero".to_string()
+                },
+                Lint::UnassignedSignal {
+                    step_type: "first".to_string(),
+                    signal: "unassigned".to_string()
+                },
+                Lint::BitWidthExceeded {
+                    step: 0,
+                    signal: "a".to_string(),
+                    bits: 8
+                },
+                Lint::StepOrder {
+                    step: 1,
+                    step_type: "second".to_string(),
+                    expected: vec!["first".to_string()]
+                },
+            ]
+        );
+    }
+
+    /// A counter whose padding steps have the transition `next(acc) == acc + increment`.
+    fn padded_counter(increment: Option<u64>) -> Circuit<Fr, (), u64> {
+        circuit::<Fr, (), u64, _>("padded", |ctx| {
+            let acc = ctx.forward("acc");
+            let step = ctx.step_type("step");
+            let padding = ctx.step_type("padding");
+
+            ctx.pragma_padding_step(padding);
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| ctx.transition(eq(acc + 1, acc.next())));
+                ctx.wg(move |ctx, v| ctx.assign(acc, v.field()));
+            });
+            ctx.step_type_def(padding, |ctx| {
+                if let Some(increment) = increment {
+                    ctx.setup(move |ctx| ctx.transition(eq(acc + increment, acc.next())));
+                }
+                ctx.wg(move |ctx, v| ctx.assign(acc, v.field()));
+            });
+
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 0);
+                ctx.add(&padding, 1);
+                ctx.add(&padding, 2);
+            });
+        })
+    }
+
+    #[test]
+    fn test_lint_padding_change() {
+        let sc = padded_counter(Some(0));
+        Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        assert_eq!(
+            WitnessLinter::new(&sc).lint(&witness),
+            vec![Lint::PaddingChange {
+                step: 2,
+                signal: "acc".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "padding step type padding does not constrain next(acc) == acc")]
+    fn test_padding_must_keep_carried_signals() {
+        let sc = padded_counter(None);
+        Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+    }
+
+    #[test]
+    #[should_panic(expected = "padding step type padding does not constrain next(acc) == acc")]
+    fn test_padding_transition_must_keep_carried_signals() {
+        let sc = padded_counter(Some(1));
+        Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    rc::Rc,
+};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::{
+    ast::{query::Queriable, FixedGen, StepType},
+    compiler::FixedGenContext,
+};
+
+use super::TraceWitness;
+
+/// Usage of a lookup table, identified by the signals and columns queried by the table side of
+/// the lookups.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupTableUsage {
+    pub table: String,
+    /// Number of lookups, across all step types, that target the table.
+    pub lookups: usize,
+    /// Number of rows where any column of the table is assigned.
+    pub populated_rows: usize,
+    /// Number of lookup inputs in the trace, one per lookup in each step instance.
+    pub inputs: usize,
+}
+
+impl LookupTableUsage {
+    /// The table or its inputs do not fit in the rows available to the circuit.
+    pub fn is_oversubscribed(&self, usable_rows: usize) -> bool {
+        self.populated_rows > usable_rows || self.inputs > usable_rows
+    }
+}
+
+impl<F: Field + Hash> TraceWitness<F> {
+    /// Reports the usage of every lookup table, sorted by table, to review lookups before tables
+    /// oversubscribed or left empty fail inside the prover. Rows of fixed columns are counted by
+    /// running `fixed_gen`, and rows of advice by the step instances assigning them.
+    pub fn lookup_report<Args>(
+        &self,
+        step_types: &HashMap<u32, Rc<StepType<F, Args>>>,
+        fixed_gen: Option<&FixedGen<F>>,
+    ) -> Vec<LookupTableUsage> {
+        let mut fixed_rows = FixedRowsRecorder::default();
+        if let Some(fixed_gen) = fixed_gen {
+            fixed_gen(&mut fixed_rows);
+        }
+
+        let mut tables: HashMap<String, (HashSet<Queriable<F>>, usize, usize)> = HashMap::new();
+
+        for step in step_types.values() {
+            let instances = self
+                .step_instances
+                .iter()
+                .filter(|instance| instance.step_type_uuid == step.uuid())
+                .count();
+
+            for lookup in step.lookups.iter() {
+                let queries: HashSet<Queriable<F>> = lookup
+                    .exprs
+                    .iter()
+                    .flat_map(|(_, dest)| dest.queriables())
+                    .collect();
+
+                let mut names: Vec<String> = queries.iter().map(|q| q.annotation()).collect();
+                names.sort();
+
+                let table = tables
+                    .entry(names.join(", "))
+                    .or_insert_with(|| (HashSet::new(), 0, 0));
+                table.0.extend(queries);
+                table.1 += 1;
+                table.2 += instances;
+            }
+        }
+
+        let mut report: Vec<LookupTableUsage> = tables
+            .into_iter()
+            .map(|(table, (queries, lookups, inputs))| {
+                let mut rows: HashSet<usize> = HashSet::new();
+                for query in queries.iter() {
+                    if let Some(offsets) = fixed_rows.rows.get(&query.uuid()) {
+                        rows.extend(offsets);
+                    }
+                }
+
+                let advice_rows = self
+                    .step_instances
+                    .iter()
+                    .filter(|instance| queries.iter().any(|q| instance.assignments.contains_key(q)))
+                    .count();
+
+                LookupTableUsage {
+                    table,
+                    lookups,
+                    populated_rows: rows.len().max(advice_rows),
+                    inputs,
+                }
+            })
+            .collect();
+
+        report.sort_by(|a, b| a.table.cmp(&b.table));
+
+        report
+    }
+}
+
+#[derive(Default)]
+struct FixedRowsRecorder {
+    rows: HashMap<u32, HashSet<usize>>,
+}
+
+impl<F> FixedGenContext<F> for FixedRowsRecorder {
+    fn assign(&mut self, offset: usize, lhs: Queriable<F>, _: F) {
+        self.rows.entry(lhs.uuid()).or_default().insert(offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::{halo2curves::bn256::Fr, plonk::ConstraintSystem};
+
+    use super::*;
+    use crate::{ast::ToField, dsl::circuit, wit_gen::GenericTraceContext};
+
+    #[test]
+    fn test_lookup_report() {
+        let mut meta = ConstraintSystem::<Fr>::default();
+        let column = meta.fixed_column();
+
+        let sc = circuit::<Fr, (), u64, _>("lookups", |ctx| {
+            let table = ctx.import_halo2_fixed("range", column);
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.add_lookup(|lookup| {
+                        lookup.add(a, table);
+                    });
+                    ctx.add_lookup(|lookup| {
+                        lookup.add(a + 1, table);
+                    });
+                });
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+
+            ctx.fixed_gen(move |ctx| {
+                for i in 0..4 {
+                    ctx.assign(i, table, i.field());
+                }
+            });
+
+            ctx.trace(move |ctx, _| {
+                for i in 0..3 {
+                    ctx.add(&step, i);
+                }
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let report = witness.lookup_report(&sc.step_types, sc.fixed_gen.as_deref());
+
+        assert_eq!(
+            report,
+            vec![LookupTableUsage {
+                table: "range".to_string(),
+                lookups: 2,
+                populated_rows: 4,
+                inputs: 6,
+            }]
+        );
+        assert!(report[0].is_oversubscribed(5));
+        assert!(!report[0].is_oversubscribed(8));
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::ast::query::Queriable;
+
+use super::TraceWitness;
+
+/// A corruption of a generated witness, to check that the constraints of a circuit reject it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WitnessMutation<F> {
+    /// Sets `signal` to `value` in the step `step`, assigning it if it was not.
+    Overwrite {
+        step: usize,
+        signal: Queriable<F>,
+        value: F,
+    },
+    /// Removes the step, the following steps move up.
+    DropStep(usize),
+    /// Inserts a copy of the step after it.
+    DuplicateStep(usize),
+}
+
+impl<F: fmt::Debug> fmt::Display for WitnessMutation<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WitnessMutation::Overwrite {
+                step,
+                signal,
+                value,
+            } => write!(
+                f,
+                "overwrite {} in step {} with {:?}",
+                signal.annotation(),
+                step,
+                value
+            ),
+            WitnessMutation::DropStep(step) => write!(f, "drop step {}", step),
+            WitnessMutation::DuplicateStep(step) => write!(f, "duplicate step {}", step),
+        }
+    }
+}
+
+impl<F: Clone + Eq + Hash> WitnessMutation<F> {
+    /// Applies the mutation to `witness`, panicking when its step is not in the witness.
+    pub fn apply(&self, witness: &mut TraceWitness<F>) {
+        let steps = witness.step_instances.len();
+        let check = |step: usize| {
+            if step >= steps {
+                panic!(
+                    "cannot mutate step {}, the witness has {} steps",
+                    step, steps
+                );
+            }
+        };
+
+        match self {
+            WitnessMutation::Overwrite {
+                step,
+                signal,
+                value,
+            } => {
+                check(*step);
+                let instance = &mut witness.step_instances[*step];
+                instance.assignments.insert(*signal, value.clone());
+                instance.provenance.remove(signal);
+            }
+            WitnessMutation::DropStep(step) => {
+                check(*step);
+                witness.step_instances.remove(*step);
+            }
+            WitnessMutation::DuplicateStep(step) => {
+                check(*step);
+                let copy = witness.step_instances[*step].clone();
+                witness.step_instances.insert(*step + 1, copy);
+            }
+        }
+    }
+}
+
+/// Corrupts a generated witness for negative tests, which assert that the prover rejects it:
+///
+/// ```ignore
+/// let corrupted = WitnessMutator::new(witness)
+///     .overwrite(3, b, Fr::from(7))
+///     .drop_step(5)
+///     .finish();
+/// ```
+///
+/// The mutations apply in order, to the witness as left by the previous ones.
+pub struct WitnessMutator<F> {
+    witness: TraceWitness<F>,
+}
+
+impl<F: Clone + Eq + Hash> WitnessMutator<F> {
+    pub fn new(witness: TraceWitness<F>) -> Self {
+        Self { witness }
+    }
+
+    pub fn overwrite(self, step: usize, signal: Queriable<F>, value: F) -> Self {
+        self.mutate(&WitnessMutation::Overwrite {
+            step,
+            signal,
+            value,
+        })
+    }
+
+    pub fn drop_step(self, step: usize) -> Self {
+        self.mutate(&WitnessMutation::DropStep(step))
+    }
+
+    pub fn duplicate_step(self, step: usize) -> Self {
+        self.mutate(&WitnessMutation::DuplicateStep(step))
+    }
+
+    pub fn mutate(mut self, mutation: &WitnessMutation<F>) -> Self {
+        mutation.apply(&mut self.witness);
+
+        self
+    }
+
+    pub fn finish(self) -> TraceWitness<F> {
+        self.witness
+    }
+}
+
+impl<F: Field + Hash> WitnessMutator<F> {
+    /// Every mutation of a single assigned cell of `witness`, its value plus one, sorted by step
+    /// and signal name.
+    pub fn single_cell_mutations(witness: &TraceWitness<F>) -> Vec<WitnessMutation<F>> {
+        let mut mutations = Vec::new();
+
+        for (step, instance) in witness.step_instances.iter().enumerate() {
+            let mut cells: Vec<(&Queriable<F>, &F)> = instance.assignments.iter().collect();
+            cells.sort_by_key(|(signal, _)| signal.annotation());
+
+            mutations.extend(
+                cells
+                    .into_iter()
+                    .map(|(signal, value)| WitnessMutation::Overwrite {
+                        step,
+                        signal: *signal,
+                        value: *value + F::ONE,
+                    }),
+            );
+        }
+
+        mutations
+    }
+
+    /// Applies each single cell mutation of `witness` to a copy of it, and returns the ones that
+    /// `accepts` does not reject. `accepts` usually runs the `MockProver` on the corrupted
+    /// witness; every cell that the constraints bind is then rejected, so the mutations returned
+    /// point at the signals that the circuit leaves unconstrained.
+    pub fn surviving_mutations<A: FnMut(&TraceWitness<F>) -> bool>(
+        witness: &TraceWitness<F>,
+        mut accepts: A,
+    ) -> Vec<WitnessMutation<F>> {
+        Self::single_cell_mutations(witness)
+            .into_iter()
+            .filter(|mutation| {
+                let mut corrupted = witness.clone();
+                mutation.apply(&mut corrupted);
+
+                accepts(&corrupted)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        dsl::{cb::eq, circuit},
+        wit_gen::{step_test::test_step, GenericTraceContext},
+    };
+
+    #[test]
+    fn test_witness_mutator() {
+        let sc = circuit::<Fr, (), u64, _>("mutated", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+                let free = ctx.internal("free");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a * 2, b)));
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                    ctx.assign(free, 0.field());
+                });
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 1);
+                ctx.add(&step, 2);
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+
+        let corrupted = WitnessMutator::new(witness.clone())
+            .duplicate_step(0)
+            .drop_step(2)
+            .overwrite(1, a, Fr::from(5))
+            .finish();
+        assert_eq!(corrupted.step_instances.len(), 2);
+        assert_eq!(corrupted.step_instances[1].assignments[&a], Fr::from(5));
+        assert_eq!(corrupted.step_instances[0], witness.step_instances[0]);
+
+        assert_eq!(WitnessMutator::single_cell_mutations(&witness).len(), 6);
+
+        let accepts = |witness: &TraceWitness<Fr>| {
+            witness.step_instances.iter().all(|instance| {
+                let step = &sc.step_types[&instance.step_type_uuid];
+                test_step(step, &instance.assignments, None).is_empty()
+            })
+        };
+        let surviving: Vec<String> = WitnessMutator::surviving_mutations(&witness, accepts)
+            .iter()
+            .map(|mutation| mutation.to_string())
+            .collect();
+        assert_eq!(surviving.len(), 2);
+        assert!(surviving[0].starts_with("overwrite free in step 0 with "));
+        assert!(surviving[1].starts_with("overwrite free in step 1 with "));
+    }
+}
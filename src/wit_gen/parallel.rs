@@ -0,0 +1,232 @@
+use std::{collections::HashMap, hash::Hash, panic, sync::Arc, thread};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::{
+    ast::{InternalSignal, StepTypeUUID, SyncStepWitnessGen},
+    compiler::TraceContext,
+    dsl::StepTypeHandler,
+    ir::Circuit,
+};
+
+use super::{
+    assignments::SignalIndex, run_wg, AssertionFailure, GenericTraceContext, StepInstance,
+    TraceOptions, TraceWitness, WgOptions,
+};
+
+/// Trace context that records the steps added, to run their witness generation afterwards.
+struct DeferredTraceContext<StepArgs> {
+    steps: Vec<(StepTypeUUID, StepArgs)>,
+    height: usize,
+    num_steps: usize,
+}
+
+impl<StepArgs> TraceContext<StepArgs> for DeferredTraceContext<StepArgs> {
+    fn add(&mut self, step: &StepTypeHandler, args: StepArgs) {
+        self.steps.push((step.uuid(), args));
+    }
+
+    fn set_height(&mut self, height: usize) {
+        self.height = height;
+    }
+
+    fn steps(&self) -> usize {
+        self.steps.len()
+    }
+
+    fn num_steps(&self) -> Option<usize> {
+        Some(self.num_steps).filter(|num_steps| *num_steps > 0)
+    }
+}
+
+/// A thread-safe witness generation function with the name, the signals and the signal index of
+/// its step type.
+type SyncStep<F, StepArgs> = (
+    Arc<SyncStepWitnessGen<F, StepArgs>>,
+    String,
+    Vec<InternalSignal>,
+    Arc<SignalIndex<F>>,
+);
+
+/// Generated step, or the step args when its step type has no thread-safe witness generation.
+type Generated<F, StepArgs> = Result<(StepInstance<F>, Option<AssertionFailure>), StepArgs>;
+
+/// Like the witness generation of `ChiquitoHalo2::witness`, running the witness generation
+/// functions of the steps in `threads` threads. The trace runs first and records the step args,
+/// then each thread runs the witness generation of a chunk of consecutive steps. The step
+/// instances are collected in the order of the trace, and the witness is the same as the one
+/// generated serially.
+///
+/// Only the step types defined with `wg_sync` run in the threads, the others run afterwards in the
+/// calling thread.
+pub fn generate_parallel<F, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    args: TraceArgs,
+    threads: usize,
+) -> Option<TraceWitness<F>>
+where
+    F: Field + Hash,
+    StepArgs: Send,
+{
+    let trace = circuit.trace.as_ref()?;
+
+    let mut deferred = DeferredTraceContext {
+        steps: Vec::new(),
+        height: 0,
+        num_steps: circuit.num_steps,
+    };
+    trace(&mut deferred, args);
+
+    let sync_wgs: HashMap<StepTypeUUID, SyncStep<F, StepArgs>> = circuit
+        .step_types
+        .values()
+        .filter_map(|step| {
+            step.sync_wg.as_ref().map(|wg| {
+                (
+                    step.uuid(),
+                    (
+                        Arc::clone(wg),
+                        step.name.clone(),
+                        step.signals.clone(),
+                        Arc::new(SignalIndex::of_step(step)),
+                    ),
+                )
+            })
+        })
+        .collect();
+
+    let chunk_size = ((deferred.steps.len() + threads.max(1) - 1) / threads.max(1)).max(1);
+    let mut chunks: Vec<Vec<(StepTypeUUID, StepArgs)>> = Vec::new();
+    for step in deferred.steps {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() < chunk_size => chunk.push(step),
+            _ => chunks.push(vec![step]),
+        }
+    }
+
+    let sync_wgs = &sync_wgs;
+    let generated: Vec<Vec<(StepTypeUUID, Generated<F, StepArgs>)>> = thread::scope(|scope| {
+        // every thread has to be spawned before joining the first one
+        #[allow(clippy::needless_collect)]
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|(uuid, args)| match sync_wgs.get(&uuid) {
+                            Some((wg, name, signals, index)) => {
+                                let index = Arc::clone(index);
+                                (
+                                    uuid,
+                                    Ok(run_wg(
+                                        uuid,
+                                        name,
+                                        signals,
+                                        index,
+                                        &**wg,
+                                        args,
+                                        WgOptions::default(),
+                                    )),
+                                )
+                            }
+                            None => (uuid, Err(args)),
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+            .collect()
+    });
+
+    let mut ctx = GenericTraceContext::for_circuit(circuit, TraceOptions::default());
+
+    for (uuid, generated) in generated.into_iter().flatten() {
+        let step = circuit.step_types.get(&uuid).expect("step type not found");
+        let (witness, failure) = match generated {
+            Ok(generated) => generated,
+            Err(args) => {
+                let index = Arc::new(SignalIndex::of_step(step));
+                run_wg(
+                    uuid,
+                    &step.name,
+                    &step.signals,
+                    index,
+                    &*step.wg,
+                    args,
+                    WgOptions::default(),
+                )
+            }
+        };
+
+        ctx.push_step(step, witness, failure);
+    }
+    if deferred.height > 0 {
+        ctx.set_height(deferred.height);
+    }
+
+    Some(ctx.get_witness())
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_generate_parallel() {
+        let sc = circuit::<Fr, u64, u64, _>("parallel", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+            let last = ctx.step_type("last");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.setup(move |ctx| ctx.constr(eq(a * a, b)));
+                ctx.wg_sync(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * v).field());
+                });
+            });
+            ctx.step_type_def(last, |ctx| {
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+
+            ctx.trace(move |ctx, steps| {
+                for i in 0..steps {
+                    ctx.add(&step, i);
+                }
+                ctx.add(&last, steps);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        (compiled.trace.as_ref().unwrap())(&mut ctx, 100);
+        let serial = ctx.get_witness();
+
+        for threads in [1, 3, 8] {
+            let parallel = generate_parallel(&compiled, 100, threads).unwrap();
+            assert_eq!(parallel, serial);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{any::type_name, fmt};
+
+use super::TraceWitness;
+
+pub type WitnessStage<F> = dyn Fn(TraceWitness<F>) -> Result<TraceWitness<F>, String>;
+
+/// A stage of a `WitnessPipeline` that rejected the witness.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineError {
+    pub stage: String,
+    pub message: String,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "witness stage {} failed: {}", self.stage, self.message)
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+/// Stages applied in order to a generated witness, to share validation and derivation steps
+/// between circuits. Each stage takes the witness, and returns it, possibly modified, or an error
+/// that stops the pipeline.
+pub struct WitnessPipeline<F> {
+    stages: Vec<(String, Box<WitnessStage<F>>)>,
+}
+
+impl<F> Default for WitnessPipeline<F> {
+    fn default() -> Self {
+        Self { stages: Vec::new() }
+    }
+}
+
+impl<F> WitnessPipeline<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage, named after its function in errors.
+    pub fn then<S>(self, stage: S) -> Self
+    where
+        S: Fn(TraceWitness<F>) -> Result<TraceWitness<F>, String> + 'static,
+    {
+        let name = type_name::<S>().to_string();
+
+        self.then_named(name, stage)
+    }
+
+    /// Appends a stage with the name given for errors, useful for closures.
+    pub fn then_named<N: Into<String>, S>(mut self, name: N, stage: S) -> Self
+    where
+        S: Fn(TraceWitness<F>) -> Result<TraceWitness<F>, String> + 'static,
+    {
+        self.stages.push((name.into(), Box::new(stage)));
+        self
+    }
+
+    pub fn run(&self, witness: TraceWitness<F>) -> Result<TraceWitness<F>, PipelineError> {
+        self.stages
+            .iter()
+            .try_fold(witness, |witness, (name, stage)| {
+                stage(witness).map_err(|message| PipelineError {
+                    stage: name.clone(),
+                    message,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+
+    fn double_height(mut witness: TraceWitness<Fr>) -> Result<TraceWitness<Fr>, String> {
+        witness.height *= 2;
+        Ok(witness)
+    }
+
+    fn check_height(witness: TraceWitness<Fr>) -> Result<TraceWitness<Fr>, String> {
+        if witness.height > 10 {
+            Err(format!("height {} over 10", witness.height))
+        } else {
+            Ok(witness)
+        }
+    }
+
+    #[test]
+    fn test_witness_pipeline() {
+        let pipeline = WitnessPipeline::new()
+            .then(double_height)
+            .then(check_height);
+
+        let witness = TraceWitness::<Fr> {
+            step_instances: Vec::new(),
+            height: 4,
+            public_values: Vec::new(),
+        };
+        assert_eq!(pipeline.run(witness).map(|w| w.height), Ok(8));
+
+        let witness = TraceWitness::<Fr> {
+            step_instances: Vec::new(),
+            height: 6,
+            public_values: Vec::new(),
+        };
+        let err = pipeline.run(witness).err().unwrap();
+        assert!(err.stage.ends_with("check_height"));
+        assert_eq!(err.message, "height 12 over 10");
+    }
+}
@@ -0,0 +1,178 @@
+use std::time::{Duration, Instant};
+
+use crate::{ast::StepTypeUUID, dsl::StepTypeHandler};
+
+use super::StepInstance;
+
+/// Progress of a trace, reported every few steps to the hook of `TraceHooks::on_progress`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceProgress {
+    /// Steps added so far.
+    pub steps: usize,
+    /// Time since the trace context was created.
+    pub elapsed: Duration,
+    pub steps_per_second: f64,
+}
+
+type StepHook<'a, F> = Box<dyn FnMut(usize, &StepInstance<F>) + 'a>;
+type PaddingHook<'a> = Box<dyn FnMut(usize) + 'a>;
+type ProgressHook<'a> = Box<dyn FnMut(&TraceProgress) + 'a>;
+
+/// Callbacks run by `GenericTraceContext` as the trace adds its steps, for example to show a
+/// progress bar or collect metrics while a long trace runs:
+///
+/// ```ignore
+/// let hooks = TraceHooks::new()
+///     .on_padding_start(&[padding], |step| println!("padding from step {}", step))
+///     .on_progress(100_000, |progress| {
+///         println!("{} steps, {:.0} steps/s", progress.steps, progress.steps_per_second)
+///     });
+/// let witness = chiquito.witness_with_hooks(args, hooks);
+/// ```
+pub struct TraceHooks<'a, F> {
+    on_step_added: Option<StepHook<'a, F>>,
+    padding: Vec<StepTypeUUID>,
+    on_padding_start: Option<PaddingHook<'a>>,
+    progress_every: usize,
+    on_progress: Option<ProgressHook<'a>>,
+
+    start: Instant,
+    padding_started: bool,
+}
+
+impl<'a, F> Default for TraceHooks<'a, F> {
+    fn default() -> Self {
+        Self {
+            on_step_added: None,
+            padding: Vec::new(),
+            on_padding_start: None,
+            progress_every: 0,
+            on_progress: None,
+            start: Instant::now(),
+            padding_started: false,
+        }
+    }
+}
+
+impl<'a, F> TraceHooks<'a, F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `hook` with the index and the instance of every step added, once all its signals are
+    /// assigned.
+    pub fn on_step_added<H: FnMut(usize, &StepInstance<F>) + 'a>(mut self, hook: H) -> Self {
+        self.on_step_added = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Runs `hook` with the index of the first step of one of the `padding` step types.
+    pub fn on_padding_start<H: FnMut(usize) + 'a>(
+        mut self,
+        padding: &[StepTypeHandler],
+        hook: H,
+    ) -> Self {
+        self.padding = padding.iter().map(|handler| handler.uuid()).collect();
+        self.on_padding_start = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Runs `hook` every `every` steps added.
+    pub fn on_progress<H: FnMut(&TraceProgress) + 'a>(mut self, every: usize, hook: H) -> Self {
+        assert!(every > 0, "progress must be reported at least every step");
+        self.progress_every = every;
+        self.on_progress = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Runs the hooks for the step `index`, just added.
+    pub(crate) fn step_added(&mut self, index: usize, instance: &StepInstance<F>) {
+        if !self.padding_started && self.padding.contains(&instance.step_type_uuid) {
+            self.padding_started = true;
+            if let Some(hook) = self.on_padding_start.as_mut() {
+                hook(index);
+            }
+        }
+
+        if let Some(hook) = self.on_step_added.as_mut() {
+            hook(index, instance);
+        }
+
+        let steps = index + 1;
+        if let Some(hook) = self.on_progress.as_mut() {
+            if steps % self.progress_every == 0 {
+                let elapsed = self.start.elapsed();
+                hook(&TraceProgress {
+                    steps,
+                    elapsed,
+                    steps_per_second: steps as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        backend::halo2::ChiquitoHalo2,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+    };
+
+    #[test]
+    fn test_trace_hooks() {
+        let padding = Cell::new(None);
+        let sc = circuit::<Fr, u64, u64, _>("hooked", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+            let pad = ctx.step_type("pad");
+            padding.set(Some(pad));
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.step_type_def(pad, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i);
+                }
+                for _ in 0..3 {
+                    ctx.add(&pad, n);
+                }
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let chiquito = ChiquitoHalo2::new(compiled);
+
+        let added = RefCell::new(0);
+        let padding_start = RefCell::new(Vec::new());
+        let progress = RefCell::new(Vec::new());
+        let hooks = TraceHooks::new()
+            .on_step_added(|_, instance| {
+                assert_eq!(instance.assignments.len(), 1);
+                *added.borrow_mut() += 1;
+            })
+            .on_padding_start(&[padding.get().unwrap()], |step| {
+                padding_start.borrow_mut().push(step)
+            })
+            .on_progress(4, |report| progress.borrow_mut().push(report.steps));
+
+        let witness = chiquito.witness_with_hooks(7, hooks).unwrap();
+        assert_eq!(witness.step_instances.len(), 10);
+        assert_eq!(*added.borrow(), 10);
+        assert_eq!(*padding_start.borrow(), vec![7]);
+        assert_eq!(*progress.borrow(), vec![4, 8]);
+    }
+}
@@ -0,0 +1,447 @@
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::{query::Queriable, Circuit, ExposeOffset},
+    ci::escape_json,
+};
+
+use super::TraceWitness;
+
+/// Size in bytes of an encoded field element, as a `uint256` word in EVM calldata.
+pub const WORD_SIZE: usize = 32;
+
+/// Version of the layout produced by `encode_public_inputs`, to be bumped when it changes.
+pub const LAYOUT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicInputError {
+    /// The byte length is not a multiple of `WORD_SIZE`.
+    InvalidLength(usize),
+    /// The word at this index is not the canonical encoding of a field element.
+    NonCanonical(usize),
+}
+
+impl fmt::Display for PublicInputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublicInputError::InvalidLength(len) => write!(
+                f,
+                "public inputs length {} is not a multiple of {} bytes",
+                len, WORD_SIZE
+            ),
+            PublicInputError::NonCanonical(index) => {
+                write!(f, "public input {} is not a canonical field element", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PublicInputError {}
+
+impl<F: PrimeField + Hash> TraceWitness<F> {
+    /// Values of the signals exposed by the circuit, in the order they were exposed, which is the
+    /// order of the rows of the instance column.
+    pub fn public_inputs<TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+    ) -> Vec<F> {
+        circuit
+            .exposed
+            .iter()
+            .map(|(signal, offset)| {
+                let step = match offset {
+                    ExposeOffset::First => self.step_instances.first(),
+                    ExposeOffset::Last => self.step_instances.last(),
+                    ExposeOffset::Step(step) => self.step_instances.get(*step),
+                }
+                .unwrap_or_else(|| panic!("exposed step {:?} not found in witness", offset));
+
+                let value = match signal {
+                    Queriable::Forward(forward, false) => {
+                        step.assignments.get(&Queriable::Forward(*forward, false))
+                    }
+                    _ => panic!("can only expose a forward signal without rotation"),
+                };
+
+                *value.unwrap_or_else(|| {
+                    panic!("exposed signal {} not assigned", signal.annotation())
+                })
+            })
+            .collect()
+    }
+}
+
+/// A public input of a circuit, see `public_input_schema`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicInputEntry {
+    /// Row of the instance column.
+    pub index: usize,
+    /// The exposed signal, or the signal of an exposed result, which is carried to the last step
+    /// by another signal.
+    pub name: String,
+    pub signal: String,
+    pub step: ExposeOffset,
+}
+
+/// Order, type and name of the public inputs of a circuit, for the integrations of a verifier,
+/// such as contracts or services, to be generated or checked against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicInputSchema {
+    /// Bits of the field of every input.
+    pub field_bits: u32,
+    pub inputs: Vec<PublicInputEntry>,
+}
+
+impl PublicInputSchema {
+    /// The schema as a JSON object. Every input is a field element encoded as a `uint256` word,
+    /// as in `encode_public_inputs`.
+    pub fn to_json(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let step = match input.step {
+                    ExposeOffset::First => "\"first\"".to_string(),
+                    ExposeOffset::Last => "\"last\"".to_string(),
+                    ExposeOffset::Step(step) => step.to_string(),
+                };
+
+                format!(
+                    "{{\"index\":{},\"name\":\"{}\",\"signal\":\"{}\",\"step\":{},\"type\":\"field\",\"encoding\":\"uint256\"}}",
+                    input.index,
+                    escape_json(&input.name),
+                    escape_json(&input.signal),
+                    step
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"layout_version\":{},\"word_size\":{},\"field_bits\":{},\"inputs\":[{}]}}",
+            LAYOUT_VERSION,
+            WORD_SIZE,
+            self.field_bits,
+            inputs.join(",")
+        )
+    }
+}
+
+/// The public inputs of `circuit`, in the order of `TraceWitness::public_inputs`.
+pub fn public_input_schema<F: PrimeField, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+) -> PublicInputSchema {
+    let inputs = circuit
+        .exposed
+        .iter()
+        .enumerate()
+        .map(|(index, (signal, offset))| {
+            let public_name = |signal: &Queriable<F>| {
+                circuit
+                    .aliases
+                    .public_name(signal.uuid(), &signal.annotation())
+                    .to_string()
+            };
+            let name = circuit
+                .exposed_results
+                .iter()
+                .find(|result| result.carrier.uuid() == signal.uuid())
+                .map(|result| public_name(&result.signal))
+                .unwrap_or_else(|| public_name(signal));
+
+            PublicInputEntry {
+                index,
+                name,
+                signal: public_name(signal),
+                step: *offset,
+            }
+        })
+        .collect();
+
+    PublicInputSchema {
+        field_bits: F::NUM_BITS,
+        inputs,
+    }
+}
+
+/// Encodes the public inputs as consecutive big-endian 32 byte words, the layout used by EVM
+/// verifiers and by aggregation circuits that read the instances of a snark.
+pub fn encode_public_inputs<F: PrimeField>(inputs: &[F]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(inputs.len() * WORD_SIZE);
+
+    for input in inputs {
+        let repr = input.to_repr();
+        let mut word = [0u8; WORD_SIZE];
+        for (i, byte) in repr.as_ref().iter().take(WORD_SIZE).enumerate() {
+            word[WORD_SIZE - 1 - i] = *byte;
+        }
+        bytes.extend_from_slice(&word);
+    }
+
+    bytes
+}
+
+/// Decodes public inputs encoded by `encode_public_inputs`.
+pub fn decode_public_inputs<F: PrimeField>(bytes: &[u8]) -> Result<Vec<F>, PublicInputError> {
+    if bytes.len() % WORD_SIZE != 0 {
+        return Err(PublicInputError::InvalidLength(bytes.len()));
+    }
+
+    bytes
+        .chunks(WORD_SIZE)
+        .enumerate()
+        .map(|(index, word)| {
+            let mut repr = F::Repr::default();
+            let repr_len = repr.as_ref().len();
+            if word[..WORD_SIZE.saturating_sub(repr_len)]
+                .iter()
+                .any(|byte| *byte != 0)
+            {
+                return Err(PublicInputError::NonCanonical(index));
+            }
+
+            for (i, byte) in repr.as_mut().iter_mut().take(WORD_SIZE).enumerate() {
+                *byte = word[WORD_SIZE - 1 - i];
+            }
+
+            Option::from(F::from_repr(repr)).ok_or(PublicInputError::NonCanonical(index))
+        })
+        .collect()
+}
+
+/// Hashes the public inputs to a single field element, so that a verifier can take one instance
+/// instead of all of them. The hash is keccak256 of `encode_public_inputs`, read as a big-endian
+/// integer and reduced modulo the field, which is how a solidity verifier computes it with
+/// `uint256(keccak256(abi.encodePacked(inputs))) % r`.
+pub fn public_inputs_commitment<F: PrimeField>(inputs: &[F]) -> F {
+    let hash = keccak256(&encode_public_inputs(inputs));
+    let base = F::from(256);
+
+    hash.iter()
+        .fold(F::ZERO, |acc, byte| acc * base + F::from(*byte as u64))
+}
+
+#[cfg(any(test, not(feature = "keccak")))]
+const KECCAK_ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+#[cfg(any(test, not(feature = "keccak")))]
+const KECCAK_ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+#[cfg(any(test, not(feature = "keccak")))]
+const KECCAK_PI_LANES: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+#[cfg(any(test, not(feature = "keccak")))]
+fn keccak_f(state: &mut [u64; 25]) {
+    for round_constant in KECCAK_ROUND_CONSTANTS {
+        // theta
+        let mut columns = [0u64; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+        }
+        for x in 0..5 {
+            let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                state[x + 5 * y] ^= d;
+            }
+        }
+
+        // rho and pi
+        let mut last = state[1];
+        for (rotation, lane) in KECCAK_ROTATIONS.iter().zip(KECCAK_PI_LANES.iter()) {
+            let current = state[*lane];
+            state[*lane] = last.rotate_left(*rotation);
+            last = current;
+        }
+
+        // chi
+        for y in 0..5 {
+            let row = [
+                state[5 * y],
+                state[5 * y + 1],
+                state[5 * y + 2],
+                state[5 * y + 3],
+                state[5 * y + 4],
+            ];
+            for x in 0..5 {
+                state[5 * y + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+
+        // iota
+        state[0] ^= round_constant;
+    }
+}
+
+/// Keccak-256 as used by the EVM, that is the original keccak padding and not SHA3-256. With the
+/// `keccak` feature, on by default, it is computed by the `tiny-keccak` crate.
+#[cfg(feature = "keccak")]
+pub(crate) fn keccak256(input: &[u8]) -> [u8; 32] {
+    use tiny_keccak::{Hasher, Keccak};
+
+    let mut hasher = Keccak::v256();
+    hasher.update(input);
+    let mut output = [0u8; 32];
+    hasher.finalize(&mut output);
+
+    output
+}
+
+/// Keccak-256 as used by the EVM, that is the original keccak padding and not SHA3-256. Without
+/// the `keccak` feature it is computed by `keccak256_fallback`.
+#[cfg(not(feature = "keccak"))]
+pub(crate) fn keccak256(input: &[u8]) -> [u8; 32] {
+    keccak256_fallback(input)
+}
+
+/// Keccak-256 without dependencies, checked against published vectors and, with the `keccak`
+/// feature, against `tiny-keccak` in the tests.
+#[cfg(any(test, not(feature = "keccak")))]
+fn keccak256_fallback(input: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+
+    let mut padded = input.to_vec();
+    padded.push(0x01);
+    padded.resize(((padded.len() + RATE - 1) / RATE) * RATE, 0);
+    *padded.last_mut().unwrap() |= 0x80;
+
+    let mut state = [0u64; 25];
+    for block in padded.chunks(RATE) {
+        for (lane, bytes) in state.iter_mut().zip(block.chunks(8)) {
+            *lane ^= u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        keccak_f(&mut state);
+    }
+
+    let mut output = [0u8; 32];
+    for (bytes, lane) in output.chunks_mut(8).zip(state.iter()) {
+        bytes.copy_from_slice(&lane.to_le_bytes());
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::dsl::circuit;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_keccak256() {
+        let vectors: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            ),
+            (
+                b"abc",
+                "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            ),
+            (
+                b"The quick brown fox jumps over the lazy dog",
+                "4d741b6f1eb29cb2a9b9911c82f56fa8d73b04959d3d9d222895df6c0b28aa15",
+            ),
+        ];
+
+        for (input, digest) in vectors {
+            assert_eq!(hex(&keccak256(input)), digest);
+            assert_eq!(hex(&keccak256_fallback(input)), digest);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "keccak")]
+    fn test_keccak256_fallback() {
+        // every length around the rate of 136 bytes, where the padding takes a new block
+        for len in 0..300 {
+            let input: Vec<u8> = (0..len).map(|i| (i * 7 + 3) as u8).collect();
+            assert_eq!(
+                keccak256_fallback(&input),
+                keccak256(&input),
+                "length {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_public_inputs() {
+        let inputs = vec![Fr::from(1), Fr::from(0x0102)];
+        let bytes = encode_public_inputs(&inputs);
+
+        assert_eq!(bytes.len(), 2 * WORD_SIZE);
+        assert_eq!(bytes[WORD_SIZE - 1], 1);
+        assert_eq!(&bytes[2 * WORD_SIZE - 2..], &[1, 2]);
+        assert_eq!(decode_public_inputs::<Fr>(&bytes), Ok(inputs.clone()));
+
+        assert_eq!(
+            decode_public_inputs::<Fr>(&bytes[1..]),
+            Err(PublicInputError::InvalidLength(2 * WORD_SIZE - 1))
+        );
+
+        assert_ne!(
+            public_inputs_commitment(&inputs),
+            public_inputs_commitment(&inputs[..1])
+        );
+    }
+
+    #[test]
+    fn test_public_input_schema() {
+        let sc = circuit::<Fr, (), (), _>("schema", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b \"quoted\"");
+            let step = ctx.step_type("step");
+            ctx.step_type_def(step, |_| {});
+
+            ctx.expose(a, ExposeOffset::First);
+            ctx.expose(b, ExposeOffset::Step(2));
+        });
+
+        let schema = public_input_schema(&sc);
+        assert_eq!(schema.inputs.len(), 2);
+        assert_eq!(schema.inputs[1].index, 1);
+        assert_eq!(schema.inputs[1].step, ExposeOffset::Step(2));
+        assert_eq!(
+            schema.to_json(),
+            format!(
+                "{{\"layout_version\":1,\"word_size\":32,\"field_bits\":{},\"inputs\":[{{\"index\":0,\"name\":\"a\",\"signal\":\"a\",\"step\":\"first\",\"type\":\"field\",\"encoding\":\"uint256\"}},{{\"index\":1,\"name\":\"b \\\"quoted\\\"\",\"signal\":\"b \\\"quoted\\\"\",\"step\":2,\"type\":\"field\",\"encoding\":\"uint256\"}}]}}",
+                Fr::NUM_BITS
+            )
+        );
+    }
+}
@@ -0,0 +1,705 @@
+use std::{collections::HashMap, fmt, fs, hash::Hash, path::Path, rc::Rc, sync::Arc};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::{query::Queriable, Aliases, StepType},
+    ir::Circuit,
+};
+
+use super::{
+    assignments::SignalIndex,
+    compress::{compress_values, CompressError, ZeroRunDecoder},
+    public_inputs::{
+        decode_public_inputs, encode_public_inputs, keccak256, PublicInputError, WORD_SIZE,
+    },
+    StepInstance, TraceWitness,
+};
+
+const HEADER: &str = "chiquito replay 1";
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Syntax {
+        line: usize,
+        content: String,
+    },
+    Value(PublicInputError),
+    FingerprintMismatch {
+        bundle: String,
+        circuit: String,
+    },
+    UnknownStepType(String),
+    /// More than one step type of the circuit has the name.
+    AmbiguousStepType(String),
+    UnknownSignal {
+        step_type: String,
+        signal: String,
+    },
+    Compressed(CompressError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "cannot access replay bundle: {}", err),
+            ReplayError::Syntax { line, content } => {
+                write!(f, "syntax error at line {}: '{}'", line, content)
+            }
+            ReplayError::Value(err) => write!(f, "invalid value: {}", err),
+            ReplayError::FingerprintMismatch { bundle, circuit } => write!(
+                f,
+                "bundle was recorded for circuit {}, not {}",
+                bundle, circuit
+            ),
+            ReplayError::UnknownStepType(name) => write!(f, "unknown step type {}", name),
+            ReplayError::AmbiguousStepType(name) => {
+                write!(f, "more than one step type is named {}", name)
+            }
+            ReplayError::UnknownSignal { step_type, signal } => {
+                write!(f, "unknown signal {} in step type {}", signal, step_type)
+            }
+            ReplayError::Compressed(err) => write!(f, "invalid compressed bundle: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Identifies the compiled constraints of a circuit, independently of the UUIDs generated when it
+/// was defined, so that a bundle recorded in one process can be checked in another.
+pub fn fingerprint<F: fmt::Debug, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+) -> String {
+    let mut step_types: Vec<&str> = circuit
+        .step_types
+        .values()
+        .map(|step| circuit.aliases.public_name(step.uuid(), &step.name))
+        .collect();
+    step_types.sort();
+
+    let columns: Vec<&str> = circuit
+        .columns
+        .iter()
+        .map(|column| column.annotation.as_str())
+        .collect();
+
+    let description = format!(
+        "{:?}\n{:?}\n{:?}\n{:?}",
+        step_types, columns, circuit.polys, circuit.lookups
+    );
+
+    keccak256(description.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// A self-contained record of a witness and its public inputs, to reproduce a failed
+/// verification outside of the process that generated the witness. Signals and step types are
+/// recorded by their public name, see `Aliases`, so their names must be unique within each step
+/// type, and read back by any of their names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayBundle<F> {
+    pub fingerprint: String,
+    pub height: usize,
+    pub public_inputs: Vec<F>,
+    pub steps: Vec<(String, Vec<(String, F)>)>,
+}
+
+impl<F: PrimeField + Hash> ReplayBundle<F> {
+    pub fn new<TraceArgs, StepArgs>(
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        witness: &TraceWitness<F>,
+        public_inputs: Vec<F>,
+    ) -> Self {
+        let steps = witness
+            .step_instances
+            .iter()
+            .map(|instance| {
+                let step = circuit
+                    .step_types
+                    .get(&instance.step_type_uuid)
+                    .expect("step type not found");
+
+                let mut assignments: Vec<(String, F)> = instance
+                    .assignments
+                    .iter()
+                    .map(|(signal, value)| {
+                        let name = signal.annotation();
+                        let name = circuit.aliases.public_name(signal.uuid(), &name);
+
+                        (name.to_string(), *value)
+                    })
+                    .collect();
+                assignments.sort_by(|a, b| a.0.cmp(&b.0));
+
+                let name = circuit.aliases.public_name(step.uuid(), &step.name);
+
+                (name.to_string(), assignments)
+            })
+            .collect();
+
+        Self {
+            fingerprint: fingerprint(circuit),
+            height: witness.height,
+            public_inputs,
+            steps,
+        }
+    }
+
+    /// Rebuilds the witness for `circuit`, which must be compiled from the same circuit definition
+    /// the bundle was recorded from.
+    pub fn witness<TraceArgs, StepArgs>(
+        &self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+    ) -> Result<TraceWitness<F>, ReplayError> {
+        let circuit_fingerprint = fingerprint(circuit);
+        if circuit_fingerprint != self.fingerprint {
+            return Err(ReplayError::FingerprintMismatch {
+                bundle: self.fingerprint.clone(),
+                circuit: circuit_fingerprint,
+            });
+        }
+
+        let mut witness = named_witness(circuit, self.height, &self.steps)?;
+        witness.public_values = self.public_inputs.clone();
+
+        Ok(witness)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, ReplayError> {
+        let mut lines = source.lines().enumerate();
+        let syntax = |(n, line): (usize, &str)| ReplayError::Syntax {
+            line: n + 1,
+            content: line.to_string(),
+        };
+
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            Some(line) => return Err(syntax(line)),
+            None => return Err(syntax((0, ""))),
+        }
+
+        let mut bundle = ReplayBundle {
+            fingerprint: String::new(),
+            height: 0,
+            public_inputs: Vec::new(),
+            steps: Vec::new(),
+        };
+
+        for (n, line) in lines {
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+            match key {
+                "fingerprint" => bundle.fingerprint = rest.to_string(),
+                "height" => bundle.height = rest.parse().map_err(|_| syntax((n, line)))?,
+                "public" => {
+                    bundle.public_inputs = rest
+                        .split_whitespace()
+                        .map(parse_value)
+                        .collect::<Result<_, _>>()?
+                }
+                "step" => bundle.steps.push((rest.to_string(), Vec::new())),
+                "assign" => {
+                    let (value, signal) = rest.split_once(' ').ok_or_else(|| syntax((n, line)))?;
+                    let step = bundle.steps.last_mut().ok_or_else(|| syntax((n, line)))?;
+
+                    step.1.push((signal.to_string(), parse_value(value)?));
+                }
+                "" => {}
+                _ => return Err(syntax((n, line))),
+            }
+        }
+
+        Ok(bundle)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        Self::parse(&fs::read_to_string(path).map_err(ReplayError::Io)?)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), ReplayError> {
+        fs::write(path, self.to_string()).map_err(ReplayError::Io)
+    }
+
+    /// Encodes the bundle in binary, with the public inputs as `encode_public_inputs` words and
+    /// the names of the steps and signals first, and then the values of the assignments
+    /// compressed with `compress_values`. Integers and string lengths are little-endian `u32`.
+    pub fn to_compressed(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut values = Vec::new();
+
+        put_string(&mut out, &self.fingerprint);
+        put_u32(&mut out, self.height);
+        put_u32(&mut out, self.public_inputs.len());
+        out.extend_from_slice(&encode_public_inputs(&self.public_inputs));
+        put_u32(&mut out, self.steps.len());
+        for (step, assignments) in self.steps.iter() {
+            put_string(&mut out, step);
+            put_u32(&mut out, assignments.len());
+            for (signal, value) in assignments.iter() {
+                put_string(&mut out, signal);
+                values.push(*value);
+            }
+        }
+        out.extend_from_slice(&compress_values(&values));
+
+        out
+    }
+
+    /// Decodes `to_compressed`, decompressing the values directly into the assignments of each
+    /// step. Every count is checked against the length of the rest of the input before anything
+    /// is allocated for it, so a malformed bundle fails instead of exhausting the memory.
+    pub fn from_compressed(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut rest = bytes;
+
+        let fingerprint = take_string(&mut rest)?;
+        let height = take_u32(&mut rest)?;
+        let public = take_count(&mut rest, WORD_SIZE)?;
+        let (words, tail) = rest.split_at(public * WORD_SIZE);
+        rest = tail;
+        let public_inputs = decode_public_inputs(words).map_err(ReplayError::Value)?;
+
+        // a step is at least the length of its name and its number of signals, a signal at
+        // least the length of its name
+        let count = take_count(&mut rest, 8)?;
+        let mut steps = Vec::with_capacity(count);
+        for _ in 0..count {
+            let step = take_string(&mut rest)?;
+            let signals = (0..take_count(&mut rest, 4)?)
+                .map(|_| take_string(&mut rest).map(|signal| (signal, F::ZERO)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            steps.push((step, signals));
+        }
+
+        let mut decoder = ZeroRunDecoder::new(rest);
+        let mut buffer = Vec::new();
+        for (_, assignments) in steps.iter_mut() {
+            buffer.resize(assignments.len(), F::ZERO);
+            decoder
+                .decode_exact(&mut buffer)
+                .map_err(ReplayError::Compressed)?;
+            for ((_, value), decoded) in assignments.iter_mut().zip(buffer.iter()) {
+                *value = *decoded;
+            }
+        }
+        if !decoder.is_done() {
+            return Err(ReplayError::Compressed(CompressError::Overflow));
+        }
+
+        Ok(Self {
+            fingerprint,
+            height,
+            public_inputs,
+            steps,
+        })
+    }
+
+    /// Writes the bundle to `path` if `result` is an error, typically the result of
+    /// `MockProver::verify`, and returns `result` unchanged, or the error of writing the bundle.
+    pub fn dump_on_failure<T, E, P: AsRef<Path>>(
+        &self,
+        result: Result<T, E>,
+        path: P,
+    ) -> Result<Result<T, E>, ReplayError> {
+        if result.is_err() {
+            self.write(&path)?;
+        }
+
+        Ok(result)
+    }
+}
+
+impl<F: PrimeField> fmt::Display for ReplayBundle<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "fingerprint {}", self.fingerprint)?;
+        writeln!(f, "height {}", self.height)?;
+
+        write!(f, "public")?;
+        for input in self.public_inputs.iter() {
+            write!(f, " {}", format_value(input))?;
+        }
+        writeln!(f)?;
+
+        for (step, assignments) in self.steps.iter() {
+            writeln!(f, "step {}", step)?;
+            for (signal, value) in assignments.iter() {
+                writeln!(f, "assign {} {}", format_value(value), signal)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves the step types and signals of `steps`, given by name, in `circuit`, see
+/// `NamedSignals`.
+pub(crate) fn named_witness<F: Copy + Eq + Hash, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    height: usize,
+    steps: &[(String, Vec<(String, F)>)],
+) -> Result<TraceWitness<F>, ReplayError> {
+    let mut names = NamedSignals::new(circuit);
+    let step_instances = steps
+        .iter()
+        .map(|(name, assignments)| names.instance(name, assignments))
+        .collect::<Result<_, _>>()?;
+
+    Ok(TraceWitness {
+        step_instances,
+        height,
+        public_values: Vec::new(),
+    })
+}
+
+/// The step types and signals of a circuit by name, to build step instances from assignments
+/// given by name. Internal signals are looked up in their step type, and forward signals by their
+/// name or `next(name)`. The names are indexed once, and the signals of each step type the first
+/// time it is found, so resolving an assignment does not depend on the number of signals.
+pub(crate) struct NamedSignals<'a, F, StepArgs> {
+    aliases: &'a Aliases,
+    step_types: HashMap<&'a str, Vec<&'a Rc<StepType<F, StepArgs>>>>,
+    forward: HashMap<String, Queriable<F>>,
+    /// The internal signals by name and the signal index of each step type, by uuid.
+    internal: HashMap<u32, (SignalNames<F>, Arc<SignalIndex<F>>)>,
+}
+
+type SignalNames<F> = HashMap<String, Queriable<F>>;
+
+impl<'a, F: Copy + Eq + Hash, StepArgs> NamedSignals<'a, F, StepArgs> {
+    pub(crate) fn new<TraceArgs>(circuit: &'a Circuit<F, TraceArgs, StepArgs>) -> Self {
+        let mut forward = HashMap::new();
+        for signal in circuit.placement.forward.keys() {
+            for queriable in [
+                Queriable::Forward(*signal, false),
+                Queriable::Forward(*signal, true),
+            ] {
+                insert_names(&mut forward, &circuit.aliases, queriable);
+            }
+        }
+
+        let mut step_types: HashMap<&str, Vec<_>> = HashMap::new();
+        for step in circuit.step_types.values() {
+            step_types.entry(&step.name).or_default().push(step);
+            for alias in circuit.aliases.get(step.uuid()) {
+                if *alias != step.name {
+                    step_types.entry(alias).or_default().push(step);
+                }
+            }
+        }
+
+        Self {
+            aliases: &circuit.aliases,
+            step_types,
+            forward,
+            internal: HashMap::new(),
+        }
+    }
+
+    /// A step instance of the step type `name` with `assignments`.
+    pub(crate) fn instance(
+        &mut self,
+        name: &str,
+        assignments: &[(String, F)],
+    ) -> Result<StepInstance<F>, ReplayError> {
+        let step = match self.step_types.get(name).map(|steps| steps.as_slice()) {
+            Some([step]) => *step,
+            Some([_, _, ..]) => return Err(ReplayError::AmbiguousStepType(name.to_string())),
+            _ => return Err(ReplayError::UnknownStepType(name.to_string())),
+        };
+        let aliases = self.aliases;
+        let (signals, index) = self.internal.entry(step.uuid()).or_insert_with(|| {
+            let mut signals = HashMap::new();
+            for signal in step.signals.iter() {
+                insert_names(&mut signals, aliases, Queriable::Internal(*signal));
+            }
+
+            (signals, Arc::new(SignalIndex::of_step(step)))
+        });
+
+        let mut instance = StepInstance::with_index(step.uuid(), Arc::clone(index));
+        for (signal, value) in assignments.iter() {
+            let queriable = signals
+                .get(signal)
+                .or_else(|| self.forward.get(signal))
+                .ok_or_else(|| ReplayError::UnknownSignal {
+                    step_type: name.to_string(),
+                    signal: signal.clone(),
+                })?;
+
+            instance.assignments.insert(*queriable, *value);
+        }
+
+        Ok(instance)
+    }
+}
+
+/// Adds the annotation and the aliases of `queriable` to `names`, unless a signal added before
+/// already has the name.
+fn insert_names<F>(names: &mut SignalNames<F>, aliases: &Aliases, queriable: Queriable<F>) {
+    for name in [queriable.annotation()]
+        .into_iter()
+        .chain(aliases.get(queriable.uuid()).iter().cloned())
+    {
+        names.entry(name).or_insert(queriable);
+    }
+}
+
+fn format_value<F: PrimeField>(value: &F) -> String {
+    encode_public_inputs(&[*value])
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_value<F: PrimeField>(hex: &str) -> Result<F, ReplayError> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| ReplayError::Syntax {
+            line: 0,
+            content: hex.to_string(),
+        })?;
+
+    decode_public_inputs(&bytes)
+        .map_err(ReplayError::Value)?
+        .pop()
+        .ok_or(ReplayError::Value(PublicInputError::InvalidLength(0)))
+}
+
+pub(crate) fn put_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+
+pub(crate) fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_u32(out, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+
+pub(crate) fn take_u32(rest: &mut &[u8]) -> Result<usize, ReplayError> {
+    if rest.len() < 4 {
+        return Err(ReplayError::Compressed(CompressError::Truncated));
+    }
+    let (value, tail) = rest.split_at(4);
+    *rest = tail;
+
+    Ok(u32::from_le_bytes(value.try_into().unwrap()) as usize)
+}
+
+/// Takes a count of items of at least `size` bytes each, which must fit in the rest of the input.
+fn take_count(rest: &mut &[u8], size: usize) -> Result<usize, ReplayError> {
+    let count = take_u32(rest)?;
+    if count.saturating_mul(size) > rest.len() {
+        return Err(ReplayError::Compressed(CompressError::Truncated));
+    }
+
+    Ok(count)
+}
+
+pub(crate) fn take_string(rest: &mut &[u8]) -> Result<String, ReplayError> {
+    let len = take_u32(rest)?;
+    if rest.len() < len {
+        return Err(ReplayError::Compressed(CompressError::Truncated));
+    }
+    let (value, tail) = rest.split_at(len);
+    *rest = tail;
+
+    String::from_utf8(value.to_vec()).map_err(|_| ReplayError::Syntax {
+        line: 0,
+        content: String::from_utf8_lossy(value).to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_replay_bundle_roundtrip() {
+        let define = || {
+            circuit::<Fr, (), u64, _>("replay", |ctx| {
+                let a = ctx.forward("a");
+                let step = ctx.step_type("step");
+
+                ctx.step_type_def(step, |ctx| {
+                    let b = ctx.internal("b");
+
+                    ctx.wg(move |ctx, v| {
+                        ctx.assign(a, v.field());
+                        ctx.assign(b, (v * 2).field());
+                    })
+                });
+
+                ctx.trace(move |ctx, _| {
+                    ctx.add(&step, 1);
+                    ctx.add(&step, 2);
+                });
+            })
+        };
+
+        let compile =
+            |sc| Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let recorded = compile(define());
+        let mut ctx = GenericTraceContext::new(&recorded.step_types);
+        (recorded.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let bundle = ReplayBundle::new(&recorded, &witness, vec![Fr::from(7)]);
+        let parsed = ReplayBundle::<Fr>::parse(&bundle.to_string()).expect("should parse");
+        assert_eq!(parsed, bundle);
+        assert_eq!(
+            ReplayBundle::<Fr>::from_compressed(&bundle.to_compressed()).expect("should decode"),
+            bundle
+        );
+
+        // a count larger than the rest of the input is rejected before it is allocated
+        let mut malformed = Vec::new();
+        put_string(&mut malformed, &bundle.fingerprint);
+        put_u32(&mut malformed, 4);
+        put_u32(&mut malformed, u32::MAX as usize);
+        assert!(matches!(
+            ReplayBundle::<Fr>::from_compressed(&malformed),
+            Err(ReplayError::Compressed(CompressError::Truncated))
+        ));
+        let mut malformed = Vec::new();
+        put_string(&mut malformed, &bundle.fingerprint);
+        put_u32(&mut malformed, 4);
+        put_u32(&mut malformed, 0);
+        put_u32(&mut malformed, 1);
+        put_string(&mut malformed, "step");
+        put_u32(&mut malformed, u32::MAX as usize);
+        assert!(matches!(
+            ReplayBundle::<Fr>::from_compressed(&malformed),
+            Err(ReplayError::Compressed(CompressError::Truncated))
+        ));
+
+        // a new definition of the same circuit has different UUIDs
+        let replayed = compile(define());
+        let replayed_witness = parsed.witness(&replayed).expect("should rebuild witness");
+
+        assert_eq!(replayed_witness.step_instances.len(), 2);
+        // the steps of a step type share the signal index of their step type
+        let indices: Vec<_> = replayed_witness
+            .step_instances
+            .iter()
+            .map(|step| step.assignments.index().expect("should be indexed"))
+            .collect();
+        assert!(Arc::ptr_eq(indices[0], indices[1]));
+        assert_eq!(
+            ReplayBundle::new(&replayed, &replayed_witness, vec![Fr::from(7)]),
+            bundle
+        );
+    }
+
+    #[test]
+    fn test_replay_bundle_aliases() {
+        let compile =
+            |sc| Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+
+        let recorded = compile(circuit::<Fr, (), u64, _>("replay", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("b");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                })
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 1));
+        }));
+        let mut ctx = GenericTraceContext::new(&recorded.step_types);
+        (recorded.trace.as_ref().unwrap())(&mut ctx, ());
+        let bundle = ReplayBundle::new(&recorded, &ctx.get_witness(), vec![]);
+
+        // the same circuit with its signals and step type renamed, keeping the old names
+        let renamed = compile(circuit::<Fr, (), u64, _>("replay", |ctx| {
+            let a = ctx.forward("input");
+            ctx.alias(a, "a");
+            let step = ctx.step_type("double");
+            ctx.step_type_alias(step, "step");
+
+            ctx.step_type_def(step, |ctx| {
+                let b = ctx.internal("doubled");
+                ctx.alias(b, "b");
+
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(b, (v * 2).field());
+                })
+            });
+            ctx.trace(move |ctx, _| ctx.add(&step, 1));
+        }));
+
+        assert_eq!(fingerprint(&renamed), bundle.fingerprint);
+        let witness = bundle.witness(&renamed).expect("should resolve aliases");
+        assert_eq!(ReplayBundle::new(&renamed, &witness, vec![]), bundle);
+
+        let by_new_names = ReplayBundle {
+            steps: vec![(
+                "double".to_string(),
+                vec![
+                    ("doubled".to_string(), Fr::from(2)),
+                    ("input".to_string(), Fr::from(1)),
+                ],
+            )],
+            ..bundle
+        };
+        assert_eq!(by_new_names.witness(&renamed).unwrap(), witness);
+    }
+
+    #[test]
+    fn test_dump_on_failure() {
+        let bundle = ReplayBundle::<Fr> {
+            fingerprint: "fingerprint".to_string(),
+            height: 0,
+            public_inputs: vec![Fr::from(7)],
+            steps: vec![("step".to_string(), vec![("a".to_string(), Fr::from(1))])],
+        };
+        let path = std::env::temp_dir().join(format!("chiquito-replay-{}.txt", std::process::id()));
+
+        assert!(matches!(
+            bundle.dump_on_failure(Ok::<_, ()>(1), &path),
+            Ok(Ok(1))
+        ));
+        assert!(!path.exists());
+
+        assert!(matches!(
+            bundle.dump_on_failure(Err::<(), _>(2), &path),
+            Ok(Err(2))
+        ));
+        assert_eq!(
+            ReplayBundle::<Fr>::read(&path).expect("should read"),
+            bundle
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // a bundle that cannot be written is reported instead of being lost
+        let missing = path.join("missing").join("bundle.txt");
+        assert!(matches!(
+            bundle.dump_on_failure(Err::<(), _>(2), missing),
+            Err(ReplayError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+//! Splitting a witness in two parts, for example to keep the assignments of some signals
+//! apart from the others until the circuit is synthesized, and joining them again. This is a
+//! bookkeeping utility: the whole witness is generated before it is split, and the digest of a
+//! `SplitWitness` only detects that its part was changed. It is not bound into the circuit or
+//! the proof, so it is not a commitment of the protocol.
+
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{ast::query::Queriable, ir::Circuit};
+
+use super::{public_inputs::keccak256, replay::ReplayBundle, StepInstance, TraceWitness};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitWitnessError {
+    StepCount {
+        selected: usize,
+        rest: usize,
+    },
+    StepType {
+        step: usize,
+    },
+    /// A signal is assigned in both parts of the step.
+    Overlap {
+        step: usize,
+        signal: String,
+    },
+    DigestMismatch,
+}
+
+impl fmt::Display for SplitWitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitWitnessError::StepCount { selected, rest } => write!(
+                f,
+                "selected part of the witness has {} steps, the rest has {}",
+                selected, rest
+            ),
+            SplitWitnessError::StepType { step } => write!(
+                f,
+                "step {} has a different step type in the two parts of the witness",
+                step
+            ),
+            SplitWitnessError::Overlap { step, signal } => write!(
+                f,
+                "signal {} is assigned in both parts of the witness of step {}",
+                signal, step
+            ),
+            SplitWitnessError::DigestMismatch => {
+                write!(f, "selected part of the witness does not match its digest")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SplitWitnessError {}
+
+impl<F: Eq + Hash> TraceWitness<F> {
+    /// Splits the witness in the assignments of the `selected` signals and the others, with the
+    /// same steps in both parts. The public values go with the selected part.
+    pub fn split(self, selected: &[Queriable<F>]) -> (TraceWitness<F>, TraceWitness<F>) {
+        let mut selected_part = TraceWitness {
+            step_instances: Vec::new(),
+            height: self.height,
+            public_values: self.public_values,
+        };
+        let mut rest = TraceWitness {
+            step_instances: Vec::new(),
+            height: self.height,
+            public_values: Vec::new(),
+        };
+
+        for instance in self.step_instances {
+            let mut selected_step = StepInstance::new(instance.step_type_uuid);
+            let mut rest_step = StepInstance::new(instance.step_type_uuid);
+
+            for (signal, value) in instance.assignments {
+                if selected.contains(&signal) {
+                    selected_step.assignments.insert(signal, value);
+                } else {
+                    rest_step.assignments.insert(signal, value);
+                }
+            }
+
+            selected_part.step_instances.push(selected_step);
+            rest.step_instances.push(rest_step);
+        }
+
+        (selected_part, rest)
+    }
+
+    /// Joins the two parts of a split witness, which must have the same steps and assign
+    /// different signals.
+    pub fn merge(
+        selected: TraceWitness<F>,
+        rest: TraceWitness<F>,
+    ) -> Result<TraceWitness<F>, SplitWitnessError> {
+        if selected.step_instances.len() != rest.step_instances.len() {
+            return Err(SplitWitnessError::StepCount {
+                selected: selected.step_instances.len(),
+                rest: rest.step_instances.len(),
+            });
+        }
+
+        let mut witness = TraceWitness {
+            step_instances: Vec::new(),
+            height: selected.height.max(rest.height),
+            public_values: selected.public_values,
+        };
+
+        for (step, (mut instance, rest)) in selected
+            .step_instances
+            .into_iter()
+            .zip(rest.step_instances)
+            .enumerate()
+        {
+            if instance.step_type_uuid != rest.step_type_uuid {
+                return Err(SplitWitnessError::StepType { step });
+            }
+
+            for (signal, value) in rest.assignments {
+                if instance.assignments.contains_key(&signal) {
+                    return Err(SplitWitnessError::Overlap {
+                        step,
+                        signal: signal.annotation(),
+                    });
+                }
+                instance.assignments.insert(signal, value);
+            }
+
+            witness.step_instances.push(instance);
+        }
+
+        Ok(witness)
+    }
+}
+
+/// The selected part of a split witness with its digest, the keccak256 hash of the compressed
+/// replay bundle of the part, which identifies the step types and signals by name and does not
+/// change between compilations of the circuit. The digest only checks that the part is joined
+/// unchanged, see the module documentation.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SplitWitness<F> {
+    pub selected: TraceWitness<F>,
+    pub digest: [u8; 32],
+}
+
+impl<F: PrimeField + Hash> SplitWitness<F> {
+    pub fn new<TraceArgs, StepArgs>(
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        selected: TraceWitness<F>,
+    ) -> Self {
+        let digest = witness_digest(circuit, &selected);
+
+        Self { selected, digest }
+    }
+
+    /// Checks the selected part against the digest and joins it with `rest`.
+    pub fn join<TraceArgs, StepArgs>(
+        self,
+        circuit: &Circuit<F, TraceArgs, StepArgs>,
+        rest: TraceWitness<F>,
+    ) -> Result<TraceWitness<F>, SplitWitnessError> {
+        if witness_digest(circuit, &self.selected) != self.digest {
+            return Err(SplitWitnessError::DigestMismatch);
+        }
+
+        TraceWitness::merge(self.selected, rest)
+    }
+}
+
+pub fn witness_digest<F: PrimeField + Hash, TraceArgs, StepArgs>(
+    circuit: &Circuit<F, TraceArgs, StepArgs>,
+    witness: &TraceWitness<F>,
+) -> [u8; 32] {
+    keccak256(&ReplayBundle::new(circuit, witness, Vec::new()).to_compressed())
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        compiler::{
+            cell_manager::SingleRowCellManager, step_selector::SimpleStepSelectorBuilder, Compiler,
+        },
+        dsl::circuit,
+        wit_gen::GenericTraceContext,
+    };
+
+    #[test]
+    fn test_split_witness() {
+        let sc = circuit::<Fr, (), u64, _>("split", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| {
+                let secret = ctx.internal("secret");
+                ctx.wg(move |ctx, v| {
+                    ctx.assign(a, v.field());
+                    ctx.assign(secret, (v * 3).field());
+                })
+            });
+            ctx.trace(move |ctx, _| {
+                ctx.add(&step, 1);
+                ctx.add(&step, 2);
+            });
+        });
+
+        let compiled =
+            Compiler::new(SingleRowCellManager {}, SimpleStepSelectorBuilder {}).compile(&sc);
+        let witness = || {
+            let mut ctx = GenericTraceContext::new(&compiled.step_types);
+            (compiled.trace.as_ref().unwrap())(&mut ctx, ());
+
+            ctx.get_witness()
+        };
+        let a = Queriable::Forward(sc.forward_signals[0], false);
+
+        let (selected, rest) = witness().split(&[a]);
+        assert_eq!(selected.step_instances[1].assignments.len(), 1);
+        assert_eq!(rest.step_instances[1].assignments.len(), 1);
+
+        let split = SplitWitness::new(&compiled, selected);
+        assert_eq!(split.join(&compiled, rest), Ok(witness()));
+
+        assert!(matches!(
+            TraceWitness::merge(witness(), witness()),
+            Err(SplitWitnessError::Overlap { step: 0, .. })
+        ));
+
+        let (selected, rest) = witness().split(&[a]);
+        let mut split = SplitWitness::new(&compiled, selected);
+        split.digest[0] ^= 1;
+        assert_eq!(
+            split.join(&compiled, rest),
+            Err(SplitWitnessError::DigestMismatch)
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::{arithmetic::Field, halo2curves::group::ff::PrimeField};
+
+use crate::ast::{query::Queriable, StepType};
+
+use super::{assignments::Assignments, format::format_field, StepInstance};
+
+/// A constraint of a step type that does not hold for the assignments under test.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepFailure<F> {
+    /// The constraint evaluates to `value` instead of zero.
+    NotSatisfied { constraint: String, value: F },
+    /// The constraint queries `signal`, which has no value in the assignments.
+    Unassigned { constraint: String, signal: String },
+}
+
+impl<F: PrimeField> fmt::Display for StepFailure<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepFailure::NotSatisfied { constraint, value } => {
+                write!(
+                    f,
+                    "constraint {} not satisfied, evaluates to {}",
+                    constraint,
+                    format_field(value)
+                )
+            }
+            StepFailure::Unassigned { constraint, signal } => write!(
+                f,
+                "constraint {} queries unassigned signal {}",
+                constraint, signal
+            ),
+        }
+    }
+}
+
+/// Evaluates the constraints of a single step type against `assignments`, without building a
+/// circuit or a trace. `next` is the instance of the following step: its forward signals give
+/// the values of `next(signal)` queries, and its step type the value of `StepTypeNext` queries.
+/// Transition constraints are only evaluated when `next` is given.
+///
+/// Returns the constraints that do not hold, in declaration order. Lookups are not checked.
+pub fn test_step<F: Field + Hash, Args>(
+    step: &StepType<F, Args>,
+    assignments: &Assignments<F>,
+    next: Option<&StepInstance<F>>,
+) -> Vec<StepFailure<F>> {
+    evaluate(step, assignments, next, true)
+}
+
+/// Like `test_step`, evaluating only the transition constraints.
+pub(crate) fn test_transitions<F: Field + Hash, Args>(
+    step: &StepType<F, Args>,
+    assignments: &Assignments<F>,
+    next: &StepInstance<F>,
+) -> Vec<StepFailure<F>> {
+    evaluate(step, assignments, Some(next), false)
+}
+
+fn evaluate<F: Field + Hash, Args>(
+    step: &StepType<F, Args>,
+    assignments: &Assignments<F>,
+    next: Option<&StepInstance<F>>,
+    with_constraints: bool,
+) -> Vec<StepFailure<F>> {
+    let resolve = |queriable: &Queriable<F>| match queriable {
+        Queriable::Forward(signal, true) => next
+            .and_then(|next| next.assignments.get(&Queriable::Forward(*signal, false)))
+            .copied(),
+        Queriable::StepTypeNext(handler) => next.map(|next| {
+            if next.step_type_uuid == handler.uuid() {
+                F::ONE
+            } else {
+                F::ZERO
+            }
+        }),
+        _ => assignments.get(queriable).copied(),
+    };
+
+    let constraints = step
+        .constraints
+        .iter()
+        .filter(|_| with_constraints)
+        .map(|constraint| (&constraint.annotation, &constraint.expr));
+    let transitions = step
+        .transition_constraints
+        .iter()
+        .filter(|_| next.is_some())
+        .map(|constraint| (&constraint.annotation, &constraint.expr));
+
+    constraints
+        .chain(transitions)
+        .filter_map(|(annotation, expr)| match expr.eval(&resolve) {
+            Ok(value) if value == F::ZERO => None,
+            Ok(value) => Some(StepFailure::NotSatisfied {
+                constraint: annotation.clone(),
+                value,
+            }),
+            Err(signal) => Some(StepFailure::Unassigned {
+                constraint: annotation.clone(),
+                signal: signal.annotation(),
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        dsl::{cb::eq, circuit},
+    };
+
+    #[test]
+    fn test_step_constraints() {
+        let sc = circuit::<Fr, (), (), _>("fibo", |ctx| {
+            let a = ctx.forward("a");
+            let b = ctx.forward("b");
+            let step = ctx.step_type("fibo");
+
+            ctx.step_type_def(step, |ctx| {
+                let c = ctx.internal("c");
+
+                ctx.setup(move |ctx| {
+                    ctx.constr(eq(a + b, c));
+                    ctx.transition(eq(b, a.next()));
+                    ctx.transition(eq(c, b.next()));
+                });
+            });
+        });
+
+        let step = sc.step_types.values().next().unwrap();
+        let a = sc.forward_signals[0];
+        let b = sc.forward_signals[1];
+        let c = Queriable::Internal(step.signals[0]);
+
+        let assignments: Assignments<Fr> = [
+            (Queriable::Forward(a, false), 1.field()),
+            (Queriable::Forward(b, false), 2.field()),
+            (c, 3.field()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(test_step(step, &assignments, None), vec![]);
+
+        let mut next = StepInstance::new(step.uuid());
+        next.assignments
+            .insert(Queriable::Forward(a, false), 2.field());
+        next.assignments
+            .insert(Queriable::Forward(b, false), 4.field());
+
+        assert_eq!(
+            test_step(step, &assignments, Some(&next)),
+            vec![StepFailure::NotSatisfied {
+                constraint: "c == next(b)".to_string(),
+                value: Fr::from(3) - Fr::from(4),
+            }]
+        );
+
+        next.assignments.remove(&Queriable::Forward(a, false));
+        assert_eq!(
+            test_step(step, &assignments, Some(&next))[0],
+            StepFailure::Unassigned {
+                constraint: "b == next(a)".to_string(),
+                signal: "next(a)".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::ast::{StepType, StepTypeUUID};
+
+use super::TraceWitness;
+
+/// Width of a step in the timeline, in pixels before zooming.
+const STEP_WIDTH: usize = 8;
+const BAR_HEIGHT: usize = 32;
+const LEGEND_ROW: usize = 20;
+const LEGEND_WIDTH: usize = 320;
+
+/// Consecutive steps of the same step type.
+struct Run {
+    step_type: StepTypeUUID,
+    start: usize,
+    len: usize,
+}
+
+impl<F> TraceWitness<F> {
+    /// Draws the step type of each step of the witness as an SVG timeline, a colored block for
+    /// each run of steps of the same step type, with a legend of the step types and their number
+    /// of steps. Hovering a block shows its step type and steps.
+    pub fn timeline_svg<StepArgs>(
+        &self,
+        step_types: &HashMap<u32, Rc<StepType<F, StepArgs>>>,
+    ) -> String {
+        let runs = self.runs();
+        let mut legend: Vec<(StepTypeUUID, usize)> = Vec::new();
+        for run in runs.iter() {
+            match legend.iter_mut().find(|(uuid, _)| *uuid == run.step_type) {
+                Some((_, steps)) => *steps += run.len,
+                None => legend.push((run.step_type, run.len)),
+            }
+        }
+
+        let name = |uuid: StepTypeUUID| match step_types.get(&uuid) {
+            Some(step) => escape_xml(&step.name),
+            None => format!("step type {}", uuid),
+        };
+        let color = |uuid: StepTypeUUID| {
+            let index = legend.iter().position(|(other, _)| *other == uuid).unwrap();
+            format!("hsl({}, 65%, 55%)", index * 137 % 360)
+        };
+
+        let width = (self.step_instances.len() * STEP_WIDTH).max(LEGEND_WIDTH);
+        let height = BAR_HEIGHT + LEGEND_ROW * (legend.len() + 1);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"monospace\" font-size=\"12\">\n",
+            width, height, width, height
+        );
+
+        for run in runs.iter() {
+            svg.push_str(&format!(
+                "  <rect x=\"{}\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"{}\"><title>{}: steps {}..{}</title></rect>\n",
+                run.start * STEP_WIDTH,
+                run.len * STEP_WIDTH,
+                BAR_HEIGHT,
+                color(run.step_type),
+                name(run.step_type),
+                run.start,
+                run.start + run.len
+            ));
+        }
+
+        for (row, (uuid, steps)) in legend.iter().enumerate() {
+            let y = BAR_HEIGHT + LEGEND_ROW * (row + 1);
+            svg.push_str(&format!(
+                "  <rect x=\"0\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{}\"/><text x=\"18\" y=\"{}\">{} ({} steps)</text>\n",
+                y - 11,
+                color(*uuid),
+                y,
+                name(*uuid),
+                steps
+            ));
+        }
+        svg.push_str("</svg>\n");
+
+        svg
+    }
+
+    /// The `timeline_svg` in a standalone HTML page, with buttons to zoom in and out of long
+    /// traces.
+    pub fn timeline_html<StepArgs>(
+        &self,
+        step_types: &HashMap<u32, Rc<StepType<F, StepArgs>>>,
+    ) -> String {
+        format!(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>step timeline</title>
+<style>#timeline {{ overflow-x: auto; }}</style>
+</head>
+<body>
+<p>{} steps <button onclick=\"zoom(2)\">+</button> <button onclick=\"zoom(0.5)\">-</button></p>
+<div id=\"timeline\">
+{}</div>
+<script>
+const svg = document.querySelector('#timeline svg');
+const width = svg.width.baseVal.value;
+const height = svg.height.baseVal.value;
+let scale = 1;
+function zoom(factor) {{
+  scale = Math.min(64, Math.max(1 / 8, scale * factor));
+  svg.setAttribute('width', width * scale);
+  svg.setAttribute('height', height * scale);
+}}
+</script>
+</body>
+</html>
+",
+            self.step_instances.len(),
+            self.timeline_svg(step_types)
+        )
+    }
+
+    fn runs(&self) -> Vec<Run> {
+        let mut runs: Vec<Run> = Vec::new();
+
+        for (index, instance) in self.step_instances.iter().enumerate() {
+            match runs.last_mut() {
+                Some(run) if run.step_type == instance.step_type_uuid => run.len += 1,
+                _ => runs.push(Run {
+                    step_type: instance.step_type_uuid,
+                    start: index,
+                    len: 1,
+                }),
+            }
+        }
+
+        runs
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use crate::{ast::ToField, dsl::circuit, wit_gen::GenericTraceContext};
+
+    #[test]
+    fn test_timeline_svg() {
+        let sc = circuit::<Fr, (), u64, _>("timeline", |ctx| {
+            let a = ctx.forward("a");
+            let add = ctx.step_type("add");
+            let padding = ctx.step_type("<padding>");
+
+            ctx.step_type_def(add, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.step_type_def(padding, |ctx| {
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()))
+            });
+            ctx.trace(move |ctx, _| {
+                for i in 0..3 {
+                    ctx.add(&add, i);
+                }
+                ctx.add(&padding, 0);
+                ctx.add(&padding, 0);
+            });
+        });
+
+        let mut ctx = GenericTraceContext::new(&sc.step_types);
+        (sc.trace.as_ref().unwrap())(&mut ctx, ());
+        let witness = ctx.get_witness();
+
+        let svg = witness.timeline_svg(&sc.step_types);
+        assert_eq!(svg.matches("<title>").count(), 2);
+        assert!(svg.contains("<title>add: steps 0..3</title>"));
+        assert!(svg.contains("<title>&lt;padding&gt;: steps 3..5</title>"));
+        assert!(svg.contains("&lt;padding&gt; (2 steps)</text>"));
+
+        let html = witness.timeline_html(&sc.step_types);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(&svg));
+    }
+}
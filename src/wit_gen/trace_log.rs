@@ -0,0 +1,192 @@
+use std::{collections::HashMap, fmt, hash::Hash};
+
+use halo2_proofs::arithmetic::Field;
+
+use crate::{compiler::TraceContext, dsl::StepTypeHandler};
+
+use super::{replay::ReplayError, GenericTraceContext, TraceWitness};
+
+const HEADER: &str = "chiquito trace 1";
+
+/// The sequence of steps added by a trace function, with their arguments encoded as text. Replaying
+/// it runs the witness generation of the steps again without the trace function, so that the
+/// witness of a log recorded on one machine can be compared with the witness generated on another.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceLog {
+    pub height: usize,
+    /// Step type name and encoded arguments of each step.
+    pub steps: Vec<(String, String)>,
+}
+
+/// A `TraceContext` that records the steps added to a `GenericTraceContext`.
+pub struct RecordingTraceContext<'a, F, StepArgs> {
+    inner: GenericTraceContext<'a, F, StepArgs>,
+    encode: &'a dyn Fn(&StepArgs) -> String,
+    log: TraceLog,
+}
+
+impl<'a, F, StepArgs> RecordingTraceContext<'a, F, StepArgs> {
+    /// `encode` must not produce line breaks.
+    pub fn new(
+        inner: GenericTraceContext<'a, F, StepArgs>,
+        encode: &'a dyn Fn(&StepArgs) -> String,
+    ) -> Self {
+        Self {
+            inner,
+            encode,
+            log: TraceLog::default(),
+        }
+    }
+
+    pub fn finish(self) -> (TraceWitness<F>, TraceLog) {
+        (self.inner.get_witness(), self.log)
+    }
+}
+
+impl<'a, F: Field + Hash, StepArgs> TraceContext<StepArgs>
+    for RecordingTraceContext<'a, F, StepArgs>
+{
+    fn add(&mut self, step: &StepTypeHandler, args: StepArgs) {
+        let encoded = (self.encode)(&args);
+        if encoded.contains('\n') {
+            panic!("encoded step arguments contain a line break: {}", encoded);
+        }
+
+        self.log.steps.push((step.annotation.to_string(), encoded));
+        self.inner.add(step, args);
+    }
+
+    fn set_height(&mut self, height: usize) {
+        self.log.height = height;
+        self.inner.set_height(height);
+    }
+
+    fn steps(&self) -> usize {
+        self.inner.steps()
+    }
+
+    fn num_steps(&self) -> Option<usize> {
+        self.inner.num_steps()
+    }
+}
+
+impl TraceLog {
+    /// Adds the recorded steps to `ctx`, with their arguments decoded by `decode`, and returns the
+    /// witness. Step types are found by name in the step types of `ctx`.
+    pub fn replay<F: Field + Hash, StepArgs>(
+        &self,
+        mut ctx: GenericTraceContext<F, StepArgs>,
+        decode: &dyn Fn(&str) -> StepArgs,
+    ) -> Result<TraceWitness<F>, ReplayError> {
+        // one handler for each step type found, not for each step
+        let mut handlers: HashMap<&str, StepTypeHandler> = HashMap::new();
+        for (name, args) in self.steps.iter() {
+            let handler = match handlers.get(name.as_str()) {
+                Some(handler) => *handler,
+                None => {
+                    let mut steps = ctx.step_types.values().filter(|step| step.name == *name);
+                    let step = steps
+                        .next()
+                        .ok_or_else(|| ReplayError::UnknownStepType(name.clone()))?;
+                    if steps.next().is_some() {
+                        return Err(ReplayError::AmbiguousStepType(name.clone()));
+                    }
+
+                    let handler = StepTypeHandler::with_uuid(step.uuid(), name);
+                    handlers.insert(name, handler);
+
+                    handler
+                }
+            };
+
+            ctx.add(&handler, decode(args));
+        }
+
+        ctx.set_height(self.height);
+
+        Ok(ctx.get_witness())
+    }
+
+    pub fn parse(source: &str) -> Result<Self, ReplayError> {
+        let mut lines = source.lines().enumerate();
+        let syntax = |(n, line): (usize, &str)| ReplayError::Syntax {
+            line: n + 1,
+            content: line.to_string(),
+        };
+
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            Some(line) => return Err(syntax(line)),
+            None => return Err(syntax((0, ""))),
+        }
+
+        let mut log = TraceLog::default();
+        for (n, line) in lines {
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+            match key {
+                "height" => log.height = rest.parse().map_err(|_| syntax((n, line)))?,
+                "add" => {
+                    let (step, args) = rest.split_once(' ').unwrap_or((rest, ""));
+                    log.steps.push((step.to_string(), args.to_string()));
+                }
+                "" => {}
+                _ => return Err(syntax((n, line))),
+            }
+        }
+
+        Ok(log)
+    }
+}
+
+impl fmt::Display for TraceLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "height {}", self.height)?;
+        for (step, args) in self.steps.iter() {
+            writeln!(f, "add {} {}", step, args)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{ast::ToField, dsl::circuit};
+
+    #[test]
+    fn test_trace_log_replay() {
+        let sc = circuit::<Fr, u64, u64, _>("log", |ctx| {
+            let a = ctx.forward("a");
+            let step = ctx.step_type("step");
+
+            ctx.step_type_def(step, |ctx| ctx.wg(move |ctx, v| ctx.assign(a, v.field())));
+            ctx.trace(move |ctx, n| {
+                for i in 0..n {
+                    ctx.add(&step, i * i);
+                }
+                ctx.set_height(8);
+            });
+        });
+
+        let encode = |args: &u64| args.to_string();
+        let mut ctx = RecordingTraceContext::new(GenericTraceContext::new(&sc.step_types), &encode);
+        (sc.trace.as_ref().unwrap())(&mut ctx, 3);
+        let (witness, log) = ctx.finish();
+
+        let parsed = TraceLog::parse(&log.to_string()).expect("should parse");
+        assert_eq!(parsed, log);
+        assert_eq!(parsed.steps[2], ("step".to_string(), "4".to_string()));
+
+        let replayed = parsed
+            .replay(GenericTraceContext::new(&sc.step_types), &|args| {
+                args.parse().unwrap()
+            })
+            .expect("should replay");
+        assert_eq!(replayed, witness);
+    }
+}
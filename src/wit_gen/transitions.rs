@@ -0,0 +1,317 @@
+use std::{fmt, hash::Hash};
+
+use halo2_proofs::halo2curves::group::ff::PrimeField;
+
+use crate::{
+    ast::{Circuit, StepTypeUUID},
+    compiler::{config::CompilerConfig, TraceContext},
+    dsl::StepTypeHandler,
+    ir,
+};
+
+use super::{
+    fuzz::FuzzRng,
+    step_test::{test_step, test_transitions, StepFailure},
+    GenericTraceContext, StepInstance,
+};
+
+/// Number of forbidden transitions checked by default, see `TransitionTester::with_forbidden`.
+const FORBIDDEN_SAMPLE: usize = 16;
+
+/// A transition whose check did not give the expected result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransitionFailure<F> {
+    /// A declared transition does not verify, with the constraints that do not hold.
+    Rejected {
+        from: String,
+        to: String,
+        failures: Vec<StepFailure<F>>,
+    },
+    /// A transition that is not declared verifies.
+    Accepted { from: String, to: String },
+}
+
+impl<F: PrimeField> fmt::Display for TransitionFailure<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionFailure::Rejected { from, to, failures } => {
+                write!(f, "declared transition {} -> {} rejected:", from, to)?;
+                for failure in failures.iter() {
+                    write!(f, "\n  {}", failure)?;
+                }
+
+                Ok(())
+            }
+            TransitionFailure::Accepted { from, to } => {
+                write!(f, "forbidden transition {} -> {} accepted", from, to)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransitionReport<F> {
+    /// Number of declared and forbidden transitions checked.
+    pub declared: usize,
+    pub forbidden: usize,
+    pub failures: Vec<TransitionFailure<F>>,
+}
+
+impl<F> TransitionReport<F> {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl<F: PrimeField> fmt::Display for TransitionReport<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} declared and {} forbidden transitions checked",
+            self.declared, self.forbidden
+        )?;
+        for failure in self.failures.iter() {
+            write!(f, "\nFAILED {}", failure)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the transitions between the step types of a circuit, seen as a state machine whose
+/// states are the step types. Each transition declared with `transition` is run as a trace of
+/// two steps, and must satisfy the constraints of both. A sample of the transitions that are not
+/// declared must then be rejected:
+///
+/// ```ignore
+/// let report = TransitionTester::new(&circuit)
+///     .transition(&add, (1, 2), &add, (3, 3))
+///     .transition(&add, (1, 2), &halt, (3, 0))
+///     .run();
+/// assert!(report.is_ok(), "{}", report);
+/// ```
+///
+/// A forbidden transition `from -> to` reuses the steps of a declared transition from `from`,
+/// with the step type of the second step replaced by `to`. Its forward signals keep the values of
+/// a valid successor, so only the constraints on the step type of the next step can reject it.
+pub struct TransitionTester<'a, F, TraceArgs, StepArgs> {
+    circuit: &'a Circuit<F, TraceArgs, StepArgs>,
+    transitions: Vec<(StepTypeHandler, StepArgs, StepTypeHandler, StepArgs)>,
+    forbidden: usize,
+    rng: FuzzRng,
+}
+
+impl<'a, F, TraceArgs, StepArgs> TransitionTester<'a, F, TraceArgs, StepArgs>
+where
+    F: PrimeField + Hash,
+    StepArgs: Clone,
+{
+    pub fn new(circuit: &'a Circuit<F, TraceArgs, StepArgs>) -> Self {
+        Self {
+            circuit,
+            transitions: Vec::new(),
+            forbidden: FORBIDDEN_SAMPLE,
+            rng: FuzzRng::new(0x5eed),
+        }
+    }
+
+    /// Declares that a step of type `from`, generated with `from_args`, can be followed by a step
+    /// of type `to`, generated with `to_args`.
+    pub fn transition(
+        mut self,
+        from: &StepTypeHandler,
+        from_args: StepArgs,
+        to: &StepTypeHandler,
+        to_args: StepArgs,
+    ) -> Self {
+        self.transitions.push((*from, from_args, *to, to_args));
+
+        self
+    }
+
+    /// Checks at most `forbidden` of the transitions that are not declared, chosen at random.
+    pub fn with_forbidden(mut self, forbidden: usize) -> Self {
+        self.forbidden = forbidden;
+
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = FuzzRng::new(seed);
+
+        self
+    }
+
+    pub fn run(&mut self) -> TransitionReport<F> {
+        let compiled = CompilerConfig::default().compile(self.circuit);
+        let mut failures = Vec::new();
+        let mut declared = Vec::new();
+
+        for (from, from_args, to, to_args) in self.transitions.iter() {
+            let (first, second) = self.minimal_trace(&compiled, from, from_args, to, to_args);
+
+            let from_step = &compiled.step_types[&from.uuid()];
+            let to_step = &compiled.step_types[&to.uuid()];
+            let rejected: Vec<StepFailure<F>> =
+                test_step(from_step, &first.assignments, Some(&second))
+                    .into_iter()
+                    .chain(test_step(to_step, &second.assignments, None))
+                    .collect();
+
+            if !rejected.is_empty() {
+                failures.push(TransitionFailure::Rejected {
+                    from: from_step.name.clone(),
+                    to: to_step.name.clone(),
+                    failures: rejected,
+                });
+            }
+
+            declared.push((first, second));
+        }
+
+        // the undeclared successors of each step type, with its first declared transition
+        let mut step_types: Vec<StepTypeUUID> = compiled.step_types.keys().copied().collect();
+        step_types.sort();
+        let mut candidates: Vec<(usize, StepTypeUUID)> = Vec::new();
+        for (index, (first, _)) in declared.iter().enumerate() {
+            let from = first.step_type_uuid;
+            if declared[..index]
+                .iter()
+                .any(|(other, _)| other.step_type_uuid == from)
+            {
+                continue;
+            }
+
+            candidates.extend(
+                step_types
+                    .iter()
+                    .filter(|to| {
+                        !declared.iter().any(|(first, second)| {
+                            first.step_type_uuid == from && second.step_type_uuid == **to
+                        })
+                    })
+                    .map(|to| (index, *to)),
+            );
+        }
+
+        let mut forbidden = 0;
+        while forbidden < self.forbidden && !candidates.is_empty() {
+            let (index, to) =
+                candidates.swap_remove(self.rng.below(candidates.len() as u64) as usize);
+            let (first, second) = &declared[index];
+            let from_step = &compiled.step_types[&first.step_type_uuid];
+
+            let mut next = second.clone();
+            next.step_type_uuid = to;
+            if test_transitions(from_step, &first.assignments, &next).is_empty() {
+                failures.push(TransitionFailure::Accepted {
+                    from: from_step.name.clone(),
+                    to: compiled.step_types[&to].name.clone(),
+                });
+            }
+
+            forbidden += 1;
+        }
+
+        TransitionReport {
+            declared: self.transitions.len(),
+            forbidden,
+            failures,
+        }
+    }
+
+    fn minimal_trace(
+        &self,
+        compiled: &ir::Circuit<F, TraceArgs, StepArgs>,
+        from: &StepTypeHandler,
+        from_args: &StepArgs,
+        to: &StepTypeHandler,
+        to_args: &StepArgs,
+    ) -> (StepInstance<F>, StepInstance<F>) {
+        let mut ctx = GenericTraceContext::new(&compiled.step_types);
+        ctx.add(from, from_args.clone());
+        ctx.add(to, to_args.clone());
+
+        let mut steps = ctx.get_witness().step_instances.into_iter();
+
+        (steps.next().unwrap(), steps.next().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use halo2_proofs::halo2curves::bn256::Fr;
+
+    use super::*;
+    use crate::{
+        ast::ToField,
+        dsl::{
+            cb::{eq, next_step_must_be, next_step_must_not_be},
+            circuit,
+        },
+    };
+
+    #[test]
+    fn test_transition_tester() {
+        let sc = circuit::<Fr, (), u64, _>("machine", |ctx| {
+            let a = ctx.forward("a");
+            let start = ctx.step_type("start");
+            let add = ctx.step_type("add");
+            let halt = ctx.step_type("halt");
+
+            ctx.step_type_def(start, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.transition(next_step_must_be(add));
+                    ctx.transition(eq(a, a.next()));
+                });
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.step_type_def(add, |ctx| {
+                ctx.setup(move |ctx| {
+                    ctx.transition(next_step_must_not_be(start));
+                    ctx.transition(eq(a + 1, a.next()));
+                });
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+            ctx.step_type_def(halt, |ctx| {
+                ctx.wg(move |ctx, v| ctx.assign(a, v.field()));
+            });
+        });
+        let [start, add, halt] = ["start", "add", "halt"].map(|name| {
+            let step = sc
+                .step_types
+                .values()
+                .find(|step| step.name == name)
+                .unwrap();
+            StepTypeHandler::with_uuid(step.uuid(), name)
+        });
+
+        let report = TransitionTester::new(&sc)
+            .transition(&start, 1, &add, 1)
+            .transition(&add, 1, &add, 2)
+            .transition(&add, 1, &halt, 2)
+            .run();
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!((report.declared, report.forbidden), (3, 3));
+
+        // add -> halt is not declared, and start -> halt is not allowed
+        let report = TransitionTester::new(&sc)
+            .transition(&start, 1, &add, 1)
+            .transition(&add, 1, &add, 2)
+            .transition(&start, 1, &halt, 1)
+            .run();
+        assert_eq!(report.forbidden, 3);
+        assert_eq!(report.failures.len(), 2);
+        assert!(matches!(
+            &report.failures[0],
+            TransitionFailure::Rejected { from, to, .. } if from == "start" && to == "halt"
+        ));
+        assert_eq!(
+            report.failures[1],
+            TransitionFailure::Accepted {
+                from: "add".to_string(),
+                to: "halt".to_string()
+            }
+        );
+    }
+}